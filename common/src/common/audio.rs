@@ -18,6 +18,95 @@ use rubato::{
 };
 
 use super::options::SystemConfig;
+use crate::numutil::NumExt;
+
+/// Mask used to mute or solo audio channels, for debugging or music analysis.
+/// The meaning of channel numbers is system-specific.
+/// This is an emulator-side override applied to the mixed output,
+/// independent of the channel enables the game itself controls through
+/// the APU registers.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct ChannelMask {
+    /// Bitmask of muted channels.
+    pub muted: u32,
+    /// The channel that is soloed, if any. While a channel is soloed, only it
+    /// is audible, even if it is also muted.
+    pub solo: Option<u8>,
+}
+
+impl ChannelMask {
+    /// If the given channel should be heard.
+    #[inline]
+    pub fn is_audible(self, channel: u8) -> bool {
+        match self.solo {
+            Some(solo) => solo == channel,
+            None => !self.muted.is_bit(channel.u16()),
+        }
+    }
+
+    /// Silence the given sample if the channel is not audible.
+    #[inline]
+    pub fn apply(self, channel: u8, sample: f32) -> f32 {
+        if self.is_audible(channel) {
+            sample
+        } else {
+            0.
+        }
+    }
+
+    /// Mute or unmute a channel.
+    pub fn set_muted(&mut self, channel: u8, muted: bool) {
+        self.muted = self.muted.set_bit(channel.u16(), muted);
+    }
+
+    /// Solo the given channel, or stop soloing it if it already is.
+    pub fn toggle_solo(&mut self, channel: u8) {
+        self.solo = match self.solo {
+            Some(solo) if solo == channel => None,
+            _ => Some(channel),
+        };
+    }
+}
+
+/// Snapshot of the state of a GB-style PSG, for debugging.
+#[derive(Debug, Clone)]
+pub struct PsgState {
+    /// If the APU is powered on.
+    pub power: bool,
+    /// Position of the frame sequencer.
+    pub sequencer_position: i8,
+    /// Current value of all sound registers as read by the CPU,
+    /// by address.
+    pub registers: Vec<(u32, u8)>,
+    /// State of the 4 channels: Pulse 1 and 2, Wave, Noise.
+    pub channels: [PsgChannelState; 4],
+}
+
+/// Snapshot of a single PSG channel, see [PsgState].
+#[derive(Debug, Default, Copy, Clone)]
+pub struct PsgChannelState {
+    /// If the channel is playing.
+    pub enabled: bool,
+    /// If the channel's DAC is on. When off, the channel can't be enabled.
+    pub dac_enabled: bool,
+    /// If the length counter is active and will stop the channel.
+    pub length_enabled: bool,
+    /// Remaining length counter value.
+    pub length_counter: u16,
+    /// Frequency register value. For the noise channel,
+    /// this is the polynomial counter register instead.
+    pub frequency: u16,
+    /// Current volume: The envelope volume, or the volume code for
+    /// the wave channel.
+    pub volume: u8,
+    /// Current digital output, 0-15.
+    pub output: u8,
+}
+
+impl PsgChannelState {
+    pub const NAMES: [&'static str; 4] = ["Pulse 1", "Pulse 2", "Wave", "Noise"];
+}
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 #[cfg_attr(feature = "serde_config", derive(serde::Deserialize, serde::Serialize))]
@@ -106,25 +195,57 @@ impl Display for AudioSampler {
     }
 }
 
+/// Runtime statistics of an [AudioBuffer], for tuning audio settings.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct AudioStats {
+    /// Samples currently buffered and waiting for output,
+    /// at the input sample rate.
+    pub buffered: usize,
+    /// Times output was requested before enough samples were buffered,
+    /// which required the system to be run from the audio thread.
+    pub underruns: u64,
+    /// Estimated time between a sample being produced by the system and
+    /// it being handed to the audio device, in milliseconds.
+    /// Made of the buffered samples and one chunk of output.
+    pub latency_ms: f32,
+}
+
 pub struct AudioBuffer {
     sampler: Mutex<Box<dyn VecResampler<f32>>>,
     temp_output: [Vec<f32>; 2],
     next_frames: usize,
 
     pub input: [Vec<f32>; 2],
+    /// Unmixed output of every audio channel, if enabled.
+    /// Samples are mono and at the input sample rate.
+    stems: Option<Vec<Vec<f32>>>,
     input_sr: usize,
     output_chunk_size: usize,
     output_sr: usize,
     sampling: AudioSampler,
+    underruns: u64,
+    /// Fraction of an input sample left over from the last chunk when
+    /// running at a speed that is not a whole number, so that no samples
+    /// are lost or repeated over time.
+    speed_carry: f64,
 }
 
 impl AudioBuffer {
-    pub fn fill_buffer(&mut self, buf: &mut [f32], skip: usize, volume: f32) {
-        assert_ne!(0, skip);
+    /// Fill the output buffer with resampled audio, running at the given
+    /// speed multiplier. At speeds other than 1x, `speed` times as many
+    /// input samples are consumed, averaged down to the amount the
+    /// resampler needs.
+    pub fn fill_buffer(&mut self, buf: &mut [f32], speed: f32, volume: f32) {
+        assert!(speed > 0.0);
         assert_eq!(buf.len() / 2, self.temp_output[0].len());
-        if skip != 1 {
+        if speed != 1.0 {
+            let exact = self.next_frames as f64 * speed as f64 + self.speed_carry;
+            let needed = (exact.floor() as usize).min(self.input[0].len());
+            self.speed_carry = exact.fract();
+            let frames = self.next_frames;
             self.input.iter_mut().for_each(|v| {
-                *v = v.iter().step_by(skip).copied().collect();
+                let decimated = decimate(&v[..needed], frames);
+                v.splice(..needed, decimated);
             });
         }
 
@@ -149,11 +270,59 @@ impl AudioBuffer {
         self.next_frames = sampler.input_frames_next();
     }
 
-    pub fn can_fill_buffer(&self, skip: usize) -> bool {
-        (self.next_frames * skip) <= self.input[0].len()
+    pub fn can_fill_buffer(&self, speed: f32) -> bool {
+        let needed = self.next_frames as f64 * speed as f64 + self.speed_carry;
+        needed.floor() as usize <= self.input[0].len()
+    }
+
+    /// Record that output was requested while [can_fill_buffer] was false.
+    pub fn record_underrun(&mut self) {
+        self.underruns += 1;
+    }
+
+    /// Get statistics about the current state of the buffer.
+    pub fn stats(&self) -> AudioStats {
+        let buffered = self.input[0].len();
+        let buffered_ms = buffered as f32 * 1000.0 / self.input_sr as f32;
+        let chunk_ms = self.output_chunk_size as f32 * 1000.0 / self.output_sr as f32;
+        AudioStats {
+            buffered,
+            underruns: self.underruns,
+            latency_ms: buffered_ms + chunk_ms,
+        }
+    }
+
+    /// Enable recording the given amount of channels into separate stem
+    /// buffers, or disable it if `channels` is 0.
+    pub fn set_stem_channels(&mut self, channels: usize) {
+        self.stems = (channels != 0).then(|| vec![vec![]; channels]);
+    }
+
+    /// Push one sample for every channel into the stem buffers.
+    /// Does nothing if stems are disabled.
+    #[inline]
+    pub fn push_stems(&mut self, samples: &[f32]) {
+        if let Some(stems) = &mut self.stems {
+            for (stem, sample) in stems.iter_mut().zip(samples) {
+                stem.push(*sample);
+            }
+        }
+    }
+
+    /// Take all stem samples recorded so far, leaving the buffers empty.
+    pub fn take_stems(&mut self) -> Option<Vec<Vec<f32>>> {
+        let stems = self.stems.as_mut()?;
+        Some(stems.iter_mut().map(std::mem::take).collect())
+    }
+
+    pub fn input_sr(&self) -> usize {
+        self.input_sr
     }
 
     pub fn set_input_sr(&mut self, sr: usize) {
+        if sr == self.input_sr {
+            return;
+        }
         self.input_sr = sr;
         self.reinit_sampler();
     }
@@ -186,6 +355,7 @@ impl AudioBuffer {
             size,
         ));
         self.input.iter_mut().for_each(|v| v.clear());
+        self.speed_carry = 0.0;
         self.next_frames = self.sampler.lock().unwrap().input_frames_next();
     }
 
@@ -197,16 +367,95 @@ impl AudioBuffer {
             temp_output: [vec![], vec![]],
             next_frames,
             input: [vec![], vec![]],
+            stems: None,
             input_sr: 48000,
             output_sr: config.sample_rate,
             output_chunk_size: 1024,
             sampling: config.resampler,
+            underruns: 0,
+            speed_carry: 0.0,
         }
     }
 }
 
+/// Resample `input` to `len` samples by averaging each block of input
+/// samples that falls onto an output sample. This low-pass filters the
+/// audio when fast forwarding, which avoids the aliasing simply dropping
+/// samples would cause. If `input` is shorter than `len`, samples are
+/// repeated instead.
+pub fn decimate(input: &[f32], len: usize) -> Vec<f32> {
+    if input.is_empty() {
+        return vec![0.0; len];
+    }
+    let ratio = input.len() as f64 / len as f64;
+    (0..len)
+        .map(|i| {
+            let start = ((i as f64 * ratio) as usize).min(input.len() - 1);
+            let end = (((i + 1) as f64 * ratio) as usize).clamp(start + 1, input.len());
+            input[start..end].iter().sum::<f32>() / (end - start) as f32
+        })
+        .collect()
+}
+
 impl Default for AudioBuffer {
     fn default() -> Self {
         Self::with_config(&SystemConfig::default())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Run the buffer at the given speed with a ramp as input, returning
+    /// the amount of input samples consumed per output sample and the
+    /// slope of the ramp in the output.
+    fn run_at_speed(speed: f32) -> (f64, f64) {
+        const CHUNK: usize = 512;
+        const CHUNKS: usize = 40;
+        let mut buffer = AudioBuffer::with_config(&SystemConfig::default());
+        buffer.set_output_sr(48000);
+        buffer.update_output_chunk_size(CHUNK);
+
+        let mut pushed = 0;
+        let mut output = Vec::<f32>::new();
+        let mut buf = [0.0; CHUNK * 2];
+        for _ in 0..CHUNKS {
+            while buffer.input[0].len() < 8192 {
+                let sample = pushed as f32 * 1e-4;
+                buffer.input[0].push(sample);
+                buffer.input[1].push(sample);
+                pushed += 1;
+            }
+            assert!(buffer.can_fill_buffer(speed));
+            buffer.fill_buffer(&mut buf, speed, 1.0);
+            output.extend(buf.iter().step_by(2));
+        }
+        assert_eq!(output.len(), CHUNK * CHUNKS);
+
+        let consumed = (pushed - buffer.input[0].len()) as f64;
+        let (a, b) = (CHUNK * 4, output.len() - CHUNK);
+        let slope = (output[b] - output[a]) as f64 / (b - a) as f64 / 1e-4;
+        (consumed / output.len() as f64, slope)
+    }
+
+    #[test]
+    fn fast_forward_pacing() {
+        for speed in [1.0, 2.0, 3.0, 2.5] {
+            let (consumed, slope) = run_at_speed(speed);
+            let speed = speed as f64;
+            assert!(
+                (consumed - speed).abs() < speed * 0.02,
+                "{speed}: {consumed}"
+            );
+            assert!((slope - speed).abs() < speed * 0.02, "{speed}: {slope}");
+        }
+    }
+
+    #[test]
+    fn decimate_averages() {
+        assert_eq!(decimate(&[1.0, 3.0, 5.0, 7.0, 9.0, 11.0], 2), [3.0, 9.0]);
+        assert_eq!(decimate(&[2.0, 4.0, 6.0, 8.0], 2), [3.0, 7.0]);
+        assert_eq!(decimate(&[], 3), [0.0; 3]);
+    }
+}
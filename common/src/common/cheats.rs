@@ -0,0 +1,300 @@
+// Copyright (c) 2024 Leela Aurelia, git@elia.garden
+//
+// Unless otherwise noted, this file is released and thus subject to the
+// terms of the Mozilla Public License Version 2.0 (MPL-2.0) or the
+// GNU General Public License Version 3 (GPL-3).
+// If a copy of these licenses was not distributed with this file, you can
+// obtain them at https://mozilla.org/MPL/2.0/ and http://www.gnu.org/licenses/.
+
+use std::fmt::Display;
+
+use super::debugger::Width;
+use crate::CoreKind;
+
+/// Handle of a cheat added with [Cheats::add].
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct CheatId(u32);
+
+/// Reason a cheat code failed to parse.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CheatError {
+    /// The code contained no lines.
+    Empty,
+    /// Cheats are not supported on this system.
+    UnsupportedSystem(Option<CoreKind>),
+    /// A line has a length that matches none of the formats supported on
+    /// the system.
+    InvalidLength {
+        line: String,
+        len: usize,
+        system: CoreKind,
+    },
+    /// A line contains a character that is not a hex digit.
+    InvalidHex { line: String, char: char },
+    /// A GBA code uses a code type that is not supported.
+    UnsupportedType { line: String, kind: u8 },
+}
+
+impl Display for CheatError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CheatError::Empty => write!(f, "Cheat code is empty"),
+            CheatError::UnsupportedSystem(Some(system)) => {
+                write!(f, "Cheats are not supported on {}", system.name())
+            }
+            CheatError::UnsupportedSystem(None) => {
+                write!(f, "Cheats are not supported on this system")
+            }
+            CheatError::InvalidLength { line, len, system } => {
+                let expected = match system {
+                    CoreKind::Ggc => "6 or 9 (Game Genie) or 8 (GameShark)",
+                    _ => "16 (GameShark)",
+                };
+                write!(f, "'{line}' has {len} digits, expected {expected}")
+            }
+            CheatError::InvalidHex { line, char } => {
+                write!(f, "'{line}' contains '{char}', which is not a hex digit")
+            }
+            CheatError::UnsupportedType { line, kind } => {
+                write!(f, "'{line}' uses unsupported code type {kind:X}")
+            }
+        }
+    }
+}
+
+/// A single memory write performed by a cheat.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct CheatWrite {
+    pub addr: u32,
+    pub value: u32,
+    pub width: Width,
+    /// Only write if memory currently contains this value.
+    pub compare: Option<u32>,
+}
+
+/// A cheat code, made up of one or more writes.
+#[derive(Debug, Clone)]
+pub struct Cheat {
+    pub id: CheatId,
+    /// The code as it was entered.
+    pub code: String,
+    pub writes: Vec<CheatWrite>,
+}
+
+/// Cheats currently active. Writes to RAM are applied by the core once per
+/// frame, see [crate::Core::apply_cheats]; writes to ROM on every read,
+/// see [crate::Core::set_rom_cheats].
+#[derive(Default)]
+pub struct Cheats {
+    pub active: Vec<Cheat>,
+    next_id: u32,
+}
+
+impl Cheats {
+    /// Parse and activate a cheat code for the given system. Multiple
+    /// codes that belong together can be given on separate lines.
+    /// The format of each line is detected by its amount of hex digits,
+    /// ignoring whitespace and dashes:
+    /// - GB, 6 or 9: Game Genie (`ABC-DEF` or `ABC-DEF-GHI`)
+    /// - GB, 8: GameShark (`ttvvllhh`, the RAM bank `tt` is ignored)
+    /// - GBA, 16: GameShark/Action Replay, unencrypted (`0aaaaaaa 000000vv` for
+    ///   byte, `1aaaaaaa 0000vvvv` for halfword and `2aaaaaaa vvvvvvvv` for
+    ///   word writes)
+    ///
+    /// Other systems do not support cheats.
+    pub fn add(&mut self, raw: &str, system: Option<CoreKind>) -> Result<CheatId, CheatError> {
+        let system = match system {
+            Some(system @ (CoreKind::Ggc | CoreKind::Gga)) => system,
+            _ => return Err(CheatError::UnsupportedSystem(system)),
+        };
+        let writes = raw
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty())
+            .map(|line| parse_line(line, system))
+            .collect::<Result<Vec<_>, _>>()?;
+        if writes.is_empty() {
+            return Err(CheatError::Empty);
+        }
+
+        let id = CheatId(self.next_id);
+        self.next_id += 1;
+        self.active.push(Cheat {
+            id,
+            code: raw.trim().to_string(),
+            writes,
+        });
+        Ok(id)
+    }
+
+    /// Deactivate a cheat, returning it. `None` if it was already removed.
+    pub fn remove(&mut self, id: CheatId) -> Option<Cheat> {
+        let idx = self.active.iter().position(|c| c.id == id)?;
+        Some(self.active.remove(idx))
+    }
+}
+
+fn parse_line(line: &str, system: CoreKind) -> Result<CheatWrite, CheatError> {
+    let mut digits = Vec::with_capacity(16);
+    for char in line.chars().filter(|c| !c.is_whitespace() && *c != '-') {
+        match char.to_digit(16) {
+            Some(digit) => digits.push(digit),
+            None => {
+                return Err(CheatError::InvalidHex {
+                    line: line.to_string(),
+                    char,
+                })
+            }
+        }
+    }
+    let num = |range: std::ops::Range<usize>| digits[range].iter().fold(0, |acc, d| acc << 4 | d);
+
+    match (system, digits.len()) {
+        (CoreKind::Ggc, 6 | 9) => {
+            // Game Genie: ABC-DEF-GHI
+            // AB is the value, FCDE the address XOR 0xF000, and GI the
+            // compare value, rotated left by 2 and XOR 0xBA
+            let addr = ((digits[5] ^ 0xF) << 12) | num(2..5);
+            let compare = (digits.len() == 9)
+                .then(|| ((digits[6] << 4 | digits[8]) as u8).rotate_right(2) ^ 0xBA);
+            Ok(CheatWrite {
+                addr,
+                value: num(0..2),
+                width: Width::Byte,
+                compare: compare.map(u32::from),
+            })
+        }
+        (CoreKind::Ggc, 8) => {
+            // GameShark: ttvvllhh
+            Ok(CheatWrite {
+                addr: num(6..8) << 8 | num(4..6),
+                value: num(2..4),
+                width: Width::Byte,
+                compare: None,
+            })
+        }
+        (CoreKind::Gga, 16) => {
+            let width = match digits[0] {
+                0 => Width::Byte,
+                1 => Width::Halfword,
+                2 => Width::Word,
+                kind => {
+                    return Err(CheatError::UnsupportedType {
+                        line: line.to_string(),
+                        kind: kind as u8,
+                    })
+                }
+            };
+            Ok(CheatWrite {
+                addr: num(1..8),
+                value: num(8..16),
+                width,
+                compare: None,
+            })
+        }
+        (_, len) => Err(CheatError::InvalidLength {
+            line: line.to_string(),
+            len,
+            system,
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(code: &str, system: CoreKind) -> Vec<CheatWrite> {
+        let mut cheats = Cheats::default();
+        let id = cheats.add(code, Some(system)).unwrap();
+        cheats.remove(id).unwrap().writes
+    }
+
+    fn write(addr: u32, value: u32, width: Width, compare: Option<u32>) -> CheatWrite {
+        CheatWrite {
+            addr,
+            value,
+            width,
+            compare,
+        }
+    }
+
+    #[test]
+    fn gb_game_genie() {
+        assert_eq!(
+            decode("3EA-F8B-4C1", CoreKind::Ggc),
+            [write(0x4AF8, 0x3E, Width::Byte, Some(0xEA))]
+        );
+        assert_eq!(
+            decode("00A-17B", CoreKind::Ggc),
+            [write(0x4A17, 0x00, Width::Byte, None)]
+        );
+    }
+
+    #[test]
+    fn gb_gameshark() {
+        assert_eq!(
+            decode("01FF57CF", CoreKind::Ggc),
+            [write(0xCF57, 0xFF, Width::Byte, None)]
+        );
+        // Multiple lines, whitespace is ignored
+        assert_eq!(
+            decode("0163 D3D5\n 010F57C0 ", CoreKind::Ggc),
+            [
+                write(0xD5D3, 0x63, Width::Byte, None),
+                write(0xC057, 0x0F, Width::Byte, None)
+            ]
+        );
+    }
+
+    #[test]
+    fn gba_gameshark() {
+        assert_eq!(
+            decode("03007D2A 000000FF", CoreKind::Gga),
+            [write(0x3007D2A, 0xFF, Width::Byte, None)]
+        );
+        assert_eq!(
+            decode("12025F10 0000270F", CoreKind::Gga),
+            [write(0x2025F10, 0x270F, Width::Halfword, None)]
+        );
+        assert_eq!(
+            decode("22000040 0001869F", CoreKind::Gga),
+            [write(0x2000040, 0x1869F, Width::Word, None)]
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let mut cheats = Cheats::default();
+        let gb = Some(CoreKind::Ggc);
+        assert_eq!(cheats.add(" \n", gb), Err(CheatError::Empty));
+        assert_eq!(
+            cheats.add("01FF57CZ", gb),
+            Err(CheatError::InvalidHex {
+                line: "01FF57CZ".into(),
+                char: 'Z'
+            })
+        );
+        // GBA codes are not valid on GB, and the other way around
+        assert!(matches!(
+            cheats.add("03007D2A 000000FF", gb),
+            Err(CheatError::InvalidLength { len: 16, .. })
+        ));
+        assert!(matches!(
+            cheats.add("01FF57CF", Some(CoreKind::Gga)),
+            Err(CheatError::InvalidLength { len: 8, .. })
+        ));
+        assert_eq!(
+            cheats.add("32000000 00000001", Some(CoreKind::Gga)),
+            Err(CheatError::UnsupportedType {
+                line: "32000000 00000001".into(),
+                kind: 3
+            })
+        );
+        assert_eq!(
+            cheats.add("01FF57CF", Some(CoreKind::Nds)),
+            Err(CheatError::UnsupportedSystem(Some(CoreKind::Nds)))
+        );
+        assert!(cheats.active.is_empty());
+    }
+}
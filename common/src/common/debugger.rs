@@ -8,7 +8,7 @@
 
 use std::{cmp::Ordering, fmt::Debug, sync::Mutex, time::Instant};
 
-use crate::{numutil::NumExt, Pointer};
+use crate::{numutil::NumExt, Colour, Pointer};
 
 /// Debugger info that is required to be known by the system.
 /// Is generic over cores.
@@ -20,6 +20,9 @@ pub struct Debugger {
     /// If instructions should be traced and printed to a file, this contains
     /// the instructions to be printed / file contents.
     pub traced_instructions: Option<String>,
+    /// If set, only instructions with a PC inside this inclusive range
+    /// are traced.
+    pub trace_range: Option<(Pointer, Pointer)>,
 
     /// If the system is running. If false, any calls to functions advancing
     /// the system based on outside sources (time, sound) will do nothing.
@@ -29,6 +32,10 @@ pub struct Debugger {
     pub breakpoints: Vec<Breakpoint>,
     /// The hit breakpoint's index.
     pub breakpoint_hit: Option<usize>,
+    /// A list of memory watchpoints the system should stop on.
+    pub watchpoints: Vec<Watchpoint>,
+    /// The last watchpoint that was hit, together with the access.
+    pub watchpoint_hit: Option<WatchpointHit>,
 
     /// The diagnostic level that is currently enabled.
     /// Any diagnostic events with a severity lower than this will not be
@@ -36,6 +43,10 @@ pub struct Debugger {
     pub diagnostic_level: Severity,
     /// Diagnostic events that have occurred.
     pub diagnostic_events: Mutex<Vec<DiagnosticEvent>>,
+    /// If the system should pause when it hits something invalid or
+    /// unimplemented, like an unknown opcode, instead of continuing.
+    /// See [Debugger::invalid_state].
+    pub pause_on_invalid: bool,
 }
 
 impl Debugger {
@@ -55,6 +66,38 @@ impl Debugger {
         }
     }
 
+    /// Called on memory reads and writes of `width` bytes done by the CPU,
+    /// which might trigger a watchpoint. If one does, the system stops
+    /// after the current instruction; the access itself still happens.
+    #[inline]
+    pub fn memory_accessed(&mut self, addr: Pointer, width: u32, value: u32, write: bool) {
+        if !self.watchpoints.is_empty() {
+            self.check_watchpoints(addr, width, value, write);
+        }
+    }
+
+    fn check_watchpoints(&mut self, addr: Pointer, width: u32, value: u32, write: bool) {
+        let Some(index) = self
+            .watchpoints
+            .iter()
+            .position(|wp| wp.matches(addr, width, value, write))
+        else {
+            return;
+        };
+
+        self.watchpoint_hit = Some(WatchpointHit {
+            index,
+            addr,
+            value,
+            write,
+        });
+        self.running = false;
+        self.add_traced_instruction(|| {
+            let kind = if write { "Write" } else { "Read" };
+            format!("{kind} of {value:#X} at {addr:#X} hit watchpoint")
+        });
+    }
+
     /// Called before an instruction is executed, which might trigger a BP.
     /// If it does, function returns false and inst should not be executed.
     pub fn should_execute(&mut self, pc: Pointer) -> bool {
@@ -82,6 +125,15 @@ impl Debugger {
             && self.traced_instructions.as_ref().unwrap().len() <= 20_000_000
     }
 
+    /// If the instruction at the given PC should be traced.
+    #[inline]
+    pub fn tracing_pc(&self, pc: Pointer) -> bool {
+        self.tracing()
+            && self
+                .trace_range
+                .map_or(true, |(start, end)| (start..=end).contains(&pc))
+    }
+
     /// Add another instruction to trace.
     pub fn add_traced_instruction(&mut self, writer: impl FnOnce() -> String) {
         if let Some(instr) = self.traced_instructions.as_mut() {
@@ -107,6 +159,32 @@ impl Debugger {
                 });
         }
     }
+
+    /// Report that the system hit something invalid or unimplemented,
+    /// like an unknown opcode. Logged as an error; if [pause_on_invalid] is
+    /// enabled, the system is also paused and the event is recorded
+    /// together with the given dump of the system state, regardless of
+    /// the diagnostic level. The dump is only created in that case.
+    pub fn invalid_state(&mut self, evt_type: &str, event: String, dump: impl FnOnce() -> String) {
+        if !self.pause_on_invalid {
+            self.log(evt_type, event, Severity::Error);
+            return;
+        }
+
+        self.running = false;
+        let event = format!("{event}\n{}", dump());
+        log::error!("Paused on invalid state: {event}");
+        self.diagnostic_events
+            .lock()
+            .unwrap()
+            .push(DiagnosticEvent {
+                evt_type: evt_type.to_string(),
+                event,
+                severity: Severity::Error,
+                time: Instant::now(),
+                state: None,
+            });
+    }
 }
 
 /// A breakpoint.
@@ -123,6 +201,58 @@ pub struct Breakpoint {
     pub write: bool,
 }
 
+/// A watchpoint, stopping the system when memory inside its address range
+/// is read or written.
+#[derive(Clone, Debug)]
+pub struct Watchpoint {
+    /// First address watched.
+    pub start: Pointer,
+    /// Last address watched, inclusive.
+    pub end: Pointer,
+    /// If this watchpoint triggers on reads.
+    pub read: bool,
+    /// If this watchpoint triggers on writes.
+    pub write: bool,
+    /// If set, only accesses of this value trigger the watchpoint.
+    pub value: Option<u32>,
+}
+
+impl Watchpoint {
+    /// If an access of `width` bytes at the given address triggers this
+    /// watchpoint.
+    pub fn matches(&self, addr: Pointer, width: u32, value: u32, write: bool) -> bool {
+        let last = addr.wrapping_add(width - 1);
+        let kind_matches = if write { self.write } else { self.read };
+        kind_matches
+            && addr <= self.end
+            && last >= self.start
+            && self.value.map_or(true, |v| v == value)
+    }
+}
+
+impl Default for Watchpoint {
+    fn default() -> Self {
+        Self {
+            start: 0,
+            end: 0,
+            read: true,
+            write: true,
+            value: None,
+        }
+    }
+}
+
+/// A memory access that hit a watchpoint.
+#[derive(Clone, Copy, Debug)]
+pub struct WatchpointHit {
+    /// Index of the watchpoint in [Debugger::watchpoints].
+    pub index: usize,
+    pub addr: Pointer,
+    /// Value that was read or written.
+    pub value: u32,
+    pub write: bool,
+}
+
 /// A diagnostic event that might be interesting during debugging.
 #[derive(Debug)]
 pub struct DiagnosticEvent {
@@ -158,6 +288,143 @@ impl PartialOrd for Severity {
     }
 }
 
+/// Attributes of a single sprite/object, decoded from OAM.
+/// Exact ranges are platform-specific.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SpriteInfo {
+    /// Position on screen in pixels, can be negative for sprites
+    /// partially off-screen.
+    pub x: i16,
+    pub y: i16,
+    /// Index of the (first) tile.
+    pub tile: u16,
+    pub x_flip: bool,
+    pub y_flip: bool,
+    /// Priority relative to backgrounds. On GB, this is the
+    /// "BG over OBJ" bit; on GBA the priority from 0 (highest) to 3.
+    pub priority: u8,
+    /// Palette index.
+    pub palette: u8,
+}
+
+/// A fully rendered background tile map, together with the part of it that
+/// is currently shown on screen.
+#[derive(Clone, Debug, Default)]
+pub struct TileMap {
+    /// Size of the map in pixels.
+    pub width: usize,
+    pub height: usize,
+    /// Pixels of the map, row by row.
+    pub pixels: Vec<Colour>,
+    /// Position in the map that is shown at the top left of the screen.
+    pub scroll: [usize; 2],
+    /// Size of the part of the map shown on screen, in pixels.
+    pub visible: [usize; 2],
+}
+
+impl TileMap {
+    /// Get the part of the map shown on screen as `[x, y, width, height]`
+    /// rectangles. Since the screen wraps around the edges of the map,
+    /// this can be up to 4 rectangles.
+    pub fn visible_rects(&self) -> Vec<[usize; 4]> {
+        let xs = Self::wrap(self.scroll[0], self.visible[0], self.width);
+        let ys = Self::wrap(self.scroll[1], self.visible[1], self.height);
+        ys.iter()
+            .flat_map(|(y, h)| xs.iter().map(move |(x, w)| [*x, *y, *w, *h]))
+            .collect()
+    }
+
+    /// Split the range starting at `start` with length `len` into
+    /// `(start, len)` parts that do not cross the end of `size`.
+    fn wrap(start: usize, len: usize, size: usize) -> Vec<(usize, usize)> {
+        if size == 0 || len == 0 {
+            return vec![];
+        }
+        let start = start % size;
+        let len = len.min(size);
+        if start + len <= size {
+            vec![(start, len)]
+        } else {
+            vec![(start, size - start), (0, len - (size - start))]
+        }
+    }
+}
+
+/// A region of a system's address space, like RAM or ROM, for
+/// memory viewers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MemoryRegion {
+    pub name: &'static str,
+    /// Address of the first byte of the region.
+    pub start: u32,
+    /// Size of the region in bytes, without any mirrors.
+    pub len: u32,
+    /// If the region can be modified with `Core::set_memory`.
+    pub writable: bool,
+}
+
+impl MemoryRegion {
+    pub const fn new(name: &'static str, start: u32, len: u32, writable: bool) -> Self {
+        Self {
+            name,
+            start,
+            len,
+            writable,
+        }
+    }
+
+    /// Address of the last byte of the region.
+    pub fn end(&self) -> u32 {
+        self.start + self.len.saturating_sub(1)
+    }
+}
+
+/// State of a system's interrupts, for debugging.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct InterruptState {
+    /// The interrupt master enable flag (IME).
+    pub master_enable: bool,
+    /// If the CPU itself is currently masking interrupts, like with the
+    /// I bit in CPSR on ARM CPUs.
+    pub cpu_masked: bool,
+    /// If the CPU is halted, waiting for an interrupt.
+    pub halted: bool,
+    /// All interrupt sources of the system, in order of their bit in IE/IF.
+    pub sources: Vec<InterruptSource>,
+}
+
+impl InterruptState {
+    /// Sources that are both requested and enabled.
+    /// These get serviced if interrupts are not masked by IME or the CPU.
+    pub fn pending(&self) -> impl Iterator<Item = &InterruptSource> {
+        self.sources.iter().filter(|s| s.enabled && s.requested)
+    }
+
+    /// Create the sources from the names of all interrupts and the values
+    /// of IE and IF.
+    pub fn sources_from_bits(names: &[&'static str], ie: u32, if_: u32) -> Vec<InterruptSource> {
+        names
+            .iter()
+            .enumerate()
+            .map(|(bit, name)| InterruptSource {
+                name,
+                enabled: ie.is_bit(bit as u16),
+                requested: if_.is_bit(bit as u16),
+            })
+            .collect()
+    }
+}
+
+/// A single source of interrupts.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InterruptSource {
+    pub name: &'static str,
+    /// If the interrupt is enabled in IE.
+    pub enabled: bool,
+    /// If the interrupt is requested in IF.
+    pub requested: bool,
+}
+
 /// Width of a value to be read/written from memory.
 #[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
 pub enum Width {
@@ -209,3 +476,47 @@ pub fn search_array(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn map(scroll: [usize; 2], visible: [usize; 2]) -> TileMap {
+        TileMap {
+            width: 256,
+            height: 256,
+            pixels: vec![],
+            scroll,
+            visible,
+        }
+    }
+
+    #[test]
+    fn visible_rects() {
+        // Fully inside the map
+        assert_eq!(
+            map([16, 8], [160, 144]).visible_rects(),
+            [[16, 8, 160, 144]]
+        );
+        // Wrapping around both edges
+        assert_eq!(
+            map([200, 150], [160, 144]).visible_rects(),
+            [
+                [200, 150, 56, 106],
+                [0, 150, 104, 106],
+                [200, 0, 56, 38],
+                [0, 0, 104, 38]
+            ]
+        );
+        // Clipped, like a window that is only partially on screen
+        assert_eq!(map([0, 0], [60, 40]).visible_rects(), [[0, 0, 60, 40]]);
+        // Larger than the map, clipped to its size
+        assert_eq!(
+            map([8, 0], [512, 100]).visible_rects(),
+            [[8, 0, 248, 100], [0, 0, 8, 100]]
+        );
+        // Fully hidden
+        assert!(map([0, 0], [0, 144]).visible_rects().is_empty());
+        assert!(map([0, 0], [160, 0]).visible_rects().is_empty());
+    }
+}
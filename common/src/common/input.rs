@@ -10,6 +10,10 @@ use std::{collections::BTreeMap, fmt::Write, ops::Bound, path::PathBuf};
 
 use crate::{numutil::NumExt, Time};
 
+/// Callback invoked by cores when a cartridge's rumble motor turns on
+/// (`true`) or off (`false`). See [crate::Common::set_rumble].
+pub type RumbleCallback = Box<dyn FnMut(bool) + Send + Sync>;
+
 /// Buttons on a system. Not all are used for all systems.
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 #[cfg_attr(feature = "serde_config", derive(serde::Deserialize, serde::Serialize))]
@@ -27,10 +31,14 @@ pub enum Button {
     L = 9,
     X = 10,
     Y = 11,
+    L2 = 12,
+    R2 = 13,
+    L3 = 14,
+    R3 = 15,
 }
 
 impl Button {
-    pub const BUTTONS: [Self; 12] = [
+    pub const BUTTONS: [Self; 16] = [
         Self::A,
         Self::B,
         Self::Select,
@@ -43,6 +51,10 @@ impl Button {
         Self::L,
         Self::X,
         Self::Y,
+        Self::L2,
+        Self::R2,
+        Self::L3,
+        Self::R3,
     ];
 }
 
@@ -6,19 +6,32 @@
 // If a copy of these licenses was not distributed with this file, you can
 // obtain them at https://mozilla.org/MPL/2.0/ and http://www.gnu.org/licenses/.
 
+use std::{mem, sync::OnceLock};
+
+use cheats::Cheats;
 use debugger::Debugger;
-use input::Input;
-use options::{EmulateOptions, SystemConfig};
+use input::{Input, RumbleCallback};
+use options::{EmulateOptions, GameHacks, SystemConfig};
+use speed::SpeedMeter;
 use video::FrameBuffer;
 
 use self::audio::AudioBuffer;
 
 pub mod audio;
+pub mod cheats;
 pub mod debugger;
 pub mod input;
 pub mod options;
+#[cfg(feature = "serde")]
+pub mod replay;
+pub mod speed;
 pub mod video;
 
+/// Lowest speed multiplier systems can run at.
+pub const MIN_SPEED: f32 = 0.25;
+/// Highest speed multiplier systems can run at.
+pub const MAX_SPEED: f32 = 16.0;
+
 /// Common fields shared by all systems.
 #[derive(Default)]
 #[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
@@ -34,22 +47,181 @@ pub struct Common {
     #[cfg_attr(feature = "serde", serde(skip, default))]
     pub audio_buffer: AudioBuffer,
     pub input: Input,
+    /// Cache for [crate::Core::rom_hash].
+    #[cfg_attr(feature = "serde", serde(skip, default))]
+    pub rom_hash: OnceLock<u64>,
+    /// Measurement of emulation speed, see
+    /// [crate::Core::emulation_speed_percent].
+    #[cfg_attr(feature = "serde", serde(skip, default))]
+    pub speed: SpeedMeter,
+    /// Compatibility hacks for the loaded game, detected by the core or
+    /// taken from [SystemConfig::game_hacks] when it was loaded.
+    #[cfg_attr(feature = "serde", serde(skip, default))]
+    pub game_hacks: GameHacks,
+    /// Callback for rumble motor changes, set by the frontend.
+    #[cfg_attr(feature = "serde", serde(skip, default))]
+    pub rumble: Option<RumbleCallback>,
+    /// If the rumble motor is currently on.
+    #[cfg_attr(feature = "serde", serde(skip, default))]
+    rumble_on: bool,
+    /// If the rumble motor was on at any point since the last
+    /// [Common::take_rumble].
+    #[cfg_attr(feature = "serde", serde(skip, default))]
+    rumble_latch: bool,
+    /// Active cheat codes, see [crate::Core::add_cheat].
+    #[cfg_attr(feature = "serde", serde(skip, default))]
+    pub cheats: Cheats,
 }
 
 impl Common {
     pub fn with_config(config: SystemConfig) -> Self {
+        let mut video_buffer = FrameBuffer::default();
+        video_buffer.set_history_len(config.frame_history);
         Self {
+            video_buffer,
             audio_buffer: AudioBuffer::with_config(&config),
             config,
             ..Default::default()
         }
     }
 
+    /// Start fast forwarding at the given speed while the hold button is
+    /// pressed, or stop if `None`. See [EmulateOptions::fast_forward_speed].
+    pub fn set_fast_forward_hold(&mut self, speed: Option<f32>) {
+        self.options.fast_forward_hold = speed;
+        self.update_speed();
+    }
+
+    /// Toggle fast forwarding at the given speed.
+    /// See [EmulateOptions::fast_forward_speed].
+    pub fn toggle_fast_forward(&mut self, speed: f32) {
+        self.options.fast_forward_toggle = match self.options.fast_forward_toggle {
+            Some(_) => None,
+            None => Some(speed),
+        };
+        self.update_speed();
+    }
+
+    fn update_speed(&mut self) {
+        let speed = self
+            .options
+            .fast_forward_speed()
+            .clamp(MIN_SPEED, MAX_SPEED);
+        self.options.speed_multiplier = speed;
+        // Only show about as many frames as at regular speed
+        self.video_buffer.frameskip = speed.max(1.0) as usize - 1;
+    }
+
+    /// Set the speed the system runs at when not fast forwarding, see
+    /// [crate::Core::set_speed_multiplier].
+    pub fn set_speed_multiplier(&mut self, speed: f32) {
+        self.options.base_speed = speed.clamp(MIN_SPEED, MAX_SPEED);
+        self.update_speed();
+    }
+
+    /// Set the state of the cartridge's rumble motor. The [Common::rumble]
+    /// callback is only invoked when it changes.
+    pub fn set_rumble(&mut self, on: bool) {
+        self.rumble_latch |= on;
+        if on != self.rumble_on {
+            self.rumble_on = on;
+            if let Some(callback) = &mut self.rumble {
+                callback(on);
+            }
+        }
+    }
+
+    /// If the rumble motor was on at any point since this was last called.
+    /// Frontends polling the motor state use this so that pulses shorter
+    /// than their polling interval are not lost.
+    pub fn take_rumble(&mut self) -> bool {
+        mem::replace(&mut self.rumble_latch, self.rumble_on)
+    }
+
     pub fn restore_from(&mut self, old: Self) {
         self.debugger = old.debugger;
         self.options = old.options;
         self.config = old.config;
+        self.video_buffer.set_history_len(self.config.frame_history);
         self.audio_buffer = old.audio_buffer;
         self.audio_buffer.reinit_sampler();
+        self.rom_hash = old.rom_hash;
+        self.game_hacks = old.game_hacks;
+        self.rumble = old.rumble;
+        // The motor is off after a reset or state load
+        self.rumble_on = old.rumble_on;
+        self.set_rumble(false);
+        self.cheats = old.cheats;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    #[test]
+    fn rumble_off_after_restore() {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let mut old = Common::default();
+        let log = calls.clone();
+        old.rumble = Some(Box::new(move |on| log.lock().unwrap().push(on)));
+        old.set_rumble(true);
+
+        let mut new = Common::default();
+        new.restore_from(old);
+        assert_eq!(*calls.lock().unwrap(), [true, false]);
+        new.set_rumble(true);
+        assert_eq!(*calls.lock().unwrap(), [true, false, true]);
+    }
+
+    #[test]
+    fn rumble_pulses_are_latched() {
+        let mut common = Common::default();
+        assert!(!common.take_rumble());
+        // A pulse between two polls is still seen once
+        common.set_rumble(true);
+        common.set_rumble(false);
+        assert!(common.take_rumble());
+        assert!(!common.take_rumble());
+
+        // Staying on is seen on every poll
+        common.set_rumble(true);
+        assert!(common.take_rumble());
+        assert!(common.take_rumble());
+    }
+
+    #[test]
+    fn speed_is_clamped() {
+        let mut common = Common::default();
+        common.set_speed_multiplier(100.0);
+        assert_eq!(common.options.speed_multiplier, MAX_SPEED);
+        common.set_speed_multiplier(0.0);
+        assert_eq!(common.options.speed_multiplier, MIN_SPEED);
+        common.toggle_fast_forward(100.0);
+        assert_eq!(common.options.speed_multiplier, MAX_SPEED);
+        assert_eq!(common.video_buffer.frameskip, MAX_SPEED as usize - 1);
+    }
+
+    #[test]
+    fn fast_forward_restores_base_speed() {
+        let mut common = Common::default();
+        common.set_speed_multiplier(0.5);
+        common.toggle_fast_forward(4.0);
+        common.set_fast_forward_hold(Some(8.0));
+        assert_eq!(common.options.speed_multiplier, 8.0);
+        common.set_fast_forward_hold(None);
+        assert_eq!(common.options.speed_multiplier, 4.0);
+        common.toggle_fast_forward(4.0);
+        assert_eq!(common.options.speed_multiplier, 0.5);
+        assert_eq!(common.video_buffer.frameskip, 0);
+
+        // Changing the base speed while fast forwarding takes effect after
+        common.set_fast_forward_hold(Some(2.0));
+        common.set_speed_multiplier(0.75);
+        assert_eq!(common.options.speed_multiplier, 2.0);
+        common.set_fast_forward_hold(None);
+        assert_eq!(common.options.speed_multiplier, 0.75);
     }
 }
@@ -6,9 +6,10 @@
 // If a copy of these licenses was not distributed with this file, you can
 // obtain them at https://mozilla.org/MPL/2.0/ and http://www.gnu.org/licenses/.
 
-use std::vec;
+use std::{collections::HashMap, vec};
 
-use super::audio::AudioSampler;
+use super::audio::{AudioSampler, ChannelMask};
+use crate::{Colour, CoreKind, Time};
 
 /// Options that are used by the GUI and shared between all systems.
 /// These can be changed at runtime.
@@ -18,53 +19,214 @@ pub struct EmulateOptions {
     /// order. `true` while rewinding.
     pub invert_audio_samples: bool,
     /// Speed multiplier the system should run at.
-    /// ex. 1x is regular speed, 2x is double speed.
+    /// ex. 1x is regular speed, 2x is double speed, 0.5x half speed.
     /// Affects [advance_delta] and sound sample output.
-    pub speed_multiplier: usize,
+    /// Set with [crate::Core::set_speed_multiplier], or by
+    /// [crate::Common::set_fast_forward_hold]
+    /// and [crate::Common::toggle_fast_forward] when fast forwarding;
+    /// should not be changed directly.
+    pub speed_multiplier: f32,
+    /// Speed multiplier the system runs at when not fast forwarding,
+    /// as set with [crate::Core::set_speed_multiplier].
+    pub base_speed: f32,
+    /// Speed of fast forward while the hold button is pressed.
+    pub fast_forward_hold: Option<f32>,
+    /// Speed of fast forward if it was toggled on.
+    pub fast_forward_toggle: Option<f32>,
+    /// Audio channels that are muted or soloed. Since this is part of the
+    /// options and not the game state, it is kept when loading a savestate
+    /// or rewinding, and not affected by the game's own APU registers.
+    pub channel_mask: ChannelMask,
+}
+
+impl EmulateOptions {
+    /// Speed the system should run at given the fast forward state.
+    /// Holding fast forward takes precedence over the toggle, and once
+    /// released the toggle applies again, followed by the base speed.
+    pub fn fast_forward_speed(&self) -> f32 {
+        self.fast_forward_hold
+            .or(self.fast_forward_toggle)
+            .unwrap_or(self.base_speed)
+    }
 }
 
 impl Default for EmulateOptions {
     fn default() -> Self {
         Self {
             invert_audio_samples: false,
-            speed_multiplier: 1,
+            speed_multiplier: 1.0,
+            base_speed: 1.0,
+            fast_forward_hold: None,
+            fast_forward_toggle: None,
+            channel_mask: ChannelMask::default(),
         }
     }
 }
 
 /// Configuration used when initializing the system.
-/// These options don't change at runtime.
+/// Most options only take effect when a system is loaded or reset;
+/// the ones that can change at runtime are listed in
+/// [SystemConfig::apply_live] and applied by `Core::apply_config`.
 #[derive(Clone)]
 #[cfg_attr(feature = "serde_config", derive(serde::Deserialize, serde::Serialize))]
 #[cfg_attr(feature = "serde_config", serde(default))]
 pub struct SystemConfig {
     /// How to handle CGB mode.
     pub mode: CgbMode,
-    /// If save states should be compressed.
+    /// The accuracy profile last applied to this config. Only used to
+    /// show the current choice, since the profile is applied by
+    /// [AccuracyProfile::apply] when picked.
+    pub accuracy: AccuracyProfile,
+    /// If save states should be compressed. States are always loaded
+    /// regardless of this, since compression is detected automatically.
     pub compress_savestates: bool,
     /// If CGB colours should be corrected.
     pub cgb_colour_correction: bool,
+    /// Colour grading applied to CGB background colours.
+    pub cgb_bg_grading: ColourGrading,
+    /// Colour grading applied to CGB object colours.
+    pub cgb_obj_grading: ColourGrading,
+    /// How GBA colours are expanded to 8 bits per channel.
+    pub gba_colour_expansion: ColourExpansion,
+    /// If GBA colours should be corrected to look like on the GBA's LCD,
+    /// darker and less saturated. Only applied with
+    /// [ColourExpansion::Simple], since [ColourExpansion::LcdGamma]
+    /// already simulates the LCD's response.
+    pub gba_colour_correction: bool,
+    /// If the GBA BIOS should only be readable while executing inside of it,
+    /// like on real hardware. Reads from elsewhere return the last opcode
+    /// fetched from the BIOS instead.
+    pub gba_bios_protection: bool,
+    /// If writes to GBA flash and EEPROM saves should take time like on
+    /// hardware, with the chip reporting itself as busy until done.
+    /// Off by default, since instant writes are more compatible.
+    pub save_write_latency: bool,
+    /// If the GB CPU should see the byte currently being transferred when
+    /// reading outside of HRAM and IO during OAM DMA, like on real hardware.
+    pub oam_dma_bus_conflict: bool,
+    /// If the GB CPU should emulate the HALT bug, where the byte after a HALT
+    /// executed with interrupts disabled and an interrupt pending gets read
+    /// twice. Some games rely on it.
+    pub gb_halt_bug: bool,
+    /// If the DMG should emulate the OAM corruption bug, where accessing OAM
+    /// or using 16-bit increments/decrements on an address in OAM while
+    /// the PPU is scanning OAM corrupts sprite data.
+    pub gb_oam_bug: bool,
+    /// If a CGB speed switch should stop the CPU for as long as on real
+    /// hardware, instead of finishing immediately.
+    pub gb_speed_switch_timing: bool,
+    /// If the GB CPU should be locked out of VRAM and OAM while the PPU is
+    /// using them, and out of OAM during OAM DMA, like on real hardware.
+    /// Reads return 0xFF and writes are ignored.
+    pub gb_ppu_memory_locking: bool,
+    /// If the GB LCD's slow response should be simulated by blending each
+    /// frame with the previous ones. Some games and demos flicker between
+    /// frames to produce additional shades that only show with this.
+    pub gb_lcd_ghosting: bool,
+    /// If the GB PPU should draw lines pixel by pixel using the pixel FIFO
+    /// instead of all at once at the end of mode 3. This makes mode 3 take
+    /// a variable amount of time and mid-scanline register writes take
+    /// effect, at the cost of speed.
+    /// Experimental, so it is not enabled by any [AccuracyProfile].
+    pub gb_accurate_ppu: bool,
+    /// If ROMs without the Nintendo logo in their header should still be
+    /// detected as GB when their file extension or valid header checksum
+    /// suggests so, for homebrew and test ROMs that leave it out.
+    /// Off by default, since it can misdetect ROMs of other systems.
+    pub gb_lenient_detection: bool,
     /// If the 'bootrom' or BIOS should be skipped, where applicable.
     pub skip_bootrom: bool,
     /// If the system should start running immediately when loading a ROM.
     pub run_on_open: bool,
+    /// Amount of recent frames to keep for debugging, see
+    /// [crate::Core::frame_history]. 0 disables it; every frame kept
+    /// costs a full frame's worth of memory.
+    pub frame_history: usize,
     /// Audio volume multiplier
     pub volume: f32,
     /// Audio volume multiplier while fast forwarding
     pub volume_ff: f32,
+    /// Per-system audio volume, used instead of [volume] and [volume_ff]
+    /// for systems that have an entry.
+    pub system_volume: HashMap<CoreKind, SystemVolume>,
+    /// Lowest volume multiplier that will be applied.
+    pub volume_min: f32,
+    /// Highest volume multiplier that will be applied.
+    pub volume_max: f32,
     /// Audio output sample rate
     pub sample_rate: usize,
     /// Audio resampler
     pub resampler: AudioSampler,
-    /// If the interpreter should cache
+    /// Maximum amount of cycles to emulate for a single frame of the frontend,
+    /// to keep a system that never finishes a frame from hanging the
+    /// frontend. Very generous by default.
+    pub max_cycles_per_frame: Time,
+    /// If the ARM interpreter should cache decoded instruction blocks.
     pub cached_interpreter: bool,
+    /// Maximum amount of instructions the ARM instruction cache may hold
+    /// before it is cleared, to bound its memory usage. 0 for no limit.
+    pub cached_interpreter_limit: usize,
+    /// If the ARM CPU should detect loops waiting on an interrupt or
+    /// a memory value and skip ahead to the next event while in one.
+    /// Disabling this and [cached_interpreter] gives pure interpretation,
+    /// which is useful for finding out if an optimization causes an issue.
+    pub waitloop_detection: bool,
     /// If the PPU should run on a sepearate thread.
     pub threaded_ppu: bool,
+    /// Light level reported by cartridge solar sensors,
+    /// 0 being darkness and 255 bright sunlight.
+    pub solar_level: u8,
     /// BIOSes to use / load.
     pub bioses: Vec<ConsoleBios>,
+    /// Compatibility hacks for specific games, keyed by
+    /// [crate::Core::rom_hash]. Applied automatically when a matching ROM is
+    /// loaded, replacing the built-in hacks a core detects from the
+    /// cartridge header.
+    pub game_hacks: HashMap<u64, GameHacks>,
 }
 
 impl SystemConfig {
+    /// Copy all options that can change while a system is running from
+    /// the given config:
+    /// - Volume: global and fast-forward volume, per-system volume and the
+    ///   volume range
+    /// - GB accuracy: OAM DMA bus conflicts, the HALT bug, the OAM corruption
+    ///   bug, speed switch timing, PPU memory locking, LCD ghosting and the
+    ///   accurate PPU
+    /// - GBA BIOS protection, save write latency, the per-frame cycle cap and
+    ///   the solar sensor level
+    /// - ARM CPU optimizations: the cached interpreter, its cache limit and
+    ///   waitloop detection
+    /// - GBC and GBA colour correction and GBC colour grading, which need to be
+    ///   applied by the system as well
+    ///
+    /// Everything else requires a reset or reload to take effect.
+    pub fn apply_live(&mut self, other: &SystemConfig) {
+        self.volume = other.volume;
+        self.volume_ff = other.volume_ff;
+        self.system_volume.clone_from(&other.system_volume);
+        self.volume_min = other.volume_min;
+        self.volume_max = other.volume_max;
+        self.oam_dma_bus_conflict = other.oam_dma_bus_conflict;
+        self.gb_halt_bug = other.gb_halt_bug;
+        self.gb_oam_bug = other.gb_oam_bug;
+        self.gb_speed_switch_timing = other.gb_speed_switch_timing;
+        self.gb_ppu_memory_locking = other.gb_ppu_memory_locking;
+        self.gb_lcd_ghosting = other.gb_lcd_ghosting;
+        self.gb_accurate_ppu = other.gb_accurate_ppu;
+        self.gba_bios_protection = other.gba_bios_protection;
+        self.save_write_latency = other.save_write_latency;
+        self.max_cycles_per_frame = other.max_cycles_per_frame;
+        self.solar_level = other.solar_level;
+        self.cached_interpreter = other.cached_interpreter;
+        self.cached_interpreter_limit = other.cached_interpreter_limit;
+        self.waitloop_detection = other.waitloop_detection;
+        self.cgb_colour_correction = other.cgb_colour_correction;
+        self.gba_colour_correction = other.gba_colour_correction;
+        self.cgb_bg_grading = other.cgb_bg_grading;
+        self.cgb_obj_grading = other.cgb_obj_grading;
+    }
+
     /// Get the BIOS for a given console ID.
     pub fn get_bios(&self, console_id: &str) -> Option<&[u8]> {
         self.bioses
@@ -72,23 +234,59 @@ impl SystemConfig {
             .find(|bios| bios.console_id == console_id)
             .and_then(|bios| bios.bios.as_deref())
     }
+
+    /// Get the volume multiplier to use for the given system, falling back
+    /// to the global volume if the system has none set.
+    pub fn volume_for(&self, kind: Option<CoreKind>, fast_forward: bool) -> f32 {
+        let system = kind.and_then(|k| self.system_volume.get(&k));
+        let volume = match (system, fast_forward) {
+            (Some(sys), false) => sys.volume,
+            (Some(sys), true) => sys.volume_ff,
+            (None, false) => self.volume,
+            (None, true) => self.volume_ff,
+        };
+        volume.clamp(self.volume_min, self.volume_max.max(self.volume_min))
+    }
 }
 
 impl Default for SystemConfig {
     fn default() -> Self {
         Self {
             mode: CgbMode::Prefer,
+            accuracy: AccuracyProfile::Balanced,
             compress_savestates: false,
             cgb_colour_correction: false,
+            cgb_bg_grading: ColourGrading::None,
+            cgb_obj_grading: ColourGrading::None,
+            gba_colour_expansion: ColourExpansion::Simple,
+            gba_colour_correction: false,
+            gba_bios_protection: true,
+            save_write_latency: false,
+            oam_dma_bus_conflict: false,
+            gb_halt_bug: true,
+            gb_oam_bug: false,
+            gb_speed_switch_timing: true,
+            gb_ppu_memory_locking: false,
+            gb_lcd_ghosting: false,
+            gb_accurate_ppu: false,
+            gb_lenient_detection: false,
             skip_bootrom: false,
             run_on_open: true,
+            frame_history: 0,
             volume: 0.5,
             volume_ff: 0.25,
+            system_volume: HashMap::new(),
+            volume_min: 0.0,
+            volume_max: 1.0,
             sample_rate: 48000,
             resampler: AudioSampler::Cubic,
+            max_cycles_per_frame: 500_000_000,
             cached_interpreter: true,
+            cached_interpreter_limit: 1_000_000,
+            waitloop_detection: true,
             // WASM doesn't do threads
             threaded_ppu: !cfg!(target_arch = "wasm32"),
+            solar_level: 0x80,
             bioses: vec![
                 ConsoleBios {
                     console_id: "dmg".into(),
@@ -126,10 +324,97 @@ impl Default for SystemConfig {
                     bios: None,
                 },
             ],
+            game_hacks: HashMap::new(),
         }
     }
 }
 
+/// Volume settings for a single system.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde_config", derive(serde::Deserialize, serde::Serialize))]
+pub struct SystemVolume {
+    /// Audio volume multiplier
+    pub volume: f32,
+    /// Audio volume multiplier while fast forwarding
+    pub volume_ff: f32,
+}
+
+/// Compatibility hacks for a single game, to work around rendering issues
+/// that are not (yet) fixed properly. See [SystemConfig::game_hacks].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde_config", derive(serde::Deserialize, serde::Serialize))]
+#[cfg_attr(feature = "serde_config", serde(default))]
+pub struct GameHacks {
+    /// Graphics layers to never render, with the same bits as
+    /// `Core::set_hidden_layers`. Combined with the layers hidden there.
+    pub hidden_layers: u8,
+    /// Ignore the colour special effect selected by the game (alpha
+    /// blending, brightness changes). GBA only.
+    pub disable_blending: bool,
+    /// Emulate the DMG STAT write quirk: Writing STAT outside of mode 3
+    /// or while LY=LYC requests a STAT interrupt, as if all interrupt
+    /// sources were briefly enabled. Some games hang without it. GB only,
+    /// ignored in CGB mode.
+    /// https://gbdev.io/pandocs/STAT.html#spurious-stat-interrupts
+    pub stat_write_quirk: bool,
+    /// Make 64K flash saves an Atmel chip from the start, reporting its ID
+    /// to games that check it before saving. GBA only.
+    pub atmel_flash: bool,
+}
+
+/// A preset for all options that trade accuracy for speed, so users do not
+/// have to pick each of them. Individual options can still be changed after
+/// a profile was applied.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde_config", derive(serde::Deserialize, serde::Serialize))]
+pub enum AccuracyProfile {
+    /// Use all optimizations and skip all hardware quirks that are optional.
+    Fast,
+    /// Use all optimizations and emulate the quirks games commonly depend
+    /// on: The GB HALT bug, CGB speed switch timing and GBA BIOS protection.
+    /// Same as the default config.
+    #[default]
+    Balanced,
+    /// Emulate all optional quirks, adding OAM DMA bus conflicts, the DMG
+    /// OAM corruption bug, GB PPU memory locking and save write latency to
+    /// [Self::Balanced].
+    /// The GB pixel FIFO is still experimental and not part of any profile.
+    /// Disables the ARM cached interpreter, waitloop detection and the
+    /// threaded PPU, since they can cause subtle differences.
+    Accurate,
+}
+
+impl AccuracyProfile {
+    pub const ALL: &'static [Self] = &[Self::Fast, Self::Balanced, Self::Accurate];
+
+    /// Set all options covered by this profile in the given config.
+    pub fn apply(self, config: &mut SystemConfig) {
+        let quirks = self != Self::Fast;
+        let accurate = self == Self::Accurate;
+        config.accuracy = self;
+
+        config.gb_halt_bug = quirks;
+        config.gb_speed_switch_timing = quirks;
+        config.gba_bios_protection = quirks;
+
+        config.oam_dma_bus_conflict = accurate;
+        config.gb_oam_bug = accurate;
+        config.gb_ppu_memory_locking = accurate;
+        config.save_write_latency = accurate;
+
+        config.cached_interpreter = !accurate;
+        config.waitloop_detection = !accurate;
+        // WASM doesn't do threads
+        config.threaded_ppu = !accurate && !cfg!(target_arch = "wasm32");
+    }
+}
+
+impl std::fmt::Display for AccuracyProfile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
 /// How to handle CGB mode depending on cart compatibility.
 #[derive(Debug, Clone, PartialEq, Eq)]
 #[cfg_attr(feature = "serde_config", derive(serde::Deserialize, serde::Serialize))]
@@ -144,6 +429,149 @@ pub enum CgbMode {
     Never,
 }
 
+/// Colour grading applied on top of a system's colours, to simulate
+/// different display tints or to make colours easier to tell apart
+/// with colour blindness.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde_config", derive(serde::Deserialize, serde::Serialize))]
+pub enum ColourGrading {
+    /// Leave colours unchanged.
+    #[default]
+    None,
+    /// Yellowish tint, like an older screen.
+    Warm,
+    /// Blueish tint.
+    Cool,
+    Grayscale,
+    /// Correction for red-blind users.
+    Protanopia,
+    /// Correction for green-blind users.
+    Deuteranopia,
+    /// Correction for blue-blind users.
+    Tritanopia,
+}
+
+impl ColourGrading {
+    pub const ALL: &'static [Self] = &[
+        Self::None,
+        Self::Warm,
+        Self::Cool,
+        Self::Grayscale,
+        Self::Protanopia,
+        Self::Deuteranopia,
+        Self::Tritanopia,
+    ];
+
+    /// Get the RGB matrix for this grading; rows are the output channels.
+    pub fn matrix(self) -> [[f32; 3]; 3] {
+        match self {
+            Self::None => [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]],
+            Self::Warm => [[1.0, 0.05, 0.0], [0.0, 0.95, 0.0], [0.0, 0.0, 0.8]],
+            Self::Cool => [[0.85, 0.0, 0.0], [0.0, 0.95, 0.05], [0.0, 0.05, 1.0]],
+            Self::Grayscale => [[0.299, 0.587, 0.114]; 3],
+            // Daltonize: Shift the colour information lost with the
+            // deficiency into channels that can still be seen.
+            Self::Protanopia => Self::daltonize(
+                [
+                    [0.567, 0.433, 0.0],
+                    [0.558, 0.442, 0.0],
+                    [0.0, 0.242, 0.758],
+                ],
+                [[0.0, 0.0, 0.0], [0.7, 1.0, 0.0], [0.7, 0.0, 1.0]],
+            ),
+            Self::Deuteranopia => Self::daltonize(
+                [[0.625, 0.375, 0.0], [0.7, 0.3, 0.0], [0.0, 0.3, 0.7]],
+                [[0.0, 0.0, 0.0], [0.7, 1.0, 0.0], [0.7, 0.0, 1.0]],
+            ),
+            Self::Tritanopia => Self::daltonize(
+                [[0.95, 0.05, 0.0], [0.0, 0.433, 0.567], [0.0, 0.475, 0.525]],
+                [[1.0, 0.0, 0.7], [0.0, 1.0, 0.7], [0.0, 0.0, 0.0]],
+            ),
+        }
+    }
+
+    /// Apply this grading to a colour.
+    pub fn apply(self, colour: Colour) -> Colour {
+        if self == Self::None {
+            return colour;
+        }
+        let m = self.matrix();
+        let mut out = colour;
+        for (out, row) in out.iter_mut().zip(m) {
+            let value =
+                row[0] * colour[0] as f32 + row[1] * colour[1] as f32 + row[2] * colour[2] as f32;
+            *out = value.clamp(0.0, 255.0) as u8;
+        }
+        out
+    }
+
+    /// Build a correction matrix `I + shift * (I - simulation)`.
+    fn daltonize(simulation: [[f32; 3]; 3], shift: [[f32; 3]; 3]) -> [[f32; 3]; 3] {
+        let mut out = [[0.0; 3]; 3];
+        for (row, out_row) in out.iter_mut().enumerate() {
+            for (col, out) in out_row.iter_mut().enumerate() {
+                let error: f32 = (0..3)
+                    .map(|k| shift[row][k] * ((k == col) as u8 as f32 - simulation[k][col]))
+                    .sum();
+                *out = (row == col) as u8 as f32 + error;
+            }
+        }
+        out
+    }
+}
+
+impl std::fmt::Display for ColourGrading {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+/// How 5-bit colour channels are converted to 8-bit sRGB.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde_config", derive(serde::Deserialize, serde::Serialize))]
+pub enum ColourExpansion {
+    /// Repeat the upper bits: `c << 3 | c >> 2`. Brighter than hardware.
+    #[default]
+    Simple,
+    /// Apply the response curve of the GBA LCD (gamma 4.0),
+    /// then encode the result as sRGB.
+    LcdGamma,
+}
+
+impl ColourExpansion {
+    pub const ALL: &'static [Self] = &[Self::Simple, Self::LcdGamma];
+
+    /// Make a table mapping every 5-bit channel value to its 8-bit value.
+    pub fn table(self) -> [u8; 32] {
+        let mut table = [0; 32];
+        for (value, out) in table.iter_mut().enumerate() {
+            let value = value as u8;
+            *out = match self {
+                Self::Simple => (value << 3) | (value >> 2),
+                Self::LcdGamma => {
+                    let linear = (value as f32 / 31.0).powf(4.0);
+                    let srgb = if linear <= 0.0031308 {
+                        linear * 12.92
+                    } else {
+                        1.055 * linear.powf(1.0 / 2.4) - 0.055
+                    };
+                    (srgb * 255.0).round() as u8
+                }
+            };
+        }
+        table
+    }
+}
+
+impl std::fmt::Display for ColourExpansion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Simple => write!(f, "Simple"),
+            Self::LcdGamma => write!(f, "LCD gamma (sRGB)"),
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 #[cfg_attr(feature = "serde_config", derive(serde::Deserialize, serde::Serialize))]
 pub struct ConsoleBios {
@@ -151,3 +579,21 @@ pub struct ConsoleBios {
     pub console_name: String,
     pub bios: Option<Vec<u8>>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn colour_expansion_known_values() {
+        let simple = ColourExpansion::Simple.table();
+        assert_eq!([simple[0], simple[16], simple[31]], [0, 132, 255]);
+
+        // Mid grey: (16 / 31)^4 = 0.071 linear, which is 75 in sRGB
+        let gamma = ColourExpansion::LcdGamma.table();
+        assert_eq!([gamma[0], gamma[16], gamma[31]], [0, 75, 255]);
+        // Darker than the simple expansion everywhere in between
+        assert!((1..31).all(|c| gamma[c] < simple[c]));
+        assert!(gamma.windows(2).all(|w| w[0] <= w[1]));
+    }
+}
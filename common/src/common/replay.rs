@@ -0,0 +1,262 @@
+// Copyright (c) 2024 Leela Aurelia, git@elia.garden
+//
+// Unless otherwise noted, this file is released and thus subject to the
+// terms of the Mozilla Public License Version 2.0 (MPL-2.0) or the
+// GNU General Public License Version 3 (GPL-3).
+// If a copy of these licenses was not distributed with this file, you can
+// obtain them at https://mozilla.org/MPL/2.0/ and http://www.gnu.org/licenses/.
+
+//! Replay bundles: A savestate, the hash of the ROM it belongs to
+//! and an input replay starting at that state, together with the hash of
+//! the final frame. Playing one back on the same ROM must produce the same
+//! final frame, which makes them useful for reproducing bugs.
+
+use std::{fmt::Display, mem, path::PathBuf};
+
+use super::{
+    input::{InputReplay, ReplayState},
+    video,
+};
+use crate::{
+    serialize::{self, StateError},
+    Core, Time,
+};
+
+/// Version of the bundle format. Needs to be increased whenever the
+/// format or the save states of a core change incompatibly, which makes
+/// older bundles unusable.
+pub const BUNDLE_VERSION: u32 = 2;
+
+/// A deterministic replay, see module documentation.
+#[derive(Clone, serde::Deserialize, serde::Serialize)]
+pub struct ReplayBundle {
+    /// Format version, see [BUNDLE_VERSION]. Needs to stay the first field.
+    pub version: u32,
+    /// Hash of the ROM the replay was recorded on.
+    pub rom_hash: u64,
+    /// Save state at the start of the replay.
+    pub state: Vec<u8>,
+    /// Inputs, starting at [state].
+    pub replay: InputReplay,
+    /// System time the replay ends at.
+    pub end_time: Time,
+    /// Hash of the frame finished at [end_time].
+    pub final_frame: u64,
+}
+
+impl ReplayBundle {
+    /// Start recording a new bundle on the given core.
+    /// Inputs are recorded until [finish] is called.
+    pub fn start(core: &mut dyn Core, file: PathBuf) -> Self {
+        core.c_mut().input.replay = ReplayState::Recording(InputReplay::empty(file.clone()));
+        Self {
+            version: BUNDLE_VERSION,
+            rom_hash: core.rom_hash(),
+            state: core.save_state(),
+            replay: InputReplay::empty(file),
+            end_time: 0,
+            final_frame: 0,
+        }
+    }
+
+    /// Stop recording and finish the bundle by running the core until
+    /// the next frame. Returns `None` if the core was not recording.
+    pub fn finish(mut self, core: &mut dyn Core) -> Option<Self> {
+        let ReplayState::Recording(replay) = mem::take(&mut core.c_mut().input.replay) else {
+            return None;
+        };
+        self.replay = replay;
+
+        let running = mem::replace(&mut core.c_mut().debugger.running, true);
+        let frame = core.produce_frame();
+        core.c_mut().debugger.running = running;
+
+        self.final_frame = video::frame_hash(&frame?);
+        self.end_time = core.get_time();
+        Some(self)
+    }
+
+    /// Play back this bundle on the given core, and check that it produces
+    /// the same final frame it did when recorded.
+    /// The core will be left at the end of the replay.
+    pub fn verify(&self, core: &mut dyn Core) -> Result<(), ReplayError> {
+        if core.rom_hash() != self.rom_hash {
+            return Err(ReplayError::RomMismatch);
+        }
+
+        core.load_state(&self.state)
+            .map_err(ReplayError::InvalidState)?;
+        core.c_mut().input.replay = ReplayState::Playback(self.replay.clone());
+
+        let running = mem::replace(&mut core.c_mut().debugger.running, true);
+        let mut frame = None;
+        while core.get_time() < self.end_time {
+            match core.produce_frame() {
+                Some(f) => frame = Some(f),
+                None => break,
+            }
+        }
+        core.c_mut().debugger.running = running;
+        core.c_mut().input.replay = ReplayState::None;
+
+        let actual = frame.map(|f| video::frame_hash(&f));
+        match actual {
+            Some(actual) if actual == self.final_frame => Ok(()),
+            Some(actual) => Err(ReplayError::FrameMismatch {
+                expected: self.final_frame,
+                actual,
+            }),
+            None => Err(ReplayError::NoFrame),
+        }
+    }
+
+    /// Serialize the bundle to a file that can be loaded with
+    /// [deserialize].
+    pub fn serialize(&self) -> Vec<u8> {
+        serialize::serialize(self, false)
+    }
+
+    /// Load a bundle created with [serialize]. Fails for bundles of other
+    /// versions, see [BUNDLE_VERSION].
+    pub fn deserialize(file: &[u8]) -> Result<Self, ReplayError> {
+        // Only read the version first, the rest might not decode
+        let version = serialize::deserialize::<u32>(file).map_err(ReplayError::InvalidState)?;
+        if version != BUNDLE_VERSION {
+            return Err(ReplayError::Version(version));
+        }
+        serialize::deserialize(file).map_err(ReplayError::InvalidState)
+    }
+}
+
+/// Reason a replay bundle failed to verify.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReplayError {
+    /// The loaded ROM is not the one the bundle was recorded on.
+    RomMismatch,
+    /// The final frame is different.
+    FrameMismatch { expected: u64, actual: u64 },
+    /// The core stopped before producing the final frame.
+    NoFrame,
+    /// The bundle or its save state could not be loaded.
+    InvalidState(StateError),
+    /// The bundle was made by a different version, see [BUNDLE_VERSION].
+    Version(u32),
+}
+
+impl Display for ReplayError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReplayError::RomMismatch => write!(f, "Replay was recorded on a different ROM"),
+            ReplayError::FrameMismatch { expected, actual } => write!(
+                f,
+                "Final frame differs (expected {expected:016X}, got {actual:016X})"
+            ),
+            ReplayError::NoFrame => write!(f, "System stopped before reaching the final frame"),
+            ReplayError::InvalidState(err) => write!(f, "{err}"),
+            ReplayError::Version(version) => write!(
+                f,
+                "Replay bundle has version {version}, only version {BUNDLE_VERSION} is supported"
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::{common::input::Button, tests::TestCore};
+
+    /// Record a bundle where A is pressed a while after the start.
+    fn record() -> (TestCore, ReplayBundle) {
+        let mut core = TestCore {
+            frame_time: 10,
+            colour: [0x10, 0x20, 0x30, 0xFF],
+            ..TestCore::default()
+        };
+        core.c.debugger.running = true;
+        core.step_cycles(5);
+        let bundle = ReplayBundle::start(&mut core, PathBuf::new());
+        core.step_cycles(7);
+        core.c.input.set(core.time, Button::A, true);
+        core.step_cycles(13);
+        // Frames the frontend did not display yet
+        while core.c.video_buffer.pop().is_some() {}
+        let bundle = bundle.finish(&mut core).unwrap();
+        (core, bundle)
+    }
+
+    #[test]
+    fn record_and_verify() {
+        let (mut core, bundle) = record();
+        assert_eq!(bundle.replay.states.len(), 1);
+        // The final frame has A pressed
+        let pressed = video::frame_hash(&[[0x11, 0x20, 0x30, 0xFF]; 4 * 3]);
+        assert_eq!(bundle.final_frame, pressed);
+
+        let bundle = ReplayBundle::deserialize(&bundle.serialize()).unwrap();
+        core.c.input.set(core.time, Button::A, false);
+        assert_eq!(bundle.verify(&mut core), Ok(()));
+        assert_eq!(core.time, bundle.end_time);
+    }
+
+    #[test]
+    fn verify_detects_mismatch() {
+        let (mut core, mut bundle) = record();
+        bundle.replay.states.clear();
+        let released = video::frame_hash(&[[0x10, 0x20, 0x30, 0xFF]; 4 * 3]);
+        assert_eq!(
+            bundle.verify(&mut core),
+            Err(ReplayError::FrameMismatch {
+                expected: bundle.final_frame,
+                actual: released
+            })
+        );
+
+        bundle.rom_hash ^= 1;
+        assert_eq!(bundle.verify(&mut core), Err(ReplayError::RomMismatch));
+    }
+
+    fn bundle(version: u32) -> ReplayBundle {
+        ReplayBundle {
+            version,
+            rom_hash: 0x1234,
+            state: vec![1, 2, 3],
+            replay: InputReplay::empty(PathBuf::new()),
+            end_time: 1000,
+            final_frame: 0x5678,
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let loaded = ReplayBundle::deserialize(&bundle(BUNDLE_VERSION).serialize()).unwrap();
+        assert_eq!(loaded.rom_hash, 0x1234);
+        assert_eq!(loaded.state, [1, 2, 3]);
+        assert_eq!(loaded.end_time, 1000);
+        assert_eq!(loaded.final_frame, 0x5678);
+    }
+
+    #[test]
+    fn rejects_other_versions() {
+        let file = bundle(BUNDLE_VERSION + 1).serialize();
+        assert!(matches!(
+            ReplayBundle::deserialize(&file),
+            Err(ReplayError::Version(v)) if v == BUNDLE_VERSION + 1
+        ));
+    }
+
+    #[test]
+    fn rejects_corrupt_files() {
+        let file = bundle(BUNDLE_VERSION).serialize();
+        assert!(matches!(
+            ReplayBundle::deserialize(&file[..file.len() / 2]),
+            Err(ReplayError::InvalidState(_))
+        ));
+        assert!(matches!(
+            ReplayBundle::deserialize(&[]),
+            Err(ReplayError::InvalidState(StateError::Empty))
+        ));
+    }
+}
@@ -0,0 +1,54 @@
+// Copyright (c) 2024 Leela Aurelia, git@elia.garden
+//
+// Unless otherwise noted, this file is released and thus subject to the
+// terms of the Mozilla Public License Version 2.0 (MPL-2.0) or the
+// GNU General Public License Version 3 (GPL-3).
+// If a copy of these licenses was not distributed with this file, you can
+// obtain them at https://mozilla.org/MPL/2.0/ and http://www.gnu.org/licenses/.
+
+use web_time::Instant;
+
+use crate::Time;
+
+/// Measures the speed of emulation relative to real time, by comparing
+/// emulated time to wall-clock time over a short window.
+#[derive(Default)]
+pub struct SpeedMeter {
+    /// Start of the current measurement window, in real time and in
+    /// system cycles.
+    window_start: Option<(Instant, Time)>,
+    /// Speed measured over the last finished window, 1.0 being real time.
+    speed: Option<f32>,
+}
+
+impl SpeedMeter {
+    /// Length of a measurement window in seconds.
+    const WINDOW: f32 = 0.5;
+
+    /// Record that the system is now at the given time, running at the given
+    /// clock rate.
+    pub fn record(&mut self, now: Time, clock: f32) {
+        let Some((start, start_cycles)) = self.window_start else {
+            self.window_start = Some((Instant::now(), now));
+            return;
+        };
+
+        let real = start.elapsed().as_secs_f32();
+        if real >= Self::WINDOW {
+            let emulated = now.saturating_sub(start_cycles) as f32 / clock;
+            self.speed = Some(emulated / real);
+            self.window_start = Some((Instant::now(), now));
+        }
+    }
+
+    /// Forget the current measurement, for example because the system
+    /// was paused or a different ROM was loaded.
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Speed of emulation in percent of real time, if measured yet.
+    pub fn percent(&self) -> Option<f32> {
+        self.speed.map(|speed| speed * 100.0)
+    }
+}
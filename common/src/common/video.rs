@@ -6,9 +6,13 @@
 // If a copy of these licenses was not distributed with this file, you can
 // obtain them at https://mozilla.org/MPL/2.0/ and http://www.gnu.org/licenses/.
 
-use std::collections::VecDeque;
+use std::{
+    collections::VecDeque,
+    io::{self, Write},
+    sync::OnceLock,
+};
 
-use crate::Colour;
+use crate::{numutil, Colour, Core};
 
 /// Frame buffer for video output. Also used to implement frameskip.
 #[derive(Default)]
@@ -21,6 +25,13 @@ pub struct FrameBuffer {
     pub frameskip: usize,
     /// Number of frames until the next frame is added to the buffer.
     n_until_next: usize,
+    /// Most recent frames produced, oldest first, for debugging.
+    /// Unlike [buffer], frames are not removed when displayed.
+    #[cfg_attr(feature = "serde", serde(skip, default))]
+    history: VecDeque<Vec<Colour>>,
+    /// Amount of frames kept in [history]. 0 disables it.
+    #[cfg_attr(feature = "serde", serde(skip, default))]
+    history_len: usize,
 }
 
 impl FrameBuffer {
@@ -34,6 +45,11 @@ impl FrameBuffer {
         self.buffer.pop_back()
     }
 
+    /// Get the newest frame in the buffer without removing it.
+    pub fn peek_recent(&self) -> Option<&[Colour]> {
+        self.buffer.back().map(Vec::as_slice)
+    }
+
     /// Notify the buffer that the system is starting to render the next frame.
     pub fn start_next_frame(&mut self) {
         if self.n_until_next == 0 {
@@ -51,6 +67,18 @@ impl FrameBuffer {
 
     /// Push a new frame to the buffer.
     pub fn push(&mut self, frame: Vec<Colour>) {
+        if self.history_len != 0 {
+            if self.history.len() == self.history_len {
+                self.history.pop_front();
+            }
+            self.history.push_back(frame.clone());
+        }
+        self.requeue(frame);
+    }
+
+    /// Put a frame into the buffer for display again, without recording it
+    /// in the frame history.
+    pub fn requeue(&mut self, frame: Vec<Colour>) {
         self.buffer.push_back(frame);
         if self.buffer.len() > 4 {
             self.pop(); // Drop oldest frame to prevent large buffer and
@@ -62,4 +90,184 @@ impl FrameBuffer {
     pub fn has_frame(&self) -> bool {
         !self.buffer.is_empty()
     }
+
+    /// Get the most recent frames produced, oldest first.
+    /// Empty unless enabled with [set_history_len].
+    pub fn history(&self) -> Vec<Vec<Colour>> {
+        self.history.iter().cloned().collect()
+    }
+
+    /// Set the amount of recent frames to keep in the history.
+    /// 0 disables it and frees all frames kept.
+    pub fn set_history_len(&mut self, len: usize) {
+        self.history_len = len;
+        while self.history.len() > len {
+            self.history.pop_front();
+        }
+        if len == 0 {
+            self.history = VecDeque::new();
+        }
+    }
+}
+
+/// Hash the contents of a frame. Frontends can use this to detect frames
+/// that are identical to the previous one.
+/// The hash is stable between versions and platforms, so it can be stored.
+pub fn frame_hash(frame: &[Colour]) -> u64 {
+    numutil::fnv1a(frame.as_flattened())
+}
+
+/// Simulate the colours of the GBA LCD, which are darker and less
+/// saturated than the raw colours on a modern display.
+/// Expects a colour with channels expanded by repeating their upper bits,
+/// like [super::options::ColourExpansion::Simple] does.
+/// Based on https://near.sh/articles/video/color-emulation, using an
+/// LCD gamma of 4.0 and an output gamma of 2.2.
+pub fn gba_lcd_correction(colour: Colour) -> Colour {
+    /// Range of linear light values covered by the output table. Channels can
+    /// go above 1 since the colour mixing weights add up to more than 255.
+    const MAX_LINEAR: f32 = 1.25;
+    const OUT_STEPS: usize = 1024;
+    static TABLES: OnceLock<([f32; 256], [u8; OUT_STEPS + 1])> = OnceLock::new();
+
+    let (input, output) = TABLES.get_or_init(|| {
+        let mut input = [0.0; 256];
+        for (value, out) in input.iter_mut().enumerate() {
+            // Only the upper 5 bits are the original colour
+            *out = ((value >> 3) as f32 / 31.0).powf(4.0);
+        }
+        let mut output = [0; OUT_STEPS + 1];
+        for (step, out) in output.iter_mut().enumerate() {
+            let linear = step as f32 * MAX_LINEAR / OUT_STEPS as f32;
+            *out = (linear.powf(1.0 / 2.2) * 255.0 * (255.0 / 280.0))
+                .round()
+                .min(255.0) as u8;
+        }
+        (input, output)
+    });
+
+    let [r, g, b] = [0, 1, 2].map(|i| input[colour[i] as usize]);
+    let encode = |linear: f32| {
+        let step = (linear * OUT_STEPS as f32 / MAX_LINEAR).round() as usize;
+        output[step.min(OUT_STEPS)]
+    };
+    [
+        encode((50.0 * g + 255.0 * r) / 255.0),
+        encode((30.0 * b + 230.0 * g + 10.0 * r) / 255.0),
+        encode((220.0 * b + 10.0 * g + 50.0 * r) / 255.0),
+        colour[3],
+    ]
+}
+
+/// Encode a frame of the given size as an RGBA PNG image.
+#[cfg(feature = "png")]
+pub fn write_png<W: Write>(
+    writer: W,
+    frame: &[Colour],
+    size: [usize; 2],
+) -> Result<(), png::EncodingError> {
+    let mut encoder = png::Encoder::new(writer, size[0] as u32, size[1] as u32);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(frame.as_flattened())
+}
+
+/// Writes frames as raw RGBA bytes, for piping video into external tools
+/// like ffmpeg without the crate needing to encode video itself.
+///
+/// The stream optionally starts with a single header line of the form
+/// `GGRAW <width> <height> <fps>\n`, followed by the frames without
+/// any separation. For ffmpeg, disable the header and use
+/// `-f rawvideo -pix_fmt rgba -s <width>x<height> -r <fps> -i -`.
+pub struct RawVideoWriter<W: Write> {
+    writer: W,
+    size: [usize; 2],
+}
+
+impl<W: Write> RawVideoWriter<W> {
+    /// Create a writer for frames of the given core, writing the header
+    /// if requested.
+    pub fn new(mut writer: W, core: &dyn Core, header: bool) -> io::Result<Self> {
+        let size = core.screen_size();
+        if header {
+            let fps = core.kind().map(|k| k.frame_rate()).unwrap_or(60.0);
+            writeln!(writer, "GGRAW {} {} {fps}", size[0], size[1])?;
+        }
+        Ok(Self { writer, size })
+    }
+
+    /// Write a single frame.
+    pub fn write_frame(&mut self, frame: &[Colour]) -> io::Result<()> {
+        if frame.len() != self.size[0] * self.size[1] {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Frame size does not match stream size",
+            ));
+        }
+        self.writer.write_all(frame.as_flattened())
+    }
+
+    /// Run the core for the given amount of frames,
+    /// writing every frame produced.
+    pub fn run(&mut self, core: &mut dyn Core, frames: usize) -> io::Result<()> {
+        for _ in 0..frames {
+            let frame = core.render_one_frame();
+            self.write_frame(&frame)?;
+        }
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(shade: u8) -> Vec<Colour> {
+        vec![[shade, shade, shade, 0xFF]; 4]
+    }
+
+    #[test]
+    fn history_keeps_recent_frames() {
+        let mut buffer = FrameBuffer::default();
+        buffer.push(frame(0));
+        assert!(buffer.history().is_empty());
+
+        buffer.set_history_len(3);
+        for shade in 1..=5 {
+            buffer.push(frame(shade));
+        }
+        // Displaying frames does not remove them from the history
+        while buffer.pop().is_some() {}
+        assert_eq!(buffer.history(), [frame(3), frame(4), frame(5)]);
+
+        buffer.set_history_len(1);
+        assert_eq!(buffer.history(), [frame(5)]);
+        buffer.set_history_len(0);
+        assert!(buffer.history().is_empty());
+    }
+
+    #[test]
+    fn lcd_correction_black_and_white() {
+        assert_eq!(gba_lcd_correction([0, 0, 0, 0xFF]), [0, 0, 0, 0xFF]);
+        // The LCD's white has a slight tint
+        assert_eq!(gba_lcd_correction([0xFF; 4]), [252, 238, 242, 0xFF]);
+    }
+
+    #[test]
+    fn lcd_correction_desaturates() {
+        for primary in 0..3 {
+            let mut colour = [0, 0, 0, 0xFF];
+            colour[primary] = 0xFF;
+            let corrected = gba_lcd_correction(colour);
+            // Still mostly the primary, but dimmer and mixed with another
+            let others = (0..3).filter(|&i| i != primary).map(|i| corrected[i]);
+            assert!(corrected[primary] < 0xF0, "{corrected:?}");
+            assert!(
+                others.clone().all(|c| c < corrected[primary]),
+                "{corrected:?}"
+            );
+            assert!(others.max().unwrap() > 0x40, "{corrected:?}");
+        }
+    }
 }
@@ -99,8 +99,10 @@ pub fn set_mmio_apply<T: NumExt>(
     while current_byte < T::WIDTH {
         let (from_start_offset, reg_size) = inner(addr + current_byte, value, mask);
         let written = reg_size - from_start_offset;
-        value >>= written * 8;
-        mask >>= written * 8;
+        // Shifting out all 32 bits overflows, but there is nothing left to
+        // write then anyway
+        value = value.checked_shr(written * 8).unwrap_or(0);
+        mask = mask.checked_shr(written * 8).unwrap_or(0);
         current_byte += written;
     }
 }
@@ -11,6 +11,7 @@
 #[macro_use]
 pub mod io;
 pub mod memory_mapper;
+pub mod psg;
 pub mod scheduler;
 pub mod storage;
 pub mod thin_pager;
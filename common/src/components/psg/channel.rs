@@ -48,7 +48,13 @@ impl<C: Channel> LengthCountedChannel<C> {
     }
 
     pub fn write_sound_length(&mut self, data: u8) {
-        self.length = self.max_length - data as u16;
+        self.load_length(self.max_length - data as u16);
+    }
+
+    /// Set the length counter directly, for PSGs that take it from a
+    /// lookup table instead of counting up to the maximum.
+    pub fn load_length(&mut self, length: u16) {
+        self.length = length;
         self.current_counter = self.length;
     }
 
@@ -60,6 +66,10 @@ impl<C: Channel> LengthCountedChannel<C> {
         self.counter_decrease_enable
     }
 
+    pub fn length_counter(&self) -> u16 {
+        self.current_counter
+    }
+
     pub fn clock_length_counter(&mut self) {
         if self.counter_decrease_enable {
             if self.current_counter == 0 {
@@ -13,6 +13,9 @@ pub struct EnvelopGenerator {
     current_volume: u8,
     sweep_increase: bool,
     period: u8,
+    /// Restart a decreasing envelope at 15 once it reached 0, instead of
+    /// stopping. Only used by the NES.
+    looping: bool,
 
     envelope_can_run: bool,
 
@@ -29,6 +32,16 @@ impl EnvelopGenerator {
         self.counter = self.period;
     }
 
+    /// Set the envelope parameters directly, for PSGs with a different
+    /// register layout. Unlike with [Self::write_envelope_register], the
+    /// volume is only reset on the next [Self::trigger].
+    pub fn set_parameters(&mut self, starting_volume: u8, increase: bool, period: u8, looping: bool) {
+        self.starting_volume = starting_volume;
+        self.sweep_increase = increase;
+        self.period = period;
+        self.looping = looping;
+    }
+
     pub fn read_envelope_register(&self) -> u8 {
         ((self.starting_volume & 0xF) << 4) | ((self.sweep_increase as u8) << 3) | (self.period & 7)
     }
@@ -51,11 +64,13 @@ impl EnvelopGenerator {
                     if self.current_volume < 15 {
                         self.current_volume += 1;
                     }
+                } else if self.current_volume == 0 && self.looping {
+                    self.current_volume = 15;
                 } else {
                     self.current_volume = self.current_volume.saturating_sub(1);
                 }
 
-                if self.current_volume == 0 || self.current_volume == 15 {
+                if !self.looping && (self.current_volume == 0 || self.current_volume == 15) {
                     self.envelope_can_run = false;
                 }
             }
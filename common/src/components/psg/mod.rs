@@ -0,0 +1,18 @@
+// Copyright (c) 2024 Leela Aurelia, git@elia.garden
+//
+// Unless otherwise noted, this file is released and thus subject to the
+// terms of the Mozilla Public License Version 2.0 (MPL-2.0) or the
+// GNU General Public License Version 3 (GPL-3).
+// If a copy of these licenses was not distributed with this file, you can
+// obtain them at https://mozilla.org/MPL/2.0/ and http://www.gnu.org/licenses/.
+
+//! Building blocks of PSG audio channels, shared between the GB and NES
+//! APUs. Abridged from mizu:
+//! https://github.com/Amjad50/mizu/tree/master/mizu-core/src/apu
+//! Thank you to it's authors!
+
+mod channel;
+mod envelope;
+
+pub use channel::{Channel, Dac, LengthCountedChannel};
+pub use envelope::EnvelopGenerator;
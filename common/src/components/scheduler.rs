@@ -12,7 +12,14 @@ pub type Time = u64;
 pub type TimeS = i64;
 
 /// A scheduler used by the emulation cores to schedule peripherals.
-/// It is generic over the possible events and uses a binary heap.
+/// It is generic over the possible events and keeps them in a vector
+/// sorted by execution time, with the next event at the end.
+///
+/// Ordering of events due at the same time is deterministic:
+/// they are dispatched in reverse order of scheduling, meaning the event
+/// scheduled last runs first. Cancelling events does not change the
+/// order of the remaining ones. Cores may rely on this, so it
+/// must not be changed without checking all of them.
 #[derive(Default)]
 #[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
 pub struct Scheduler<E: Kind> {
@@ -23,6 +30,15 @@ pub struct Scheduler<E: Kind> {
     /// Events currently awaiting execution.
     #[cfg_attr(feature = "serde", serde(bound = ""))]
     events: Vec<ScheduledEvent<E>>,
+    /// How often each kind of event was dispatched in the current frame.
+    /// Only collected for profiling.
+    #[cfg(feature = "event-stats")]
+    #[cfg_attr(feature = "serde", serde(skip))]
+    counts: Vec<(&'static str, u32)>,
+    /// [counts] of the last finished frame, until taken.
+    #[cfg(feature = "event-stats")]
+    #[cfg_attr(feature = "serde", serde(skip))]
+    frame_counts: Vec<(&'static str, u32)>,
 }
 
 impl<E: Kind> Scheduler<E> {
@@ -42,6 +58,9 @@ impl<E: Kind> Scheduler<E> {
 
         // Ensure the event list is still sorted
         // (Swap the new element further back until it is in the right spot)
+        // Stopping at the first event that is due at the same time or later
+        // puts the new event in front of others due at the same time,
+        // see the type documentation.
         // I tried multiple implementations (using Vec::swap, Vec::insert)
         // and this was the fastest.
         for idx in (1..self.events.len()).rev() {
@@ -83,6 +102,8 @@ impl<E: Kind> Scheduler<E> {
                 .last()
                 .map(|e| e.execute_at)
                 .unwrap_or(Time::MAX);
+            #[cfg(feature = "event-stats")]
+            self.count(event.kind);
             Some(Event {
                 kind: event.kind,
                 late_by: (self.time - event.execute_at) as TimeS,
@@ -108,6 +129,8 @@ impl<E: Kind> Scheduler<E> {
             .last()
             .map(|e| e.execute_at)
             .unwrap_or(Time::MAX);
+        #[cfg(feature = "event-stats")]
+        self.count(event.kind);
         Event {
             kind: event.kind,
             late_by: 0,
@@ -156,6 +179,36 @@ impl<E: Kind> Scheduler<E> {
     pub fn now(&self) -> Time {
         self.time
     }
+
+    /// Notify the scheduler that the system finished a frame, for
+    /// [take_event_counts].
+    #[inline]
+    pub fn end_frame(&mut self) {
+        #[cfg(feature = "event-stats")]
+        {
+            self.frame_counts = std::mem::take(&mut self.counts);
+        }
+    }
+
+    /// Take the amount of events of each kind that were dispatched in the
+    /// last finished frame, see [end_frame]. Empty if no frame finished
+    /// since the last call.
+    /// Always empty unless the `event-stats` feature is enabled.
+    pub fn take_event_counts(&mut self) -> Vec<(&'static str, u32)> {
+        #[cfg(feature = "event-stats")]
+        return std::mem::take(&mut self.frame_counts);
+        #[cfg(not(feature = "event-stats"))]
+        Vec::new()
+    }
+
+    #[cfg(feature = "event-stats")]
+    fn count(&mut self, kind: E) {
+        let name = kind.name();
+        match self.counts.iter_mut().find(|(n, _)| *n == name) {
+            Some((_, count)) => *count += 1,
+            None => self.counts.push((name, 1)),
+        }
+    }
 }
 
 /// An event awaiting execution
@@ -174,9 +227,18 @@ struct ScheduledEvent<E: Kind> {
 pub trait Kind:
     for<'de> serde::Deserialize<'de> + serde::Serialize + PartialEq + Copy + Clone
 {
+    /// Name of the event, used to group events for profiling.
+    fn name(&self) -> &'static str {
+        "Event"
+    }
 }
 #[cfg(not(feature = "serde"))]
-pub trait Kind: PartialEq + Copy + Clone {}
+pub trait Kind: PartialEq + Copy + Clone {
+    /// Name of the event, used to group events for profiling.
+    fn name(&self) -> &'static str {
+        "Event"
+    }
+}
 
 /// Event that is ready to be handled.
 #[derive(Copy, Clone)]
@@ -189,3 +251,91 @@ pub struct Event<E: Kind> {
     /// - `late_by` will be 1010 - 1000 = 10.
     pub late_by: TimeS,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Default, PartialEq, Copy, Clone)]
+    #[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+    enum TestEvent {
+        #[default]
+        A,
+        B,
+        C,
+        D,
+    }
+
+    impl Kind for TestEvent {
+        fn name(&self) -> &'static str {
+            match self {
+                TestEvent::A => "A",
+                _ => "Other",
+            }
+        }
+    }
+
+    fn drain(scheduler: &mut Scheduler<TestEvent>) -> Vec<TestEvent> {
+        std::iter::from_fn(|| scheduler.get_next_pending())
+            .map(|event| event.kind)
+            .collect()
+    }
+
+    #[test]
+    #[cfg(feature = "event-stats")]
+    fn event_counts_per_frame() {
+        let mut scheduler = Scheduler::default();
+        for (event, after) in [
+            (TestEvent::A, 1),
+            (TestEvent::B, 2),
+            (TestEvent::A, 3),
+            (TestEvent::C, 30),
+        ] {
+            scheduler.schedule(event, after);
+        }
+        scheduler.advance(10);
+        drain(&mut scheduler);
+        // Nothing until the frame is finished
+        assert!(scheduler.take_event_counts().is_empty());
+
+        scheduler.end_frame();
+        scheduler.advance(30);
+        scheduler.pop();
+        assert_eq!(scheduler.take_event_counts(), [("A", 2), ("Other", 1)]);
+        // Taking resets them
+        assert!(scheduler.take_event_counts().is_empty());
+
+        scheduler.end_frame();
+        assert_eq!(scheduler.take_event_counts(), [("Other", 1)]);
+        scheduler.end_frame();
+        assert!(scheduler.take_event_counts().is_empty());
+    }
+
+    #[test]
+    fn simultaneous_events_run_last_scheduled_first() {
+        let mut scheduler = Scheduler::default();
+        scheduler.schedule(TestEvent::A, 10);
+        scheduler.schedule(TestEvent::B, 5);
+        scheduler.schedule(TestEvent::C, 10);
+        scheduler.schedule(TestEvent::D, 10);
+
+        scheduler.advance(10);
+        assert_eq!(
+            drain(&mut scheduler),
+            [TestEvent::B, TestEvent::D, TestEvent::C, TestEvent::A]
+        );
+    }
+
+    #[test]
+    fn cancelling_keeps_tie_order() {
+        let mut scheduler = Scheduler::default();
+        for event in [TestEvent::A, TestEvent::B, TestEvent::C, TestEvent::D] {
+            scheduler.schedule(event, 10);
+        }
+        assert!(scheduler.cancel_single(TestEvent::C));
+
+        assert_eq!(scheduler.pop().kind, TestEvent::D);
+        assert_eq!(scheduler.now(), 10);
+        assert_eq!(drain(&mut scheduler), [TestEvent::B, TestEvent::A]);
+    }
+}
@@ -6,7 +6,11 @@
 // If a copy of these licenses was not distributed with this file, you can
 // obtain them at https://mozilla.org/MPL/2.0/ and http://www.gnu.org/licenses/.
 
-use std::path::PathBuf;
+use std::{
+    fmt::Display,
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 /// Empty struct holding methods used for interacting with the file system,
 /// for storing game save data / cartridge RAM.
@@ -29,7 +33,9 @@ impl Storage {
 
         if let Some(rtc) = save.rtc {
             let path = Self::get_path(path.unwrap(), "rtc");
-            std::fs::write(path, format!("{}", rtc)).ok(); // TODO handle error
+            std::fs::write(path, format_rtc(rtc, save.rtc_offset)).ok(); // TODO
+                                                                         // handle
+                                                                         // error
         }
     }
 
@@ -48,9 +54,14 @@ impl Storage {
         let path = Self::get_path(path.unwrap(), "rtc");
         let rtc = std::fs::read_to_string(path)
             .ok()
-            .and_then(|s| s.parse::<u64>().ok());
+            .and_then(|s| parse_rtc(&s));
 
-        Some(GameSave { ram, rtc, title })
+        Some(GameSave {
+            ram,
+            rtc: rtc.map(|(start, _)| start),
+            rtc_offset: rtc.map_or(0, |(_, offset)| offset),
+            title,
+        })
     }
 
     /// "hello/my/rom.gb" -> "hello/my/rom.$ext"
@@ -72,7 +83,10 @@ impl Storage {
 
         if let Some(rtc) = save.rtc {
             Self::local_storage()
-                .set(&format!("{}-rtc", save.title), &format!("{}", rtc))
+                .set(
+                    &format!("{}-rtc", save.title),
+                    &format_rtc(rtc, save.rtc_offset),
+                )
                 .ok();
         }
     }
@@ -92,9 +106,14 @@ impl Storage {
             .get(&format!("{}-rtc", &title))
             .ok()
             .flatten();
-        let rtc = stor.and_then(|s| s.parse::<u64>().ok());
+        let rtc = stor.and_then(|s| parse_rtc(&s));
 
-        Some(GameSave { ram, rtc, title })
+        Some(GameSave {
+            ram,
+            rtc: rtc.map(|(start, _)| start),
+            rtc_offset: rtc.map_or(0, |(_, offset)| offset),
+            title,
+        })
     }
 
     /// Get the browser's local storage.
@@ -108,8 +127,265 @@ impl Storage {
 pub struct GameSave {
     /// The game's RAM, or other backup method storage.
     pub ram: Vec<u8>,
-    /// RTC time, for GGC games.
+    /// RTC time, for GGC games. This is the UNIX time at which the RTC
+    /// would have been at 0.
     pub rtc: Option<u64>,
+    /// Difference between the RTC time set with `Core::set_rtc_time`
+    /// and the host clock, in seconds. 0 when following the host clock.
+    pub rtc_offset: i64,
     /// Game title. Used for saving to disk.
     pub title: String,
 }
+
+impl GameSave {
+    /// Convert this save to the `.srm` layout RetroArch uses for saves with
+    /// an MBC3 RTC, which is the save RAM followed by the common 48-byte RTC
+    /// footer also used by BGB, VBA-M and mGBA. All fields are little-endian:
+    ///
+    /// | Offset | Size | Content                                         |
+    /// |--------|------|-------------------------------------------------|
+    /// | 0x00   | 4    | Seconds                                         |
+    /// | 0x04   | 4    | Minutes                                         |
+    /// | 0x08   | 4    | Hours                                           |
+    /// | 0x0C   | 4    | Days, low 8 bits                                |
+    /// | 0x10   | 4    | Bit 0: day bit 8, bit 6: halt, bit 7: day carry |
+    /// | 0x14   | 20   | The 5 fields above again, latched               |
+    /// | 0x28   | 8    | UNIX timestamp the save was written at          |
+    ///
+    /// Saves without an RTC are just the save RAM, same as our `.sav` files.
+    pub fn to_retroarch_srm(&self) -> Vec<u8> {
+        let mut srm = self.ram.clone();
+        let Some(start) = self.rtc else {
+            return srm;
+        };
+
+        let now = unix_time();
+        let elapsed = now.saturating_sub(start);
+        let days = elapsed / 86400;
+        let regs = [
+            elapsed % 60,
+            (elapsed / 60) % 60,
+            (elapsed / 3600) % 24,
+            days & 0xFF,
+            ((days >> 8) & 1) | (u64::from(days > 0x1FF) << 7),
+        ];
+        for _ in 0..2 {
+            for reg in regs {
+                srm.extend_from_slice(&(reg as u32).to_le_bytes());
+            }
+        }
+        srm.extend_from_slice(&now.to_le_bytes());
+        srm
+    }
+
+    /// Load a save from a RetroArch `.srm` file, see [to_retroarch_srm]
+    /// for the layout. The variant of the footer with a 32-bit timestamp
+    /// is also accepted. Since save RAM sizes are multiples of 512 bytes,
+    /// the footer is detected by the file's size.
+    pub fn from_retroarch_srm(srm: &[u8], title: String) -> Self {
+        let footer = match srm.len() % 512 {
+            48 | 44 => srm.len() % 512,
+            _ => 0,
+        };
+        let (ram, footer) = srm.split_at(srm.len() - footer);
+        let rtc = (!footer.is_empty()).then(|| {
+            let field = |i: usize| {
+                u64::from(u32::from_le_bytes(
+                    footer[i * 4..i * 4 + 4].try_into().unwrap(),
+                ))
+            };
+            let days = field(3) | ((field(4) & 1) << 8);
+            let elapsed = field(0) + field(1) * 60 + field(2) * 3600 + days * 86400;
+            let saved_at = match footer.len() {
+                48 => u64::from_le_bytes(footer[40..48].try_into().unwrap()),
+                _ => field(10),
+            };
+            saved_at.saturating_sub(elapsed)
+        });
+
+        Self {
+            ram: ram.to_vec(),
+            rtc,
+            rtc_offset: 0,
+            title,
+        }
+    }
+}
+
+/// Format RTC data for the `.rtc` file: The start time, followed by the
+/// offset if there is one.
+fn format_rtc(start: u64, offset: i64) -> String {
+    if offset == 0 {
+        format!("{start}")
+    } else {
+        format!("{start} {offset}")
+    }
+}
+
+/// Parse RTC data written by [format_rtc]. Files without an offset, like
+/// the ones written by older versions, have an offset of 0.
+fn parse_rtc(data: &str) -> Option<(u64, i64)> {
+    let mut parts = data.split_whitespace();
+    let start = parts.next()?.parse().ok()?;
+    let offset = match parts.next() {
+        Some(offset) => offset.parse().ok()?,
+        None => 0,
+    };
+    Some((start, offset))
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+/// Reason a save could not be imported.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SaveError {
+    /// The current game does not have a save.
+    NoSave,
+    /// The save RAM does not have the size of the game's save.
+    SizeMismatch { expected: usize, actual: usize },
+}
+
+impl Display for SaveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SaveError::NoSave => write!(f, "The current game does not have a save"),
+            SaveError::SizeMismatch { expected, actual } => write!(
+                f,
+                "Save has {actual} bytes of RAM, but the game's save has {expected} bytes"
+            ),
+        }
+    }
+}
+
+/// Kind and size of a game's save, detected from its ROM without
+/// needing to load it into a system.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SaveInfo {
+    /// Name of the backup hardware, e.g. "SRAM" or "Flash 128K".
+    pub kind: &'static str,
+    /// Size of the save file in bytes.
+    pub size: usize,
+    /// If the game also has an RTC, which is stored separately.
+    pub rtc: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn srm_round_trip() {
+        let ram: Vec<u8> = (0..0x2000).map(|i| i as u8).collect();
+        let start = unix_time() - (3 * 86400 + 5 * 3600 + 7 * 60 + 11);
+        let save = GameSave {
+            ram: ram.clone(),
+            rtc: Some(start),
+            rtc_offset: 0,
+            title: String::new(),
+        };
+
+        let srm = save.to_retroarch_srm();
+        assert_eq!(srm.len(), ram.len() + 48);
+        let loaded = GameSave::from_retroarch_srm(&srm, String::new());
+        assert_eq!(loaded.ram, ram);
+        // The footer only has a resolution of seconds, and time might have
+        // passed between writing and reading it
+        assert!(loaded.rtc.unwrap().abs_diff(start) <= 1);
+    }
+
+    /// A `.srm` footer as written by other emulators: 5 days, 3 hours,
+    /// 2 minutes and 1 second on the clock at the given timestamp, with
+    /// day bit 8 set, so 261 days in total.
+    fn srm_footer(saved_at: u64, wide_timestamp: bool) -> Vec<u8> {
+        let regs: [u32; 5] = [1, 2, 3, 5, 1];
+        let mut footer = Vec::new();
+        for _ in 0..2 {
+            for reg in regs {
+                footer.extend_from_slice(&reg.to_le_bytes());
+            }
+        }
+        if wide_timestamp {
+            footer.extend_from_slice(&saved_at.to_le_bytes());
+        } else {
+            footer.extend_from_slice(&(saved_at as u32).to_le_bytes());
+        }
+        footer
+    }
+
+    #[test]
+    fn srm_fixture() {
+        let saved_at = 1_700_000_000;
+        let elapsed = 261 * 86400 + 3 * 3600 + 2 * 60 + 1;
+        let ram = vec![0x5A; 0x200];
+        for wide in [true, false] {
+            let mut srm = ram.clone();
+            srm.extend(srm_footer(saved_at, wide));
+            assert_eq!(srm.len(), 0x200 + if wide { 48 } else { 44 });
+
+            let loaded = GameSave::from_retroarch_srm(&srm, String::new());
+            assert_eq!(loaded.ram, ram);
+            assert_eq!(loaded.rtc, Some(saved_at - elapsed));
+        }
+
+        // Anything else is just RAM, even if it does not fill a whole block
+        let srm = vec![0x5A; 0x200 + 40];
+        let loaded = GameSave::from_retroarch_srm(&srm, String::new());
+        assert_eq!(loaded.ram, srm);
+        assert_eq!(loaded.rtc, None);
+    }
+
+    #[test]
+    fn srm_footer_layout() {
+        let ram = vec![0xA5; 0x800];
+        let elapsed = 261 * 86400 + 3 * 3600 + 2 * 60 + 1;
+        let save = GameSave {
+            ram: ram.clone(),
+            rtc: Some(unix_time() - elapsed),
+            rtc_offset: 0,
+            title: String::new(),
+        };
+        let srm = save.to_retroarch_srm();
+        // The footer follows the RAM directly, without any padding
+        assert_eq!(&srm[..0x800], ram);
+        let footer = &srm[0x800..];
+        assert_eq!(footer.len(), 48);
+
+        // Time might have passed between setting and writing the save
+        let saved_at = u64::from_le_bytes(footer[40..].try_into().unwrap());
+        let written = saved_at - save.rtc.unwrap();
+        assert!(written - elapsed <= 1);
+        let mut expected = srm_footer(saved_at, true);
+        expected[0..4].copy_from_slice(&((written % 60) as u32).to_le_bytes());
+        expected[20..24].copy_from_slice(&((written % 60) as u32).to_le_bytes());
+        assert_eq!(footer, expected);
+    }
+
+    #[test]
+    fn srm_without_rtc_is_plain_ram() {
+        let save = GameSave {
+            ram: vec![0xAB; 0x8000],
+            rtc: None,
+            rtc_offset: 0,
+            title: String::new(),
+        };
+        let srm = save.to_retroarch_srm();
+        assert_eq!(srm, save.ram);
+        let loaded = GameSave::from_retroarch_srm(&srm, String::new());
+        assert_eq!(loaded.ram, save.ram);
+        assert_eq!(loaded.rtc, None);
+    }
+
+    #[test]
+    fn rtc_file_keeps_offset() {
+        assert_eq!(parse_rtc(&format_rtc(1234, 0)), Some((1234, 0)));
+        assert_eq!(parse_rtc(&format_rtc(1234, -3600)), Some((1234, -3600)));
+        assert_eq!(parse_rtc("1234\n"), Some((1234, 0)));
+        assert_eq!(parse_rtc("1234 x"), None);
+        assert_eq!(parse_rtc(""), None);
+    }
+}
@@ -8,25 +8,39 @@
 
 #![feature(btree_cursors)]
 
-use std::{any::Any, cell::UnsafeCell, cmp::Ordering, sync::Arc};
+use std::{any::Any, cell::UnsafeCell, cmp::Ordering, mem, sync::Arc};
 
-use common::debugger::Width;
 pub use common::Common;
+use common::{
+    audio::{AudioStats, PsgState},
+    cheats::{CheatError, CheatId, CheatWrite},
+    debugger::{InterruptState, MemoryRegion, SpriteInfo, TileMap, Width},
+    input::Button,
+    options::SystemConfig,
+};
 pub use components::scheduler::{Time, TimeS};
-use components::storage::GameSave;
+use components::storage::{GameSave, SaveError};
+use serialize::StateError;
 
 pub mod common;
 pub mod components;
 #[macro_use]
 pub mod macros;
 pub mod numutil;
-#[cfg(feature = "serde")]
 pub mod serialize;
 
 /// Maximum pointer size used by any system. This is used in some places, like
 /// the debugger, to avoid needing to use generic parameters.
 pub type Pointer = u32;
 
+/// A named hardware register, identified by either its name or its address.
+/// See [Core::set_io_register].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IoRegister<'a> {
+    Name(&'a str),
+    Addr(u32),
+}
+
 /// Colour type used by the system's PPUs for image data.
 /// This type is analogus to egui's `Color32`, which allows the GUI to
 /// simply `mem::transmute` it without having to perform any explicit
@@ -34,12 +48,100 @@ pub type Pointer = u32;
 /// to depend on the rather heavy egui.
 pub type Colour = [u8; 4];
 
+/// The kind of system a core emulates. Used for settings that are
+/// remembered separately for each system.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde_config", derive(serde::Deserialize, serde::Serialize))]
+pub enum CoreKind {
+    Ggc,
+    Gga,
+    Nds,
+    Nes,
+    Psx,
+}
+
+impl CoreKind {
+    pub const ALL: [CoreKind; 5] = [
+        CoreKind::Ggc,
+        CoreKind::Gga,
+        CoreKind::Nds,
+        CoreKind::Nes,
+        CoreKind::Psx,
+    ];
+
+    /// Human-readable name of the system.
+    pub fn name(self) -> &'static str {
+        match self {
+            CoreKind::Ggc => "Game Boy (Color)",
+            CoreKind::Gga => "Game Boy Advance",
+            CoreKind::Nds => "DS",
+            CoreKind::Nes => "NES",
+            CoreKind::Psx => "PlayStation",
+        }
+    }
+
+    /// Native refresh rate of the system's display, in frames per second.
+    pub fn frame_rate(self) -> f64 {
+        match self {
+            CoreKind::Ggc | CoreKind::Gga => 59.7275,
+            CoreKind::Nds => 59.8261,
+            CoreKind::Nes => 60.0988,
+            CoreKind::Psx => 59.2936,
+        }
+    }
+
+    /// All buttons the system has.
+    pub fn buttons(self) -> &'static [Button] {
+        match self {
+            CoreKind::Ggc | CoreKind::Nes => &Button::BUTTONS[..8],
+            CoreKind::Gga => &Button::BUTTONS[..10],
+            CoreKind::Nds => &Button::BUTTONS[..12],
+            CoreKind::Psx => &Button::BUTTONS,
+        }
+    }
+}
+
 pub trait Core: Send + Sync {
+    /// The kind of system this core emulates, if any.
+    fn kind(&self) -> Option<CoreKind> {
+        None
+    }
+
     /// Advance by one step, where step is system-defined.
     fn advance(&mut self);
     /// Advance the system clock by _at least_ the given delta in seconds.
     /// Might advance more.
     fn advance_delta(&mut self, delta: f32);
+    /// Advance the system by the given amount of cycles, as closely as
+    /// instruction boundaries allow, and return how many were actually
+    /// advanced. Cycles are in the unit of [get_time].
+    /// Runs even while paused, but stops early if a breakpoint or
+    /// watchpoint is hit, or if the clock does not advance.
+    fn step_cycles(&mut self, cycles: Time) -> Time {
+        let start = self.get_time();
+        let target = start + cycles;
+        while self.get_time() < target {
+            let before = self.get_time();
+            self.advance();
+            let debugger = &self.c().debugger;
+            if debugger.breakpoint_hit.is_some()
+                || debugger.watchpoint_hit.is_some()
+                || self.get_time() == before
+            {
+                break;
+            }
+        }
+        self.get_time() - start
+    }
+
+    /// Set the speed multiplier the system runs at, clamped to between
+    /// [common::MIN_SPEED] and [common::MAX_SPEED]. Fractional speeds are
+    /// supported. Fast forwarding through [Common::set_fast_forward_hold] or
+    /// [Common::toggle_fast_forward] overrides this while active.
+    fn set_speed_multiplier(&mut self, speed: f32) {
+        self.c_mut().set_speed_multiplier(speed);
+    }
+
     /// Reset the console, while keeping the current cartridge inserted.
     fn reset(&mut self);
     /// Skip BIOS, bootroms, or similar; immediately boot inserted game.
@@ -49,7 +151,8 @@ pub trait Core: Send + Sync {
     fn save_state(&mut self) -> Vec<u8>;
     /// Load a state produced by [save_state].
     /// Will restore the current cartridge and debugger.
-    fn load_state(&mut self, state: &[u8]);
+    /// The system is left unchanged if the state cannot be loaded.
+    fn load_state(&mut self, state: &[u8]) -> Result<(), StateError>;
 
     /// Get the current system time.
     fn get_time(&self) -> Time;
@@ -57,6 +160,51 @@ pub trait Core: Send + Sync {
     fn screen_size(&self) -> [usize; 2];
     /// Make a save for the game to be put to disk.
     fn make_save(&self) -> Option<GameSave>;
+    /// Replace the game's save with the given one and mark it as modified.
+    /// The game might have already read parts of the old save, so the
+    /// system should be reset afterwards.
+    fn load_save(&mut self, _save: GameSave) {}
+    /// Returns if the game's save was modified since it was last cleared
+    /// with [clear_save_dirty]. Cores that do not track this always report
+    /// the save as dirty.
+    fn save_is_dirty(&self) -> bool {
+        true
+    }
+    /// Mark the game's save as no longer modified, usually after it was
+    /// written to disk.
+    fn clear_save_dirty(&mut self) {}
+    /// Export only the game's save RAM and RTC, without the rest of the
+    /// system state. The format is the one of [GameSave::to_retroarch_srm],
+    /// so it can also be used as a regular save file.
+    fn export_save_ram(&self) -> Option<Vec<u8>> {
+        self.make_save().map(|save| save.to_retroarch_srm())
+    }
+    /// Import save RAM and RTC produced by [export_save_ram].
+    /// Same caveats as [load_save] apply. The save RAM must have the size
+    /// of the game's current save, otherwise nothing is changed.
+    fn import_save_ram(&mut self, bytes: &[u8]) -> Result<(), SaveError> {
+        let current = self.make_save().ok_or(SaveError::NoSave)?;
+        let save = GameSave::from_retroarch_srm(bytes, current.title);
+        if save.ram.len() != current.ram.len() {
+            return Err(SaveError::SizeMismatch {
+                expected: current.ram.len(),
+                actual: save.ram.len(),
+            });
+        }
+        self.load_save(save);
+        Ok(())
+    }
+
+    /// Get the time of the cartridge's real-time clock as Unix seconds.
+    /// `None` if the game has no RTC. Follows the host clock unless changed
+    /// with [set_rtc_time].
+    fn rtc_time(&self) -> Option<u64> {
+        None
+    }
+    /// Set the time of the cartridge's real-time clock, for example to
+    /// trigger daily events. The RTC keeps running from the given time.
+    /// Does nothing if the game has no RTC.
+    fn set_rtc_time(&mut self, _unix_seconds: u64) {}
 
     /// Get the value at the given memory address.
     /// The width parameter specifies the size of the value to read.
@@ -72,68 +220,450 @@ pub trait Core: Send + Sync {
     fn search_memory(&self, _value: u32, _width: Width, _kind: Ordering) -> Vec<u32> {
         vec![]
     }
+    /// Get the regions of the system's address space, in ascending order.
+    /// Memory viewers can display them using [get_memory].
+    /// Systems with multiple CPUs that see different address spaces
+    /// prefix the region names with the CPU, and map the address spaces
+    /// of the other CPUs to addresses the main CPU does not use.
+    fn memory_regions(&self) -> Vec<MemoryRegion> {
+        vec![]
+    }
     /// Get the value of all registers. Exact meaning is platform-specific.
     fn get_registers(&self) -> Vec<usize> {
         unimplemented!("Not implemented for this core")
     }
+    /// Get an approximate backtrace as a list of addresses, starting with
+    /// the current instruction followed by the return addresses of the
+    /// functions it was called from. This is heuristic: It is found by
+    /// looking for values on the stack that point after call instructions,
+    /// so it might contain stale frames or miss some.
+    fn stack_trace(&self) -> Vec<u32> {
+        vec![]
+    }
+    /// Get how often each kind of scheduler event was dispatched during the
+    /// last finished frame, for profiling. Empty if no frame finished since
+    /// the last call, or unless `common` was built with the `event-stats`
+    /// feature.
+    fn take_event_counts(&mut self) -> Vec<(&'static str, u32)> {
+        vec![]
+    }
     /// Get the ROM currently loaded.
     fn get_rom(&self) -> Vec<u8>;
+    /// Get a hash of the ROM currently loaded, for identifying the game
+    /// across sessions. It is stable between versions and platforms,
+    /// and computed only once per core.
+    fn rom_hash(&self) -> u64 {
+        *self
+            .c()
+            .rom_hash
+            .get_or_init(|| numutil::fnv1a(&self.get_rom()))
+    }
     /// Set the value at the given memory address.
     /// The width parameter specifies the size of the value to write.
     /// Remaining bits are ignored.
+    /// Writes done this way can trigger watchpoints; see [edit_memory].
     fn set_memory(&mut self, _addr: u32, _value: u32, _width: Width) {
         unimplemented!("Not implemented for this core")
     }
+    /// Like [set_memory], but for edits done by the user or the frontend,
+    /// which do not trigger watchpoints.
+    fn edit_memory(&mut self, addr: u32, value: u32, width: Width) {
+        let watchpoints = mem::take(&mut self.c_mut().debugger.watchpoints);
+        self.set_memory(addr, value, width);
+        self.c_mut().debugger.watchpoints = watchpoints;
+    }
+    /// If the given address can be written with [set_memory] to change
+    /// memory contents. `false` for ROM and similar, where writes are
+    /// ignored or have side effects like bank switching instead.
+    /// Cores that do not implement [set_memory] report no memory as writable.
+    fn is_writable_memory(&self, _addr: u32) -> bool {
+        false
+    }
+    /// Load a raw memory dump at the given address, writing it through
+    /// [edit_memory]. Aligned pairs of bytes are written as halfwords, since
+    /// some systems handle byte writes to some regions differently.
+    /// Bytes at addresses that are not writable are skipped and logged.
+    /// Returns the amount of bytes written.
+    fn load_memory_region(&mut self, addr: u32, bytes: &[u8]) -> usize {
+        let mut written = 0;
+        let mut offset = 0;
+        while offset < bytes.len() {
+            let at = addr.wrapping_add(offset as u32);
+            let halfword = at & 1 == 0 && offset + 1 < bytes.len();
+            let size = if halfword { 2 } else { 1 };
+            if self.is_writable_memory(at) {
+                if halfword {
+                    let value = numutil::hword(bytes[offset], bytes[offset + 1]);
+                    self.edit_memory(at, value as u32, Width::Halfword);
+                } else {
+                    self.edit_memory(at, bytes[offset] as u32, Width::Byte);
+                }
+                written += size;
+            }
+            offset += size;
+        }
+
+        if written < bytes.len() {
+            log::warn!(
+                "Ignored {} bytes of memory dump at 0x{addr:X} that are not writable",
+                bytes.len() - written
+            );
+        }
+        written
+    }
+    /// Get the attributes of all sprites in OAM, in OAM order.
+    fn sprites(&self) -> Vec<SpriteInfo> {
+        vec![]
+    }
+    /// Write the attributes of the sprite with the given index back to OAM.
+    /// Bits of OAM not covered by [SpriteInfo] are kept.
+    fn set_sprite(&mut self, _idx: usize, _sprite: SpriteInfo) {}
+    /// Render the full tile map of the given layer (see [layers]), with
+    /// the area currently scrolled onto the screen.
+    /// None if the layer is not a tile map in the current video mode.
+    fn tile_map(&self, _layer: usize) -> Option<TileMap> {
+        None
+    }
+    /// Get the current state of the system's interrupts.
+    fn interrupt_state(&self) -> InterruptState {
+        InterruptState::default()
+    }
+    /// Named memory-mapped hardware registers of this system,
+    /// as `(name, address, width)`. See [io_registers].
+    fn io_register_table(&self) -> &'static [(&'static str, u32, Width)] {
+        &[]
+    }
+    /// Get the current value of all named hardware registers,
+    /// as `(name, address, value)`. Values are read like [get_memory] does;
+    /// cores should override this to show the last written value of
+    /// write-only registers instead, marking them in the name.
+    fn io_registers(&self) -> Vec<(String, u32, u32)> {
+        self.io_register_table()
+            .iter()
+            .map(|(name, addr, width)| (name.to_string(), *addr, self.get_memory(*addr, *width)))
+            .collect()
+    }
+    /// Write a named hardware register, given either by its name or its
+    /// address. The write goes through [set_memory], so it has the same
+    /// side effects as a write by the running program.
+    /// Returns `false` if no such register exists.
+    fn set_io_register(&mut self, register: IoRegister, value: u32) -> bool {
+        let entry = self
+            .io_register_table()
+            .iter()
+            .find(|(name, addr, _)| match register {
+                IoRegister::Name(reg) => name.eq_ignore_ascii_case(reg),
+                IoRegister::Addr(reg) => *addr == reg,
+            });
+        match entry {
+            Some(&(_, addr, width)) => {
+                self.set_memory(addr, value, width);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Set the position touched on the screen, for systems with a
+    /// touchscreen. Coordinates are in pixels of the full screen output
+    /// as given by [screen_size]; `None` releases the touch.
+    fn set_touch(&mut self, _pos: Option<[usize; 2]>) {}
+
+    /// Get the most recent frames produced, oldest first. Only available if
+    /// enabled with [SystemConfig::frame_history], empty otherwise.
+    fn frame_history(&self) -> Vec<Vec<Colour>> {
+        self.c().video_buffer.history()
+    }
+
+    /// Apply changes to the configuration that can take effect while the
+    /// system is running, see [SystemConfig::apply_live].
+    fn apply_config(&mut self, config: &SystemConfig) {
+        self.c_mut().config.apply_live(config);
+    }
+
+    /// Get the current speed of emulation in percent of real time,
+    /// measured over the last half second. 100% is full speed, 400% when
+    /// fast forwarding at 4x. `None` if not measured yet, which is the case
+    /// after loading a ROM or pausing.
+    fn emulation_speed_percent(&self) -> Option<f32> {
+        self.c().speed.percent()
+    }
+
+    /// Get runtime statistics of the audio output.
+    fn audio_stats(&self) -> AudioStats {
+        self.c().audio_buffer.stats()
+    }
+
+    /// Get a snapshot of the system's PSG state, for systems that
+    /// have a GB-style PSG.
+    fn psg_state(&self) -> Option<PsgState> {
+        None
+    }
+
+    /// Names of the audio channels, indexed by their number in
+    /// [ChannelMask] and in the stems returned by [take_audio_stems].
+    fn audio_channels(&self) -> &'static [&'static str] {
+        &[]
+    }
+    /// Enable or disable writing the output of every audio channel into
+    /// its own buffer, next to the regular mixed output.
+    fn set_audio_stems(&mut self, enabled: bool) {
+        let channels = if enabled {
+            self.audio_channels().len()
+        } else {
+            0
+        };
+        self.c_mut().audio_buffer.set_stem_channels(channels);
+    }
+    /// Take the per-channel samples produced since the last call, at the
+    /// system's audio input sample rate. `None` if stems are disabled.
+    fn take_audio_stems(&mut self) -> Option<Vec<Vec<f32>>> {
+        self.c_mut().audio_buffer.take_stems()
+    }
+
+    /// Names of the graphics layers that can be hidden for debugging,
+    /// indexed by their bit in [hidden_layers].
+    fn layers(&self) -> &'static [&'static str] {
+        &[]
+    }
+    /// Get the mask of graphics layers currently hidden for debugging.
+    fn hidden_layers(&self) -> u32 {
+        0
+    }
+    /// Hide graphics layers for debugging, independent of what the game
+    /// enabled. Each set bit hides the layer at that index in [layers].
+    fn set_hidden_layers(&mut self, _mask: u32) {}
 
     fn c(&self) -> &Common;
     fn c_mut(&mut self) -> &mut Common;
     fn as_any(&mut self) -> &mut dyn Any;
 
+    /// Advance until the next frame is complete and return it.
+    /// Gives up after `max_cycles_per_frame` (see config) cycles,
+    /// returning `None`, to not hang when no frames get produced.
     fn produce_frame(&mut self) -> Option<Vec<Colour>> {
+        let start = self.get_time();
+        let cap = self.c().config.max_cycles_per_frame;
+        let over_budget = |core: &Self| {
+            let over = core.get_time().saturating_sub(start) > cap;
+            if over {
+                log::warn!("No frame produced after {cap} cycles, giving up");
+            }
+            over
+        };
+
         while self.c().debugger.running && self.c_mut().video_buffer.pop().is_none() {
             self.advance();
+            if over_budget(self) {
+                return None;
+            }
         }
 
         // Do it twice: Color buffer will be empty after a save state load,
         // we need to render one frame in full
         while self.c().debugger.running && !self.c().video_buffer.has_frame() {
             self.advance();
+            if over_budget(self) {
+                return None;
+            }
         }
         self.c_mut().video_buffer.pop()
     }
 
+    /// Parse and activate a cheat code. See [Cheats::add] for the formats
+    /// supported on each system.
+    fn add_cheat(&mut self, raw: &str) -> Result<CheatId, CheatError> {
+        let system = self.kind();
+        let id = self.c_mut().cheats.add(raw, system)?;
+        self.update_rom_cheats();
+        Ok(id)
+    }
+
+    /// Deactivate a cheat code added with [add_cheat].
+    fn remove_cheat(&mut self, id: CheatId) {
+        if self.c_mut().cheats.remove(id).is_some() {
+            self.update_rom_cheats();
+        }
+    }
+
+    /// Apply all active cheats of [Common::cheats] that write to writable
+    /// memory, by writing through [set_memory]. Writes with a compare value
+    /// are only done if memory contains it. Writes to memory that is not
+    /// writable (ROM) are applied on read instead, see [set_rom_cheats].
+    /// Cores supporting cheats call this once per frame, when entering
+    /// VBlank.
+    fn apply_cheats(&mut self) {
+        for idx in 0..self.c().cheats.active.len() {
+            for w in 0..self.c().cheats.active[idx].writes.len() {
+                let write = self.c().cheats.active[idx].writes[w];
+                if !self.is_writable_memory(write.addr)
+                    || write
+                        .compare
+                        .is_some_and(|cmp| self.get_memory(write.addr, write.width) != cmp)
+                {
+                    continue;
+                }
+                self.edit_memory(write.addr, write.value, write.width);
+            }
+        }
+    }
+
+    /// Give all active cheat writes to memory that is not writable (ROM)
+    /// to [set_rom_cheats]. Called whenever the active cheats change.
+    fn update_rom_cheats(&mut self) {
+        let writes = self
+            .c()
+            .cheats
+            .active
+            .iter()
+            .flat_map(|cheat| &cheat.writes)
+            .filter(|write| !self.is_writable_memory(write.addr))
+            .copied()
+            .collect();
+        self.set_rom_cheats(writes);
+    }
+    /// Set the cheat writes to ROM, like Game Genie codes. Cores substitute
+    /// their value whenever the address is read, as long as the byte in ROM
+    /// matches the compare value, so they apply to the correct ROM bank
+    /// only. Does nothing on cores that do not support it.
+    fn set_rom_cheats(&mut self, _writes: Vec<CheatWrite>) {}
+
+    /// Encode the most recent frame in the video buffer as an RGBA PNG,
+    /// for screenshots. Returns `None` if no frame is available.
+    /// The frame stays in the buffer, so it is still displayed.
+    #[cfg(feature = "png")]
+    fn screenshot_png(&mut self) -> Option<Vec<u8>> {
+        let frame = self.c().video_buffer.peek_recent()?;
+        let mut png = Vec::new();
+        common::video::write_png(&mut png, frame, self.screen_size()).ok()?;
+        Some(png)
+    }
+
+    /// Advance until the next frame is complete and return it, even if the
+    /// system is not running. Used for stepping frame-by-frame and taking
+    /// screenshots while paused.
+    /// Returns a white frame if the system does not produce one in time,
+    /// for example when the display is turned off.
+    fn render_one_frame(&mut self) -> Vec<Colour> {
+        // Several frames' worth of instructions on all systems.
+        const MAX_STEPS: usize = 10_000_000;
+
+        let frameskip = mem::take(&mut self.c_mut().video_buffer.frameskip);
+        while self.c_mut().video_buffer.pop().is_some() {}
+
+        let mut frame = None;
+        for _ in 0..MAX_STEPS {
+            self.advance();
+            frame = self.c_mut().video_buffer.pop();
+            if frame.is_some() {
+                break;
+            }
+        }
+
+        self.c_mut().video_buffer.frameskip = frameskip;
+        frame.unwrap_or_else(|| {
+            let [width, height] = self.screen_size();
+            vec![[0xFF; 4]; width * height]
+        })
+    }
+
+    /// Advance by exactly one frame like [render_one_frame], returning it
+    /// together with all audio samples produced while rendering it.
+    /// Samples are interleaved stereo at the system's input sample rate,
+    /// before resampling and volume are applied; see
+    /// [common::audio::AudioBuffer::input_sr].
+    /// Samples still buffered for regular audio output are discarded.
+    fn produce_av_frame(&mut self) -> (Vec<Colour>, Vec<f32>) {
+        self.c_mut()
+            .audio_buffer
+            .input
+            .iter_mut()
+            .for_each(Vec::clear);
+        let frame = self.render_one_frame();
+        let [left, right] = mem::take(&mut self.c_mut().audio_buffer.input);
+        let samples = left
+            .into_iter()
+            .zip(right)
+            .flat_map(|(l, r)| [l, r])
+            .collect();
+        (frame, samples)
+    }
+
     fn produce_samples(&mut self, samples: &mut [f32]) {
         if !self.c().debugger.running {
             samples.fill(0.0);
             return;
         }
 
-        let skip = self.c().options.speed_multiplier;
+        let speed = self.c().options.speed_multiplier;
         let invert = self.c().options.invert_audio_samples;
-        let volume = if skip == 1 {
-            self.c().config.volume
-        } else {
-            self.c().config.volume_ff
-        };
+        let volume = self.c().config.volume_for(self.kind(), speed > 1.0);
 
         self.c_mut()
             .audio_buffer
             .update_output_chunk_size(samples.len() / 2);
-        while !self.c().audio_buffer.can_fill_buffer(skip) {
+        let underrun = !self.c().audio_buffer.can_fill_buffer(speed);
+        while !self.c().audio_buffer.can_fill_buffer(speed) {
             if !self.c().debugger.running {
                 samples.fill(0.0);
                 return;
             }
             self.advance();
         }
+        // Only counted if the system kept running, stopping at a
+        // breakpoint is not a problem with the audio settings
+        if underrun {
+            self.c_mut().audio_buffer.record_underrun();
+        }
 
-        self.c_mut().audio_buffer.fill_buffer(samples, skip, volume);
+        self.c_mut()
+            .audio_buffer
+            .fill_buffer(samples, speed, volume);
         if invert {
             samples.reverse();
         }
     }
 }
 
+impl dyn Core {
+    /// Run the system frame-by-frame until the given condition is met,
+    /// or the given amount of frames have been rendered.
+    /// The condition is checked after every frame.
+    /// Useful for test automation and other headless tools.
+    pub fn run_until(
+        &mut self,
+        max_frames: usize,
+        mut cond: impl FnMut(&mut Self) -> bool,
+    ) -> RunResult {
+        let mut frame = Vec::new();
+        for frames in 1..=max_frames {
+            frame = self.render_one_frame();
+            if cond(self) {
+                return RunResult {
+                    condition_met: true,
+                    frames,
+                    frame,
+                };
+            }
+        }
+        RunResult {
+            condition_met: false,
+            frames: max_frames,
+            frame,
+        }
+    }
+}
+
+/// Outcome of running a core with `run_until`.
+pub struct RunResult {
+    /// If the condition was met, `false` if the frame limit was hit instead.
+    pub condition_met: bool,
+    /// Amount of frames that were rendered.
+    pub frames: usize,
+    /// The last frame rendered, empty if no frames were rendered.
+    pub frame: Vec<Colour>,
+}
+
 /// Unsafe, mutable Arc.
 #[repr(transparent)]
 pub struct UnsafeArc<T>(Arc<UnsafeCell<T>>);
@@ -197,3 +727,167 @@ mod imp {
 
 unsafe impl<T: Send> Send for UnsafeArc<T> {}
 unsafe impl<T: Sync> Sync for UnsafeArc<T> {}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// A core that only advances its clock, producing a silent audio sample
+    /// every cycle and a frame of the given colour every `frame_time`
+    /// cycles. The pressed buttons are XORed into the red channel.
+    /// If `rewind_to` is set, the clock jumps back to it on the next
+    /// advance, like on a state load. Save states only contain the clock.
+    #[derive(Default)]
+    pub(crate) struct TestCore {
+        pub c: Common,
+        pub time: Time,
+        pub frame_time: Time,
+        pub colour: Colour,
+        pub rewind_to: Option<Time>,
+    }
+
+    impl Core for TestCore {
+        fn advance(&mut self) {
+            self.time = self.rewind_to.take().unwrap_or(self.time) + 1;
+            for channel in &mut self.c.audio_buffer.input {
+                channel.push(0.0);
+            }
+            if self.frame_time != 0 && self.time % self.frame_time == 0 {
+                let mut colour = self.colour;
+                colour[0] ^= self.c.input.state(self.time).0 as u8;
+                self.c.video_buffer.push(vec![colour; 4 * 3]);
+            }
+        }
+
+        fn advance_delta(&mut self, _delta: f32) {
+            self.advance();
+        }
+
+        fn reset(&mut self) {}
+
+        fn skip_bootrom(&mut self) {}
+
+        fn save_state(&mut self) -> Vec<u8> {
+            self.time.to_le_bytes().to_vec()
+        }
+
+        fn load_state(&mut self, state: &[u8]) -> Result<(), StateError> {
+            let time = state
+                .try_into()
+                .map_err(|_| StateError::Invalid("Wrong state size".to_string()))?;
+            self.time = Time::from_le_bytes(time);
+            Ok(())
+        }
+
+        fn get_time(&self) -> Time {
+            self.time
+        }
+
+        fn screen_size(&self) -> [usize; 2] {
+            [4, 3]
+        }
+
+        fn make_save(&self) -> Option<GameSave> {
+            None
+        }
+
+        fn get_rom(&self) -> Vec<u8> {
+            vec![]
+        }
+
+        fn c(&self) -> &Common {
+            &self.c
+        }
+
+        fn c_mut(&mut self) -> &mut Common {
+            &mut self.c
+        }
+
+        fn as_any(&mut self) -> &mut dyn Any {
+            self
+        }
+    }
+
+    #[test]
+    fn av_frame_has_one_frame_of_samples() {
+        let mut core = TestCore {
+            time: 50,
+            frame_time: 100,
+            colour: [1, 2, 3, 0xFF],
+            ..TestCore::default()
+        };
+        // Left over from regular audio output, discarded
+        core.c.audio_buffer.input = [vec![1.0; 20], vec![1.0; 20]];
+
+        let (frame, samples) = core.produce_av_frame();
+        assert_eq!(frame, vec![[1, 2, 3, 0xFF]; 4 * 3]);
+        // One stereo sample per cycle until the frame finished at 100
+        assert_eq!(samples, vec![0.0; 50 * 2]);
+        assert_eq!(core.time, 100);
+
+        let (frame, samples) = core.produce_av_frame();
+        assert_eq!(frame, vec![[1, 2, 3, 0xFF]; 4 * 3]);
+        assert_eq!(samples.len(), 100 * 2);
+        assert!(core.c.audio_buffer.input.iter().all(Vec::is_empty));
+    }
+
+    #[test]
+    fn underruns_only_while_running() {
+        let mut core = TestCore::default();
+        let mut samples = vec![1.0; 512];
+        core.produce_samples(&mut samples);
+        assert!(samples.iter().all(|s| *s == 0.0));
+        assert_eq!(core.c.audio_buffer.stats().underruns, 0);
+
+        core.c.debugger.running = true;
+        core.produce_samples(&mut samples);
+        assert_eq!(core.c.audio_buffer.stats().underruns, 1);
+        // Enough samples are left over for another chunk
+        let time = core.time;
+        core.step_cycles(10_000);
+        core.produce_samples(&mut samples);
+        assert_eq!(core.c.audio_buffer.stats().underruns, 1);
+        assert_eq!(core.time, time + 10_000);
+    }
+
+    #[test]
+    fn frame_budget_survives_clock_going_back() {
+        let mut core = TestCore {
+            time: 1000,
+            rewind_to: Some(0),
+            ..TestCore::default()
+        };
+        core.c.config.max_cycles_per_frame = 100;
+        core.c.debugger.running = true;
+
+        // Still gives up once the clock is past the budget again
+        assert_eq!(core.produce_frame(), None);
+        assert_eq!(core.time, 1101);
+    }
+
+    #[test]
+    #[cfg(feature = "png")]
+    fn screenshot_png_decodes_to_screen_size() {
+        let mut core = TestCore {
+            frame_time: 1,
+            colour: [0x12, 0x34, 0x56, 0xFF],
+            ..TestCore::default()
+        };
+        assert_eq!(core.screenshot_png(), None);
+        core.advance();
+
+        let png = core.screenshot_png().unwrap();
+        let mut reader = png::Decoder::new(png.as_slice()).read_info().unwrap();
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut pixels).unwrap();
+        assert_eq!(
+            [info.width as usize, info.height as usize],
+            core.screen_size()
+        );
+        assert_eq!(info.color_type, png::ColorType::Rgba);
+        assert_eq!(&pixels[..4], &[0x12, 0x34, 0x56, 0xFF]);
+
+        // The frame is still there for display
+        assert!(core.c.video_buffer.has_frame());
+    }
+}
@@ -15,17 +15,23 @@ macro_rules! common_functions {
     ($clock:expr, $pause_event:expr, $size:expr) => {
         fn advance_delta(&mut self, delta: f32) {
             if !self.c.debugger.running {
+                self.c.speed.reset();
                 return;
             }
 
             let target =
-                ($clock as f32 * delta * self.c.options.speed_multiplier as f32) as ::common::TimeS;
-            self.scheduler.schedule($pause_event, target);
+                ($clock as f32 * delta * self.c.options.speed_multiplier) as ::common::TimeS;
+            let cap = self.c.config.max_cycles_per_frame as ::common::TimeS;
+            if target > cap {
+                log::warn!("Tried to advance by {target} cycles, capping to {cap}");
+            }
+            self.scheduler.schedule($pause_event, target.min(cap));
 
             self.c.in_tick = true;
             while self.c.debugger.running && self.c.in_tick {
                 self.advance();
             }
+            self.c.speed.record(self.scheduler.now(), $clock as f32);
 
             if self.c.audio_buffer.input[0].len() > 100_000 {
                 self.c.audio_buffer.input[0].truncate(100);
@@ -39,12 +45,11 @@ macro_rules! common_functions {
         }
 
         #[cfg(feature = "serde")]
-        fn load_state(&mut self, state: &[u8]) {
-            let old_self = mem::replace(
-                self,
-                ::common::serialize::deserialize(state, self.c.config.compress_savestates),
-            );
+        fn load_state(&mut self, state: &[u8]) -> Result<(), ::common::serialize::StateError> {
+            let new_self = ::common::serialize::deserialize(state)?;
+            let old_self = mem::replace(self, new_self);
             self.restore_from(old_self);
+            Ok(())
         }
 
         #[cfg(not(feature = "serde"))]
@@ -53,7 +58,9 @@ macro_rules! common_functions {
         }
 
         #[cfg(not(feature = "serde"))]
-        fn load_state(&mut self, state: &[u8]) {}
+        fn load_state(&mut self, state: &[u8]) -> Result<(), ::common::serialize::StateError> {
+            Ok(())
+        }
 
         fn get_time(&self) -> ::common::Time {
             self.scheduler.now()
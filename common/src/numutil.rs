@@ -282,3 +282,23 @@ pub fn get_u64(dword: u64, idx: u32) -> u16 {
     let idx = idx.us();
     u16::from_le_bytes([bytes_in[idx], bytes_in[idx + 1]])
 }
+
+/// 64-bit FNV-1a hash of the given data. Unlike the standard library's
+/// hashers, this is guaranteed to be stable, so it can be stored.
+pub fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xCBF2_9CE4_8422_2325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100_0000_01B3)
+    })
+}
+
+/// CRC-32 (IEEE) checksum of the given data, as used by zip and PNG.
+pub fn crc32(data: &[u8]) -> u32 {
+    let crc = data.iter().fold(u32::MAX, |mut crc, byte| {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg());
+        }
+        crc
+    });
+    !crc
+}
@@ -6,43 +6,164 @@
 // If a copy of these licenses was not distributed with this file, you can
 // obtain them at https://mozilla.org/MPL/2.0/ and http://www.gnu.org/licenses/.
 
+//! Serialization of save states and other data. Everything produced here
+//! starts with a header byte of flags describing the format, followed by
+//! (optionally zstd-compressed) bincode.
+
+use std::fmt::Display;
+
+/// Header flag: The data after the header is zstd-compressed.
+const FLAG_ZSTD: u8 = 1;
+/// All header flags this version knows about.
+#[cfg(feature = "serde")]
+const KNOWN_FLAGS: u8 = FLAG_ZSTD;
+
+/// Reason serialized data could not be loaded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StateError {
+    /// The data is empty.
+    Empty,
+    /// The header contains flags this version does not know, the data
+    /// was likely made by a newer version or is not a state at all.
+    UnknownFlags(u8),
+    /// The data is compressed, but zstd support was not compiled in.
+    NoZstd,
+    /// The data could not be decoded, it was likely made by a different
+    /// version or is corrupt.
+    Invalid(String),
+}
+
+impl Display for StateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StateError::Empty => write!(f, "State is empty"),
+            StateError::UnknownFlags(flags) => write!(f, "Unknown state format {flags:02X}"),
+            StateError::NoZstd => write!(f, "Cannot load compressed state without zstd support"),
+            StateError::Invalid(err) => {
+                write!(f, "State is invalid or from another version: {err}")
+            }
+        }
+    }
+}
+
+/// Returns if the given data was produced by [serialize] with zstd enabled
+/// or by [compress].
+pub fn is_compressed(data: &[u8]) -> bool {
+    data.first().is_some_and(|flags| flags & FLAG_ZSTD != 0)
+}
+
 /// Serialize an object that can be loaded with [deserialize].
-/// It is (optionally zstd-compressed) bincode.
-#[cfg(feature = "zstd")]
+/// Compression is only done if zstd support was compiled in.
+#[cfg(feature = "serde")]
 pub fn serialize<T: serde::Serialize>(thing: &T, with_zstd: bool) -> Vec<u8> {
+    #[cfg(feature = "zstd")]
     if with_zstd {
-        let mut dest = vec![];
+        let mut dest = vec![FLAG_ZSTD];
         let mut writer = zstd::stream::Encoder::new(&mut dest, 3).unwrap();
         bincode::serialize_into(&mut writer, thing).unwrap();
         writer.finish().unwrap();
-        dest
-    } else {
-        bincode::serialize(thing).unwrap()
+        return dest;
     }
+    #[cfg(not(feature = "zstd"))]
+    let _ = with_zstd;
+
+    let mut dest = vec![0];
+    bincode::serialize_into(&mut dest, thing).unwrap();
+    dest
 }
 
 /// Deserialize an object that was made with [serialize].
-/// It is (optionally zstd-compressed) bincode.
-#[cfg(feature = "zstd")]
-pub fn deserialize<T: serde::de::DeserializeOwned>(state: &[u8], with_zstd: bool) -> T {
-    if with_zstd {
-        let decoder = zstd::stream::Decoder::new(state).unwrap();
-        bincode::deserialize_from(decoder).unwrap()
+/// Compression is detected from the header.
+#[cfg(feature = "serde")]
+pub fn deserialize<T: serde::de::DeserializeOwned>(data: &[u8]) -> Result<T, StateError> {
+    let (&flags, payload) = data.split_first().ok_or(StateError::Empty)?;
+    if flags & !KNOWN_FLAGS != 0 {
+        return Err(StateError::UnknownFlags(flags));
+    }
+
+    if flags & FLAG_ZSTD != 0 {
+        deserialize_zstd(payload)
     } else {
-        bincode::deserialize(state).unwrap()
+        bincode::deserialize(payload).map_err(|err| StateError::Invalid(err.to_string()))
     }
 }
 
-/// Serialize an object that can be loaded with [deserialize].
-/// It is (optionally zstd-compressed) bincode.
-#[cfg(not(feature = "zstd"))]
-pub fn serialize<T: serde::Serialize>(thing: &T, _with_zstd: bool) -> Vec<u8> {
-    bincode::serialize(thing).unwrap()
+#[cfg(all(feature = "serde", feature = "zstd"))]
+fn deserialize_zstd<T: serde::de::DeserializeOwned>(payload: &[u8]) -> Result<T, StateError> {
+    let decoder =
+        zstd::stream::Decoder::new(payload).map_err(|err| StateError::Invalid(err.to_string()))?;
+    bincode::deserialize_from(decoder).map_err(|err| StateError::Invalid(err.to_string()))
 }
 
-/// Deserialize an object that was made with [serialize].
-/// It is (optionally zstd-compressed) bincode.
-#[cfg(not(feature = "zstd"))]
-pub fn deserialize<T: serde::de::DeserializeOwned>(state: &[u8], with_zstd: bool) -> T {
-    bincode::deserialize(state).unwrap()
+#[cfg(all(feature = "serde", not(feature = "zstd")))]
+fn deserialize_zstd<T: serde::de::DeserializeOwned>(_payload: &[u8]) -> Result<T, StateError> {
+    Err(StateError::NoZstd)
+}
+
+/// Compress data produced by [serialize] without zstd, producing the same
+/// format as [serialize] does with zstd enabled.
+/// Returns the data unchanged if it is already compressed or zstd support
+/// was not compiled in.
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    #[cfg(feature = "zstd")]
+    if let Some((0, payload)) = data.split_first() {
+        let mut dest = vec![FLAG_ZSTD];
+        zstd::stream::copy_encode(payload, &mut dest, 3).unwrap();
+        return dest;
+    }
+    data.to_vec()
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+    struct State {
+        regs: [u32; 16],
+        ram: Vec<u8>,
+    }
+
+    fn state() -> State {
+        State {
+            regs: std::array::from_fn(|i| i as u32 * 0x1111),
+            ram: (0..0x4000).map(|i| (i / 64) as u8).collect(),
+        }
+    }
+
+    #[test]
+    fn round_trip_raw() {
+        let data = serialize(&state(), false);
+        assert!(!is_compressed(&data));
+        assert_eq!(deserialize::<State>(&data), Ok(state()));
+    }
+
+    #[test]
+    #[cfg(feature = "zstd")]
+    fn round_trip_compressed() {
+        let raw = serialize(&state(), false);
+        let data = serialize(&state(), true);
+        assert!(is_compressed(&data));
+        assert!(data.len() < raw.len());
+        assert_eq!(deserialize::<State>(&data), Ok(state()));
+
+        // Compressing afterwards gives the same format
+        let compressed = compress(&raw);
+        assert!(is_compressed(&compressed));
+        assert_eq!(deserialize::<State>(&compressed), Ok(state()));
+    }
+
+    #[test]
+    fn invalid_data() {
+        assert_eq!(deserialize::<State>(&[]), Err(StateError::Empty));
+        assert_eq!(
+            deserialize::<State>(&[0x80, 1, 2, 3]),
+            Err(StateError::UnknownFlags(0x80))
+        );
+        let data = serialize(&state(), false);
+        assert!(matches!(
+            deserialize::<State>(&data[..100]),
+            Err(StateError::Invalid(_))
+        ));
+    }
 }
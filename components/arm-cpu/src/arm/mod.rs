@@ -85,7 +85,9 @@ impl<S: ArmSystem> SysWrapper<S> {
                 let lr = pc - 4;
                 cpu.set_lr(lr);
             } else {
-                cpu.is_halted = !cpu.waitloop.on_jump(&cpu.registers, pc, nn);
+                if cpu.waitloop_enabled {
+                    cpu.is_halted = !cpu.waitloop.on_jump(&cpu.registers, pc, nn);
+                }
             }
             self.set_pc(pc.wrapping_add_signed(nn));
         }
@@ -54,8 +54,11 @@ pub trait ArmSystem: Sized + 'static {
     fn will_execute(&mut self, pc: u32);
 
     /// Get the value at the given memory address.
+    /// Unaligned addresses must be force-aligned to the access width;
+    /// rotating the result is done by the CPU where needed.
     fn get<T: RwType>(&mut self, addr: u32) -> T;
     /// Set the value at the given memory address.
+    /// Unaligned addresses must be force-aligned to the access width.
     fn set<T: RwType>(&mut self, addr: u32, value: T);
     /// Get the access time in S/N cycles for the given memory address.
     /// The type is mut here due to things like the prefetch buffer,
@@ -63,11 +66,16 @@ pub trait ArmSystem: Sized + 'static {
     fn wait_time<T: RwType>(&mut self, addr: u32, access: Access) -> u16;
 
     /// Get the value at the given memory address and add to the system clock.
+    /// On ARMv4, unaligned halfword reads (LDRH) return the aligned halfword
+    /// rotated right by 8. Unaligned word reads are only rotated by
+    /// LDR and SWP, see [SysWrapper::read_word_ldrswp]; LDM and others
+    /// use the aligned value as-is.
     fn read<T: RwType>(&mut self, addr: u32, access: Access) -> T::ReadOutput {
         let time = self.wait_time::<T>(addr, access);
         self.add_sn_cycles(time);
 
         let value = self.get::<T>(addr).u32();
+        self.report_access(addr, T::WIDTH, value, false);
         self.cpu()
             .waitloop
             .on_read(addr, value.u32(), T::from_u32(u32::MAX).u32());
@@ -89,6 +97,7 @@ pub trait ArmSystem: Sized + 'static {
         self.add_sn_cycles(time);
         self.cpu().waitloop.on_write();
         self.debugger().write_occurred(addr);
+        self.report_access(addr, T::WIDTH, value.u32(), true);
         self.set(addr, value);
     }
 
@@ -107,6 +116,37 @@ pub trait ArmSystem: Sized + 'static {
 
     /// Get the debugger for this system.
     fn debugger(&mut self) -> &mut Debugger;
+
+    /// Report a data access to the debugger for watchpoints. If the system
+    /// got stopped, end the current block so that cached blocks stop after
+    /// this instruction as well.
+    fn report_access(&mut self, addr: u32, width: u32, value: u32, write: bool) {
+        let debugger = self.debugger();
+        debugger.memory_accessed(addr, width, value, write);
+        if !debugger.running {
+            self.cpu().block_ended = true;
+        }
+    }
+
+    /// Dump registers and memory around the PC, for diagnostics.
+    fn state_dump(&mut self) -> String {
+        let cpu = self.cpur();
+        let mut dump = String::new();
+        for reg in 0..16 {
+            dump.push_str(&format!("R{reg:<2} = {:08X}  ", cpu.reg(reg)));
+            if reg % 4 == 3 {
+                dump.push('\n');
+            }
+        }
+        dump.push_str(&format!("CPSR = {:08X}\n", cpu.cpsr));
+
+        let pc = cpu.pc() & !3;
+        for addr in (pc.wrapping_sub(0x10)..pc.wrapping_add(0x10)).step_by(4) {
+            let value = self.get::<u32>(addr);
+            dump.push_str(&format!("\n{addr:08X}: {value:08X}"));
+        }
+        dump
+    }
 }
 
 /// Wrapper for the system that adds a few utility functions.
@@ -125,6 +165,7 @@ impl<S: ArmSystem> SysWrapper<S> {
         let time = self.wait_time::<u16>(addr, kind);
         self.add_sn_cycles(time);
         let val = self.get::<u16>(addr).u32();
+        self.report_access(addr, 2, val, false);
         if !S::IS_V5 && addr.is_bit(0) {
             // Unaligned on ARMv4
             (val >> 8) as i8 as i16 as u32
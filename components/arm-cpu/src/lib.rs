@@ -18,10 +18,10 @@ mod optimizations;
 pub mod registers;
 mod thumb;
 
-use std::fmt::Write;
+use std::{fmt::Write, iter};
 
 use access::{CODE, NONSEQ, SEQ};
-use common::numutil::NumExt;
+use common::{common::options::SystemConfig, numutil::NumExt};
 pub use exceptions::*;
 use optimizations::waitloop::WaitloopData;
 use registers::Flag;
@@ -29,7 +29,7 @@ use registers::Flag;
 use crate::{
     arm::ArmInst,
     interface::{ArmSystem, RwType, SysWrapper},
-    optimizations::caching::{Cache, CacheEntry, CachedInst},
+    optimizations::caching::{Cache, CacheEntry, CacheStats, CachedInst},
     registers::{FiqReg, Flag::Thumb, ModeReg},
     thumb::ThumbInst,
 };
@@ -59,6 +59,8 @@ pub struct Cpu<S: ArmSystem + 'static> {
     pub cache: Cache<S>,
     #[cfg_attr(feature = "serde", serde(skip, default))]
     waitloop: WaitloopData,
+    #[cfg_attr(feature = "serde", serde(skip, default))]
+    waitloop_enabled: bool,
 }
 
 impl<S: ArmSystem> Cpu<S> {
@@ -73,9 +75,17 @@ impl<S: ArmSystem> Cpu<S> {
         let gg = SysWrapper::new(gg);
         if gg.cpu().cache.enabled {
             if let Some(cache) = gg.cpu().cache.get(pc) {
+                #[cfg(feature = "cache-stats")]
+                {
+                    gg.cpu().cache.stats.hits += 1;
+                }
                 Cpu::run_cache(gg, cache);
                 return;
             } else if pc < 0x1000_0000 {
+                #[cfg(feature = "cache-stats")]
+                {
+                    gg.cpu().cache.stats.misses += 1;
+                }
                 Cpu::try_make_cache(gg);
                 return;
             }
@@ -175,9 +185,10 @@ impl<S: ArmSystem> Cpu<S> {
                     break;
                 }
             }
-            gg.cpu()
-                .cache
-                .put(start_pc, CacheEntry::Thumb(Box::leak(Box::new(block))));
+            gg.cpu().cache.put(
+                start_pc,
+                CacheEntry::Thumb(Box::leak(block.into_boxed_slice())),
+            );
         } else {
             let mut block = Vec::with_capacity(5);
             while !gg.cpu().block_ended {
@@ -205,9 +216,10 @@ impl<S: ArmSystem> Cpu<S> {
                     break;
                 }
             }
-            gg.cpu()
-                .cache
-                .put(start_pc, CacheEntry::Arm(Box::leak(Box::new(block))));
+            gg.cpu().cache.put(
+                start_pc,
+                CacheEntry::Arm(Box::leak(block.into_boxed_slice())),
+            );
         }
     }
 
@@ -222,12 +234,12 @@ impl<S: ArmSystem> Cpu<S> {
         gg.cpu().pipeline[1] = gg.get::<TY>(gg.cpur().pc()).u32();
         gg.cpu().access_type = SEQ;
 
-        Self::trace_inst::<TY>(gg, inst);
+        Self::trace_inst::<TY>(gg, inst, pc.wrapping_sub(TY::WIDTH * 2));
         (inst, sn_cycles, pc)
     }
 
-    fn trace_inst<TY: NumExt + 'static>(gg: &mut S, inst: u32) {
-        if gg.debugger().tracing() {
+    fn trace_inst<TY: NumExt + 'static>(gg: &mut S, inst: u32, pc: u32) {
+        if gg.debugger().tracing_pc(pc) {
             let cpsr = gg.cpu().cpsr;
             let mnem = if TY::WIDTH == 2 {
                 ThumbInst::of(inst.u16()).to_string()
@@ -302,6 +314,95 @@ impl<S: ArmSystem> Cpu<S> {
         gg.cpu().pipeline_valid = true;
     }
 
+    /// Apply the optimization options of the given config, see
+    /// [SystemConfig::cached_interpreter] and
+    /// [SystemConfig::waitloop_detection]. Caches are only initialized
+    /// if caching was not already enabled.
+    pub fn apply_optimizations(&mut self, config: &SystemConfig) {
+        if config.cached_interpreter && !self.cache.enabled {
+            self.cache.init();
+        } else if !config.cached_interpreter {
+            self.cache = Cache::default();
+        }
+        self.cache.limit = config.cached_interpreter_limit;
+        self.waitloop_enabled = config.waitloop_detection;
+    }
+
+    /// Statistics about the instruction cache. Only collected when the
+    /// `cache-stats` feature is enabled.
+    pub fn cache_stats(&self) -> CacheStats {
+        self.cache.stats
+    }
+
+    /// Disassemble the cached block starting at the given address, if there
+    /// is one.
+    pub fn dump_cache_block(&self, pc: u32) -> Option<String> {
+        self.cache.get(pc).map(|block| block.disassemble(pc))
+    }
+
+    /// Make a backtrace of return addresses, starting with the current
+    /// instruction. This is only a heuristic: LR and the words on the stack
+    /// are taken to be return addresses if they point right after a call
+    /// instruction (BL, BLX or `MOV LR, PC; BX`) in memory that `is_code`
+    /// accepts. Leftover values from earlier calls can show up, and frames
+    /// using unusual ways of calling are missed.
+    pub fn stack_trace(
+        &self,
+        read: impl Fn(u32) -> u32,
+        is_code: impl Fn(u32) -> bool,
+    ) -> Vec<u32> {
+        const MAX_FRAMES: usize = 32;
+        const MAX_STACK_WORDS: u32 = 256;
+
+        let mut frames = vec![self.pc().wrapping_sub(self.inst_size())];
+        let stack = (0..MAX_STACK_WORDS).map(|i| read(self.sp().wrapping_add(i * 4)));
+        for value in iter::once(self.lr()).chain(stack) {
+            if frames.len() >= MAX_FRAMES {
+                break;
+            }
+            if let Some(ret) = Self::as_return_address(value, &read, &is_code) {
+                // Non-leaf functions push LR, don't list it twice
+                if frames.last() != Some(&ret) {
+                    frames.push(ret);
+                }
+            }
+        }
+        frames
+    }
+
+    /// Check if the value looks like a return address, see [Cpu::stack_trace].
+    /// Returns the address without the THUMB bit.
+    fn as_return_address(
+        value: u32,
+        read: &impl Fn(u32) -> u32,
+        is_code: &impl Fn(u32) -> bool,
+    ) -> Option<u32> {
+        if value & 1 != 0 {
+            // THUMB: BL/BLX pair or BLX register
+            let ret = value & !1;
+            if ret < 4 || !is_code(ret - 4) {
+                return None;
+            }
+            let halfword = |addr: u32| (read(addr & !3) >> ((addr & 2) * 8)) as u16;
+            let (prefix, suffix) = (halfword(ret - 4), halfword(ret - 2));
+            let bl = prefix >> 11 == 0b11110 && matches!(suffix >> 11, 0b11111 | 0b11101);
+            let blx_reg = suffix & 0xFF87 == 0x4780;
+            (bl || blx_reg).then_some(ret)
+        } else if value & 3 == 0 {
+            // ARM: BL/BLX, BLX register or MOV LR, PC followed by a branch
+            if value < 8 || !is_code(value - 8) {
+                return None;
+            }
+            let inst = read(value - 4);
+            let bl = inst & 0x0F00_0000 == 0x0B00_0000 || inst & 0xFE00_0000 == 0xFA00_0000;
+            let blx_reg = inst & 0x0FFF_FFF0 == 0x012F_FF30;
+            let mov_lr_pc = read(value - 8) & 0x0FFF_FFFF == 0x01A0_E00F;
+            (bl || blx_reg || mov_lr_pc).then_some(value)
+        } else {
+            None
+        }
+    }
+
     #[inline]
     fn inc_pc_by(&mut self, count: u32) -> u32 {
         self.registers[15] = self.registers[15].wrapping_add(count);
@@ -331,6 +432,7 @@ impl<S: ArmSystem> Default for Cpu<S> {
             pipeline_valid: false,
             cache: Cache::default(),
             waitloop: WaitloopData::default(),
+            waitloop_enabled: true,
 
             ime: false,
             ie: 0,
@@ -10,7 +10,7 @@ mod alu;
 
 use std::fmt::Display;
 
-use common::{common::debugger::Severity, numutil::NumExt};
+use common::numutil::NumExt;
 
 use super::interface::{ArmSystem, SysWrapper};
 use crate::{access::NONSEQ, Cpu, Exception};
@@ -21,11 +21,11 @@ impl<S: ArmSystem> SysWrapper<S> {
     }
 
     pub fn und_inst<T: Display>(&mut self, code: T) {
-        self.debugger().log(
-            "unknown-opcode",
-            format!("Unknown opcode '{code}'"),
-            Severity::Error,
-        );
+        let dump = self.debugger().pause_on_invalid.then(|| self.state_dump());
+        self.debugger()
+            .invalid_state("unknown-opcode", format!("Unknown opcode '{code}'"), || {
+                dump.unwrap_or_default()
+            });
         Cpu::exception_occurred(self, Exception::Undefined);
     }
 
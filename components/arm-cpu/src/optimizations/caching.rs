@@ -6,20 +6,58 @@
 // If a copy of these licenses was not distributed with this file, you can
 // obtain them at https://mozilla.org/MPL/2.0/ and http://www.gnu.org/licenses/.
 
-use std::{mem, ptr};
+use std::{fmt::Write, mem, ptr};
 
 use common::{components::thin_pager::ThinPager, numutil::NumExt};
 
 use crate::{
     arm::ArmHandler,
     interface::{ArmSystem, SysWrapper},
-    thumb::ThumbHandler,
+    thumb::{ThumbHandler, ThumbInst},
+    Cpu,
 };
 
 /// Storage for instruction caching.
 pub struct Cache<S: ArmSystem> {
     pages: Vec<Option<Box<PageData<S>>>>,
     pub enabled: bool,
+    /// Maximum amount of instructions in all blocks before the whole cache
+    /// is cleared. 0 for no limit.
+    pub limit: usize,
+    /// Amount of instructions in all blocks currently cached.
+    size: usize,
+    /// Statistics, only collected with the `cache-stats` feature.
+    pub stats: CacheStats,
+}
+
+/// Statistics about the instruction cache, for performance analysis.
+/// Only collected when the `cache-stats` feature is enabled,
+/// otherwise all zero.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct CacheStats {
+    /// Number of blocks compiled since caching was enabled.
+    pub blocks: u64,
+    /// Total number of instructions in compiled blocks.
+    pub instructions: u64,
+    /// Number of times a cached block was run.
+    pub hits: u64,
+    /// Number of times no cached block was available and one was made.
+    pub misses: u64,
+    /// Number of pages that were invalidated due to writes.
+    pub invalidations: u64,
+    /// Number of times the cache was cleared for exceeding its limit.
+    pub evictions: u64,
+}
+
+impl CacheStats {
+    /// Average length of a compiled block in instructions.
+    pub fn average_block_len(&self) -> f64 {
+        if self.blocks == 0 {
+            0.0
+        } else {
+            self.instructions as f64 / self.blocks as f64
+        }
+    }
 }
 
 impl<S: ArmSystem> Cache<S> {
@@ -33,6 +71,11 @@ impl<S: ArmSystem> Cache<S> {
         }
     }
 
+    /// Amount of instructions in all blocks currently cached.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
     /// Invalidate all caches in the given page.
     pub fn invalidate_address(&mut self, pc: u32) {
         if !self.enabled || pc > 0xFFF_FFFF {
@@ -40,7 +83,21 @@ impl<S: ArmSystem> Cache<S> {
         }
 
         let page = ThinPager::addr_to_page(pc);
-        self.pages[page] = None;
+        if let Some(page) = self.pages[page].take() {
+            self.size -= page.size;
+            #[cfg(feature = "cache-stats")]
+            {
+                self.stats.invalidations += 1;
+            }
+        }
+    }
+
+    /// Drop all blocks, freeing their memory.
+    fn clear(&mut self) {
+        for page in &mut self.pages {
+            *page = None;
+        }
+        self.size = 0;
     }
 
     /// Put a cache at the given PC.
@@ -48,22 +105,31 @@ impl<S: ArmSystem> Cache<S> {
         if !self.enabled {
             return;
         }
+        #[cfg(feature = "cache-stats")]
+        {
+            self.stats.blocks += 1;
+            self.stats.instructions += entry.len() as u64;
+        }
+
+        if self.limit != 0 && self.size + entry.len() > self.limit {
+            self.clear();
+            #[cfg(feature = "cache-stats")]
+            {
+                self.stats.evictions += 1;
+            }
+        }
 
         let slot = ThinPager::addr_to_page(pc);
         let location = (pc & 0x3FFF) >> 1;
-        if let Some(page) = &mut self.pages[slot.us()] {
-            Cache::insert(&mut page.entries, location, entry);
-        } else {
-            let mut page = Box::new(PageData {
+        let page = self.pages[slot.us()].get_or_insert_with(|| {
+            Box::new(PageData {
                 entries: vec![None; 0x4000 >> 1],
-            });
-            Cache::insert(&mut page.entries, location, entry);
-            self.pages[slot.us()] = Some(page);
-        }
-    }
-
-    fn insert(set: &mut [Option<CacheEntry<S>>], location: u32, entry: CacheEntry<S>) {
-        set[location.us()] = Some(entry);
+                size: 0,
+            })
+        });
+        let old_size = page.size;
+        page.insert(location, entry);
+        self.size = self.size + page.size - old_size;
     }
 
     /// Initialize caches.
@@ -72,6 +138,8 @@ impl<S: ArmSystem> Cache<S> {
         self.pages
             .resize_with(ThinPager::addr_to_page(0xFFF_FFFF) + 1, || None);
         self.enabled = true;
+        self.size = 0;
+        self.stats = CacheStats::default();
     }
 
     /// If a block should be forcibly ended. True at page boundaries.
@@ -85,6 +153,9 @@ impl<S: ArmSystem> Default for Cache<S> {
         Self {
             pages: Vec::default(),
             enabled: false,
+            limit: 0,
+            size: 0,
+            stats: CacheStats::default(),
         }
     }
 }
@@ -92,6 +163,22 @@ impl<S: ArmSystem> Default for Cache<S> {
 #[derive(Clone)]
 pub struct PageData<S: ArmSystem> {
     pub entries: Vec<Option<CacheEntry<S>>>,
+    /// Amount of instructions in all blocks of this page.
+    size: usize,
+}
+
+impl<S: ArmSystem> PageData<S> {
+    /// Put a block at the given location, freeing the one that was
+    /// there before.
+    fn insert(&mut self, location: u32, entry: CacheEntry<S>) {
+        let slot = &mut self.entries[location.us()];
+        if let Some(old) = slot {
+            self.size -= old.len();
+            old.drop();
+        }
+        self.size += entry.len();
+        *slot = Some(entry);
+    }
 }
 
 impl<S: ArmSystem> Drop for PageData<S> {
@@ -117,6 +204,34 @@ impl<S: ArmSystem> Clone for CacheEntry<S> {
 impl<S: ArmSystem> Copy for CacheEntry<S> {}
 
 impl<S: ArmSystem> CacheEntry<S> {
+    /// Length of the block in instructions.
+    fn len(&self) -> usize {
+        match self {
+            Self::Arm(insts) => insts.len(),
+            Self::Thumb(insts) => insts.len(),
+        }
+    }
+
+    /// Disassemble the block, assuming it starts at the given address.
+    pub fn disassemble(&self, start: u32) -> String {
+        let mut buf = String::new();
+        match self {
+            Self::Arm(insts) => {
+                for (pc, inst) in (start..).step_by(4).zip(insts.iter()) {
+                    let mnem = Cpu::<S>::get_mnemonic_arm(inst.inst);
+                    writeln!(buf, "0x{pc:08X} {mnem}").ok();
+                }
+            }
+            Self::Thumb(insts) => {
+                for (pc, inst) in (start..).step_by(2).zip(insts.iter()) {
+                    let mnem = ThumbInst::of(inst.inst);
+                    writeln!(buf, "0x{pc:08X} {mnem}").ok();
+                }
+            }
+        }
+        buf
+    }
+
     fn drop(&mut self) {
         match self {
             Self::Arm(a) => {
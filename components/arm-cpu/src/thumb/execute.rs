@@ -424,7 +424,9 @@ impl<S: ArmSystem> ThumbExecutor for SysWrapper<S> {
             let nn = (inst.imm8() as i8 as i32) * 2; // Step 2
             let cpu = self.cpu();
             let pc = cpu.pc();
-            cpu.is_halted = !cpu.waitloop.on_jump(&cpu.registers, pc, nn);
+            if cpu.waitloop_enabled {
+                cpu.is_halted = !cpu.waitloop.on_jump(&cpu.registers, pc, nn);
+            }
             self.set_pc(pc.wrapping_add_signed(nn));
         }
     }
@@ -439,7 +441,9 @@ impl<S: ArmSystem> ThumbExecutor for SysWrapper<S> {
         let nn = (inst.imm10() as i32) * 2; // Step 2
         let cpu = self.cpu();
         let pc = cpu.pc();
-        cpu.is_halted = !cpu.waitloop.on_jump(&cpu.registers, pc, nn);
+        if cpu.waitloop_enabled {
+            cpu.is_halted = !cpu.waitloop.on_jump(&cpu.registers, pc, nn);
+        }
         self.set_pc(pc.wrapping_add_signed(nn));
     }
 
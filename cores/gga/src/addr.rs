@@ -6,6 +6,8 @@
 // If a copy of these licenses was not distributed with this file, you can
 // obtain them at https://mozilla.org/MPL/2.0/ and http://www.gnu.org/licenses/.
 
+use common::common::debugger::Width;
+
 // General
 pub const IE: u32 = 0x200;
 pub const IF: u32 = 0x202;
@@ -71,3 +73,27 @@ pub const FIFO_B_H: u32 = 0xA6;
 // Serial
 pub const SIOCNT: u32 = 0x128;
 pub const RCNT: u32 = 0x134;
+
+macro_rules! named {
+    ($($name:ident: $width:ident),*) => {
+        &[$((stringify!($name), 0x400_0000 + $name, Width::$width)),*]
+    };
+}
+
+/// Named registers shown in the debugger, with their full address.
+pub const NAMED_REGISTERS: &[(&str, u32, Width)] = named! {
+    DISPCNT: Halfword, DISPSTAT: Halfword, VCOUNT: Halfword,
+    BG0CNT: Halfword, BG1CNT: Halfword, BG2CNT: Halfword, BG3CNT: Halfword,
+    BG0HOFS: Halfword, BG0VOFS: Halfword, BG3VOFS: Halfword,
+    BG2PA: Halfword, BG2PB: Halfword, BG2PC: Halfword, BG2PD: Halfword,
+    BG2XL: Halfword, BG2XH: Halfword, BG2YL: Halfword, BG2YH: Halfword,
+    WIN0H: Halfword, WIN1H: Halfword, WIN0V: Halfword, WIN1V: Halfword,
+    WININ: Halfword, WINOUT: Halfword, MOSAIC: Halfword,
+    BLDCNT: Halfword, BLDALPHA: Halfword, BLDY: Halfword,
+    SOUNDCNT_H: Halfword, SOUNDBIAS_L: Halfword,
+    TM0CNT_L: Halfword, TM0CNT_H: Halfword, TM1CNT_L: Halfword, TM1CNT_H: Halfword,
+    TM2CNT_L: Halfword, TM2CNT_H: Halfword, TM3CNT_L: Halfword, TM3CNT_H: Halfword,
+    KEYINPUT: Halfword, KEYCNT: Halfword, RCNT: Halfword,
+    IE: Halfword, IF: Halfword, WAITCNT: Halfword, IME: Halfword,
+    POSTFLG: Byte
+};
@@ -18,6 +18,7 @@ use std::{
 };
 
 use common::{
+    common::audio::PsgState,
     components::scheduler::Scheduler,
     numutil::{NumExt, U16Ext},
     TimeS,
@@ -29,8 +30,16 @@ use psg::{Channel, ChannelsControl, ChannelsSelection, GenericApu, ScheduleFn};
 use super::scheduling::AdvEvent;
 use crate::{addr::FIFO_A_L, hw::dma::Dmas, scheduling::ApuEvent, GameGirlAdv, CPU_CLOCK};
 
-const SAMPLE_EVERY_N_CLOCKS: TimeS = CPU_CLOCK as TimeS / 2i64.pow(15);
 const GG_OFFS: TimeS = 4;
+/// Highest rate output samples are produced at. Higher rates selected by
+/// SOUNDBIAS are far above what the host can play back and would only make
+/// resampling more expensive, so they are produced at this rate instead;
+/// the reduced resolution still applies.
+const MAX_SAMPLE_RATE: usize = 2usize.pow(16);
+/// Size of a DMA channel's FIFO in bytes.
+const FIFO_SIZE: usize = 32;
+/// Once a FIFO holds this many bytes or less, it requests a DMA refill.
+const FIFO_REFILL: usize = 16;
 
 #[bitfield]
 #[repr(u16)]
@@ -62,10 +71,30 @@ pub struct SoundBias {
     bias: B10,
     #[skip]
     __: B4,
-    #[skip]
+    /// Amplitude resolution / sampling cycle.
+    /// 0: 9bit/32.768kHz, 1: 8bit/65.536kHz, 2: 7bit/131.072kHz, 3:
+    /// 6bit/262.144kHz
     amplitude: B2,
 }
 
+impl SoundBias {
+    /// Sample rate of the PWM output, as selected by the amplitude resolution,
+    /// limited to [MAX_SAMPLE_RATE].
+    pub fn sample_rate(&self) -> usize {
+        (2usize.pow(15) << self.amplitude()).min(MAX_SAMPLE_RATE)
+    }
+
+    /// CPU clocks between two output samples.
+    fn sample_interval(&self) -> TimeS {
+        CPU_CLOCK as TimeS / self.sample_rate() as TimeS
+    }
+
+    /// Mask to apply to the 10-bit output to get the effective resolution.
+    fn resolution_mask(&self) -> i16 {
+        !((2 << self.amplitude()) - 1)
+    }
+}
+
 impl Default for SoundBias {
     fn default() -> Self {
         0x200.into()
@@ -106,7 +135,7 @@ impl Apu {
 
             ApuEvent::PushSample => {
                 Self::push_output(gg);
-                SAMPLE_EVERY_N_CLOCKS - late_by
+                gg.apu.bias.sample_interval() - late_by
             }
         }
     }
@@ -115,7 +144,7 @@ impl Apu {
         GenericApu::init_scheduler(&mut shed(&mut gg.scheduler));
         gg.scheduler.schedule(
             AdvEvent::ApuEvent(ApuEvent::PushSample),
-            SAMPLE_EVERY_N_CLOCKS,
+            gg.apu.bias.sample_interval(),
         );
         gg.scheduler
             .schedule(AdvEvent::ApuEvent(ApuEvent::Sequencer), 0x8000);
@@ -126,6 +155,7 @@ impl Apu {
             // Master enable, also applies to DMA channels
             gg.c.audio_buffer.input[0].push(0.);
             gg.c.audio_buffer.input[1].push(0.);
+            gg.c.audio_buffer.push_stems(&[0.; 6]);
             return;
         }
         let mut left = 0;
@@ -147,6 +177,14 @@ impl Apu {
             b = gg.apu.current_samples[1] * b_vol_mul * 2;
         }
 
+        let mut stems = [0.; 6];
+        stems[4] = a as f32 / 1024.0;
+        stems[5] = b as f32 / 1024.0;
+
+        let mask = gg.c.options.channel_mask;
+        let a = if mask.is_audible(4) { a } else { 0 };
+        let b = if mask.is_audible(5) { b } else { 0 };
+
         if cnt.a_right_en() {
             right += a;
         }
@@ -160,7 +198,8 @@ impl Apu {
             left += b;
         }
 
-        let cgb_sample = gg.apu.cgb_chans.make_sample();
+        let cgb_sample = gg.apu.cgb_chans.make_sample(mask, &mut stems);
+        gg.c.audio_buffer.push_stems(&stems);
         let cgb_mul = match cnt.cgb_vol() {
             0 => 512.,  // 25%
             1 => 1024., // 50%
@@ -170,17 +209,31 @@ impl Apu {
         right += (cgb_sample[0] * cgb_mul * 0.8) as i16;
         left += (cgb_sample[1] * cgb_mul * 0.8) as i16;
 
-        let bias = gg.apu.bias.bias() as i16;
+        let bias = gg.apu.bias;
         gg.c.audio_buffer.input[0].push(Self::bias(right, bias) as f32 / 1024.0);
         gg.c.audio_buffer.input[1].push(Self::bias(left, bias) as f32 / 1024.0);
     }
 
-    fn bias(mut sample: i16, bias: i16) -> i16 {
-        sample += bias;
-        sample = sample.clamp(0, 0x3ff);
-        sample -= bias;
+    /// Apply the bias to a sample, clip it to the 10-bit output range and
+    /// reduce it to the selected resolution. The bias is removed again
+    /// afterwards, the same way the DC offset would be filtered out on
+    /// hardware.
+    fn bias(mut sample: i16, bias: SoundBias) -> i16 {
+        let level = bias.bias() as i16;
+        sample += level;
+        sample = sample.clamp(0, 0x3ff) & bias.resolution_mask();
+        sample -= level;
         sample
     }
+
+    /// SOUNDBIAS was written. Adjusts the output sample rate if the
+    /// resolution changed.
+    pub fn bias_written(gg: &mut GameGirlAdv, prev: SoundBias) {
+        if prev.amplitude() != gg.apu.bias.amplitude() {
+            let rate = gg.apu.bias.sample_rate();
+            gg.c.audio_buffer.set_input_sr(rate);
+        }
+    }
 }
 
 // Impl block for DMA channels
@@ -198,7 +251,7 @@ impl Apu {
             }
         }
 
-        if gg.apu.buffers[CH].len() <= 16 {
+        if gg.apu.buffers[CH].len() <= FIFO_REFILL {
             let dest = 0x400_0000 | (FIFO_A_L + CH.u32() * 4);
             for dma in 1..=2 {
                 if gg.dma.channels[dma].dad == dest {
@@ -209,16 +262,51 @@ impl Apu {
     }
 
     pub fn push_samples<const CH: usize>(&mut self, samples: u16) {
-        self.buffers[CH].push_back(samples.low() as i8);
-        self.buffers[CH].push_back(samples.high() as i8);
+        self.push_sample::<CH>(samples.low());
+        self.push_sample::<CH>(samples.high());
     }
 
     pub fn push_sample<const CH: usize>(&mut self, samples: u8) {
+        // The FIFO only has room for 32 bytes. Writing to a full FIFO
+        // overwrites the oldest sample instead of growing it, which would
+        // otherwise let playback lag further and further behind.
+        if self.buffers[CH].len() == FIFO_SIZE {
+            self.buffers[CH].pop_front();
+        }
         self.buffers[CH].push_back(samples as i8);
     }
+
+    /// SOUNDCNT_H was written. Clears the FIFOs if their reset bits were set.
+    pub fn cnt_h_written(&mut self, value: u16) {
+        if value.is_bit(11) {
+            self.buffers[0].clear();
+        }
+        if value.is_bit(15) {
+            self.buffers[1].clear();
+        }
+    }
 }
 
 impl Apu {
+    /// Readable PSG registers, relative to the start of IO.
+    const PSG_REGISTERS: [u16; 16] = [
+        0x60, 0x62, 0x63, 0x65, 0x68, 0x69, 0x6D, 0x70, 0x73, 0x75, 0x79, 0x7C, 0x7D, 0x80, 0x81,
+        0x84,
+    ];
+
+    /// Get a snapshot of the PSG's registers and channel state.
+    pub fn psg_state(&self) -> PsgState {
+        let mut state = self.cgb_chans.snapshot();
+        state.registers = Self::PSG_REGISTERS
+            .iter()
+            .map(|addr| {
+                let value = Self::read_register_psg(&self.cgb_chans, *addr);
+                (0x400_0000 + addr.u32(), value)
+            })
+            .collect();
+        state
+    }
+
     pub fn read_register_psg(apu: &GenericApu, addr: u16) -> u8 {
         match addr {
             0x60 => apu.pulse1.channel().read_sweep_register(),
@@ -415,3 +503,75 @@ pub fn shed(sched: &mut Scheduler<AdvEvent>) -> impl ScheduleFn + '_ {
         sched.schedule(evt, t * GG_OFFS);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use common::Core;
+
+    use super::*;
+    use crate::tests::arm_rom;
+
+    fn bias(amplitude: u16) -> SoundBias {
+        (0x200 | (amplitude << 14)).into()
+    }
+
+    #[test]
+    fn sample_rate_is_limited() {
+        assert_eq!(bias(0).sample_rate(), 32768);
+        assert_eq!(bias(1).sample_rate(), 65536);
+        assert_eq!(bias(2).sample_rate(), 65536);
+        assert_eq!(bias(3).sample_rate(), 65536);
+    }
+
+    #[test]
+    fn resolution_quantizes_output() {
+        // 9 bit drops the lowest bit, 6 bit the lowest 4
+        assert_eq!(Apu::bias(5, bias(0)), 4);
+        assert_eq!(Apu::bias(21, bias(0)), 20);
+        assert_eq!(Apu::bias(5, bias(3)), 0);
+        assert_eq!(Apu::bias(21, bias(3)), 16);
+        assert_eq!(Apu::bias(-21, bias(3)), -32);
+
+        // Output is clipped to the 10-bit range around the bias level
+        assert_eq!(Apu::bias(0x300, bias(0)), 0x1FE);
+        assert_eq!(Apu::bias(-0x300, bias(0)), -0x200);
+    }
+
+    #[test]
+    fn fifo_pcm_plays_continuously() {
+        let mut gg = arm_rom(&[0xEAFF_FFFE]); // B 0x0800_0000
+        gg.c.debugger.running = true;
+        // A rising ramp of samples in EWRAM
+        for i in 0..0x2000 {
+            gg.set(0x0200_0000 + i, i as u8);
+        }
+
+        gg.set(0x0400_0084, 0x0080u16); // Master enable
+        gg.set(0x0400_0082, 0x0B00u16); // FIFO A at 50% on both sides, timer 0, reset
+        gg.set(0x0400_00BC, 0x0200_0000u32); // DMA1 from the ramp...
+        gg.set(0x0400_00C0, 0x0400_00A0u32); // ...to FIFO A
+        gg.set(0x0400_00C6, 0xB640u16); // Repeating 32-bit FIFO DMA
+        gg.set(0x0400_0100, 0xFC00u16); // Timer 0 at 16384Hz, half the output rate
+        gg.set(0x0400_0102, 0x0080u16);
+
+        for _ in 0..10 {
+            gg.produce_frame().unwrap();
+        }
+
+        // Every sample of the ramp is output exactly twice, without gaps
+        let output = &gg.c.audio_buffer.input[0];
+        let samples = output
+            .iter()
+            .map(|s| (s * 256.0).round() as i8)
+            .collect::<Vec<_>>();
+        let runs = samples
+            .chunk_by(|a, b| a == b)
+            .map(|run| (run[0], run.len()))
+            .collect::<Vec<_>>();
+        assert!(runs.len() > 2000, "{runs:?}");
+        for pair in runs[1..runs.len() - 1].windows(2) {
+            assert_eq!(pair[1].0, pair[0].0.wrapping_add(1), "{pair:?}");
+            assert_eq!(pair[1].1, 2, "{pair:?}");
+        }
+    }
+}
@@ -14,6 +14,8 @@
 use core::slice;
 use std::{cmp, mem, ptr};
 
+use common::numutil;
+
 use crate::GameGirlAdv;
 
 const MAX_CH: usize = 12;
@@ -40,25 +42,6 @@ const fn i8_to_float(value: i8) -> f32 {
 
 #[allow(dead_code)] // TODO Still unfinished
 pub fn find_mp2k(rom: &[u8]) -> Option<u32> {
-    fn crc32(dat: &[u8]) -> u32 {
-        let mut crc = u32::MAX;
-
-        for byte in dat {
-            let mut byte = *byte;
-            for _ in 0..8 {
-                if ((crc ^ byte as u32) & 1) != 0 {
-                    crc = (crc >> 1) ^ 0xEDB88320;
-                } else {
-                    crc >>= 1;
-                }
-
-                byte >>= 1;
-            }
-        }
-
-        !crc
-    }
-
     const CRC32: u32 = 0x27EA7FCF;
     const LEN: usize = 48;
 
@@ -67,7 +50,7 @@ pub fn find_mp2k(rom: &[u8]) -> Option<u32> {
     }
 
     for addr in (0..(rom.len() - LEN)).step_by(2) {
-        let crc = crc32(&rom[addr..(addr + LEN)]);
+        let crc = numutil::crc32(&rom[addr..(addr + LEN)]);
         if CRC32 == crc {
             println!("SoundMain at 0x{addr:X}");
             let mut addr =
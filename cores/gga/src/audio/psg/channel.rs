@@ -62,6 +62,10 @@ impl<C: Channel> LengthCountedChannel<C> {
         self.counter_decrease_enable
     }
 
+    pub fn length_counter(&self) -> u16 {
+        self.current_counter
+    }
+
     pub fn clock_length_counter(&mut self) {
         if self.counter_decrease_enable {
             if self.current_counter == 0 {
@@ -15,6 +15,10 @@ type TimeS = i64;
 use bitflags::bitflags;
 pub use channel::Channel;
 use channel::{Dac, LengthCountedChannel};
+use common::{
+    common::audio::{ChannelMask, PsgChannelState, PsgState},
+    numutil::NumExt,
+};
 use noise_channel::NoiseChannel;
 use pulse_channel::PulseChannel;
 use wave_channel::WaveChannel;
@@ -131,11 +135,61 @@ impl GenericApu {
             _ => unreachable!(),
         }
     }
+
+    /// Get a snapshot of the channel state, for debugging.
+    /// Registers are left empty, since reading them is up to the system.
+    pub fn snapshot(&self) -> PsgState {
+        PsgState {
+            power: self.power,
+            sequencer_position: self.sequencer_position,
+            registers: Vec::new(),
+            channels: [
+                channel_state(
+                    &self.pulse1,
+                    self.pulse1.channel().frequency(),
+                    self.pulse1.channel().envelope().current_volume(),
+                ),
+                channel_state(
+                    &self.pulse2,
+                    self.pulse2.channel().frequency(),
+                    self.pulse2.channel().envelope().current_volume(),
+                ),
+                channel_state(
+                    &self.wave,
+                    self.wave.channel().frequency(),
+                    self.wave.channel().read_volume(),
+                ),
+                channel_state(
+                    &self.noise,
+                    self.noise.channel().read_noise_register().u16(),
+                    self.noise.channel().envelope().current_volume(),
+                ),
+            ],
+        }
+    }
+}
+
+fn channel_state<C: Channel>(
+    ch: &LengthCountedChannel<C>,
+    frequency: u16,
+    volume: u8,
+) -> PsgChannelState {
+    PsgChannelState {
+        enabled: ch.enabled(),
+        dac_enabled: ch.dac_enabled(),
+        length_enabled: ch.read_length_enable(),
+        length_counter: ch.length_counter(),
+        frequency,
+        volume,
+        output: ch.output(),
+    }
 }
 
 impl GenericApu {
     #[inline]
-    pub fn make_sample(&mut self) -> [f32; 2] {
+    /// Mix the channels into a stereo sample. The unmixed output of the
+    /// 4 channels is written into `outputs`.
+    pub fn make_sample(&mut self, mask: ChannelMask, outputs: &mut [f32]) -> [f32; 2] {
         let right_vol = self.channels_control.vol_right() as f32 + 1.;
         let left_vol = self.channels_control.vol_left() as f32 + 1.;
 
@@ -143,6 +197,12 @@ impl GenericApu {
         let pulse2 = self.pulse2.dac_output() / 8.;
         let wave = self.wave.dac_output() / 8.;
         let noise = self.noise.dac_output() / 8.;
+        outputs[..4].copy_from_slice(&[pulse1, pulse2, wave, noise]);
+
+        let pulse1 = mask.apply(0, pulse1);
+        let pulse2 = mask.apply(1, pulse2);
+        let wave = mask.apply(2, wave);
+        let noise = mask.apply(3, noise);
 
         let right_pulse1 = if self
             .channels_selection
@@ -13,17 +13,30 @@ use std::{
 };
 
 use common::{
-    components::storage::{GameSave, Storage},
+    components::storage::{GameSave, SaveInfo, Storage},
     numutil::NumExt,
+    Time,
 };
 use FlashCmdStage::*;
 use SaveType::*;
 
+use super::gpio::{Gpio, GpioDevice};
 use crate::memory::KB;
 
 // Both Macronix.
 const FLASH64_ID: [u8; 2] = [0xC2, 0x1C];
 const FLASH128_ID: [u8; 2] = [0xC2, 0x09];
+// Atmel AT29LV512, 64K with 128 byte sectors.
+const ATMEL_ID: [u8; 2] = [0x1F, 0x3D];
+
+// Approximate time backup memory takes to finish writes, in CPU cycles.
+// Only used when write latency is emulated.
+/// Programming a single byte of flash, or a sector on Atmel chips.
+const FLASH_PROGRAM_CYCLES: Time = 650;
+/// Erasing a flash sector or the entire chip.
+const FLASH_ERASE_CYCLES: Time = 30_000;
+/// Writing a 64-bit EEPROM block.
+const EEPROM_WRITE_CYCLES: Time = 115_000;
 
 #[derive(Default)]
 #[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
@@ -32,12 +45,34 @@ pub struct Cartridge {
     pub rom: Vec<u8>,
     pub ram: Vec<u8>,
     pub save_type: SaveType,
+    /// If the save RAM was modified since the last time the flag was cleared.
+    #[cfg_attr(feature = "serde", serde(skip, default))]
+    pub ram_dirty: bool,
+    /// The GPIO port.
+    pub gpio: Gpio,
+    /// If writes to the save chip should take time like on hardware.
+    /// Set from the system config.
+    #[cfg_attr(feature = "serde", serde(skip, default))]
+    pub write_latency: bool,
+    /// If 64K flash is an Atmel chip from the start, instead of only
+    /// after the game used the Atmel write sequence.
+    /// Set from the game's compatibility hacks.
+    #[cfg_attr(feature = "serde", serde(skip, default))]
+    pub atmel_flash: bool,
+    /// Time until which the save chip is busy finishing a write.
+    #[cfg_attr(feature = "serde", serde(default))]
+    busy_until: Time,
 }
 
 impl Cartridge {
-    pub fn read_ram_byte(&self, addr: usize) -> u8 {
+    pub fn read_ram_byte(&self, addr: usize, now: Time) -> u8 {
         match &self.save_type {
-            Flash64(state) if state.mode == FlashMode::Id => FLASH64_ID[addr & 1],
+            // While busy, flash chips return the inverse of bit 7 of
+            // the data being written ("data polling").
+            Flash64(_) | Flash128 { .. } if now < self.busy_until => {
+                self.read_ram_byte(addr, self.busy_until) ^ 0x80
+            }
+            Flash64(state) if state.mode == FlashMode::Id => state.chip.id()[addr & 1],
             Flash128 { state, .. } if state.mode == FlashMode::Id => FLASH128_ID[addr & 1],
 
             Flash128 { bank: 1, .. } => self.ram[addr | 0x10000],
@@ -48,25 +83,55 @@ impl Cartridge {
         }
     }
 
-    pub fn read_ram_hword(&self) -> u16 {
+    pub fn read_ram_hword(&self, now: Time) -> u16 {
         match &self.save_type {
+            // The EEPROM reports being ready by reading 1
+            Eeprom(_) if now < self.busy_until => 0,
             Eeprom(eeprom) => eeprom.read(),
             _ => 0,
         }
     }
 
-    pub fn write_ram_byte(&mut self, addr: usize, value: u8) {
-        match &mut self.save_type {
-            Flash64(state) => state.write(addr, value, &mut self.ram, None),
-            Flash128 { state, bank } => state.write(addr, value, &mut self.ram, Some(bank)),
-            Sram => self.ram[addr & 0x7FFF] = value,
-            _ => (),
+    pub fn write_ram_byte(&mut self, addr: usize, value: u8, now: Time) {
+        // Atmel sequences continue right after the first byte, so let them
+        // through to be detected
+        let continues_sector =
+            matches!(&self.save_type, Flash64(state) if state.continues_sector(addr));
+        if now < self.busy_until && !continues_sector {
+            return;
         }
+        let latency = match &mut self.save_type {
+            Flash64(state) => {
+                if self.atmel_flash {
+                    state.chip = FlashChip::Atmel;
+                }
+                state.write(addr, value, &mut self.ram, None)
+            }
+            Flash128 { state, bank } => state.write(addr, value, &mut self.ram, Some(bank)),
+            Sram => {
+                self.ram[addr & 0x7FFF] = value;
+                Some(0)
+            }
+            _ => None,
+        };
+        self.finish_write(latency, now);
     }
 
-    pub fn write_ram_hword(&mut self, value: u16) {
+    pub fn write_ram_hword(&mut self, value: u16, now: Time) {
         if let Eeprom(eeprom) = &mut self.save_type {
-            eeprom.write(value, &mut self.ram);
+            let latency = eeprom.write(value, &mut self.ram);
+            self.finish_write(latency, now);
+        }
+    }
+
+    /// Mark RAM as modified if a write changed it, and make the chip
+    /// busy for the time the write takes.
+    fn finish_write(&mut self, latency: Option<Time>, now: Time) {
+        if let Some(latency) = latency {
+            self.ram_dirty = true;
+            if self.write_latency {
+                self.busy_until = now + latency;
+            }
         }
     }
 
@@ -77,17 +142,14 @@ impl Cartridge {
 
     pub fn load_rom(&mut self, rom: Vec<u8>) {
         self.rom = rom;
-        self.save_type = self.detect_save();
+        self.save_type = Self::detect_save(&self.rom);
+        if matches!(self.gpio.device, GpioDevice::None) {
+            self.gpio.device = GpioDevice::detect(&self.game_code());
+        }
 
-        let ff_iter = iter::repeat(0xFF);
         let len = self.ram.len();
-        match self.save_type {
-            Nothing => {}
-            Eeprom(_) => self.ram.extend(ff_iter.take((8 * KB) - len)),
-            Sram => self.ram.extend(ff_iter.take((32 * KB) - len)),
-            Flash64(_) => self.ram.extend(ff_iter.take((64 * KB) - len)),
-            Flash128 { .. } => self.ram.extend(ff_iter.take((128 * KB) - len)),
-        }
+        self.ram
+            .extend(iter::repeat(0xFF).take(self.save_type.size() - len));
     }
 
     pub fn make_save(&self) -> Option<GameSave> {
@@ -96,6 +158,7 @@ impl Cartridge {
             _ => Some(GameSave {
                 ram: self.ram.clone(),
                 rtc: None,
+                rtc_offset: 0,
                 title: self.title(),
             }),
         }
@@ -114,7 +177,25 @@ impl Cartridge {
         self.read_string(0x0AC, 4)
     }
 
-    fn detect_save(&self) -> SaveType {
+    /// Detect the save type of the given ROM, the same way it is
+    /// done when loading it.
+    pub fn detect_save_info(rom: &[u8]) -> Option<SaveInfo> {
+        let ty = Self::detect_save(rom);
+        let kind = match ty {
+            Nothing => return None,
+            Eeprom(_) => "EEPROM",
+            Sram => "SRAM",
+            Flash64(_) => "Flash 64K",
+            Flash128 { .. } => "Flash 128K",
+        };
+        Some(SaveInfo {
+            kind,
+            size: ty.size(),
+            rtc: false,
+        })
+    }
+
+    fn detect_save(rom: &[u8]) -> SaveType {
         // This is not efficient
         let save_types: [(SaveType, &str); 5] = [
             (
@@ -129,9 +210,8 @@ impl Cartridge {
             (Sram, "SRAM_V"),
             (Eeprom(Eeprom::new()), "EEPROM_V"),
         ];
-        let self_str = String::from_utf8_lossy(&self.rom);
         for (ty, str) in save_types {
-            if self_str.contains(str) {
+            if find_bytes(rom, str.as_bytes()).is_some() {
                 return ty;
             }
         }
@@ -161,6 +241,10 @@ impl Cartridge {
     }
 }
 
+fn find_bytes(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
 #[derive(Debug, Default)]
 #[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
 pub enum SaveType {
@@ -175,6 +259,20 @@ pub enum SaveType {
     },
 }
 
+impl SaveType {
+    /// Size of the save RAM. EEPROM size is only known once the game
+    /// accesses it, so it is always the larger 8K.
+    pub fn size(&self) -> usize {
+        match self {
+            Nothing => 0,
+            Eeprom(_) => 8 * KB,
+            Sram => 32 * KB,
+            Flash64(_) => 64 * KB,
+            Flash128 { .. } => 128 * KB,
+        }
+    }
+}
+
 #[derive(Debug)]
 #[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
 pub struct Eeprom {
@@ -200,7 +298,10 @@ impl Eeprom {
         }
     }
 
-    pub fn write(&mut self, value: u16, ram: &mut [u8]) {
+    /// Write a bit to the EEPROM. Returns the time the write takes,
+    /// if RAM was modified.
+    pub fn write(&mut self, value: u16, ram: &mut [u8]) -> Option<Time> {
+        let mut modified = None;
         let bit = value & 1;
         self.recv_buffer = (self.recv_buffer << 1) | bit as u128;
         self.recv_count += 1;
@@ -241,10 +342,12 @@ impl Eeprom {
                     // We want to send 1's, which indicate the operation is done.
                     self.send_buffer = u128::MAX;
                     self.send_count.store(128, Ordering::Relaxed);
+                    modified = Some(EEPROM_WRITE_CYCLES);
                 }
             }
             self.reset_rx();
         }
+        modified
     }
 
     pub fn dma3_started(&mut self, dst: u32, cnt: u32) {
@@ -305,31 +408,89 @@ pub enum EepromCmd {
 pub struct FlashState {
     command_stage: Option<FlashCmdStage>,
     mode: FlashMode,
+    #[cfg_attr(feature = "serde", serde(default))]
+    chip: FlashChip,
+    /// Bytes of the current sector written, for Atmel chips.
+    #[cfg_attr(feature = "serde", serde(default))]
+    sector_written: u8,
+    /// Address of the byte programmed by the previous write, if it was
+    /// one. Used to detect Atmel chips.
+    #[cfg_attr(feature = "serde", serde(default))]
+    last_program: Option<usize>,
 }
 
 impl FlashState {
-    fn write(&mut self, addr: usize, value: u8, ram: &mut [u8], bank: Option<&mut u8>) {
+    /// Write a byte to the flash chip. Returns the time the write takes,
+    /// if RAM was modified.
+    fn write(
+        &mut self,
+        addr: usize,
+        value: u8,
+        ram: &mut [u8],
+        bank: Option<&mut u8>,
+    ) -> Option<Time> {
+        let mut modified = None;
+        let continues_sector = self.continues_sector(addr);
+        self.last_program = None;
         match (addr, value, self.command_stage) {
             (0x0, _, _) if self.mode == FlashMode::BankSelect => {
                 self.mode = FlashMode::Regular;
                 *bank.unwrap() = value & 1;
             }
 
+            // Atmel chips program an entire 128 byte sector after a write
+            // command. The sector is erased on the first byte, bytes not written
+            // after that stay erased.
+            (_, _, _) if self.mode == FlashMode::Write && self.chip == FlashChip::Atmel => {
+                if self.sector_written == 0 {
+                    for mem in ram.iter_mut().skip(addr & !0x7F).take(0x80) {
+                        *mem = 0xFF;
+                    }
+                }
+                ram[addr] = value;
+                self.sector_written += 1;
+                modified = Some(0);
+                // The sector is programmed once all bytes were received
+                if self.sector_written == 0x80 {
+                    self.mode = FlashMode::Regular;
+                    self.sector_written = 0;
+                    modified = Some(FLASH_PROGRAM_CYCLES);
+                }
+            }
+
             (_, _, _) if self.mode == FlashMode::Write => {
                 self.mode = FlashMode::Regular;
-                if bank.copied() == Some(1) {
+                if bank.as_deref() == Some(&1) {
                     ram[addr | 0x10000] = value;
                 } else {
                     ram[addr] = value;
+                    if bank.is_none() {
+                        self.last_program = Some(addr);
+                    }
                 }
+                modified = Some(FLASH_PROGRAM_CYCLES);
+            }
+
+            // The game continues writing the sector after its first byte
+            // without another command, which is the Atmel write sequence.
+            // Switch to it, erasing the rest of the sector.
+            (_, _, None) if continues_sector => {
+                self.chip = FlashChip::Atmel;
+                for mem in ram.iter_mut().skip(addr).take(0x7F) {
+                    *mem = 0xFF;
+                }
+                ram[addr] = value;
+                self.mode = FlashMode::Write;
+                self.sector_written = 2;
+                modified = Some(0);
             }
 
             (0x5555, 0xAA, None) => self.command_stage = Some(FirstWritten),
             (0x2AAA, 0x55, Some(FirstWritten)) => self.command_stage = Some(SecondWritten),
 
-            // Erase 4K sector
+            // Erase 4K sector, Atmel chips do not have this command
             (_, 0x30, Some(SecondWritten)) => {
-                if self.mode == FlashMode::Erase {
+                if self.mode == FlashMode::Erase && self.chip != FlashChip::Atmel {
                     let addr = if bank.copied() == Some(1) {
                         (addr & 0xF000) | 0x10000
                     } else {
@@ -338,6 +499,7 @@ impl FlashState {
                     for mem in ram.iter_mut().skip(addr).take(0x1000) {
                         *mem = 0xFF;
                     }
+                    modified = Some(FLASH_ERASE_CYCLES);
                 }
                 self.mode = FlashMode::Regular;
                 self.command_stage = None;
@@ -353,12 +515,16 @@ impl FlashState {
                             for mem in ram {
                                 *mem = 0xFF;
                             }
+                            modified = Some(FLASH_ERASE_CYCLES);
                         }
                         self.mode = FlashMode::Regular;
                     }
 
                     // Enter write mode
-                    0xA0 => self.mode = FlashMode::Write,
+                    0xA0 => {
+                        self.mode = FlashMode::Write;
+                        self.sector_written = 0;
+                    }
                     // Enter bank select, if banked chip
                     0xB0 if bank.is_some() => self.mode = FlashMode::BankSelect,
 
@@ -374,6 +540,17 @@ impl FlashState {
 
             _ => (),
         }
+        modified
+    }
+
+    /// If a write to the given address continues a sector whose first byte
+    /// was just programmed, without a new command.
+    fn continues_sector(&self, addr: usize) -> bool {
+        self.mode == FlashMode::Regular
+            && self.command_stage.is_none()
+            && self
+                .last_program
+                .is_some_and(|last| last & 0x7F == 0 && addr == last + 1)
     }
 
     const fn new() -> Self {
@@ -381,6 +558,32 @@ impl FlashState {
         Self {
             command_stage: None,
             mode: FlashMode::Regular,
+            chip: FlashChip::Macronix,
+            sector_written: 0,
+            last_program: None,
+        }
+    }
+}
+
+/// The manufacturer of a 64K flash chip, which determines the ID it reports
+/// and how it is programmed. Chips start out as Macronix, which every flash
+/// library supports, and switch to Atmel once the game uses the Atmel write
+/// sequence, for games that only support it or saves made on one.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub enum FlashChip {
+    /// Regular chips with 4K sectors that need to be erased before writing.
+    #[default]
+    Macronix,
+    /// Atmel chips with 128 byte sectors that are programmed at once.
+    Atmel,
+}
+
+impl FlashChip {
+    fn id(self) -> [u8; 2] {
+        match self {
+            FlashChip::Macronix => FLASH64_ID,
+            FlashChip::Atmel => ATMEL_ID,
         }
     }
 }
@@ -401,3 +604,141 @@ pub enum FlashMode {
     Erase,
     BankSelect,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn flash64() -> Cartridge {
+        Cartridge {
+            ram: vec![0xFF; 64 * KB],
+            save_type: Flash64(FlashState::new()),
+            write_latency: true,
+            ..Cartridge::default()
+        }
+    }
+
+    fn command(cart: &mut Cartridge, cmd: u8, now: Time) {
+        cart.write_ram_byte(0x5555, 0xAA, now);
+        cart.write_ram_byte(0x2AAA, 0x55, now);
+        cart.write_ram_byte(0x5555, cmd, now);
+    }
+
+    fn read_id(cart: &mut Cartridge, now: Time) -> [u8; 2] {
+        command(cart, 0x90, now);
+        let id = [cart.read_ram_byte(0, now), cart.read_ram_byte(1, now)];
+        command(cart, 0xF0, now);
+        id
+    }
+
+    #[test]
+    fn atmel_detected_from_write_sequence() {
+        let mut cart = flash64();
+        assert_eq!(read_id(&mut cart, 0), FLASH64_ID);
+
+        // A single write command followed by the entire sector, back to back
+        command(&mut cart, 0xA0, 0);
+        for i in 0..0x80 {
+            cart.write_ram_byte(0x1080 + i, i as u8, 0);
+        }
+        let sector = (0..0x80).map(|i| i as u8).collect::<Vec<_>>();
+        assert_eq!(&cart.ram[0x1080..0x1100], sector);
+
+        let now = cart.busy_until;
+        assert_eq!(read_id(&mut cart, now), ATMEL_ID);
+    }
+
+    #[test]
+    fn atmel_id_when_configured() {
+        let mut gg = crate::tests::arm_rom(&[0xEAFF_FFFE]); // B 0x0800_0000
+        gg.cart.save_type = Flash64(FlashState::new());
+        gg.cart.ram = vec![0xFF; 64 * KB];
+        gg.c.game_hacks.atmel_flash = true;
+
+        // The game probes the ID before it ever saved anything
+        gg.set(0x0E00_5555, 0xAAu8);
+        gg.set(0x0E00_2AAA, 0x55u8);
+        gg.set(0x0E00_5555, 0x90u8);
+        assert_eq!(gg.get::<u8>(0x0E00_0000), 0x1F);
+        assert_eq!(gg.get::<u8>(0x0E00_0001), 0x3D);
+    }
+
+    #[test]
+    fn macronix_byte_writes_stay_macronix() {
+        let mut cart = flash64();
+        let mut now = 0;
+        for (i, addr) in [0x1080, 0x1081, 0x1082].into_iter().enumerate() {
+            command(&mut cart, 0xA0, now);
+            cart.write_ram_byte(addr, i as u8, now);
+            now = cart.busy_until;
+        }
+        assert_eq!(&cart.ram[0x1080..0x1084], [0, 1, 2, 0xFF]);
+        assert_eq!(read_id(&mut cart, now), FLASH64_ID);
+    }
+
+    #[test]
+    fn flash_busy_after_program() {
+        let mut cart = flash64();
+        command(&mut cart, 0xA0, 100);
+        cart.write_ram_byte(0x10, 0x12, 100);
+        // Bit 7 is inverted while busy, further writes are ignored
+        let done = 100 + FLASH_PROGRAM_CYCLES;
+        assert_eq!(cart.read_ram_byte(0x10, done - 1), 0x92);
+        command(&mut cart, 0xA0, done - 1);
+        cart.write_ram_byte(0x11, 0x34, done - 1);
+        assert_eq!(cart.read_ram_byte(0x10, done), 0x12);
+        assert_eq!(cart.read_ram_byte(0x11, done), 0xFF);
+
+        // Sector erase
+        command(&mut cart, 0x80, done);
+        cart.write_ram_byte(0x5555, 0xAA, done);
+        cart.write_ram_byte(0x2AAA, 0x55, done);
+        cart.write_ram_byte(0x0000, 0x30, done);
+        let done = done + FLASH_ERASE_CYCLES;
+        assert_eq!(cart.read_ram_byte(0x10, done - 1), 0x7F);
+        assert_eq!(cart.read_ram_byte(0x10, done), 0xFF);
+    }
+
+    #[test]
+    fn flash_without_latency() {
+        let mut cart = Cartridge {
+            write_latency: false,
+            ..flash64()
+        };
+        command(&mut cart, 0xA0, 0);
+        cart.write_ram_byte(0x10, 0x12, 0);
+        assert_eq!(cart.read_ram_byte(0x10, 0), 0x12);
+        command(&mut cart, 0xA0, 0);
+        cart.write_ram_byte(0x11, 0x34, 0);
+        assert_eq!(cart.read_ram_byte(0x11, 0), 0x34);
+    }
+
+    #[test]
+    fn eeprom_busy_after_write() {
+        let mut cart = Cartridge {
+            ram: vec![0xFF; 8 * KB],
+            save_type: Eeprom(Eeprom {
+                size: EepromSize::E512,
+                ..Eeprom::new()
+            }),
+            write_latency: true,
+            ..Cartridge::default()
+        };
+        // Write command, address 1, data, end bit
+        let data = 0x0123_4567_89AB_CDEFu64;
+        let bits = [1, 0]
+            .into_iter()
+            .chain((0..6).rev().map(|i| (1 >> i) & 1))
+            .chain((0..64).rev().map(|i| (data >> i) as u16 & 1))
+            .chain([0]);
+        for bit in bits {
+            cart.write_ram_hword(bit, 100);
+        }
+        assert_eq!(cart.ram[8..16], data.to_be_bytes());
+
+        // Reads 0 until done
+        let done = 100 + EEPROM_WRITE_CYCLES;
+        assert_eq!(cart.read_ram_hword(done - 1), 0);
+        assert_eq!(cart.read_ram_hword(done), 1);
+    }
+}
@@ -0,0 +1,160 @@
+// Copyright (c) 2024 Leela Aurelia, git@elia.garden
+//
+// Unless otherwise noted, this file is released and thus subject to the
+// terms of the Mozilla Public License Version 2.0 (MPL-2.0) or the
+// GNU General Public License Version 3 (GPL-3).
+// If a copy of these licenses was not distributed with this file, you can
+// obtain them at https://mozilla.org/MPL/2.0/ and http://www.gnu.org/licenses/.
+
+//! The cartridge GPIO port, used by some carts to connect additional
+//! devices like an RTC or sensors.
+//! https://problemkaputt.de/gbatek.htm#gbacartioportgpio
+
+use common::{common::options::SystemConfig, numutil::NumExt};
+
+/// Address of the data register, in ROM space.
+pub const GPIO_DATA: u32 = 0x0800_00C4;
+/// Address of the direction register, in ROM space.
+pub const GPIO_DIRECTION: u32 = 0x0800_00C6;
+/// Address of the control register, in ROM space.
+pub const GPIO_CONTROL: u32 = 0x0800_00C8;
+
+/// State of the 4 GPIO pins.
+#[derive(Debug, Default, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct Gpio {
+    /// Current pin levels.
+    pub data: u8,
+    /// Pin directions; set bits are outputs written by the GBA, clear bits are
+    /// inputs driven by the cart's device.
+    pub direction: u8,
+    /// If the registers can be read. When not, reads return ROM contents.
+    pub readable: bool,
+    /// Device connected to the pins.
+    pub device: GpioDevice,
+}
+
+impl Gpio {
+    /// If the given address is one of the GPIO registers.
+    pub fn is_gpio_addr(addr: u32) -> bool {
+        (GPIO_DATA..(GPIO_CONTROL + 2)).contains(&addr)
+    }
+
+    /// Read a register. Only valid if [readable] is set.
+    pub fn read(&self, addr: u32) -> u16 {
+        match addr & !1 {
+            GPIO_DATA => self.data.u16(),
+            GPIO_DIRECTION => self.direction.u16(),
+            GPIO_CONTROL => self.readable as u16,
+            _ => 0,
+        }
+    }
+
+    /// Write a register. Only pins configured as outputs can be written.
+    pub fn write(&mut self, addr: u32, value: u16, config: &SystemConfig) {
+        match addr & !1 {
+            GPIO_DATA => {
+                let value = value.u8() & 0xF;
+                self.data = (self.data & !self.direction) | (value & self.direction);
+                let input = self.device.update(self.data, config);
+                self.data = (self.data & self.direction) | (input & !self.direction);
+            }
+            GPIO_DIRECTION => self.direction = value.u8() & 0xF,
+            GPIO_CONTROL => self.readable = value.is_bit(0),
+            _ => (),
+        }
+    }
+}
+
+/// Devices that can be connected to the GPIO port.
+#[derive(Debug, Default, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub enum GpioDevice {
+    #[default]
+    None,
+    /// Solar sensor, used by the Boktai series.
+    Solar(SolarSensor),
+}
+
+impl GpioDevice {
+    /// Detect the device present on a cartridge from its game code.
+    pub fn detect(game_code: &str) -> Self {
+        match game_code.get(..3) {
+            // Boktai 1, 2 and 3
+            Some("U3I" | "U32" | "U33") => Self::Solar(SolarSensor::default()),
+            _ => Self::None,
+        }
+    }
+
+    /// Update the device with the current pin levels, returning
+    /// the levels it drives on the pins.
+    fn update(&mut self, pins: u8, config: &SystemConfig) -> u8 {
+        match self {
+            Self::None => 0,
+            Self::Solar(sensor) => sensor.update(pins, config.solar_level),
+        }
+    }
+}
+
+/// The Boktai solar sensor. Converts the light level to a digital value
+/// by having the game clock a counter until it reaches the ADC value,
+/// at which point the flag pin goes high.
+/// https://problemkaputt.de/gbatek.htm#gbacartsolarsensor
+#[derive(Debug, Default, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct SolarSensor {
+    /// Amount of clock pulses since the last reset.
+    pub counter: u8,
+    /// Level of the clock pin at the last update.
+    clock: bool,
+}
+
+impl SolarSensor {
+    /// ADC value in complete darkness.
+    const DARK: u8 = 0xE8;
+    /// ADC value in the brightest sunlight.
+    const BRIGHT: u8 = 0x50;
+
+    /// Pin 0 is the clock, pin 1 resets the counter, pin 3 is the flag.
+    fn update(&mut self, pins: u8, level: u8) -> u8 {
+        let clock = pins.is_bit(0);
+        if pins.is_bit(1) {
+            self.counter = 0;
+        } else if clock && !self.clock {
+            self.counter = self.counter.saturating_add(1);
+        }
+        self.clock = clock;
+
+        let flag = self.counter >= Self::adc_value(level);
+        (flag as u8) << 3
+    }
+
+    /// Convert the light level (0-255) to the value the sensor's ADC reports.
+    /// Brighter light results in lower values.
+    fn adc_value(level: u8) -> u8 {
+        let range = (Self::DARK - Self::BRIGHT) as u16;
+        Self::DARK - (level as u16 * range / 255) as u8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::arm_rom;
+
+    #[test]
+    fn byte_writes_merge_into_register() {
+        let mut gg = arm_rom(&[]);
+        gg.set(GPIO_CONTROL, 1u8);
+        gg.set(GPIO_DIRECTION, 0b0101u8);
+        // Writing the unused high byte keeps the low one
+        gg.set(GPIO_DIRECTION + 1, 0u8);
+        gg.set(GPIO_CONTROL + 1, 0u8);
+        assert_eq!(gg.cart.gpio.direction, 0b0101);
+        assert!(gg.cart.gpio.readable);
+        assert_eq!(gg.get::<u16>(GPIO_DIRECTION), 0b0101);
+
+        gg.set(GPIO_DIRECTION, 0b0011u16);
+        assert_eq!(gg.cart.gpio.direction, 0b0011);
+    }
+}
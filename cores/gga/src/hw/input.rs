@@ -28,29 +28,104 @@ pub struct KeyControl {
 impl GameGirlAdv {
     pub fn keyinput(&self) -> u16 {
         // GGA input is active low
-        0x3FF ^ self.c.input.state(self.scheduler.now()).0
+        0x3FF ^ (self.c.input.state(self.scheduler.now()).0 & 0x3FF)
     }
 
     /// Check if KEYCNT should cause a joypad IRQ.
+    /// The IRQ is requested when the configured key condition becomes true.
     pub fn check_keycnt(&mut self) {
         let input = 0x3FF ^ self.keyinput();
         let cnt = self.memory.keycnt;
         if cnt.global_irq() {
-            let cond = cnt.irq_enables();
-            let fire = (input != self.memory.keys_prev)
-                && if !cnt.irq_is_and() {
-                    cond & input != 0
-                } else {
-                    cond & input == cond
-                };
-            if fire {
+            if cnt.matches(input) && !cnt.matches(self.memory.keys_prev) {
                 Cpu::request_interrupt(self, Interrupt::Joypad);
             }
-
             self.scheduler
                 .schedule(AdvEvent::UpdateKeypad, (CPU_CLOCK / 120.0) as TimeS);
         }
 
         self.memory.keys_prev = input;
     }
+
+    /// KEYCNT was written. If the condition is already met when the IRQ
+    /// gets enabled, it fires immediately.
+    pub fn keycnt_written(&mut self) {
+        self.scheduler.cancel_single(AdvEvent::UpdateKeypad);
+        self.memory.keys_prev = 0;
+        self.check_keycnt();
+    }
+}
+
+impl KeyControl {
+    /// If the given pressed keys satisfy the configured condition.
+    fn matches(self, input: u16) -> bool {
+        let cond = self.irq_enables() & 0x3FF;
+        if self.irq_is_and() {
+            cond != 0 && input & cond == cond
+        } else {
+            input & cond != 0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use common::common::input::Button;
+
+    use crate::{tests::arm_rom, GameGirlAdv};
+
+    fn press(gg: &mut GameGirlAdv, button: Button, state: bool) {
+        gg.c.input.set(gg.scheduler.now(), button, state);
+        gg.check_keycnt();
+    }
+
+    fn take_irq(gg: &mut GameGirlAdv) -> bool {
+        let fired = gg.cpu.if_ & 0x1000 != 0;
+        gg.set(0x0400_0202, 0x1000u16);
+        fired
+    }
+
+    #[test]
+    fn keycnt_and_mode() {
+        let mut gg = arm_rom(&[0xEAFF_FFFE]); // B 0x0800_0000
+        gg.set(0x0400_0132, 0xC00Cu16); // Start + Select, AND
+        press(&mut gg, Button::Start, true);
+        press(&mut gg, Button::A, true);
+        assert!(!take_irq(&mut gg));
+        press(&mut gg, Button::Select, true);
+        assert!(take_irq(&mut gg));
+
+        // Only fires again once the condition was false in between
+        press(&mut gg, Button::A, false);
+        assert!(!take_irq(&mut gg));
+        press(&mut gg, Button::Start, false);
+        press(&mut gg, Button::Start, true);
+        assert!(take_irq(&mut gg));
+    }
+
+    #[test]
+    fn keycnt_or_mode() {
+        let mut gg = arm_rom(&[0xEAFF_FFFE]); // B 0x0800_0000
+        gg.set(0x0400_0132, 0x4003u16); // A or B
+        press(&mut gg, Button::Start, true);
+        assert!(!take_irq(&mut gg));
+        press(&mut gg, Button::B, true);
+        assert!(take_irq(&mut gg));
+
+        // Disabled IRQ never fires
+        gg.set(0x0400_0132, 0x0003u16);
+        press(&mut gg, Button::B, false);
+        press(&mut gg, Button::A, true);
+        assert!(!take_irq(&mut gg));
+    }
+
+    #[test]
+    fn keycnt_fires_when_enabled_while_held() {
+        let mut gg = arm_rom(&[0xEAFF_FFFE]); // B 0x0800_0000
+        press(&mut gg, Button::L, true);
+        press(&mut gg, Button::R, true);
+        assert!(!take_irq(&mut gg));
+        gg.set(0x0400_0132, 0xC300u16);
+        assert!(take_irq(&mut gg));
+    }
 }
@@ -9,6 +9,7 @@
 pub mod bios;
 pub mod cartridge;
 pub mod dma;
+pub mod gpio;
 pub mod input;
 pub mod serial;
 pub mod timer;
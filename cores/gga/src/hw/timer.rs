@@ -17,6 +17,8 @@ use modular_bitfield::{bitfield, specifiers::*};
 use crate::{audio::Apu, scheduling::AdvEvent, GameGirlAdv};
 
 pub const DIVS: [u16; 4] = [1, 64, 256, 1024];
+/// Cycles between a scheduled timer overflowing and its event firing.
+const OVERFLOW_DELAY: TimeS = 3;
 
 #[bitfield]
 #[repr(u16)]
@@ -54,7 +56,21 @@ pub struct Timers {
 impl Timers {
     /// Handle overflow of a scheduled timer.
     pub fn handle_overflow_event(gg: &mut GameGirlAdv, idx: u8, late_by: TimeS) {
-        Self::overflow(gg, idx, -late_by);
+        let timer = idx.us();
+        // Restart counting from the exact time of the overflow instead of
+        // the time this event got handled, so the period does not drift.
+        // DirectSound relies on this for a steady sample rate.
+        let overflowed_at = gg
+            .scheduler
+            .now()
+            .wrapping_add_signed(-late_by - OVERFLOW_DELAY);
+        let until_ov = Self::overflow_time(gg.timers.reload[timer], gg.timers.control[timer]);
+        gg.timers.counters[timer] = gg.timers.reload[timer];
+        gg.timers.scheduled_at[timer] = overflowed_at;
+        gg.scheduler
+            .schedule(AdvEvent::TimerOverflow(idx), until_ov as TimeS - late_by);
+
+        Self::overflow(gg, idx);
     }
 
     /// Read current time of the given timer.
@@ -104,40 +120,29 @@ impl Timers {
                 // Reload counter.
                 self.counters[timer] = self.reload[timer];
             }
-            self.start_timer(sched, timer, new_ctrl, 2, 0);
+            self.start_timer(sched, timer, new_ctrl);
         }
 
         self.control[timer] = new_ctrl;
     }
 
-    fn start_timer(
-        &mut self,
-        sched: &mut Scheduler<AdvEvent>,
-        timer: usize,
-        new_ctrl: TimerCtrl,
-        base_offset: TimeS,
-        mut timer_offset: TimeS,
-    ) {
+    fn start_timer(&mut self, sched: &mut Scheduler<AdvEvent>, timer: usize, new_ctrl: TimerCtrl) {
+        // Timers only start counting a bit after the control write.
+        const START_DELAY: TimeS = 2;
         let until_ov = Self::overflow_time(self.counters[timer], new_ctrl);
-        if until_ov == 1 {
-            // Bit of a hack.
-            timer_offset += 1;
-        }
-        self.scheduled_at[timer] = sched
-            .now()
-            .wrapping_add_signed(base_offset)
-            .wrapping_add_signed(timer_offset);
+        // Bit of a hack.
+        let timer_offset = if until_ov == 1 { 1 } else { 0 };
+        self.scheduled_at[timer] = sched.now().wrapping_add_signed(START_DELAY + timer_offset);
         sched.schedule(
             AdvEvent::TimerOverflow(timer.u8()),
-            until_ov as TimeS + base_offset + 3,
+            until_ov as TimeS + START_DELAY + OVERFLOW_DELAY,
         );
     }
 
-    /// Handle an overflow and return time until next.
-    fn overflow(gg: &mut GameGirlAdv, idx: u8, offset: TimeS) {
+    /// Handle an overflow: Fire IRQ, notify the APU and cascade into the next
+    /// timer. Rescheduling is up to the caller.
+    fn overflow(gg: &mut GameGirlAdv, idx: u8) {
         let ctrl = gg.timers.control[idx.us()];
-        let reload = gg.timers.reload[idx.us()];
-        let mut value = Self::time_read_inner(&gg.timers, idx.us(), gg.scheduler.now());
 
         // Fire IRQ if enabled
         if ctrl.irq_en() {
@@ -154,29 +159,9 @@ impl Timers {
             }
         }
 
-        loop {
-            if idx != 3 && gg.timers.control[idx.us() + 1].count_up() {
-                // Next timer is set to inc when we overflow.
-                Self::inc_timer(gg, idx.us() + 1);
-            }
-
-            let new_counter = reload.u32() + (value - 0x10000);
-            gg.timers.counters[idx.us()] = new_counter.u16();
-            if new_counter < 0x10000 {
-                break;
-            }
-            value -= 0x10000 - reload.u32();
-        }
-
-        if !ctrl.count_up() {
-            Self::start_timer(
-                &mut gg.timers,
-                &mut gg.scheduler,
-                idx.us(),
-                ctrl,
-                offset,
-                -4,
-            )
+        if idx != 3 && gg.timers.control[idx.us() + 1].count_up() {
+            // Next timer is set to inc when we overflow.
+            Self::inc_timer(gg, idx.us() + 1);
         }
     }
 
@@ -193,7 +178,8 @@ impl Timers {
         match new {
             Some(val) => gg.timers.counters[idx] = val,
             None => {
-                Self::overflow(gg, idx.u8(), 0);
+                gg.timers.counters[idx] = gg.timers.reload[idx];
+                Self::overflow(gg, idx.u8());
             }
         }
     }
@@ -14,6 +14,7 @@ use crate::{
     addr::*,
     audio::{self, Apu},
     hw::dma::Dmas,
+    ppu::Ppu,
     GameGirlAdv,
 };
 
@@ -66,6 +67,7 @@ impl GameGirlAdv {
 
             // Input
             io16!(a, KEYINPUT, self.keyinput());
+            io16!(a, KEYCNT, self.memory.keycnt.into());
 
             // Serial
             io16!(a, RCNT, self.serial.rcnt);
@@ -128,14 +130,28 @@ impl GameGirlAdv {
             });
 
             // Graphics
+            iow16!(a, DISPCNT, {
+                let prev = self.ppu.regs.dispcnt.bg_mode();
+                self.ppu.regs.write(a, s8, s16);
+                Ppu::dispcnt_written(self, prev);
+            });
+            iow16!(a, DISPSTAT, {
+                self.ppu.regs.write(a, s8, s16);
+                Ppu::dispstat_written(self);
+            });
             if matches!(a, 0x00..=0x54) {
                 return self.ppu.regs.write(a, s8, s16);
             }
 
             // DMA audio
-            iow16!(a, SOUNDCNT_H, s16.apply_io(&mut self.apu.cnt));
+            iow16!(a, SOUNDCNT_H, {
+                s16.apply_io(&mut self.apu.cnt);
+                self.apu.cnt_h_written(s16.raw());
+            });
             iow16!(a, SOUNDBIAS_L, {
-                s16.apply_io(&mut self.apu.bias); // TODO update sample rate
+                let prev = self.apu.bias;
+                s16.apply_io(&mut self.apu.bias);
+                Apu::bias_written(self, prev);
             });
             for i in 0..4 {
                 iow08!(a, FIFO_A_L + i, self.apu.push_sample::<0>(s8.raw()));
@@ -152,7 +168,7 @@ impl GameGirlAdv {
             // Input
             iow16!(a, KEYCNT, {
                 s16.apply_io(&mut self.memory.keycnt);
-                self.check_keycnt();
+                self.keycnt_written();
             });
 
             // Timers + DMA
@@ -18,18 +18,22 @@ use arm_cpu::Cpu;
 use audio::Apu;
 use common::{
     common::{
-        debugger::{self, Width},
+        audio::PsgState,
+        debugger::{self, InterruptState, MemoryRegion, SpriteInfo, TileMap, Width},
         options::SystemConfig,
         Common,
     },
     common_functions,
     components::{scheduler::Scheduler, storage::GameSave, thin_pager::ThinPager},
     numutil::NumExt,
-    Core, TimeS,
+    Core, CoreKind, TimeS,
 };
 use cpu::CPU_CLOCK;
 use elf_rs::{Elf, ElfFile};
-use hw::{cartridge::Cartridge, serial::Serial};
+use hw::{
+    cartridge::{Cartridge, SaveType},
+    serial::Serial,
+};
 use memory::Memory;
 use ppu::Ppu;
 use scheduling::PpuEvent;
@@ -68,6 +72,10 @@ pub struct GameGirlAdv {
 impl Core for GameGirlAdv {
     common_functions!(CPU_CLOCK, AdvEvent::PauseEmulation, [240, 160]);
 
+    fn kind(&self) -> Option<CoreKind> {
+        Some(CoreKind::Gga)
+    }
+
     fn advance(&mut self) {
         if self.cpu.is_halted {
             // We're halted, emulate peripherals until an interrupt is pending
@@ -96,15 +104,32 @@ impl Core for GameGirlAdv {
         self.cart.make_save()
     }
 
+    fn load_save(&mut self, save: GameSave) {
+        self.cart.load_save(save);
+        self.cart.ram_dirty = true;
+    }
+
+    fn save_is_dirty(&self) -> bool {
+        self.cart.ram_dirty
+    }
+
+    fn clear_save_dirty(&mut self) {
+        self.cart.ram_dirty = false;
+    }
+
     fn get_memory(&self, addr: u32, width: Width) -> u32 {
-        self.get::<u32>(addr) & width.mask()
+        match width {
+            Width::Byte => self.get::<u8>(addr).u32(),
+            Width::Halfword => self.get::<u16>(addr).u32(),
+            Width::Word => self.get::<u32>(addr),
+        }
     }
 
     fn search_memory(&self, value: u32, width: Width, kind: Ordering) -> Vec<u32> {
         let mut values = Vec::new();
         debugger::search_array(
             &mut values,
-            &self.memory.iwram,
+            &self.memory.ewram,
             0x200_0000,
             value,
             width,
@@ -112,7 +137,7 @@ impl Core for GameGirlAdv {
         );
         debugger::search_array(
             &mut values,
-            &self.memory.ewram,
+            &self.memory.iwram,
             0x300_0000,
             value,
             width,
@@ -132,21 +157,140 @@ impl Core for GameGirlAdv {
         values
     }
 
+    fn memory_regions(&self) -> Vec<MemoryRegion> {
+        let mut regions = vec![
+            MemoryRegion::new("BIOS", 0x000_0000, self.memory.bios.len().u32(), false),
+            MemoryRegion::new("EWRAM", 0x200_0000, self.memory.ewram.len().u32(), true),
+            MemoryRegion::new("IWRAM", 0x300_0000, self.memory.iwram.len().u32(), true),
+            MemoryRegion::new("I/O", 0x400_0000, 0x400, true),
+            MemoryRegion::new("Palette", 0x500_0000, self.ppu.palette.len().u32(), true),
+            MemoryRegion::new("VRAM", 0x600_0000, self.ppu.vram.len().u32(), true),
+            MemoryRegion::new("OAM", 0x700_0000, self.ppu.oam.len().u32(), true),
+            MemoryRegion::new("ROM", 0x800_0000, self.cart.rom.len().u32(), false),
+        ];
+        // Writes to the save chip are not plain stores, see
+        // [is_writable_memory]. EEPROM is accessed serially, so there is
+        // nothing to show at its address.
+        let save = match self.cart.save_type {
+            SaveType::Sram => Some(("SRAM", 0x8000)),
+            SaveType::Flash64(_) => Some(("Flash", 0x1_0000)),
+            // Only the selected bank is visible
+            SaveType::Flash128 { bank: 0, .. } => Some(("Flash (bank 0)", 0x1_0000)),
+            SaveType::Flash128 { .. } => Some(("Flash (bank 1)", 0x1_0000)),
+            SaveType::Eeprom(_) | SaveType::Nothing => None,
+        };
+        if let Some((name, len)) = save {
+            regions.push(MemoryRegion::new(name, 0xE00_0000, len, false));
+        }
+        regions
+    }
+
     fn get_registers(&self) -> Vec<usize> {
         self.cpu.registers.into_iter().map(NumExt::us).collect()
     }
 
+    fn take_event_counts(&mut self) -> Vec<(&'static str, u32)> {
+        self.scheduler.take_event_counts()
+    }
+
+    fn stack_trace(&self) -> Vec<u32> {
+        self.cpu.stack_trace(
+            |addr| self.get::<u32>(addr),
+            // BIOS, WRAM and cartridge ROM
+            |addr| matches!(addr >> 24, 0x00 | 0x02 | 0x03 | 0x08..=0x0D),
+        )
+    }
+
     fn get_rom(&self) -> Vec<u8> {
         self.cart.rom.clone()
     }
 
     fn set_memory(&mut self, addr: u32, value: u32, width: Width) {
+        self.c
+            .debugger
+            .memory_accessed(addr, width.size() as u32, value, true);
         match width {
             Width::Byte => self.set(addr, value.u8()),
             Width::Halfword => self.set(addr, value.u16()),
             Width::Word => self.set(addr, value),
         }
     }
+
+    fn is_writable_memory(&self, addr: u32) -> bool {
+        // BIOS and cartridge ROM, including its mirrors, and the save chip:
+        // Its bus is only 8 bits wide, and flash takes writes as commands
+        !matches!(addr >> 24, 0x00 | 0x08..=0x0F)
+    }
+
+    fn apply_config(&mut self, config: &SystemConfig) {
+        self.c.config.apply_live(config);
+        self.cpu.apply_optimizations(config);
+        self.cart.write_latency = config.save_write_latency;
+    }
+
+    fn sprites(&self) -> Vec<SpriteInfo> {
+        self.ppu.sprites()
+    }
+
+    fn set_sprite(&mut self, idx: usize, sprite: SpriteInfo) {
+        self.ppu.set_sprite(idx, sprite);
+    }
+
+    fn tile_map(&self, layer: usize) -> Option<TileMap> {
+        self.ppu.tile_map(layer, self.c.config.gba_colour_expansion)
+    }
+
+    fn interrupt_state(&self) -> InterruptState {
+        InterruptState {
+            master_enable: self.cpu.ime,
+            cpu_masked: self.cpu.cpsr.is_bit(7),
+            halted: self.cpu.is_halted,
+            sources: InterruptState::sources_from_bits(
+                &[
+                    "VBlank", "HBlank", "VCounter", "Timer 0", "Timer 1", "Timer 2", "Timer 3",
+                    "Serial", "DMA 0", "DMA 1", "DMA 2", "DMA 3", "Keypad", "Game Pak",
+                ],
+                self.cpu.ie,
+                self.cpu.if_,
+            ),
+        }
+    }
+
+    fn io_register_table(&self) -> &'static [(&'static str, u32, Width)] {
+        addr::NAMED_REGISTERS
+    }
+
+    fn io_registers(&self) -> Vec<(String, u32, u32)> {
+        addr::NAMED_REGISTERS
+            .iter()
+            .map(
+                |&(name, addr, width)| match self.ppu.regs.read_write_only(addr - 0x400_0000) {
+                    Some(value) => (format!("{name} (write-only)"), addr, value.u32()),
+                    None => (name.to_string(), addr, self.get_memory(addr, width)),
+                },
+            )
+            .collect()
+    }
+
+    fn psg_state(&self) -> Option<PsgState> {
+        Some(self.apu.psg_state())
+    }
+
+    fn audio_channels(&self) -> &'static [&'static str] {
+        &["Pulse 1", "Pulse 2", "Wave", "Noise", "FIFO A", "FIFO B"]
+    }
+
+    fn layers(&self) -> &'static [&'static str] {
+        &["BG0", "BG1", "BG2", "BG3", "OBJ"]
+    }
+
+    fn hidden_layers(&self) -> u32 {
+        self.ppu.hidden_layers.u32()
+    }
+
+    fn set_hidden_layers(&mut self, mask: u32) {
+        self.ppu.hidden_layers = mask.u8() & 0x1F;
+    }
 }
 
 impl GameGirlAdv {
@@ -171,12 +315,16 @@ impl GameGirlAdv {
         if let Some(save) = save {
             self.cart.load_save(save);
         }
+        self.cart.ram_dirty = old_self.cart.ram_dirty;
 
         self.c.restore_from(old_self.c);
         self.setup_host_state();
     }
 
     pub fn setup_host_state(&mut self) {
+        self.c
+            .audio_buffer
+            .set_input_sr(self.apu.bias.sample_rate());
         self.init_memory();
         Ppu::init_render(self);
         if let Some(bios) = self.c.config.get_bios("agb") {
@@ -190,7 +338,6 @@ impl GameGirlAdv {
             .schedule(AdvEvent::PpuEvent(PpuEvent::HblankStart), 960);
         self.scheduler
             .schedule(AdvEvent::UpdateKeypad, (CPU_CLOCK / 120.0) as TimeS);
-        self.c.audio_buffer.set_input_sr(2usize.pow(15));
         self.setup_host_state();
         // self.apu.hle_hook = mplayer::find_mp2k(&cart).unwrap_or(0); TODO
         // still buggy
@@ -268,3 +415,197 @@ impl Default for GameGirlAdv {
         gg
     }
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use common::common::debugger::Watchpoint;
+
+    use super::*;
+
+    /// A ROM made of the given ARM instructions, started without the BIOS.
+    /// The PPU renders on the emulation thread, so frames are deterministic.
+    pub fn arm_rom(program: &[u32]) -> Box<GameGirlAdv> {
+        let mut rom = program
+            .iter()
+            .flat_map(|inst| inst.to_le_bytes())
+            .collect::<Vec<_>>();
+        rom.resize(rom.len().max(0x400), 0);
+        let config = SystemConfig {
+            threaded_ppu: false,
+            ..SystemConfig::default()
+        };
+        let mut gg = GameGirlAdv::new(Some(rom), None, &config);
+        gg.skip_bootrom();
+        gg
+    }
+
+    #[test]
+    fn cache_stays_within_limit() {
+        // 4096 blocks of an ADD and a branch to the next block
+        let mut program = [0xE280_0001, 0xEAFF_FFFF].repeat(0x1000); // ADD R0, R0, #1; B +4
+        program.push(0xEAFF_FFFF - 0x2000); // B 0x0800_0000
+
+        let mut gg = arm_rom(&program);
+        gg.c.config.cached_interpreter_limit = 1000;
+        gg.cpu.apply_optimizations(&gg.c.config);
+        // Run through all blocks a few times
+        while gg.cpu.registers[0] < 0x4000 {
+            gg.advance();
+            assert!(gg.cpu.cache.size() <= 1000);
+        }
+        assert!(gg.cpu.cache.size() > 0);
+
+        // Without a limit, everything stays cached
+        gg.c.config.cached_interpreter_limit = 0;
+        gg.cpu.apply_optimizations(&gg.c.config);
+        while gg.cpu.registers[0] < 0x8000 {
+            gg.advance();
+        }
+        assert_eq!(gg.cpu.cache.size(), 0x2000);
+    }
+
+    #[test]
+    fn watchpoint_stops_cached_block() {
+        let mut gg = arm_rom(&[
+            0xE3A0_0403, // MOV R0, #0x0300_0000
+            0xE3A0_1042, // MOV R1, #0x42
+            0xE580_1000, // STR R1, [R0]
+            0xE3A0_2001, // MOV R2, #1
+            0xEAFF_FFFA, // B 0x0800_0000
+        ]);
+        // Run the loop a few times to cache it
+        for _ in 0..100 {
+            gg.advance();
+        }
+
+        gg.c.debugger.watchpoints.push(Watchpoint {
+            start: 0x0300_0000,
+            end: 0x0300_0003,
+            read: false,
+            write: true,
+            value: None,
+        });
+        gg.cpu.registers[2] = 0;
+        gg.c.debugger.running = true;
+        for _ in 0..100 {
+            if !gg.c.debugger.running {
+                break;
+            }
+            gg.advance();
+        }
+        let hit = gg.c.debugger.watchpoint_hit.as_ref().unwrap();
+        assert_eq!((hit.addr, hit.value, hit.write), (0x0300_0000, 0x42, true));
+        // Stopped right after the store
+        assert_eq!(gg.cpu.registers[2], 0);
+
+        // Edits done by the frontend do not trigger watchpoints...
+        gg.c.debugger.running = true;
+        gg.c.debugger.watchpoint_hit = None;
+        gg.edit_memory(0x0300_0000, 0x43, Width::Word);
+        assert!(gg.c.debugger.running);
+        assert!(gg.c.debugger.watchpoint_hit.is_none());
+        assert_eq!(gg.c.debugger.watchpoints.len(), 1);
+        assert_eq!(gg.get::<u32>(0x0300_0000), 0x43);
+
+        // ...but other writes through set_memory do
+        gg.set_memory(0x0300_0000, 0x44, Width::Word);
+        assert!(!gg.c.debugger.running);
+        let hit = gg.c.debugger.watchpoint_hit.as_ref().unwrap();
+        assert_eq!((hit.addr, hit.value, hit.write), (0x0300_0000, 0x44, true));
+    }
+
+    #[test]
+    fn stack_trace_follows_calls() {
+        let mut gg = arm_rom(&[
+            0xE3A0_0403, // MOV R0, #0x0300_0000
+            0xE52D_0004, // PUSH {R0}, not a return address
+            0xEB00_0000, // BL 0x0800_0010
+            0xEAFF_FFFE, // B 0x0800_000C
+            0xE52D_E004, // PUSH {LR}
+            0xEB00_0000, // BL 0x0800_001C
+            0xEAFF_FFFE, // B 0x0800_0018
+            0xEAFF_FFFE, // B 0x0800_001C
+        ]);
+        gg.c.debugger.running = true;
+        for _ in 0..20 {
+            gg.advance();
+        }
+        assert_eq!(gg.stack_trace(), [0x0800_001C, 0x0800_0018, 0x0800_000C]);
+    }
+
+    #[test]
+    fn load_memory_region_round_trip() {
+        let mut gg = arm_rom(&[0xEAFF_FFFE]); // B 0x0800_0000
+                                              // Odd start and length, so both ends are written as bytes
+        let dump = (0..=0x40u8).collect::<Vec<_>>();
+        assert_eq!(gg.load_memory_region(0x0200_0001, &dump), dump.len());
+        let read = (0..dump.len() as u32)
+            .map(|i| gg.get_memory(0x0200_0001 + i, Width::Byte) as u8)
+            .collect::<Vec<_>>();
+        assert_eq!(read, dump);
+        assert_eq!(gg.get_memory(0x0200_0000, Width::Byte), 0);
+        assert_eq!(gg.get_memory(0x0200_0042, Width::Byte), 0);
+
+        // The save chip is skipped entirely
+        gg.cart.ram = vec![0xFF; 0x8000];
+        gg.cart.save_type = hw::cartridge::SaveType::Sram;
+        assert_eq!(gg.load_memory_region(0x0E00_0000, &dump), 0);
+        assert!(gg.cart.ram.iter().all(|b| *b == 0xFF));
+    }
+
+    /// A ROM that the save type with the given library ID is detected for.
+    fn save_rom(library: &str) -> Box<GameGirlAdv> {
+        let mut rom = vec![0; 0x400];
+        rom[..4].copy_from_slice(&0xEAFF_FFFEu32.to_le_bytes()); // B 0x0800_0000
+        rom[0x200..0x200 + library.len()].copy_from_slice(library.as_bytes());
+        let config = SystemConfig {
+            threaded_ppu: false,
+            ..SystemConfig::default()
+        };
+        GameGirlAdv::new(Some(rom), None, &config)
+    }
+
+    #[test]
+    fn memory_regions_follow_save_type() {
+        let save_region = |gg: &GameGirlAdv| {
+            let last = *gg.memory_regions().last().unwrap();
+            (last.name, last.start, last.len, last.writable)
+        };
+
+        let gg = save_rom("SRAM_V113");
+        assert_eq!(save_region(&gg), ("SRAM", 0xE00_0000, 0x8000, false));
+        let gg = save_rom("EEPROM_V124");
+        assert_eq!(save_region(&gg).0, "ROM");
+
+        let mut gg = save_rom("FLASH1M_V103");
+        gg.cart.ram[0] = 0x12;
+        gg.cart.ram[0x1_0000] = 0x34;
+        assert_eq!(
+            save_region(&gg),
+            ("Flash (bank 0)", 0xE00_0000, 0x1_0000, false)
+        );
+        assert_eq!(gg.get_memory(0xE00_0000, Width::Byte), 0x12);
+        for (addr, value) in [(0x5555, 0xAA), (0x2AAA, 0x55), (0x5555, 0xB0), (0, 1)] {
+            gg.set(0x0E00_0000 + addr, value as u8);
+        }
+        assert_eq!(save_region(&gg).0, "Flash (bank 1)");
+        assert_eq!(gg.get_memory(0xE00_0000, Width::Byte), 0x34);
+    }
+
+    #[test]
+    fn io_registers_show_written_values() {
+        let mut gg = arm_rom(&[0xEAFF_FFFE]); // B 0x0800_0000
+        gg.set(0x0400_0010, 0x0123u16); // BG0HOFS
+        gg.set(0x0400_004C, 0x2143u16); // MOSAIC
+        gg.set(0x0400_0132, 0x4005u16); // KEYCNT
+
+        let registers = gg.io_registers();
+        let find = |name: &str| registers.iter().find(|reg| reg.0 == name).unwrap().2;
+        assert_eq!(find("BG0HOFS (write-only)"), 0x0123);
+        assert_eq!(find("MOSAIC (write-only)"), 0x2143);
+        assert_eq!(find("KEYCNT"), 0x4005);
+        assert_eq!(find("DISPCNT"), gg.get_memory(0x0400_0000, Width::Halfword));
+        // Nothing was read as an unknown register
+        assert!(gg.c.debugger.diagnostic_events.lock().unwrap().is_empty());
+    }
+}
@@ -21,7 +21,7 @@ use common::{
 use modular_bitfield::{bitfield, specifiers::*};
 
 use crate::{
-    hw::{bios::BIOS, input::KeyControl},
+    hw::{bios::BIOS, gpio::Gpio, input::KeyControl},
     GameGirlAdv,
 };
 
@@ -75,7 +75,8 @@ pub struct Memory {
     pub keycnt: KeyControl,
     pub keys_prev: u16,
     pub waitcnt: WaitCnt,
-    /// Value to return when trying to read BIOS outside of it
+    /// Value to return when trying to read BIOS outside of it,
+    /// see `SystemConfig::gba_bios_protection`
     pub(crate) bios_value: u32,
 
     pub(crate) prefetch: Prefetch,
@@ -117,12 +118,14 @@ impl GameGirlAdv {
             // Cart save
             // EEPROM
             0x0D if T::WIDTH == 2 && self.cart.is_eeprom_at(addr) => {
-                T::from_u16(self.cart.read_ram_hword())
+                T::from_u16(self.cart.read_ram_hword(self.scheduler.now()))
             }
             // Flash / SRAM
             0x0E | 0x0F => {
                 // Reading [half]words causes the byte to be repeated
-                let byte = self.cart.read_ram_byte(addr_unaligned.us() & 0xFFFF);
+                let byte = self
+                    .cart
+                    .read_ram_byte(addr_unaligned.us() & 0xFFFF, self.scheduler.now());
                 match T::WIDTH {
                     1 => T::from_u8(byte),
                     2 => T::from_u16(hword(byte, byte)),
@@ -131,6 +134,13 @@ impl GameGirlAdv {
                 }
             }
 
+            // Cart GPIO
+            0x08 if self.cart.gpio.readable && Gpio::is_gpio_addr(addr) => {
+                let low = self.cart.gpio.read(addr);
+                let high = self.cart.gpio.read(addr + 2);
+                T::from_u32(word(low, high) >> ((addr_unaligned & 1) * 8))
+            }
+
             // Cart
             0x08..=0x0D if let Some(v) = self.cart.rom.try_get_exact(a & 0x1FF_FFFF) => v,
             // 1MB carts are special and wrap
@@ -219,16 +229,20 @@ impl GameGirlAdv {
             // VRAM with weird mirroring and byte write behavior
             0x05..=0x07 if T::WIDTH == 1 => {
                 let value = value.u8();
-                match addr {
-                    0x0500_0000..=0x0600_FFFF if !self.ppu.regs.is_bitmap_mode() => {
-                        self.set(addr & !1, hword(value, value))
-                    }
-                    0x0500_0000..=0x0601_3FFF => self.set(addr & !1, hword(value, value)),
-                    0x0602_0000..=0x06FF_FFFF if addr & 0x1_FFFF < 0x1_0000 => {
-                        // Only BG VRAM gets written to, OBJ VRAM is ignored
-                        self.set(addr & !1, hword(value, value));
-                    }
-                    _ => (), // Ignored
+                // OBJ VRAM starts later in bitmap modes, since the
+                // bitmap takes up more space. This also applies to mirrors.
+                let obj_start = if self.ppu.regs.is_bitmap_mode() {
+                    0x1_4000
+                } else {
+                    0x1_0000
+                };
+                match region {
+                    // Palette and BG VRAM: The byte is written to both
+                    // halves of the halfword
+                    0x05 => self.set(addr & !1, hword(value, value)),
+                    0x06 if addr & 0x1_FFFF < obj_start => self.set(addr & !1, hword(value, value)),
+                    // OBJ VRAM and OAM: Ignored
+                    _ => (),
                 };
             }
             0x05 => self.ppu.palette.set_wrap(a, value),
@@ -242,10 +256,32 @@ impl GameGirlAdv {
             }
             0x07 => self.ppu.oam.set_wrap(a, value),
 
+            // Cart GPIO
+            0x08 if Gpio::is_gpio_addr(addr) => {
+                let readable = self.cart.gpio.readable;
+                let value = if T::WIDTH == 1 {
+                    // Merge the byte into the rest of the register
+                    let shift = (addr_unaligned & 1) * 8;
+                    let old = self.cart.gpio.read(addr).u32();
+                    (old & !(0xFF << shift)) | (value.u32() << shift)
+                } else {
+                    value.u32() << ((addr_unaligned & 1) * 8)
+                };
+                self.cart.gpio.write(addr, value.u16(), &self.c.config);
+                if T::WIDTH == 4 {
+                    self.cart
+                        .gpio
+                        .write(addr + 2, (value >> 16).u16(), &self.c.config);
+                }
+                if readable != self.cart.gpio.readable {
+                    self.update_gpio_mapping();
+                }
+            }
+
             // Cart save
             // EEPROM
             0x0D if T::WIDTH == 2 && self.cart.is_eeprom_at(addr) => {
-                self.cart.write_ram_hword(value.u16());
+                self.cart.write_ram_hword(value.u16(), self.scheduler.now());
             }
             // Flash / SRAM
             0x0E | 0x0F => {
@@ -259,7 +295,10 @@ impl GameGirlAdv {
                     }
                     _ => unreachable!(),
                 };
-                self.cart.write_ram_byte(addr_unaligned.us() & 0xFFFF, byte);
+                // Hacks are applied after the cart was loaded
+                self.cart.atmel_flash = self.c.game_hacks.atmel_flash;
+                self.cart
+                    .write_ram_byte(addr_unaligned.us() & 0xFFFF, byte, self.scheduler.now());
             }
 
             _ => (),
@@ -271,10 +310,11 @@ impl GameGirlAdv {
             return T::from_u32(self.invalid_read::<false>(addr as u32));
         }
 
-        if self.cpur().pc() < 0x100_0000 {
+        if self.cpur().pc() < 0x100_0000 || !self.c.config.gba_bios_protection {
             self.memory.bios.get_wrap(addr)
         } else {
-            T::from_u32(self.memory.bios_value)
+            // Smaller reads get the part of the opcode at their offset
+            T::from_u32(self.memory.bios_value >> ((addr & 3) << 3))
         }
     }
 
@@ -298,8 +338,22 @@ impl GameGirlAdv {
             RO,
         );
 
-        if self.c.config.cached_interpreter {
-            self.cpu.cache.init();
+        if self.cart.gpio.readable {
+            self.update_gpio_mapping();
+        }
+
+        self.cpu.apply_optimizations(&self.c.config);
+        self.cart.write_latency = self.c.config.save_write_latency;
+    }
+
+    /// The GPIO registers are in the first ROM page, which needs to be
+    /// in the slow path while they are readable.
+    fn update_gpio_mapping(&mut self) {
+        let page = 0x800_0000..0x800_4000;
+        if self.cart.gpio.readable {
+            self.memory.pager.evict(page);
+        } else if self.cart.rom.len() >= 0x4000 {
+            self.memory.pager.map(&self.cart.rom[..0x4000], page, RO);
         }
     }
 
@@ -482,3 +536,101 @@ impl Default for Memory {
 }
 
 unsafe impl Send for Memory {}
+
+#[cfg(test)]
+mod tests {
+    use common::{numutil::ByteArrayExt, Core};
+
+    use crate::{hw::bios::BIOS, tests::arm_rom};
+
+    #[test]
+    fn unaligned_loads() {
+        let mut gg = arm_rom(&[
+            0xE3A0_0403, // MOV R0, #0x0300_0000
+            0xE590_1001, // LDR R1, [R0, #1]
+            0xE590_2002, // LDR R2, [R0, #2]
+            0xE590_3003, // LDR R3, [R0, #3]
+            0xE1D0_40B1, // LDRH R4, [R0, #1]
+            0xE1D0_50F3, // LDRSH R5, [R0, #3]
+            0xE280_6002, // ADD R6, R0, #2
+            0xE896_0080, // LDMIA R6, {R7}
+            0xE106_8099, // SWP R8, R9, [R6]
+            0xEAFF_FFFE, // B .
+        ]);
+        gg.c.debugger.running = true;
+        gg.set(0x0300_0000, 0x8877_6655u32);
+        for _ in 0..20 {
+            gg.advance();
+        }
+
+        // LDR and SWP rotate the aligned word
+        assert_eq!(gg.cpu.registers[1], 0x5588_7766);
+        assert_eq!(gg.cpu.registers[2], 0x6655_8877);
+        assert_eq!(gg.cpu.registers[3], 0x7766_5588);
+        assert_eq!(gg.cpu.registers[8], 0x6655_8877);
+        // LDRH rotates the aligned halfword, LDRSH sign-extends the byte
+        assert_eq!(gg.cpu.registers[4], 0x5500_0066);
+        assert_eq!(gg.cpu.registers[5], 0xFFFF_FF88);
+        // LDM does not rotate
+        assert_eq!(gg.cpu.registers[7], 0x8877_6655);
+        // Stores are force-aligned
+        assert_eq!(gg.get::<u32>(0x0300_0000), 0);
+    }
+
+    #[test]
+    fn video_byte_writes() {
+        let mut gg = arm_rom(&[0xEAFF_FFFE]); // B 0x0800_0000
+                                              // Palette and BG VRAM duplicate the byte, OBJ VRAM and OAM ignore it
+        gg.set(0x0500_0011, 0xABu8);
+        gg.set(0x0600_0001, 0x12u8);
+        gg.set(0x0602_0002, 0x34u8);
+        gg.set(0x0601_0000, 0x56u8);
+        gg.set(0x0603_0002, 0x56u8);
+        gg.set(0x0700_0000, 0x78u8);
+        assert_eq!(gg.get::<u16>(0x0500_0010), 0xABAB);
+        assert_eq!(gg.get::<u16>(0x0600_0000), 0x1212);
+        assert_eq!(gg.get::<u16>(0x0600_0002), 0x3434);
+        assert_eq!(gg.get::<u32>(0x0601_0000), 0);
+        assert_eq!(gg.get::<u16>(0x0700_0000), 0);
+
+        // In bitmap modes, OBJ VRAM starts later, also in mirrors
+        gg.set(0x0400_0000, 0x0003u16);
+        gg.set(0x0601_0000, 0x56u8);
+        gg.set(0x0603_0002, 0x78u8);
+        gg.set(0x0601_4000, 0x9Au8);
+        gg.set(0x0603_4000, 0x9Au8);
+        assert_eq!(gg.get::<u32>(0x0601_0000), 0x7878_5656);
+        assert_eq!(gg.get::<u16>(0x0601_4000), 0);
+    }
+
+    /// Read the BIOS from game code as a word, byte at 1 and halfword at 2.
+    fn read_bios(protection: bool) -> [u32; 3] {
+        let mut gg = arm_rom(&[
+            0xE3A0_0000, // MOV R0, #0
+            0xE590_1000, // LDR R1, [R0]
+            0xE5D0_2001, // LDRB R2, [R0, #1]
+            0xE1D0_30B2, // LDRH R3, [R0, #2]
+            0xEAFF_FFFE, // B .
+        ]);
+        gg.c.debugger.running = true;
+        gg.c.config.gba_bios_protection = protection;
+        for _ in 0..10 {
+            gg.advance();
+        }
+        [1, 2, 3].map(|reg| gg.cpu.registers[reg])
+    }
+
+    #[test]
+    fn bios_protection() {
+        // The last opcode fetched from the BIOS, 0xE129_F000 after boot
+        assert_eq!(read_bios(true), [0xE129_F000, 0xF0, 0xE129]);
+        assert_eq!(
+            read_bios(false),
+            [
+                BIOS.get_exact::<u32>(0),
+                BIOS[1].into(),
+                BIOS.get_exact::<u16>(2).into()
+            ]
+        );
+    }
+}
@@ -16,11 +16,20 @@
 
 pub mod registers;
 mod render;
+mod tile_map;
 
 use std::sync::Arc;
 
-use arm_cpu::{Cpu, Interrupt};
-use common::{common::video::FrameBuffer, numutil::NumExt, Colour};
+use arm_cpu::{interface::ArmSystem, Cpu, Interrupt};
+use common::{
+    common::{
+        debugger::SpriteInfo,
+        options::{ColourExpansion, GameHacks},
+        video::{self, FrameBuffer},
+    },
+    numutil::{ByteArrayExt, NumExt},
+    Colour, Core,
+};
 use registers::*;
 use render::{PpuRender, PpuRendererKind};
 
@@ -59,6 +68,11 @@ pub struct Ppu {
     pub vram: Arc<[u8]>,
     pub oam: Arc<[u8]>,
 
+    /// Layers hidden for debugging: Bits 0-3 are the backgrounds,
+    /// bit 4 the objects.
+    #[cfg_attr(feature = "serde", serde(skip, default))]
+    pub hidden_layers: u8,
+
     // Renderer
     #[cfg_attr(feature = "serde", serde(skip, default))]
     render: PpuRendererKind,
@@ -69,7 +83,7 @@ impl Ppu {
         let (next_event, cycles) = match event {
             PpuEvent::HblankStart => {
                 if gg.c.video_buffer.should_render_this_frame() {
-                    gg.ppu.render_line();
+                    gg.ppu.render_line(gg.c.game_hacks);
                 }
 
                 Self::maybe_interrupt(gg, Interrupt::HBlank);
@@ -91,8 +105,10 @@ impl Ppu {
                         gg.ppu.regs.dispstat.set_in_vblank(true);
                         Self::maybe_interrupt(gg, Interrupt::VBlank);
                         Dmas::update_all(gg, Reason::VBlank);
+                        gg.apply_cheats();
                     }
-                    // VBlank flag gets set one scanline early
+                    // VBlank flag gets cleared one scanline early, on the
+                    // last line of the frame
                     _ if vcount == (VBLANK_END - 1) => {
                         gg.ppu.regs.dispstat.set_in_vblank(false);
                     }
@@ -100,9 +116,14 @@ impl Ppu {
                         gg.ppu.regs.vcount = 0;
                         gg.ppu.end_frame();
                         if gg.c.video_buffer.should_render_this_frame() {
-                            gg.ppu.push_output(&mut gg.c.video_buffer);
+                            // LCD gamma expansion already simulates the LCD,
+                            // correcting on top of it would apply it twice
+                            let correction = gg.c.config.gba_colour_correction
+                                && gg.c.config.gba_colour_expansion == ColourExpansion::Simple;
+                            gg.ppu.push_output(&mut gg.c.video_buffer, correction);
                         }
                         gg.c.video_buffer.start_next_frame();
+                        gg.scheduler.end_frame();
                     }
                     _ => (),
                 }
@@ -122,20 +143,76 @@ impl Ppu {
             .schedule(AdvEvent::PpuEvent(next_event), cycles - late_by);
     }
 
+    /// DISPSTAT was written. The VCOUNT match flag follows the comparison
+    /// immediately when the target line changes, and a new match raises
+    /// the IRQ just like one at the start of a line.
+    pub fn dispstat_written(gg: &mut GameGirlAdv) {
+        let was_match = gg.ppu.regs.dispstat.vcounter_match();
+        let vcount_match = gg.ppu.regs.vcount.u8() == gg.ppu.regs.dispstat.vcount();
+        gg.ppu.regs.dispstat.set_vcounter_match(vcount_match);
+        if vcount_match && !was_match {
+            Self::maybe_interrupt(gg, Interrupt::VCounter);
+        }
+    }
+
+    /// DISPCNT was written. Switching to a prohibited background mode is
+    /// reported here once, instead of on every line rendered in it.
+    /// Pauses the system if `Debugger::pause_on_invalid` is set.
+    pub fn dispcnt_written(gg: &mut GameGirlAdv, prev: BackgroundMode) {
+        let mode = gg.ppu.regs.dispcnt.bg_mode();
+        if mode as u8 != prev as u8
+            && matches!(
+                mode,
+                BackgroundMode::ProhibitedA | BackgroundMode::ProhibitedB
+            )
+        {
+            let dump = gg.c.debugger.pause_on_invalid.then(|| gg.state_dump());
+            gg.c.debugger.invalid_state(
+                "invalid-ppu-mode",
+                format!("Invalid PPU mode {mode:?}"),
+                || dump.unwrap_or_default(),
+            );
+        }
+    }
+
+    /// If the given layer (BG0-3, then 4 for OBJ) is shown, which is the case
+    /// unless it was hidden for debugging. Independent of the game's DISPCNT.
+    pub fn layer_enabled(&self, layer: usize) -> bool {
+        !self.hidden_layers.is_bit(layer as u16)
+    }
+
+    /// Show or hide the given layer (BG0-3, then 4 for OBJ) for debugging.
+    /// Like [Self::hidden_layers], this is not part of savestates.
+    pub fn set_layer_enabled(&mut self, layer: usize, on: bool) {
+        self.hidden_layers = self.hidden_layers.set_bit(layer as u16, !on);
+    }
+
     fn maybe_interrupt(gg: &mut GameGirlAdv, int: Interrupt) {
         if gg.ppu.regs.dispstat.irq_enables().is_bit(int as u16) {
             Cpu::request_interrupt(gg, int);
         }
     }
 
-    fn render_line(&mut self) {
+    fn render_line(&mut self, hacks: GameHacks) {
         if self.regs.vcount >= HEIGHT.u16() {
             return;
         }
 
-        self.render.do_line(self.regs.clone());
+        let mut regs = self.regs.clone();
+        let hidden = self.hidden_layers | hacks.hidden_layers;
+        if hidden != 0 {
+            let bg_en = regs.dispcnt.bg_en() & !hidden;
+            let obj_en = regs.dispcnt.obj_en() && !hidden.is_bit(4);
+            regs.dispcnt.set_bg_en(bg_en);
+            regs.dispcnt.set_obj_en(obj_en);
+        }
+        if hacks.disable_blending {
+            regs.bldcnt.set_special_effect(SpecialEffect::None);
+        }
+        self.render.do_line(regs);
 
-        // Update affines
+        // Update affines. This also happens during forced blank, so that
+        // affine backgrounds continue at the right position once it ends.
         for bg in 2..4 {
             if self.regs.bg_enabled(bg.u16()) {
                 self.regs.bg_scale[bg - 2].latched.0 += self.regs.bg_scale[bg - 2].pb as i32;
@@ -150,8 +227,13 @@ impl Ppu {
         self.regs.bg_scale[1].latch();
     }
 
-    fn push_output(&mut self, buf: &mut FrameBuffer) {
-        if let Some(last_frame) = self.render.get_last() {
+    fn push_output(&mut self, buf: &mut FrameBuffer, colour_correction: bool) {
+        if let Some(mut last_frame) = self.render.get_last() {
+            if colour_correction {
+                for pixel in &mut last_frame {
+                    *pixel = video::gba_lcd_correction(*pixel);
+                }
+            }
             buf.push(last_frame);
         }
     }
@@ -161,9 +243,65 @@ impl Ppu {
             Arc::clone(&gg.ppu.palette),
             Arc::clone(&gg.ppu.vram),
             Arc::clone(&gg.ppu.oam),
+            gg.c.config.gba_colour_expansion,
         );
         gg.ppu.render = PpuRendererKind::new(render, gg.c.config.threaded_ppu);
     }
+
+    /// Get all 128 objects in OAM, for debugging.
+    pub fn sprites(&self) -> Vec<SpriteInfo> {
+        (0..128)
+            .map(|idx| {
+                let [attr0, attr1, attr2] = self.obj_attrs(idx);
+                let y = attr0 & 0xFF;
+                let x = attr1 & 0x1FF;
+                SpriteInfo {
+                    // Both wrap around, past the right/bottom edge of the screen
+                    // objects start showing up on the left/top instead
+                    x: if x >= 0x100 {
+                        x as i16 - 0x200
+                    } else {
+                        x as i16
+                    },
+                    y: if y >= HEIGHT as u16 {
+                        y as i16 - 0x100
+                    } else {
+                        y as i16
+                    },
+                    tile: attr2 & 0x3FF,
+                    x_flip: attr1.is_bit(12),
+                    y_flip: attr1.is_bit(13),
+                    priority: attr2.bits(10, 2).u8(),
+                    palette: attr2.bits(12, 4).u8(),
+                }
+            })
+            .collect()
+    }
+
+    /// Write an object back to OAM, for debugging.
+    pub fn set_sprite(&mut self, idx: usize, sprite: SpriteInfo) {
+        if idx >= 128 {
+            return;
+        }
+        let [attr0, attr1, _] = self.obj_attrs(idx);
+        let attr0 = (attr0 & !0xFF) | (sprite.y as u16 & 0xFF);
+        let attr1 = ((attr1 & !0x31FF) | (sprite.x as u16 & 0x1FF))
+            .set_bit(12, sprite.x_flip)
+            .set_bit(13, sprite.y_flip);
+        let attr2 = (sprite.tile & 0x3FF)
+            | ((sprite.priority.u16() & 3) << 10)
+            | ((sprite.palette.u16() & 0xF) << 12);
+
+        let base = idx * 8;
+        self.oam.set_exact(base, attr0);
+        self.oam.set_exact(base + 2, attr1);
+        self.oam.set_exact(base + 4, attr2);
+    }
+
+    fn obj_attrs(&self, idx: usize) -> [u16; 3] {
+        let base = idx * 8;
+        [0, 2, 4].map(|offs| self.oam.get_exact(base + offs))
+    }
 }
 
 impl Default for Ppu {
@@ -175,7 +313,469 @@ impl Default for Ppu {
             vram: Arc::new([0; 96 * KB]),
             oam: Arc::new([0; KB]),
 
+            hidden_layers: 0,
             render: PpuRendererKind::Invalid,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use common::common::options::SystemConfig;
+
+    use super::*;
+    use crate::tests::arm_rom;
+
+    fn next_frame(gg: &mut GameGirlAdv) -> Vec<Colour> {
+        loop {
+            gg.advance();
+            if let Some(frame) = gg.c.video_buffer.pop() {
+                return frame;
+            }
+        }
+    }
+
+    #[test]
+    fn prohibited_mode_shows_backdrop() {
+        let mut gg = arm_rom(&[0xEAFF_FFFE]); // B 0x0800_0000
+        gg.c.debugger.running = true;
+        // Red backdrop, white mode 3 bitmap
+        gg.set(0x0500_0000, 0x001Fu16);
+        for addr in (0x0600_0000..0x0601_2C00).step_by(2) {
+            gg.set(addr, 0x7FFFu16);
+        }
+        gg.set(0x0400_0000, 0x0403u16);
+        assert!(next_frame(&mut gg).iter().all(|px| px == &[0xFF; 4]));
+
+        // The bitmap is not shown, even though BG2 is still enabled
+        gg.set(0x0400_0000, 0x0406u16);
+        next_frame(&mut gg);
+        assert!(next_frame(&mut gg)
+            .iter()
+            .all(|px| px == &[0xFF, 0, 0, 0xFF]));
+    }
+
+    #[test]
+    fn colour_correction_applies_live() {
+        let mut gg = arm_rom(&[0xEAFF_FFFE]); // B 0x0800_0000
+        gg.c.debugger.running = true;
+        // White backdrop, no layers
+        gg.set(0x0500_0000, 0x7FFFu16);
+        gg.set(0x0400_0000, 0x0000u16);
+        assert!(next_frame(&mut gg).iter().all(|px| px == &[0xFF; 4]));
+
+        let mut config = SystemConfig {
+            gba_colour_correction: true,
+            ..SystemConfig::default()
+        };
+        gg.apply_config(&config);
+        let corrected = video::gba_lcd_correction([0xFF; 4]);
+        assert!(next_frame(&mut gg).iter().all(|px| px == &corrected));
+
+        config.gba_colour_correction = false;
+        gg.apply_config(&config);
+        assert!(next_frame(&mut gg).iter().all(|px| px == &[0xFF; 4]));
+    }
+
+    fn advance_to_line(gg: &mut GameGirlAdv, line: u16) {
+        while gg.ppu.regs.vcount != line {
+            gg.advance();
+        }
+    }
+
+    #[test]
+    fn forced_blank_mid_frame() {
+        let mut gg = arm_rom(&[0xEAFF_FFFE]); // B 0x0800_0000
+        gg.c.debugger.running = true;
+        // Mode 3 bitmap with a different colour on every line, drawn through
+        // the affine background
+        for y in 0..HEIGHT as u32 {
+            for x in 0..WIDTH as u32 {
+                gg.set(0x0600_0000 + (y * WIDTH as u32 + x) * 2, y.u16());
+            }
+        }
+        gg.set(0x0400_0020, 0x0100u16); // BG2PA
+        gg.set(0x0400_0026, 0x0100u16); // BG2PD
+        gg.set(0x0400_0000, 0x0403u16);
+        next_frame(&mut gg);
+        let reference = next_frame(&mut gg);
+
+        advance_to_line(&mut gg, 40);
+        gg.set(0x0400_0000, 0x0483u16);
+        advance_to_line(&mut gg, 60);
+        gg.set(0x0400_0000, 0x0403u16);
+        let frame = next_frame(&mut gg);
+
+        for (y, (line, reference)) in frame.chunks(WIDTH).zip(reference.chunks(WIDTH)).enumerate() {
+            if (40..60).contains(&y) {
+                assert!(line.iter().all(|px| px == &[0xFF; 4]), "line {y}");
+            } else {
+                // Lines after the blank continue where they should
+                assert_eq!(line, reference, "line {y}");
+                assert_ne!(line[0], [0xFF; 4], "line {y}");
+            }
+        }
+    }
+
+    #[test]
+    fn dispstat_vblank_timing() {
+        let mut gg = arm_rom(&[0xEAFF_FFFE]); // B 0x0800_0000
+        gg.c.debugger.running = true;
+        let in_vblank = |gg: &mut GameGirlAdv| gg.get::<u16>(0x0400_0004) & 1 != 0;
+
+        for (line, vblank) in [(159, false), (160, true), (226, true), (227, false)] {
+            advance_to_line(&mut gg, line);
+            assert_eq!(in_vblank(&mut gg), vblank, "line {line}");
+        }
+        // Line 227 is the last one, VCOUNT never reads 228
+        while gg.ppu.regs.vcount == 227 {
+            gg.advance();
+        }
+        assert_eq!(gg.get::<u16>(0x0400_0006), 0);
+        assert!(!in_vblank(&mut gg));
+    }
+
+    #[test]
+    fn vcount_match_on_dispstat_write() {
+        let mut gg = arm_rom(&[0xEAFF_FFFE]); // B 0x0800_0000
+        gg.c.debugger.running = true;
+        advance_to_line(&mut gg, 50);
+        gg.set(0x0400_0202, 0xFFFFu16);
+
+        // Target line 50 with the IRQ enabled: Matches right away
+        gg.set(0x0400_0004, 0x3220u16);
+        assert_eq!(gg.get::<u16>(0x0400_0004) & 4, 4);
+        assert_eq!(gg.cpu.if_ & 4, 4);
+
+        // Another target line clears the flag again
+        gg.set(0x0400_0202, 0xFFFFu16);
+        gg.set(0x0400_0004, 0x3320u16);
+        assert_eq!(gg.get::<u16>(0x0400_0004) & 4, 0);
+        assert_eq!(gg.cpu.if_ & 4, 0);
+
+        // Writing while already matching does not raise the IRQ again
+        advance_to_line(&mut gg, 51);
+        assert_eq!(gg.cpu.if_ & 4, 4);
+        gg.set(0x0400_0202, 0xFFFFu16);
+        gg.set(0x0400_0004, 0x3320u16);
+        assert_eq!(gg.get::<u16>(0x0400_0004) & 4, 4);
+        assert_eq!(gg.cpu.if_ & 4, 0);
+    }
+
+    #[test]
+    fn mosaic_applies_before_blending() {
+        let mut gg = arm_rom(&[0xEAFF_FFFE]); // B 0x0800_0000
+        gg.c.debugger.running = true;
+        // BG0 colours 1-8 are shades of red, BG1 colours 9-12 of blue
+        for i in 1..=8u16 {
+            gg.set(0x0500_0000 + i.u32() * 2, i * 3);
+        }
+        for i in 0..4u16 {
+            gg.set(0x0500_0012 + i.u32() * 2, (7 + i * 8) << 10);
+        }
+        // Tile 1 uses colour x + 1 on column x, tile 2 colour 9 + x % 4
+        for row in 0..8 {
+            gg.set(0x0600_0020 + row * 4, 0x8765_4321u32);
+            gg.set(0x0600_0040 + row * 4, 0xCBA9_CBA9u32);
+        }
+        for entry in 0..0x400 {
+            gg.set(0x0600_4000 + entry * 2, 1u16);
+            gg.set(0x0600_4800 + entry * 2, 2u16);
+        }
+        gg.set(0x0400_0008, 0x0840u16); // BG0: Mosaic, map at block 8
+        gg.set(0x0400_000A, 0x0901u16); // BG1: Map at block 9, behind BG0
+        gg.set(0x0400_004C, 0x0003u16); // 4 pixel wide mosaic
+        gg.set(0x0400_0050, 0x0241u16); // Blend BG0 onto BG1...
+        gg.set(0x0400_0052, 0x0808u16); // ...at 50% each
+        gg.set(0x0400_0000, 0x0300u16);
+        next_frame(&mut gg);
+        let frame = next_frame(&mut gg);
+
+        // BG0 is mosaicked before being blended with the unmosaicked BG1,
+        // so red changes every 4 pixels while blue changes every pixel
+        let expand = |c: u16| ((c << 3) | (c >> 2)) as u8;
+        for (x, px) in frame[..WIDTH].iter().enumerate() {
+            let bg0 = 3 * ((x & !3) % 8 + 1) as u16;
+            let bg1 = 7 + 8 * (x % 4) as u16;
+            let expected = [expand((bg0 * 8) >> 4), 0, expand((bg1 * 8) >> 4), 255];
+            assert_eq!(px, &expected, "x {x}");
+        }
+    }
+
+    #[test]
+    fn mosaic_bitmap_matches_reference() {
+        let mut gg = arm_rom(&[0xEAFF_FFFE]); // B 0x0800_0000
+        gg.c.debugger.running = true;
+        // Mode 3 bitmap with a different colour on every pixel
+        for y in 0..HEIGHT as u32 {
+            for x in 0..WIDTH as u32 {
+                let colour = (x & 31) | ((y & 31) << 5) | ((((x >> 5) + (y >> 5) * 8) & 31) << 10);
+                gg.set(0x0600_0000 + (y * WIDTH as u32 + x) * 2, colour.u16());
+            }
+        }
+        gg.set(0x0400_000C, 0x0040u16); // BG2: Mosaic
+        gg.set(0x0400_0020, 0x0100u16); // BG2PA
+        gg.set(0x0400_0026, 0x0100u16); // BG2PD
+        gg.set(0x0400_004C, 0x0033u16); // 4x4 mosaic
+        gg.set(0x0400_0000, 0x0403u16);
+        next_frame(&mut gg);
+        let frame = next_frame(&mut gg);
+
+        let png = include_bytes!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/testdata/mosaic_mode3.png"
+        ));
+        let mut reader = png::Decoder::new(&png[..]).read_info().unwrap();
+        let mut reference = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut reference).unwrap();
+        for (i, (px, reference)) in frame.iter().zip(reference.chunks(4)).enumerate() {
+            assert_eq!(&px[..], reference, "x {} y {}", i % WIDTH, i / WIDTH);
+        }
+    }
+
+    #[test]
+    fn window_wraps_vertically() {
+        let mut gg = arm_rom(&[0xEAFF_FFFE]); // B 0x0800_0000
+        gg.c.debugger.running = true;
+        // Red backdrop, white mode 3 bitmap only shown inside window 0
+        gg.set(0x0500_0000, 0x001Fu16);
+        for addr in (0x0600_0000..0x0601_2C00).step_by(2) {
+            gg.set(addr, 0x7FFFu16);
+        }
+        gg.set(0x0400_0040, 0x00F0u16); // WIN0H: Full width
+        gg.set(0x0400_0044, 0x7828u16); // WIN0V: Top 120, bottom 40
+        gg.set(0x0400_0048, 0x0004u16); // WININ: BG2 in window 0
+        gg.set(0x0400_004A, 0x0000u16); // WINOUT: Nothing outside
+        gg.set(0x0400_0000, 0x2403u16);
+        next_frame(&mut gg);
+        let frame = next_frame(&mut gg);
+
+        for (y, line) in frame.chunks(WIDTH).enumerate() {
+            let expected = if !(40..120).contains(&y) {
+                [0xFF; 4]
+            } else {
+                [0xFF, 0, 0, 0xFF]
+            };
+            assert!(line.iter().all(|px| px == &expected), "line {y}");
+        }
+    }
+
+    #[test]
+    fn equal_priority_layers() {
+        let mut gg = arm_rom(&[0xEAFF_FFFE]); // B 0x0800_0000
+        gg.c.debugger.running = true;
+        // BG0 is red on the left half, BG1 green everywhere, objects blue
+        gg.set(0x0500_0002, 0x001Fu16);
+        gg.set(0x0500_0022, 0x03E0u16);
+        gg.set(0x0500_0202, 0x7C00u16);
+        for row in 0..8 {
+            gg.set(0x0600_0020 + row * 4, 0x1111_1111u32);
+            gg.set(0x0601_0000 + row * 4, 0x1111_1111u32);
+        }
+        for entry in 0..0x400 {
+            gg.set(0x0600_4000 + entry * 2, u16::from(entry % 32 < 16));
+            gg.set(0x0600_4800 + entry * 2, 0x1001u16);
+        }
+        gg.set(0x0400_0008, 0x0800u16); // BG0: Map at block 8
+        gg.set(0x0400_000A, 0x0900u16); // BG1: Map at block 9, same priority
+
+        for obj in 0..128 {
+            gg.set(0x0700_0000 + obj * 8, 0x0200u16);
+        }
+        let objs = [
+            (0x0000u16, 0u16, 0x0000u16), // Same priority as the backgrounds
+            (0x0400, 16, 0x0000),         // Semi-transparent over BG0
+            (0x0000, 32, 0x0400),         // Behind the backgrounds
+            (0x0400, 200, 0x0000),        // Semi-transparent over BG1
+        ];
+        for (i, (attr0, attr1, attr2)) in objs.into_iter().enumerate() {
+            let base = 0x0700_0000 + i as u32 * 8;
+            gg.set(base, attr0);
+            gg.set(base + 2, attr1);
+            gg.set(base + 4, attr2);
+        }
+        // Only BG0 is a second target; no first targets and no effect.
+        // Semi-transparent objects blend regardless
+        gg.set(0x0400_0050, 0x0100u16);
+        gg.set(0x0400_0052, 0x0808u16);
+        gg.set(0x0400_0000, 0x1340u16);
+        next_frame(&mut gg);
+        let frame = next_frame(&mut gg);
+
+        let expand = |c: u16| ((c << 3) | (c >> 2)) as u8;
+        let red = [0xFF, 0, 0, 0xFF];
+        let green = [0, 0xFF, 0, 0xFF];
+        let blue = [0, 0, 0xFF, 0xFF];
+        let blend = [expand(15), 0, expand(15), 0xFF];
+        for (x, px) in frame[4 * WIDTH..5 * WIDTH].iter().enumerate() {
+            let expected = match x {
+                0..8 | 200..208 => blue,
+                16..24 => blend,
+                0..128 => red,
+                _ => green,
+            };
+            assert_eq!(px, &expected, "x {x}");
+        }
+    }
+
+    /// Set up object 0 as an 8x8 256-colour affine sprite with a different
+    /// colour on every texel, using affine group 0 with the given
+    /// parameters. All other objects are hidden, the backdrop is blue.
+    fn affine_sprite(gg: &mut GameGirlAdv, attr0: u16, attr1: u16, tile: u16, params: [u16; 4]) {
+        gg.set(0x0500_0000, 0x7C00u16);
+        for idx in 1..=64u16 {
+            gg.set(0x0500_0200 + idx.u32() * 2, idx);
+        }
+        for texel in (0..64u16).step_by(2) {
+            let pair = (texel + 1) | ((texel + 2) << 8);
+            gg.set(0x0601_0000 + tile.u32() * 0x20 + texel.u32(), pair);
+        }
+        for obj in 1..128 {
+            gg.set(0x0700_0000 + obj * 8, 0x0200u16);
+        }
+        gg.set(0x0700_0000, attr0);
+        gg.set(0x0700_0002, attr1);
+        gg.set(0x0700_0004, tile);
+        for (i, param) in params.into_iter().enumerate() {
+            gg.set(0x0700_0006 + i as u32 * 8, param);
+        }
+    }
+
+    /// Colour of the given texel of a sprite made by [affine_sprite], or
+    /// the backdrop if it is outside of the sprite.
+    fn affine_texel(tx: i32, ty: i32) -> Colour {
+        if (0..8).contains(&tx) && (0..8).contains(&ty) {
+            let c = (1 + tx + ty * 8) as u16;
+            let expand = |c: u16| ((c << 3) | (c >> 2)) as u8;
+            [expand(c & 0x1F), expand(c >> 5), 0, 0xFF]
+        } else {
+            [0, 0, 0xFF, 0xFF]
+        }
+    }
+
+    #[test]
+    fn affine_sprite_rotated_double_size() {
+        let mut gg = arm_rom(&[0xEAFF_FFFE]); // B 0x0800_0000
+        gg.c.debugger.running = true;
+        // Y 40, X 40, double size; rotated by 90 degrees: tex_x = iy, tex_y = -ix
+        affine_sprite(&mut gg, 0x2328, 0x0028, 0, [0x0000, 0x0100, 0xFF00, 0x0000]);
+        gg.set(0x0400_0000, 0x1040u16);
+        next_frame(&mut gg);
+        let frame = next_frame(&mut gg);
+
+        for y in 32..64 {
+            for x in 32..64 {
+                // Double size: The 8x8 sprite sits in the center of a 16x16
+                // box at 40,40
+                let (ix, iy) = (x - 48, y - 48);
+                let inside_box = (40..56).contains(&x) && (40..56).contains(&y);
+                let expected = if inside_box {
+                    affine_texel(iy + 4, -ix + 4)
+                } else {
+                    affine_texel(-1, -1)
+                };
+                let px = frame[y as usize * WIDTH + x as usize];
+                assert_eq!(px, expected, "x {x} y {y}");
+            }
+        }
+    }
+
+    #[test]
+    fn affine_sprite_rotated_mosaic() {
+        let mut gg = arm_rom(&[0xEAFF_FFFE]); // B 0x0800_0000
+        gg.c.debugger.running = true;
+        // Y 40, X 40, mosaic; rotated by 90 degrees: tex_x = iy, tex_y = -ix
+        affine_sprite(&mut gg, 0x3128, 0x0028, 0, [0x0000, 0x0100, 0xFF00, 0x0000]);
+        // Object mosaic of 2 pixels horizontally, 4 lines vertically
+        gg.set(0x0400_004C, 0x3100u16);
+        gg.set(0x0400_0000, 0x1040u16);
+        next_frame(&mut gg);
+        let frame = next_frame(&mut gg);
+
+        for y in 32..56 {
+            for x in 32..56 {
+                // Mosaic snaps the position inside the sprite to the top
+                // left of its block before it gets rotated
+                let (sx, sy) = (x - 40, y - 40);
+                let inside_box = (0..8).contains(&sx) && (0..8).contains(&sy);
+                let expected = if inside_box {
+                    let (ix, iy) = (sx - sx % 2 - 4, sy - sy % 4 - 4);
+                    affine_texel(iy + 4, -ix + 4)
+                } else {
+                    affine_texel(-1, -1)
+                };
+                let px = frame[y as usize * WIDTH + x as usize];
+                assert_eq!(px, expected, "x {x} y {y}");
+            }
+        }
+    }
+
+    #[test]
+    fn affine_sprite_wraps_vertically() {
+        let mut gg = arm_rom(&[0xEAFF_FFFE]); // B 0x0800_0000
+        gg.c.debugger.running = true;
+        // Y 248, X 100, double size; rotated by 45 degrees
+        affine_sprite(&mut gg, 0x23F8, 0x0064, 0, [0x00B5, 0x00B5, 0xFF4B, 0x00B5]);
+        gg.set(0x0400_0000, 0x1040u16);
+        next_frame(&mut gg);
+        let frame = next_frame(&mut gg);
+
+        for y in 0..16 {
+            for x in 92..124 {
+                // The 16x16 box starts at line 248 and wraps around to the
+                // top of the screen, the lower half of it is visible
+                let (ix, iy) = (x - 108, y);
+                let inside_box = y < 8 && (100..116).contains(&x);
+                let expected = if inside_box {
+                    let tx = ((181 * ix + 181 * iy) >> 8) + 4;
+                    let ty = ((-181 * ix + 181 * iy) >> 8) + 4;
+                    affine_texel(tx, ty)
+                } else {
+                    affine_texel(-1, -1)
+                };
+                let px = frame[y as usize * WIDTH + x as usize];
+                assert_eq!(px, expected, "x {x} y {y}");
+            }
+        }
+    }
+
+    #[test]
+    fn affine_sprite_bitmap_mode_tiles() {
+        // In bitmap modes, the lower half of sprite VRAM holds the bitmap
+        // and sprites using tiles from it are not drawn
+        for (tile, shown) in [(0, false), (512, true)] {
+            let mut gg = arm_rom(&[0xEAFF_FFFE]); // B 0x0800_0000
+            gg.c.debugger.running = true;
+            affine_sprite(&mut gg, 0x2128, 0x0028, tile, [0x0100, 0, 0, 0x0100]);
+            // Mode 3 with BG2 on, the bitmap itself is all black
+            gg.set(0x0400_0000, 0x1443u16);
+            next_frame(&mut gg);
+            let frame = next_frame(&mut gg);
+            let px = frame[44 * WIDTH + 44];
+            assert_eq!(px == affine_texel(4, 4), shown, "tile {tile}");
+        }
+    }
+
+    #[test]
+    fn prohibited_mode_pauses_once() {
+        let mut gg = arm_rom(&[0xEAFF_FFFE]); // B 0x0800_0000
+        gg.c.debugger.pause_on_invalid = true;
+        gg.c.debugger.running = true;
+        let events = |gg: &GameGirlAdv| gg.c.debugger.diagnostic_events.lock().unwrap().len();
+
+        gg.set(0x0400_0000, 0x0006u16);
+        assert!(!gg.c.debugger.running);
+        assert_eq!(events(&gg), 1);
+        let event = gg.c.debugger.diagnostic_events.lock().unwrap()[0]
+            .event
+            .clone();
+        assert!(event.contains("08000000: EAFFFFFE"), "{event}");
+
+        // Only changes to a prohibited mode are reported
+        gg.c.debugger.running = true;
+        gg.set(0x0400_0000, 0x0106u16);
+        assert!(gg.c.debugger.running);
+        gg.set(0x0400_0000, 0x0007u16);
+        assert_eq!(events(&gg), 2);
+    }
+}
@@ -75,6 +75,41 @@ impl PpuRegisters {
         (0, 0, 5)
     }
 
+    /// Get the value last written to a write-only register, for the
+    /// debugger. `None` if the register is readable or unknown.
+    pub fn read_write_only(&self, a: u32) -> Option<u16> {
+        if matches!(a, 0x10..=0x1F) {
+            return Some(self.bg_offsets[(a.us() & 0xF) >> 1]);
+        }
+
+        for i in 0..2 {
+            let scale = &self.bg_scale[i.us()];
+            let value = match a.wrapping_sub(i * 0x10) {
+                BG2PA => scale.pa as u16,
+                BG2PB => scale.pb as u16,
+                BG2PC => scale.pc as u16,
+                BG2PD => scale.pd as u16,
+                BG2XL => scale.xl,
+                BG2XH => scale.xh,
+                BG2YL => scale.yl,
+                BG2YH => scale.yh,
+                _ => continue,
+            };
+            return Some(value);
+        }
+
+        let [win0, win1] = &self.windows;
+        Some(match a {
+            WIN0H => hword(win0.right, win0.left),
+            WIN1H => hword(win1.right, win1.left),
+            WIN0V => hword(win0.bottom, win0.top),
+            WIN1V => hword(win1.bottom, win1.top),
+            MOSAIC => self.mosaic.into(),
+            BLDY => self.bldy,
+            _ => return None,
+        })
+    }
+
     pub fn write(&mut self, a: u32, s8: IoSection<u8>, s16: IoSection<u16>) -> (u32, u32) {
         iow16!(a, DISPCNT, s16.apply_io(&mut self.dispcnt));
         iow16!(a, GREENSWAP, s16.apply_io(&mut self.greenswap));
@@ -339,19 +374,22 @@ impl Window {
 
     #[inline]
     pub fn bottom(&self) -> usize {
-        let top = self.top as usize;
-        let mut bottom = self.bottom as usize;
-        if bottom > WIDTH || bottom < top {
-            bottom = WIDTH;
-        }
-        bottom
+        self.bottom as usize
     }
 
+    /// If the given line is inside the window. The hardware turns the window
+    /// on at the top line and off at the bottom line, so if bottom is above
+    /// top, the window wraps around: it covers everything from top to the
+    /// end of the screen and from the start of the screen to bottom.
     #[inline]
     pub fn contains_y(&self, y: usize) -> bool {
         let top = self.top();
         let bottom = self.bottom();
-        y >= top && y < bottom
+        if top <= bottom {
+            y >= top && y < bottom
+        } else {
+            y >= top || y < bottom
+        }
     }
 }
 
@@ -15,7 +15,7 @@ use std::{
     thread,
 };
 
-use common::{numutil::NumExt, Colour};
+use common::{common::options::ColourExpansion, numutil::NumExt, Colour};
 use objects::ObjPixel;
 
 use super::{BackgroundMode, PpuRegisters, HEIGHT, TRANS, WIDTH};
@@ -106,14 +106,21 @@ pub struct PpuRender {
     bg_layers: [Layer; 4],
     /// Pixel output of the object layer.
     obj_layer: [ObjPixel; WIDTH],
+    /// Table for expanding 5-bit colour channels to 8 bits.
+    expansion: [u8; 32],
 }
 
 impl PpuRender {
     fn render_line(&mut self) {
+        // Forced blank only affects the current line: Nothing gets rendered,
+        // so the layer buffers stay clean for the next one. Since the
+        // registers are a per-line snapshot, games toggling it mid-frame
+        // for VRAM access get white lines exactly where it was set.
         if self.r.dispcnt.forced_blank_enable() {
             let start = self.r.vcount.us() * WIDTH;
+            let white = self.expansion[31];
             for pixel in 0..WIDTH {
-                self.pixels[start + pixel] = [255, 255, 255, 255];
+                self.pixels[start + pixel] = [white, white, white, 255];
             }
             return;
         }
@@ -129,7 +136,13 @@ impl PpuRender {
             BackgroundMode::Mode3 => self.render_mode3(),
             BackgroundMode::Mode4 => self.render_mode4(),
             BackgroundMode::Mode5 => self.render_mode5(),
-            inv => log::warn!("Invalid PPU mode {inv:?}"),
+            // Prohibited modes display no backgrounds, leaving only
+            // objects and the backdrop. All background buffers are
+            // empty, since they get cleared after every line.
+            // Ppu::dispcnt_written reports switching to them.
+            BackgroundMode::ProhibitedA | BackgroundMode::ProhibitedB => {
+                self.finalize_scanline(0..=3)
+            }
         }
     }
 
@@ -140,7 +153,12 @@ impl PpuRender {
         self.obj_layer = serde_obj_arr();
     }
 
-    pub fn new(palette: Arc<[u8]>, vram: Arc<[u8]>, oam: Arc<[u8]>) -> Self {
+    pub fn new(
+        palette: Arc<[u8]>,
+        vram: Arc<[u8]>,
+        oam: Arc<[u8]>,
+        expansion: ColourExpansion,
+    ) -> Self {
         Self {
             r: PpuRegisters::default(),
             palette,
@@ -150,6 +168,7 @@ impl PpuRender {
             pixels: serde_colour_arr(),
             bg_layers: serde_layer_arr(),
             obj_layer: serde_obj_arr(),
+            expansion: expansion.table(),
         }
     }
 }
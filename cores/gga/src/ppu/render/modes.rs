@@ -35,10 +35,11 @@ impl PpuRender {
             return;
         }
 
+        let ref_point = self.affine_ref_point(2);
         let wrap = self.r.bg_cnt[2].overflow_mode() == OverflowMode::Wraparound;
         for x in 0..WIDTH {
             let mut point = affine_transform_point(
-                self.r.bg_scale[0].latched,
+                ref_point,
                 x as i32,
                 self.r.bg_scale[0].pa as i32,
                 self.r.bg_scale[0].pc as i32,
@@ -57,6 +58,7 @@ impl PpuRender {
             self.bg_layers[2][x] = self.hword_to_colour_vram(pixel << 1);
         }
 
+        self.apply_bg_mosaic_h(2);
         self.finalize_scanline(2..=2);
     }
 
@@ -66,10 +68,11 @@ impl PpuRender {
         }
 
         let start_addr = self.bitmap_start_addr();
+        let ref_point = self.affine_ref_point(2);
         let wrap = self.r.bg_cnt[2].overflow_mode() == OverflowMode::Wraparound;
         for x in 0..WIDTH {
             let mut point = affine_transform_point(
-                self.r.bg_scale[0].latched,
+                ref_point,
                 x as i32,
                 self.r.bg_scale[0].pa as i32,
                 self.r.bg_scale[0].pc as i32,
@@ -91,6 +94,7 @@ impl PpuRender {
             }
         }
 
+        self.apply_bg_mosaic_h(2);
         self.finalize_scanline(2..=2);
     }
 
@@ -99,10 +103,11 @@ impl PpuRender {
             return;
         }
 
+        let ref_point = self.affine_ref_point(2);
         let wrap = self.r.bg_cnt[2].overflow_mode() == OverflowMode::Wraparound;
         for x in 0..WIDTH {
             let mut point = affine_transform_point(
-                self.r.bg_scale[0].latched,
+                ref_point,
                 x as i32,
                 self.r.bg_scale[0].pa as i32,
                 self.r.bg_scale[0].pc as i32,
@@ -121,6 +126,7 @@ impl PpuRender {
             self.bg_layers[2][x] = self.hword_to_colour_vram(pixel << 1);
         }
 
+        self.apply_bg_mosaic_h(2);
         self.finalize_scanline(2..=2);
     }
 
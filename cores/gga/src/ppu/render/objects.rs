@@ -27,6 +27,9 @@ const OBJ_Y_SIZE: [u16; 16] = [8, 16, 32, 64, 8, 8, 16, 32, 16, 32, 32, 64, 8, 8
 #[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
 pub struct ObjPixel {
     pub colour: Colour,
+    /// If an object in OBJ window mode covers this pixel with a
+    /// non-transparent texel, placing it inside the OBJ window.
+    /// Set regardless of priority, since these objects are not drawn.
     pub is_window: bool,
     pub is_alpha: bool,
     pub priority: u8,
@@ -63,13 +66,12 @@ impl PpuRender {
     }
 
     fn render_obj_normal(&mut self, obj: Object) {
-        let line = self.r.vcount as i32;
         let position = obj.position();
         let (width, height) = obj.size();
 
-        if !obj.draw_on(self.r.vcount, position.1, height as u8) {
+        let Some(sprite_y) = obj.line_in_sprite(self.r.vcount, height as i32) else {
             return; // Not on this line or invalid
-        }
+        };
 
         let base_addr = 0x1_0000 + (0x20 * obj.tilenum().u32());
         if self.r.is_bitmap_mode() && obj.tilenum() < 512 {
@@ -78,7 +80,6 @@ impl PpuRender {
         let tile_width = obj.tile_width(self.r.dispcnt.character_mapping_mode(), width);
         let tile_size = obj.tile_size();
 
-        let sprite_y = line - position.1;
         let sprite_y = if obj.rotscal().is_bit(4) {
             height as i32 - sprite_y - 1
         } else {
@@ -125,18 +126,22 @@ impl PpuRender {
     }
 
     fn render_obj_affine(&mut self, obj: Object, double: bool) {
-        let line = self.r.vcount as i32;
         let position = obj.position();
         let (width, height) = obj.size();
         let (width, height) = (width as i32, height as i32);
+        // Double-size objects get a bounding box twice as large in each
+        // dimension, with the texture still centred in it
         let (bounds_w, bounds_h) = if double {
             (width * 2, height * 2)
         } else {
             (width, height)
         };
 
-        if !obj.draw_on(self.r.vcount, position.1, bounds_h as u8) {
+        let Some(sprite_y) = obj.line_in_sprite(self.r.vcount, bounds_h) else {
             return; // Not on this line or invalid
+        };
+        if self.r.is_bitmap_mode() && obj.tilenum() < 512 {
+            return; // Invalid tile number for bitmap mode(s)
         }
 
         let base_addr = 0x1_0000 + (0x20 * obj.tilenum().u32());
@@ -145,7 +150,11 @@ impl PpuRender {
 
         let rotscal = self.get_rotscal(obj.rotscal());
         let (half_w, half_h) = (bounds_w / 2, bounds_h / 2);
-        let iy = line - (position.1 + half_h);
+        // Mosaic applies to the position inside the bounding box before
+        // the transform, since texture space is rotated relative to the
+        // screen
+        let sprite_y = Self::maybe_mosaic(sprite_y, obj.mosaic_en(), self.r.mosaic.obj_v());
+        let iy = sprite_y - half_h;
 
         for ix in (-half_w)..half_w {
             let screen_x = position.0 + half_w + ix;
@@ -161,15 +170,16 @@ impl PpuRender {
                 continue;
             }
 
+            let ix =
+                Self::maybe_mosaic(ix + half_w, obj.mosaic_en(), self.r.mosaic.obj_h()) - half_w;
             let trans_x = ((rotscal[0] * ix) + (rotscal[1] * iy)) >> 8;
             let trans_y = ((rotscal[2] * ix) + (rotscal[3] * iy)) >> 8;
             let tex_x = trans_x + width / 2;
             let tex_y = trans_y + height / 2;
 
+            // Texture coordinates outside of the object are transparent,
+            // they do not wrap around
             if tex_x >= 0 && tex_x < width && tex_y >= 0 && tex_y < height {
-                let tex_x = Self::maybe_mosaic(tex_x, obj.mosaic_en(), self.r.mosaic.obj_h());
-                let tex_y = Self::maybe_mosaic(tex_y, obj.mosaic_en(), self.r.mosaic.obj_v());
-
                 let tile_addr = base_addr
                     + xy2dw(
                         (tex_x / 8) as usize,
@@ -208,6 +218,10 @@ impl PpuRender {
         self.obj_layer[x]
     }
 
+    /// Write a pixel of an object to the object layer. Mosaic was already
+    /// applied when picking the texel, so the semi-transparent flag set here
+    /// belongs to the same object as the mosaicked colour, and blending
+    /// it later during composition sees the final object layer.
     fn write_obj_pixel(&mut self, x: usize, colour: Colour, obj: Object) {
         let pixel = &mut self.obj_layer[x];
         match obj.mode() {
@@ -275,10 +289,15 @@ impl Object {
         }
     }
 
-    fn draw_on(self, line: u16, self_y: i32, size_y: u8) -> bool {
-        self.valid()
-            && (line as i32 >= self_y)
-            && ((line as i32) < ((self_y).wrapping_add(size_y as i32)))
+    /// Get the line inside the object's bounding box that is displayed on
+    /// the given screen line, if any. The Y coordinate wraps around at 256,
+    /// so tall objects near the bottom continue at the top of the screen.
+    fn line_in_sprite(self, line: u16, size_y: i32) -> Option<i32> {
+        if !self.valid() {
+            return None;
+        }
+        let sprite_y = (line as i32 - self.y() as i32) & 0xFF;
+        (sprite_y < size_y).then_some(sprite_y)
     }
 
     fn valid(self) -> bool {
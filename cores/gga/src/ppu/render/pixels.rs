@@ -132,13 +132,6 @@ impl PpuRender {
                 self.pixels[xy2d(x + 1, y)][1] = a;
             }
         }
-
-        // Consider forced blank
-        if self.r.dispcnt.forced_blank_enable() {
-            for x in 0..WIDTH {
-                self.pixels[xy2d(x, y)] = [255; 4];
-            }
-        }
     }
 
     fn finalize_pixel(
@@ -167,7 +160,8 @@ impl PpuRender {
         drop(it);
 
         // Now that backgrounds are taken care of, we need to check if there is an
-        // object pixel that takes priority of one of the layers
+        // object pixel that takes priority of one of the layers.
+        // Objects win against backgrounds of the same priority.
         let obj_entry = self.obj_pixel(x);
 
         if win.ctrl.obj_en() && obj_entry.colour[3] != 0 {
@@ -181,36 +175,33 @@ impl PpuRender {
         }
 
         let obj_alpha_blend = top_layer.is_object() && obj_entry.is_alpha;
-        let top_flags = self.r.bldcnt.first_target();
-        let bot_flags = self.r.bldcnt.second_target();
-
-        let sfx_enabled = (self.r.bldcnt.special_effect() != SpecialEffect::None
-            || obj_alpha_blend)
-            && (top_flags & top_layer.kind as u8) != 0; // sfx must at least have a first target configured
+        let top_is_first = (self.r.bldcnt.first_target() & top_layer.kind as u8) != 0;
+        let bot_is_second = (self.r.bldcnt.second_target() & bot_layer.kind as u8) != 0;
 
-        let mut pixel = if win.ctrl.special_en() && sfx_enabled {
-            if obj_alpha_blend && (bot_flags & bot_layer.kind as u8) != 0 {
-                self.do_alpha(top_layer.pixel, bot_layer.pixel)
-            } else {
-                let (top_layer, bot_layer) = (top_layer, bot_layer);
-
-                match self.r.bldcnt.special_effect() {
-                    // Only alphablend with a second target.
-                    SpecialEffect::AlphaBlend if (bot_flags & bot_layer.kind as u8) != 0 => {
-                        self.do_alpha(top_layer.pixel, bot_layer.pixel)
-                    }
-                    SpecialEffect::BrightnessInc => self.do_brighten(top_layer.pixel),
-                    SpecialEffect::BrightnessDec => self.do_darken(top_layer.pixel),
-                    _ => top_layer.pixel,
+        let mut pixel = if !win.ctrl.special_en() {
+            // no blending, just use the top pixel
+            top_layer.pixel
+        } else if obj_alpha_blend && bot_is_second {
+            // Semi-transparent objects are always a first target and always
+            // alpha blend, regardless of BLDCNT; this takes priority over
+            // brightness effects.
+            self.do_alpha(top_layer.pixel, bot_layer.pixel)
+        } else if top_is_first {
+            match self.r.bldcnt.special_effect() {
+                // Only alphablend with a second target.
+                SpecialEffect::AlphaBlend if bot_is_second => {
+                    self.do_alpha(top_layer.pixel, bot_layer.pixel)
                 }
+                SpecialEffect::BrightnessInc => self.do_brighten(top_layer.pixel),
+                SpecialEffect::BrightnessDec => self.do_darken(top_layer.pixel),
+                _ => top_layer.pixel,
             }
         } else {
-            // no blending, just use the top pixel
             top_layer.pixel
         };
 
         for col in pixel.iter_mut().take(3) {
-            *col = (*col << 3) | (*col >> 2);
+            *col = self.expansion[*col as usize & 0x1F];
         }
         self.pixels[xy2d(x, y)] = pixel;
     }
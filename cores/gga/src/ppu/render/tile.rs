@@ -25,8 +25,9 @@ impl PpuRender {
         let screen_block_base = cnt.screen_base_block().us() * 0x800;
         let char_block_base = cnt.character_base_block().us() * 0x4000;
         let size = cnt.screen_size();
-        let bg_y = self.r.vcount.wrapping_add(vofs);
-        let bg_y = Self::maybe_mosaic(bg_y as i32, cnt.mosaic_en(), self.r.mosaic.bg_v()) as u16;
+        // Vertical mosaic works on screen lines, before scrolling
+        let line = Self::maybe_mosaic(self.r.vcount as i32, cnt.mosaic_en(), self.r.mosaic.bg_v());
+        let bg_y = (line as u16).wrapping_add(vofs);
 
         for tile in -1..31 {
             let bg_x = (tile << 3) + hofs;
@@ -68,20 +69,44 @@ impl PpuRender {
             }
         }
 
-        // Apply X MOSAIC if needed
+        self.apply_bg_mosaic_h(bg);
+    }
+
+    /// Apply X MOSAIC to the given BG if needed. This is done on the finished
+    /// layer, so transparency and therefore blend targets get mosaicked as
+    /// well before the layers are composed and blended.
+    pub(super) fn apply_bg_mosaic_h(&mut self, bg: u16) {
         let mos_x = self.r.mosaic.bg_h().us() + 1;
-        if cnt.mosaic_en() && mos_x > 1 {
-            for x in (0..WIDTH).step_by(mos_x + 1) {
-                for i in 1..mos_x {
-                    if (x + i) >= WIDTH {
-                        return;
-                    }
-                    self.bg_layers[bg.us()][x + i] = self.bg_layers[bg.us()][x];
+        if !self.r.bg_cnt[bg.us()].mosaic_en() || mos_x == 1 {
+            return;
+        }
+        for x in (0..WIDTH).step_by(mos_x) {
+            for i in 1..mos_x {
+                if (x + i) >= WIDTH {
+                    return;
                 }
+                self.bg_layers[bg.us()][x + i] = self.bg_layers[bg.us()][x];
             }
         }
     }
 
+    /// Reference point of the given affine BG for the current line.
+    /// Since it advances by PB/PD every line, vertical mosaic uses the one
+    /// of the first line of the current mosaic block instead.
+    pub(super) fn affine_ref_point(&self, bg: u16) -> Point {
+        let scal = self.r.bg_scale[bg.us() - 2];
+        let mos_y = self.r.mosaic.bg_v() as i32 + 1;
+        if !self.r.bg_cnt[bg.us()].mosaic_en() || mos_y == 1 {
+            return scal.latched;
+        }
+        let lines_back = self.r.vcount as i32 % mos_y;
+        let Point(x, y) = scal.latched;
+        Point(
+            x - lines_back * scal.pb as i32,
+            y - lines_back * scal.pd as i32,
+        )
+    }
+
     pub(super) fn set_pixel(&mut self, bg: u16, x: i16, palette: u8, colour_idx: u8) {
         if !(0..(WIDTH as i16)).contains(&x) || colour_idx == 0 {
             return;
@@ -116,7 +141,7 @@ impl PpuRender {
         let size = [128, 256, 512, 1024][cnt.screen_size().us()];
         let scal = self.r.bg_scale[bg.us() - 2];
 
-        let Point(bg_x, bg_y) = scal.latched;
+        let Point(bg_x, bg_y) = self.affine_ref_point(bg);
         for pixel_x in 0..(WIDTH as i32) {
             let mut x = (bg_x + scal.pa as i32 * pixel_x) >> 8;
             let mut y = (bg_y + scal.pc as i32 * pixel_x) >> 8;
@@ -130,8 +155,6 @@ impl PpuRender {
                 y &= size - 1;
             }
 
-            let x = Self::maybe_mosaic(x, cnt.mosaic_en(), self.r.mosaic.bg_h());
-            let y = Self::maybe_mosaic(y, cnt.mosaic_en(), self.r.mosaic.bg_v());
             let map_addr = screen_block_base + (((y >> 3) * (size >> 3)) + (x >> 3)) as usize;
             let map = self.vram[map_addr];
 
@@ -141,5 +164,6 @@ impl PpuRender {
             let colour = self.vram[tile_addr];
             self.set_pixel(bg, pixel_x as i16, 0, colour);
         }
+        self.apply_bg_mosaic_h(bg);
     }
 }
@@ -0,0 +1,96 @@
+// Copyright (c) 2024 Leela Aurelia, git@elia.garden
+//
+// Unless otherwise noted, this file is released and thus subject to the
+// terms of the Mozilla Public License Version 2.0 (MPL-2.0) or the
+// GNU General Public License Version 3 (GPL-3).
+// If a copy of these licenses was not distributed with this file, you can
+// obtain them at https://mozilla.org/MPL/2.0/ and http://www.gnu.org/licenses/.
+
+use common::{
+    common::{debugger::TileMap, options::ColourExpansion},
+    numutil::{hword, NumExt},
+};
+
+use super::{BackgroundMode, PaletteMode, Ppu, HEIGHT, WIDTH};
+
+impl Ppu {
+    /// Render the full tile map of the given text background, for debugging.
+    /// The visible area is the one selected by BGxHOFS/BGxVOFS.
+    /// None if the background is not a text background in the current mode.
+    pub fn tile_map(&self, bg: usize, expansion: ColourExpansion) -> Option<TileMap> {
+        let text_bgs = match self.regs.dispcnt.bg_mode() {
+            BackgroundMode::Mode0 => 4,
+            BackgroundMode::Mode1 => 2,
+            _ => 0,
+        };
+        if bg >= text_bgs {
+            return None;
+        }
+
+        let cnt = self.regs.bg_cnt[bg];
+        let screen_block_base = cnt.screen_base_block().us() * 0x800;
+        let char_block_base = cnt.character_base_block().us() * 0x4000;
+        let bpp8 = cnt.palette_mode() == PaletteMode::Single256;
+        let size = cnt.screen_size().us();
+        let (tiles_x, tiles_y) = ([32, 64, 32, 64][size], [32, 32, 64, 64][size]);
+        let width = tiles_x * 8;
+
+        let table = expansion.table();
+        let colour = |idx: u8| {
+            let value = hword(self.palette[idx.us() * 2], self.palette[idx.us() * 2 + 1]);
+            [
+                table[value.bits(0, 5).us()],
+                table[value.bits(5, 5).us()],
+                table[value.bits(10, 5).us()],
+                255,
+            ]
+        };
+
+        let mut pixels = vec![colour(0); width * tiles_y * 8];
+        for tile_y in 0..tiles_y {
+            for tile_x in 0..tiles_x {
+                // Each screen block holds 32x32 tiles
+                let block = (tile_x / 32) + (tile_y / 32) * (tiles_x / 32);
+                let map_addr =
+                    screen_block_base + block * 0x800 + ((tile_y % 32) * 32 + (tile_x % 32)) * 2;
+                let map = hword(self.vram[map_addr], self.vram[map_addr + 1]);
+                let tile_idx = map.bits(0, 10).us();
+                let palette = map.bits(12, 4).u8();
+
+                for line in 0..8 {
+                    let y = if map.is_bit(11) { 7 - line } else { line };
+                    for pixel in 0..8 {
+                        let x = if map.is_bit(10) { 7 - pixel } else { pixel };
+                        let idx = if bpp8 {
+                            self.vram[(char_block_base + tile_idx * 64 + y * 8 + x) & 0xFFFF]
+                        } else {
+                            let byte = self.vram
+                                [(char_block_base + tile_idx * 32 + y * 4 + x / 2) & 0xFFFF];
+                            let idx = if x & 1 == 1 { byte >> 4 } else { byte & 0xF };
+                            if idx == 0 {
+                                0
+                            } else {
+                                (palette << 4) + idx
+                            }
+                        };
+                        if idx != 0 {
+                            let pos = (tile_y * 8 + line) * width + tile_x * 8 + pixel;
+                            pixels[pos] = colour(idx);
+                        }
+                    }
+                }
+            }
+        }
+
+        Some(TileMap {
+            width,
+            height: tiles_y * 8,
+            pixels,
+            scroll: [
+                self.regs.bg_offsets[bg * 2].us() & 0x1FF,
+                self.regs.bg_offsets[bg * 2 + 1].us() & 0x1FF,
+            ],
+            visible: [WIDTH, HEIGHT],
+        })
+    }
+}
@@ -50,7 +50,17 @@ impl AdvEvent {
     }
 }
 
-impl Kind for AdvEvent {}
+impl Kind for AdvEvent {
+    fn name(&self) -> &'static str {
+        match self {
+            PauseEmulation => "Pause",
+            UpdateKeypad => "Keypad",
+            PpuEvent(_) => "PPU",
+            ApuEvent(_) => "APU",
+            TimerOverflow(_) => "Timer",
+        }
+    }
+}
 
 /// Events the APU generates.
 #[derive(Copy, Clone, Eq, PartialEq)]
@@ -90,25 +90,53 @@ impl Inst {
     }
 }
 
-pub(crate) fn get_next(gg: &mut GameGirl) -> Inst {
-    let first = gg.read8(gg.cpu.pc);
-    let inst = match first {
-        EXT => Inst(first, gg.arg8()),
-        _ => Inst(first, 0),
-    };
-
-    gg.c.debugger.add_traced_instruction(|| {
-        format!(
-            "PC={:04X}, SP={:04X}, AF={:04X}, BC={:04X}, DE={:4X}, HL={:04X}, I={}",
-            gg.cpu.pc,
+/// Report an opcode that does not exist on the CPU. Real hardware locks up;
+/// we just treat it as a NOP.
+fn unknown_opcode(gg: &mut GameGirl, opcode: u8) {
+    let pc = gg.cpu.pc;
+    let dump = gg.c.debugger.pause_on_invalid.then(|| {
+        let mut dump = format!(
+            "PC={:04X}, SP={:04X}, AF={:04X}, BC={:04X}, DE={:04X}, HL={:04X}\n",
+            pc,
             gg.cpu.sp,
             gg.cpu.dreg(AF),
             gg.cpu.dreg(BC),
             gg.cpu.dreg(DE),
             gg.cpu.dreg(HL),
-            NAMES[inst.0.us()]
-        )
+        );
+        for addr in pc.wrapping_sub(8)..pc.wrapping_add(8) {
+            dump.push_str(&format!("{:02X} ", gg.get::<u8>(addr)));
+        }
+        dump
     });
+    gg.c.debugger.invalid_state(
+        "unknown-opcode",
+        format!("Unknown opcode 0x{opcode:02X} at 0x{pc:04X}"),
+        || dump.unwrap_or_default(),
+    );
+}
+
+pub(crate) fn get_next(gg: &mut GameGirl) -> Inst {
+    let first = gg.fetch8(gg.cpu.pc);
+    let inst = match first {
+        EXT => Inst(first, gg.arg8()),
+        _ => Inst(first, 0),
+    };
+
+    if gg.c.debugger.tracing_pc(gg.cpu.pc.u32()) {
+        gg.c.debugger.add_traced_instruction(|| {
+            format!(
+                "PC={:04X}, SP={:04X}, AF={:04X}, BC={:04X}, DE={:4X}, HL={:04X}, I={}",
+                gg.cpu.pc,
+                gg.cpu.sp,
+                gg.cpu.dreg(AF),
+                gg.cpu.dreg(BC),
+                gg.cpu.dreg(DE),
+                gg.cpu.dreg(HL),
+                NAMES[inst.0.us()]
+            )
+        });
+    }
 
     inst
 }
@@ -152,11 +180,13 @@ pub(super) fn execute(gg: &mut GameGirl, inst: Inst) -> bool {
         }
 
         0x03 | 0x13 | 0x23 => {
+            gg.idu_used(gg.cpu.dreg(BCDEHLAF[reg]));
             gg.cpu
                 .set_dreg(BCDEHLAF[reg], gg.cpu.dreg(BCDEHLAF[reg]).wrapping_add(1));
             gg.advance_clock(1);
         }
         0x33 => {
+            gg.idu_used(gg.cpu.sp);
             gg.cpu.sp = gg.cpu.sp.wrapping_add(1);
             gg.advance_clock(1);
         }
@@ -249,21 +279,23 @@ pub(super) fn execute(gg: &mut GameGirl, inst: Inst) -> bool {
         }
         0x2A => {
             let addr = gg.mod_ret_hl(1);
-            let value = gg.read8(addr);
+            let value = gg.read8_inc(addr);
             gg.cpu.set_reg(A, value);
         }
         0x3A => {
             let addr = gg.mod_ret_hl(-1);
-            let value = gg.read8(addr);
+            let value = gg.read8_inc(addr);
             gg.cpu.set_reg(A, value);
         }
 
         0x0B | 0x1B | 0x2B => {
+            gg.idu_used(gg.cpu.dreg(BCDEHLAF[reg]));
             gg.cpu
                 .set_dreg(BCDEHLAF[reg], gg.cpu.dreg(BCDEHLAF[reg]).wrapping_sub(1));
             gg.advance_clock(1);
         }
         0x3B => {
+            gg.idu_used(gg.cpu.sp);
             gg.advance_clock(1);
             gg.cpu.sp = gg.cpu.sp.wrapping_sub(1);
         }
@@ -303,7 +335,11 @@ pub(super) fn execute(gg: &mut GameGirl, inst: Inst) -> bool {
         // -----------------------------------
         // 0x40 - 0x7F
         // -----------------------------------
-        0x76 if !gg.cpu.ime && (gg[IF] & gg[IE] & 0x1F) != 0 => gg.cpu.halt_bug = true,
+        // HALT bug: With IME off and an interrupt pending, HALT does not halt
+        // and the next byte gets read twice
+        0x76 if !gg.cpu.ime && (gg[IF] & gg[IE] & 0x1F) != 0 => {
+            gg.cpu.halt_bug = gg.c.config.gb_halt_bug
+        }
         0x76 => gg.cpu.halted = true,
         0x40..=0x7F => {
             let reg = (inst.0 - 0x40) >> 3;
@@ -438,6 +474,9 @@ pub(super) fn execute(gg: &mut GameGirl, inst: Inst) -> bool {
 
         0x20 | 0x30 | 0x28 | 0x38 | 0xC0 | 0xD0 | 0xC8 | 0xD8 => gg.advance_clock(1), /* Internal delay on failed jump */
         0xC2 | 0xD2 | 0xC4 | 0xD4 | 0xCA | 0xDA | 0xCC | 0xDC => gg.advance_clock(2), /* Internal delay on failed jump */
+        0xD3 | 0xDB | 0xDD | 0xE3 | 0xE4 | 0xEB | 0xEC | 0xED | 0xF4 | 0xFC | 0xFD => {
+            unknown_opcode(gg, inst.0)
+        }
         _ => (),
     }
     inst.inc_pc()
@@ -25,11 +25,43 @@ pub struct Cpu {
     pub sp: u16,
     pub ime: bool,
     pub regs: [u8; 8],
-    halted: bool,
+    pub(crate) halted: bool,
     halt_bug: bool,
 }
 
 impl Cpu {
+    /// Make a backtrace of return addresses, starting with the current
+    /// instruction. Heuristic: Words on the stack are taken to be return
+    /// addresses if they point right after a CALL or RST instruction in
+    /// memory code can run from. This excludes VRAM, cartridge RAM and IO,
+    /// where 0xFF (RST 38) is a common value.
+    pub fn stack_trace(gg: &GameGirl) -> Vec<u32> {
+        const MAX_FRAMES: usize = 32;
+        const MAX_STACK_WORDS: u16 = 128;
+
+        let mut frames = vec![gg.cpu.pc.u32()];
+        let mut sp = gg.cpu.sp;
+        for _ in 0..MAX_STACK_WORDS {
+            if frames.len() >= MAX_FRAMES || sp >= 0xFFFE {
+                break;
+            }
+            let value = gg.get::<u16>(sp);
+            sp = sp.wrapping_add(2);
+            if value < 3 {
+                continue;
+            }
+            let is_code =
+                |addr: u16| matches!(addr, 0x0000..=0x7FFF | 0xC000..=0xDFFF | 0xFF80..=0xFFFE);
+            let call = is_code(value - 3)
+                && matches!(gg.get::<u8>(value - 3), 0xC4 | 0xCC | 0xCD | 0xD4 | 0xDC);
+            let rst = is_code(value - 1) && gg.get::<u8>(value - 1) & 0xC7 == 0xC7;
+            if call || rst {
+                frames.push(value.u32());
+            }
+        }
+        frames
+    }
+
     /// Execute the next instruction, moving the entire system forward.
     pub(super) fn exec_next_inst(gg: &mut GameGirl) {
         if gg.cpu.halted {
@@ -239,3 +271,69 @@ impl Interrupt {
         self as u16
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use common::{common::options::SystemConfig, Core};
+
+    use super::*;
+    use crate::tests::mbc3_rom;
+
+    /// Run a HALT with IME off and an interrupt pending, followed by an
+    /// INC A. Returns A afterwards.
+    fn halt_with_pending_irq(halt_bug: bool) -> u8 {
+        let mut rom = mbc3_rom();
+        rom[0x100..0x10C].copy_from_slice(&[
+            0xF3, // DI
+            0x3E, 0x01, // LD A, 1
+            0xE0, 0xFF, // LDH (IE), A
+            0xE0, 0x0F, // LDH (IF), A
+            0xAF, // XOR A
+            0x76, // HALT
+            0x3C, // INC A
+            0x18, 0xFE, // JR -2
+        ]);
+        let config = SystemConfig {
+            gb_halt_bug: halt_bug,
+            ..SystemConfig::default()
+        };
+        let mut gg = GameGirl::with_cart(rom, None, &config).unwrap();
+        gg.skip_bootrom();
+        gg.c.debugger.running = true;
+        for _ in 0..20 {
+            gg.advance();
+        }
+        assert_eq!(gg.cpu.pc, 0x10A);
+        assert!(!gg.cpu.halted);
+        gg.cpu.reg(Reg::A)
+    }
+
+    #[test]
+    fn halt_bug_reads_next_byte_twice() {
+        assert_eq!(halt_with_pending_irq(true), 2);
+        assert_eq!(halt_with_pending_irq(false), 1);
+    }
+
+    #[test]
+    fn stack_trace_call_and_rst() {
+        let mut rom = mbc3_rom();
+        rom[0x100..0x10A].copy_from_slice(&[
+            0x31, 0xF0, 0xDF, // LD SP, 0xDFF0
+            0x01, 0x01, 0x80, // LD BC, 0x8001
+            0xC5, // PUSH BC
+            0xCD, 0x00, 0x02, // CALL 0x200
+        ]);
+        rom[0x200] = 0xEF; // RST 28
+        rom[0x28..0x2A].copy_from_slice(&[0x18, 0xFE]); // JR -2
+        let mut gg = GameGirl::with_cart(rom, None, &SystemConfig::default()).unwrap();
+        gg.skip_bootrom();
+        // Looks like RST 38, but is in VRAM
+        gg.set(0x8000, 0xFFu8);
+        gg.c.debugger.running = true;
+        for _ in 0..10 {
+            gg.advance();
+        }
+        assert_eq!(gg.cpu.pc, 0x28);
+        assert_eq!(Cpu::stack_trace(&gg), [0x28, 0x201, 0x10A]);
+    }
+}
@@ -9,6 +9,8 @@
 //! This file contains a bunch of IO registers used by the MMU and it's
 //! subcomponents.
 
+use common::common::debugger::Width;
+
 pub const HIGH_START: u16 = 0xFF00;
 
 pub const BOOTROM_DISABLE: u16 = 0xFF50 - HIGH_START;
@@ -65,6 +67,20 @@ pub const VRAM_SELECT: u16 = 0xFF4F - HIGH_START;
 pub const WRAM_SELECT: u16 = 0xFF70 - HIGH_START;
 pub const KEY1: u16 = 0xFF4D - HIGH_START;
 
+macro_rules! named {
+    ($($name:ident),*) => {
+        &[$((stringify!($name), (HIGH_START + $name) as u32, Width::Byte)),*]
+    };
+}
+
+/// Named registers shown in the debugger, with their full address.
+pub const NAMED_REGISTERS: &[(&str, u32, Width)] = named! {
+    JOYP, SB, SC, DIV, TIMA, TMA, TAC, IF,
+    LCDC, STAT, SCY, SCX, LY, LYC, DMA, BGP, OBP0, OBP1, WY, WX,
+    KEY1, VRAM_SELECT, HDMA_SRC_HIGH, HDMA_SRC_LOW, HDMA_DEST_HIGH, HDMA_DEST_LOW, HDMA_START,
+    BCPS, BCPD, OCPS, OCPD, OPRI, WRAM_SELECT, IE
+};
+
 /// DMG BOOT ROM, Bootix made by Hacktix: https://github.com/Hacktix/Bootix
 /// Thank you, Hacktix! This is Version 1.2.
 pub const BOOTIX_ROM: &[u8; 256] = include_bytes!("bootrom/bootix_dmg.bin");
@@ -10,14 +10,15 @@
 //! https://github.com/Amjad50/mizu/tree/master/mizu-core/src/apu
 //! Thank you to it's authors!
 
-mod channel;
-mod envelope;
 mod noise_channel;
 mod pulse_channel;
 mod wave_channel;
 
 use bitflags::bitflags;
-use channel::{Channel, Dac, LengthCountedChannel};
+use common::{
+    common::audio::{AudioBuffer, ChannelMask, PsgChannelState, PsgState},
+    components::psg::{Channel, Dac, LengthCountedChannel},
+};
 use noise_channel::NoiseChannel;
 use pulse_channel::PulseChannel;
 use wave_channel::WaveChannel;
@@ -263,6 +264,9 @@ impl Apu {
                 let freq = (self.wave.channel().frequency() & 0xFF) | (((data as u16) & 0x7) << 8);
                 self.wave.channel_mut().write_frequency(freq);
 
+                if data & 0x80 != 0 {
+                    self.wave.channel_mut().trigger_corruption();
+                }
                 Self::write_channel_length_enable_and_trigger(
                     &mut *self.wave,
                     is_length_clock_next,
@@ -336,10 +340,49 @@ impl Apu {
         (p2 << 4) | p1
     }
 
+    /// Get a snapshot of all registers and channel state, for debugging.
+    pub fn snapshot(&self) -> PsgState {
+        PsgState {
+            power: self.power,
+            sequencer_position: self.sequencer_position,
+            registers: (0xFF10..=0xFF26)
+                .map(|addr| (addr, self.read_register_gg(addr as u16)))
+                .collect(),
+            channels: [
+                channel_state(
+                    &self.pulse1,
+                    self.pulse1.channel().frequency(),
+                    self.pulse1.channel().envelope().current_volume(),
+                ),
+                channel_state(
+                    &self.pulse2,
+                    self.pulse2.channel().frequency(),
+                    self.pulse2.channel().envelope().current_volume(),
+                ),
+                channel_state(
+                    &self.wave,
+                    self.wave.channel().frequency(),
+                    self.wave.channel().read_volume(),
+                ),
+                channel_state(
+                    &self.noise,
+                    self.noise.channel().read_noise_register() as u16,
+                    self.noise.channel().envelope().current_volume(),
+                ),
+            ],
+        }
+    }
+
     /// The APU is clocked by the divider, on the falling edge of the bit 12
     /// of the divider, this is needed since the divider can be clocked manually
     /// by resetting it to 0 on write
-    pub fn clock(&mut self, double_speed: bool, divider: u8, buf: &mut [Vec<f32>; 2]) {
+    pub fn clock(
+        &mut self,
+        double_speed: bool,
+        divider: u8,
+        buf: &mut AudioBuffer,
+        mask: ChannelMask,
+    ) {
         // 2 in normal speed, 1 in double speed
         let clocks = (!double_speed) as u8 + 1;
 
@@ -356,7 +399,7 @@ impl Apu {
 
         self.sample_counter += 1.;
         if self.sample_counter >= SAMPLE_EVERY_N_CLOCKS {
-            self.push_output(buf);
+            self.push_output(buf, mask);
             self.sample_counter -= SAMPLE_EVERY_N_CLOCKS;
         }
 
@@ -406,7 +449,7 @@ impl Apu {
 }
 
 impl Apu {
-    fn push_output(&mut self, buf: &mut [Vec<f32>; 2]) {
+    fn push_output(&mut self, buf: &mut AudioBuffer, mask: ChannelMask) {
         let right_vol = self.channels_control.vol_right() as f32 + 1.;
         let left_vol = self.channels_control.vol_left() as f32 + 1.;
 
@@ -414,6 +457,12 @@ impl Apu {
         let pulse2 = self.pulse2.dac_output() / 8.;
         let wave = self.wave.dac_output() / 8.;
         let noise = self.noise.dac_output() / 8.;
+        buf.push_stems(&[pulse1, pulse2, wave, noise]);
+
+        let pulse1 = mask.apply(0, pulse1);
+        let pulse2 = mask.apply(1, pulse2);
+        let wave = mask.apply(2, wave);
+        let noise = mask.apply(3, noise);
 
         let right_pulse1 = if self
             .channels_selection
@@ -491,8 +540,8 @@ impl Apu {
 
         let right_sample = right_pulse1 + right_pulse2 + right_wave + right_noise;
         let left_sample = left_pulse1 + left_pulse2 + left_wave + left_noise;
-        buf[0].push(right_sample);
-        buf[1].push(left_sample);
+        buf.input[0].push(right_sample);
+        buf.input[1].push(left_sample);
     }
 
     fn power_off(&mut self) {
@@ -561,3 +610,19 @@ impl Apu {
         }
     }
 }
+
+fn channel_state<C: Channel>(
+    ch: &LengthCountedChannel<C>,
+    frequency: u16,
+    volume: u8,
+) -> PsgChannelState {
+    PsgChannelState {
+        enabled: ch.enabled(),
+        dac_enabled: ch.dac_enabled(),
+        length_enabled: ch.read_length_enable(),
+        length_counter: ch.length_counter(),
+        frequency,
+        volume,
+        output: ch.output(),
+    }
+}
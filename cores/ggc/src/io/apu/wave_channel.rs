@@ -88,6 +88,30 @@ impl WaveChannel {
     pub fn reset_buffer_index(&mut self) {
         self.buffer_position = 0;
     }
+
+    /// The channel is about to be triggered. If its DMG, the channel is
+    /// playing and will clock next, meaning that it is reading buffer now,
+    /// then activate the wave-ram rewrite bug.
+    /// Needs to be called before the channel gets enabled by the trigger.
+    ///
+    /// Some bytes from wave-ram are rewritten based on the current index
+    pub fn trigger_corruption(&mut self) {
+        if !(self.is_dmg && self.channel_enable && self.frequency_timer == 0) {
+            return;
+        }
+
+        // get the next index that will be incremented to in the next clock
+        let index = ((self.buffer_position + 1) & 0x1F) / 2;
+
+        if index < 4 {
+            self.buffer[0] = self.buffer[index as usize];
+        } else {
+            let four_bytes_align_start = ((index / 4) * 4) as usize;
+            for i in 0..4 {
+                self.buffer[i] = self.buffer[four_bytes_align_start + i];
+            }
+        }
+    }
 }
 
 impl WaveChannel {
@@ -135,24 +159,6 @@ impl Channel for WaveChannel {
     }
 
     fn trigger(&mut self) {
-        // if its DMG and will clock next, meaning that it is reading buffer now,
-        // then activate the wave-ram rewrite bug
-        //
-        // Some bytes from wave-ram are rewritten based on the current index
-        if self.is_dmg && self.frequency_timer == 0 {
-            // get the next index that will be incremented to in the next clock
-            let index = ((self.buffer_position + 1) & 0x1F) / 2;
-
-            if index < 4 {
-                self.buffer[0] = self.buffer[index as usize];
-            } else {
-                let four_bytes_align_start = ((index / 4) * 4) as usize;
-                for i in 0..4 {
-                    self.buffer[i] = self.buffer[four_bytes_align_start + i];
-                }
-            }
-        }
-
         self.buffer_position = 0;
         // no idea why `3` works here, but with this tests pass and found it
         // in other emulators
@@ -167,3 +173,68 @@ impl Channel for WaveChannel {
         self.dac_enable
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A playing channel with a wave RAM of 0x00, 0x11, ..., 0xFF, about
+    /// to read the byte at the given index.
+    fn channel(is_dmg: bool, index: u8) -> WaveChannel {
+        let mut wave = WaveChannel::new(is_dmg);
+        for offset in 0..16 {
+            wave.write_buffer(offset, offset * 0x11);
+        }
+        wave.set_dac_enable(true);
+        wave.write_frequency(0x7FF);
+        wave.set_enable(true);
+        wave.trigger();
+        // The first clock only counts down the trigger delay, the second
+        // moves to position 1 and every one after that by 2
+        for _ in 0..=index {
+            wave.clock();
+        }
+        assert_eq!((wave.buffer_position + 1) / 2, index);
+        wave
+    }
+
+    #[test]
+    fn trigger_corrupts_first_byte() {
+        let mut wave = channel(true, 1);
+        wave.trigger_corruption();
+        let mut expected: Vec<u8> = (0..16).map(|i| i * 0x11).collect();
+        expected[0] = 0x11;
+        assert_eq!(wave.buffer.to_vec(), expected);
+    }
+
+    #[test]
+    fn trigger_corrupts_aligned_block() {
+        let mut wave = channel(true, 5);
+        wave.trigger_corruption();
+        let mut expected: Vec<u8> = (0..16).map(|i| i * 0x11).collect();
+        expected.copy_within(4..8, 0);
+        assert_eq!(wave.buffer.to_vec(), expected);
+    }
+
+    #[test]
+    fn no_corruption_when_not_reading() {
+        let expected: Vec<u8> = (0..16).map(|i| i * 0x11).collect();
+        // CGB does not have the quirk
+        let mut wave = channel(false, 5);
+        wave.trigger_corruption();
+        assert_eq!(wave.buffer.to_vec(), expected);
+
+        // Stopped channel
+        let mut wave = channel(true, 5);
+        wave.set_enable(false);
+        wave.trigger_corruption();
+        assert_eq!(wave.buffer.to_vec(), expected);
+
+        // Not about to clock
+        let mut wave = channel(true, 5);
+        wave.write_frequency(0x700);
+        wave.clock();
+        wave.trigger_corruption();
+        assert_eq!(wave.buffer.to_vec(), expected);
+    }
+}
@@ -11,7 +11,11 @@ use std::{
     time::{SystemTime, UNIX_EPOCH},
 };
 
-use common::{components::storage::GameSave, numutil::NumExt};
+use common::{
+    common::{cheats::CheatWrite, options::GameHacks},
+    components::storage::{GameSave, SaveInfo},
+    numutil::NumExt,
+};
 
 use crate::io::cartridge::MBCKind::*;
 
@@ -21,6 +25,10 @@ const KIND: u16 = 0x0147;
 const ROM_BANKS: u16 = 0x0148;
 const RAM_BANKS: u16 = 0x0149;
 const BANK_COUNT_1MB: u16 = 64;
+/// End of the cartridge header, ROMs need to be at least this long.
+const HEADER_END: usize = 0x150;
+/// Highest valid ROM size in the header, 8MB.
+const MAX_ROM_BANKS: u8 = 8;
 
 /// Struct representing the game cartridge.
 #[derive(Clone)]
@@ -36,16 +44,52 @@ pub struct Cartridge {
     pub(super) ram: Vec<u8>,
     pub ram_bank: u8,
     pub ram_enable: bool,
+    /// If the save RAM was modified since the last time the flag was cleared.
+    #[cfg_attr(feature = "serde", serde(skip, default))]
+    pub ram_dirty: bool,
 
     pub kind: MBCKind,
+    /// If the rumble motor of MBC5 rumble carts is on.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub rumble: bool,
+    /// Cheats replacing bytes of ROM when read, like Game Genie codes.
+    /// ROM pages containing them are not mapped for fast reads,
+    /// see [crate::GameGirl::update_rom_pages].
+    #[cfg_attr(feature = "serde", serde(skip, default))]
+    pub cheats: Vec<CheatWrite>,
 }
 
 impl Cartridge {
+    /// If any of the ROM cheats are in the given 4K block of the
+    /// address space.
+    pub fn has_cheats_in(&self, block: usize) -> bool {
+        self.cheats
+            .iter()
+            .any(|cheat| cheat.addr.us() >> 12 == block)
+    }
+
+    /// Apply ROM cheats to a byte read from ROM. Cheats with a compare
+    /// value only apply if the byte matches it, which makes them only
+    /// affect the ROM bank they were made for.
+    fn apply_cheats(&self, addr: u16, byte: u8) -> u8 {
+        self.cheats
+            .iter()
+            .find(|cheat| {
+                cheat.addr == addr.u32() && cheat.compare.map_or(true, |cmp| cmp == byte.u32())
+            })
+            .map_or(byte, |cheat| cheat.value.u8())
+    }
+
     pub(super) fn read(&self, addr: u16) -> u8 {
         let a = addr as usize;
         match addr {
-            0x0000..=0x3FFF => self.rom[a + (0x4000 * self.rom0_bank as usize)],
-            0x4000..=0x7FFF => self.rom[(a & 0x3FFF) + (0x4000 * self.rom1_bank as usize)],
+            0x0000..=0x3FFF => {
+                self.apply_cheats(addr, self.rom[a + (0x4000 * self.rom0_bank as usize)])
+            }
+            0x4000..=0x7FFF => self.apply_cheats(
+                addr,
+                self.rom[(a & 0x3FFF) + (0x4000 * self.rom1_bank as usize)],
+            ),
             0xA000..=0xBFFF => match &self.kind {
                 MBC2 if self.ram_enable => self.ram[a & 0x1FF],
                 MBC3RTC {
@@ -64,6 +108,7 @@ impl Cartridge {
 
     pub(super) fn write(&mut self, addr: u16, value: u8) {
         let count = self.ram_bank_count();
+        let has_rumble = self.has_rumble();
         match (&mut self.kind, addr) {
             // MBC2
             (MBC2, 0x0000..=0x3FFF) if addr.is_bit(8) => {
@@ -71,6 +116,7 @@ impl Cartridge {
             }
             (MBC2, 0xA000..=0xBFFF) if self.ram_enable => {
                 self.ram[addr.us() & 0x1FF] = value | 0xF0;
+                self.ram_dirty = true;
             }
 
             // MBC3 with RTC
@@ -102,6 +148,7 @@ impl Cartridge {
                 0xA000..=0xBFFF,
             ) => {
                 rtc.set(*reg, value);
+                self.ram_dirty = true;
             }
 
             // Shared between all (except MBC2 and RTCs...)
@@ -110,12 +157,20 @@ impl Cartridge {
             }
             (_, 0xA000..=0xBFFF) if !self.ram.is_empty() && self.ram_enable => {
                 self.ram[(addr & 0x1FFF).us() + (0x2000 * self.ram_bank.us())] = value;
+                self.ram_dirty = true;
             }
 
             // Shared between some
             (MBC3, 0x4000..=0x5FFF) if count > 0 => {
                 self.ram_bank = (value & 0x03) % count;
             }
+            // On rumble carts, bit 3 controls the motor instead of RAM banking
+            (MBC5, 0x4000..=0x5FFF) if has_rumble => {
+                self.rumble = value.is_bit(3);
+                if count > 0 {
+                    self.ram_bank = (value & 0x07) % count;
+                }
+            }
             (MBC5, 0x4000..=0x5FFF) if count > 0 => {
                 self.ram_bank = (value & 0x0F) % count;
             }
@@ -154,6 +209,11 @@ impl Cartridge {
         }
     }
 
+    /// If this is an MBC5 cart with a rumble motor.
+    fn has_rumble(&self) -> bool {
+        matches!(self.rom.get(KIND.us()), Some(0x1C..=0x1E))
+    }
+
     fn mbc1_bank2_update(&mut self, bank2: u8, ram_mode: bool) {
         self.ram_bank = if self.ram_bank_count() == 4 && ram_mode {
             bank2
@@ -177,17 +237,39 @@ impl Cartridge {
     }
 
     pub fn ram_bank_count(&self) -> u8 {
-        match self.rom[RAM_BANKS.us()] {
-            0 if matches!(self.kind, MBC2) => 1,
-            0 => 0,
-            2 => 1,
-            3 => 4,
-            4 => 16,
-            5 => 8,
-            _ => panic!("Unknown cartridge controller"),
+        Self::ram_banks(&self.rom, &self.kind).expect("Unknown cartridge controller")
+    }
+
+    fn ram_banks(rom: &[u8], kind: &MBCKind) -> Option<u8> {
+        match rom.get(RAM_BANKS.us())? {
+            0 if matches!(kind, MBC2) => Some(1),
+            0 => Some(0),
+            2 => Some(1),
+            3 => Some(4),
+            4 => Some(16),
+            5 => Some(8),
+            _ => None,
         }
     }
 
+    /// Detect the save RAM of the given ROM from its header, the same way
+    /// it is done when loading it.
+    pub fn detect_save_info(rom: &[u8]) -> Option<SaveInfo> {
+        let kind = Self::mbc_kind(*rom.get(KIND.us())?);
+        let banks = Self::ram_banks(rom, &kind)?;
+        if banks == 0 {
+            return None;
+        }
+        Some(SaveInfo {
+            kind: match kind {
+                MBC2 => "MBC2 RAM",
+                _ => "SRAM",
+            },
+            size: 0x2000 * banks.us(),
+            rtc: matches!(kind, MBC3RTC { .. }),
+        })
+    }
+
     pub fn supports_cgb(&self) -> bool {
         self.rom[CGB_FLAG.us()].is_bit(7)
     }
@@ -210,33 +292,63 @@ impl Cartridge {
         buf
     }
 
-    pub fn from_rom(rom: Vec<u8>) -> Self {
-        let kind = rom[KIND as usize];
+    /// Built-in compatibility hacks for games known to need them, detected
+    /// from the header title.
+    pub fn game_hacks(&self) -> GameHacks {
+        match self.title(false).as_str() {
+            // Both wait for the interrupt caused by the STAT write quirk
+            "ROAD RASH" | "XERD NO DENSETSU" => GameHacks {
+                stat_write_quirk: true,
+                ..GameHacks::default()
+            },
+            _ => GameHacks::default(),
+        }
+    }
+
+    /// Create a cartridge from the given ROM. Returns `None` if the ROM
+    /// is too short to contain a header, or the header specifies an
+    /// unknown ROM or RAM size.
+    /// ROMs shorter than their header claims are padded with 0xFF.
+    pub fn from_rom(mut rom: Vec<u8>) -> Option<Self> {
+        if rom.len() < HEADER_END || rom[ROM_BANKS.us()] > MAX_ROM_BANKS {
+            return None;
+        }
+        let rom_size = 0x4000 * (2 << rom[ROM_BANKS.us()]);
+        if rom.len() < rom_size {
+            rom.resize(rom_size, 0xFF);
+        }
+        let kind = Self::mbc_kind(rom[KIND.us()]);
+        let ram_banks = Self::ram_banks(&rom, &kind)?;
         let mut cart = Self {
             rom,
-            kind: match kind {
-                0x01..=0x03 => MBC1 {
-                    ram_mode: false,
-                    bank2: 0,
-                },
-                0x05..=0x06 => MBC2,
-                0x0F..=0x10 => MBC3RTC {
-                    rtc: Rtc {
-                        start: 0,
-                        latched_at: None,
-                    },
-                    rtc_reg: None,
-                    latch_prepare: false,
-                },
-                0x11..=0x13 => MBC3,
-                0x19..=0x1E => MBC5,
-                _ => NoMBC,
-            },
+            kind,
             ..Self::dummy()
         };
         cart.ram
-            .extend(iter::repeat(0).take(0x2000 * cart.ram_bank_count().us()));
-        cart
+            .extend(iter::repeat(0).take(0x2000 * ram_banks.us()));
+        Some(cart)
+    }
+
+    fn mbc_kind(kind: u8) -> MBCKind {
+        match kind {
+            0x01..=0x03 => MBC1 {
+                ram_mode: false,
+                bank2: 0,
+            },
+            0x05..=0x06 => MBC2,
+            0x0F..=0x10 => MBC3RTC {
+                rtc: Rtc {
+                    start: 0,
+                    latched_at: None,
+                    offset: 0,
+                },
+                rtc_reg: None,
+                latch_prepare: false,
+            },
+            0x11..=0x13 => MBC3,
+            0x19..=0x1E => MBC5,
+            _ => NoMBC,
+        }
     }
 
     pub fn make_save(&self) -> Option<GameSave> {
@@ -248,6 +360,11 @@ impl Cartridge {
                 } else {
                     None
                 },
+                rtc_offset: if let MBC3RTC { rtc, .. } = &self.kind {
+                    rtc.offset
+                } else {
+                    0
+                },
                 title: self.title(true),
             })
         } else {
@@ -255,10 +372,30 @@ impl Cartridge {
         }
     }
 
-    pub fn load_save(&mut self, save: GameSave) {
+    pub fn load_save(&mut self, mut save: GameSave) {
+        // Saves from other emulators may be shorter or longer than the RAM
+        // of the cartridge; banked accesses assume it is the full size
+        save.ram.resize(self.ram.len(), 0xFF);
         self.ram = save.ram;
         if let MBC3RTC { rtc, .. } = &mut self.kind {
             rtc.start = save.rtc.unwrap_or_else(Rtc::since_unix);
+            rtc.offset = save.rtc_offset;
+        }
+    }
+
+    /// Get the time of the RTC as Unix seconds, if the cart has one.
+    pub fn rtc_time(&self) -> Option<u64> {
+        match &self.kind {
+            MBC3RTC { rtc, .. } => Some(rtc.time()),
+            _ => None,
+        }
+    }
+
+    /// Set the time of the RTC, if the cart has one.
+    pub fn set_rtc_time(&mut self, unix_seconds: u64) {
+        if let MBC3RTC { rtc, .. } = &mut self.kind {
+            rtc.set_time(unix_seconds);
+            self.ram_dirty = true;
         }
     }
 
@@ -270,7 +407,10 @@ impl Cartridge {
             ram: vec![],
             ram_bank: 0,
             ram_enable: false,
+            ram_dirty: false,
             kind: NoMBC,
+            rumble: false,
+            cheats: Vec::new(),
         }
     }
 }
@@ -299,11 +439,28 @@ pub enum MBCKind {
 pub struct Rtc {
     pub(crate) start: u64,
     latched_at: Option<u64>,
+    /// Difference between the time set with [Rtc::set_time] and the host
+    /// clock, in seconds. 0 when following the host clock.
+    #[cfg_attr(feature = "serde", serde(default))]
+    offset: i64,
 }
 
 impl Rtc {
     fn latch(&mut self) {
-        self.latched_at = Some(Self::since_unix());
+        self.latched_at = Some(Self::since_unix().saturating_sub(self.start));
+    }
+
+    /// Get the time of the RTC as Unix seconds.
+    fn time(&self) -> u64 {
+        Self::since_unix().saturating_add_signed(self.offset)
+    }
+
+    /// Set the time of the RTC. The counter moves by the same amount as the
+    /// time, so that games see the time pass. Both are kept in saves.
+    fn set_time(&mut self, unix_seconds: u64) {
+        let delta = unix_seconds as i64 - self.time() as i64;
+        self.offset += delta;
+        self.start = self.start.saturating_add_signed(-delta);
     }
 
     fn get(&self, idx: u8) -> u16 {
@@ -321,7 +478,7 @@ impl Rtc {
 
     fn diff(&self) -> u64 {
         self.latched_at
-            .unwrap_or_else(|| Self::since_unix() - self.start)
+            .unwrap_or_else(|| Self::since_unix().saturating_sub(self.start))
     }
 
     fn since_unix() -> u64 {
@@ -334,3 +491,45 @@ impl Rtc {
 
 const RTC_DIVIDERS: &[u64] = &[1, 60, 3600, 86400];
 const RTC_MODULO: &[u64] = &[60, 60, 24, 511];
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use common::common::options::SystemConfig;
+
+    use super::*;
+    use crate::{tests::mbc3_rom, GameGirl};
+
+    /// An MBC5 cart of the given type with 4 RAM banks. Returns it along
+    /// with every state the rumble callback was called with.
+    fn mbc5(kind: u8) -> (Box<GameGirl>, Arc<Mutex<Vec<bool>>>) {
+        let mut rom = mbc3_rom();
+        rom[KIND.us()] = kind;
+        let mut gg = GameGirl::with_cart(rom, None, &SystemConfig::default()).unwrap();
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let calls2 = calls.clone();
+        gg.c.rumble = Some(Box::new(move |on| calls2.lock().unwrap().push(on)));
+        (gg, calls)
+    }
+
+    #[test]
+    fn rumble_bit_drives_callback() {
+        let (mut gg, calls) = mbc5(0x1E);
+        gg.set(0x4000, 0x08);
+        // Still on, only changes the RAM bank
+        gg.set(0x4000, 0x0B);
+        assert_eq!(gg.cart.ram_bank, 3);
+        gg.set(0x5FFF, 0x01);
+        assert_eq!(gg.cart.ram_bank, 1);
+        assert_eq!(*calls.lock().unwrap(), [true, false]);
+    }
+
+    #[test]
+    fn no_rumble_without_motor() {
+        let (mut gg, calls) = mbc5(0x1B);
+        gg.set(0x4000, 0x08);
+        assert_eq!(gg.cart.ram_bank, 0);
+        assert!(calls.lock().unwrap().is_empty());
+    }
+}
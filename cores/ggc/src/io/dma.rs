@@ -30,8 +30,7 @@ pub fn dma_written(gg: &mut GameGirl, value: u8) {
 /// This implementation writes everything at once
 /// once the timer of 648 cycles is up.
 pub fn do_oam_dma(gg: &mut GameGirl) {
-    let src = gg.dma.u16() * 0x100;
-    let mut src = if src > 0xDF00 { src - 0x2000 } else { src };
+    let mut src = dma_source(gg);
     for dest in 0..0xA0 {
         gg.mem.oam[dest] = gg.get(src);
         src += 1;
@@ -40,6 +39,42 @@ pub fn do_oam_dma(gg: &mut GameGirl) {
     gg.mem.dma_restarted = false;
 }
 
+/// Returns the byte the OAM DMA is currently transferring, if one is
+/// in progress. The CPU sees this value on the bus when reading below
+/// OAM; OAM itself reads 0xFF, HRAM and IO registers are unaffected.
+pub fn bus_conflict_value(gg: &GameGirl) -> Option<u8> {
+    let offset = transfer_offset(gg)?;
+    Some(gg.get(dma_source(gg) + offset))
+}
+
+/// Returns if an OAM DMA transfer is currently copying bytes, as opposed
+/// to still starting up after the write to the DMA register.
+pub fn transfer_active(gg: &GameGirl) -> bool {
+    transfer_offset(gg).is_some()
+}
+
+/// Offset of the byte the OAM DMA is currently transferring, if it is.
+fn transfer_offset(gg: &GameGirl) -> Option<u16> {
+    let start = gg.mem.pending_dma?;
+    // The transfer starts 2 M-cycles after the write to the DMA register,
+    // then copies one byte per M-cycle. A restarted transfer keeps the
+    // previous one running during that time.
+    let m_cycles = (gg.scheduler.now() - start) >> gg.t_shift;
+    if m_cycles < 2 && !gg.mem.dma_restarted {
+        return None;
+    }
+    Some(m_cycles.saturating_sub(2).min(0x9F) as u16)
+}
+
+fn dma_source(gg: &GameGirl) -> u16 {
+    let src = gg.dma.u16() * 0x100;
+    if src > 0xDF00 {
+        src - 0x2000
+    } else {
+        src
+    }
+}
+
 /// HDMA VRAM transfer available only on CGB.
 #[derive(Default)]
 #[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
@@ -125,3 +160,61 @@ impl Hdma {
         gg.hdma.transfer_left -= 1;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use common::{common::options::SystemConfig, Core};
+
+    use super::*;
+    use crate::{
+        io::addr::{DMA, HIGH_START, LCDC},
+        tests::mbc3_rom,
+    };
+
+    #[test]
+    fn bus_conflict_during_oam_dma() {
+        let config = SystemConfig {
+            oam_dma_bus_conflict: true,
+            ..SystemConfig::default()
+        };
+        let mut gg = GameGirl::with_cart(mbc3_rom(), None, &config).unwrap();
+        gg.skip_bootrom();
+        for i in 0..0xA0 {
+            gg.set(0xC000 + i, 0x40 + i as u8);
+        }
+        gg.set(0xD000, 0x99);
+        gg.set(0xFF80, 0x12);
+
+        gg.set(HIGH_START + DMA, 0xC0);
+        // Nothing is on the bus during the 2 M-cycle startup
+        assert_eq!(gg.read8(0xD000), 0x99);
+        assert_eq!(gg.read8(0xD000), 0x40);
+        assert_eq!(gg.read8(0xD000), 0x41);
+        // OAM and HRAM are not affected
+        assert_eq!(gg.read8(0xFE00), 0xFF);
+        assert_eq!(gg.read8(0xFF80), 0x12);
+        assert_eq!(gg.read8(0xD000), 0x44);
+
+        gg.advance_clock(0xA0);
+        assert_eq!(gg.read8(0xD000), 0x99);
+        assert_eq!(gg.read8(0xFE00), 0x40);
+    }
+
+    #[test]
+    fn oam_locked_once_transfer_starts() {
+        let config = SystemConfig {
+            gb_ppu_memory_locking: true,
+            ..SystemConfig::default()
+        };
+        let mut gg = GameGirl::with_cart(mbc3_rom(), None, &config).unwrap();
+        gg.skip_bootrom();
+        gg.set(HIGH_START + LCDC, 0);
+
+        gg.set(HIGH_START + DMA, 0xC0);
+        // Still starting up, OAM can be written
+        gg.write8(0xFE10, 0x55);
+        assert_eq!(gg.mem.oam[0x10], 0x55);
+        gg.write8(0xFE11, 0x66);
+        assert_ne!(gg.mem.oam[0x11], 0x66);
+    }
+}
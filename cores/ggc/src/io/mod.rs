@@ -25,7 +25,9 @@ use crate::{
         apu::Apu,
         cartridge::Cartridge,
         dma::Hdma,
+        ppu::{OamCorruption, Ppu, DISP_EN},
         scheduling::{GGEvent, PpuEvent},
+        serial::Serial,
         timer::Timer,
     },
     T_CLOCK_HZ,
@@ -38,6 +40,7 @@ pub mod dma;
 pub mod joypad;
 pub mod ppu;
 pub mod scheduling;
+pub mod serial;
 pub mod timer;
 
 /// The memory of the GG, containing big arrays holding internal memory.
@@ -68,16 +71,78 @@ pub struct Memory {
 
 impl GameGirl {
     pub fn read8(&mut self, addr: u16) -> u8 {
+        let value = self.fetch8(addr);
+        self.c
+            .debugger
+            .memory_accessed(addr.u32(), 1, value.u32(), false);
+        value
+    }
+
+    /// Read a byte of the current CPU instruction. Same as [read8], but
+    /// does not trigger watchpoints.
+    pub fn fetch8(&mut self, addr: u16) -> u8 {
+        self.advance_clock(1);
+        Ppu::oam_bug(self, addr, OamCorruption::Read);
+        self.read_bus(addr)
+    }
+
+    /// Read a byte while the CPU increments or decrements the register
+    /// holding the address, like `LD A, [HL+]` and `POP` do.
+    /// Only differs from [read8] in how it triggers the OAM corruption bug.
+    pub fn read8_inc(&mut self, addr: u16) -> u8 {
         self.advance_clock(1);
+        Ppu::oam_bug(self, addr, OamCorruption::ReadIncrease);
+        let value = self.read_bus(addr);
+        self.c
+            .debugger
+            .memory_accessed(addr.u32(), 1, value.u32(), false);
+        value
+    }
+
+    fn read_bus(&mut self, addr: u16) -> u8 {
+        // OAM is not on the bus the DMA uses, it reads 0xFF in [GameGirl::get]
+        if self.c.config.oam_dma_bus_conflict && addr < 0xFE00 {
+            if let Some(value) = dma::bus_conflict_value(self) {
+                return value;
+            }
+        }
+        if self.locked_from_cpu(addr) {
+            return 0xFF;
+        }
         self.get(addr)
     }
 
+    /// If the CPU cannot access the given address since the PPU or OAM DMA
+    /// is using it: VRAM while drawing, OAM during OAM scan and drawing,
+    /// and OAM writes while an OAM DMA is transferring, but not during its
+    /// 2 M-cycle startup. Reads of OAM during DMA are blocked
+    /// by [GameGirl::get] regardless of this.
+    /// Only when enabled with `gb_ppu_memory_locking`.
+    fn locked_from_cpu(&self, addr: u16) -> bool {
+        if !self.c.config.gb_ppu_memory_locking {
+            return false;
+        }
+        let mode = if self[LCDC].is_bit(DISP_EN) {
+            self[STAT] & 3
+        } else {
+            0
+        };
+        match addr {
+            0x8000..=0x9FFF => mode == 3,
+            0xFE00..=0xFE9F => mode >= 2 || dma::transfer_active(self),
+            _ => false,
+        }
+    }
+
     pub fn read_s8(&mut self, addr: u16) -> i8 {
         self.read8(addr) as i8
     }
 
     pub fn write8(&mut self, addr: u16, value: u8) {
         self.c.debugger.write_occurred(addr.u32());
+        self.c
+            .debugger
+            .memory_accessed(addr.u32(), 1, value.u32(), true);
 
         // TODO Hack to pass another mooneye test
         if addr == (TMA + 0xFF00) {
@@ -85,7 +150,16 @@ impl GameGirl {
         }
 
         self.advance_clock(1);
-        self.set(addr, value);
+        Ppu::oam_bug(self, addr, OamCorruption::Write);
+        if !self.locked_from_cpu(addr) {
+            self.set(addr, value);
+        }
+    }
+
+    /// Notify of the CPU's increment/decrement unit operating on the given
+    /// value, which can trigger the OAM corruption bug.
+    pub fn idu_used(&mut self, value: u16) {
+        Ppu::oam_bug(self, value, OamCorruption::Write);
     }
 
     pub fn read16(&mut self, addr: u16) -> u16 {
@@ -101,23 +175,28 @@ impl GameGirl {
 
     /// Get an 8-bit argument for the current CPU instruction.
     pub fn arg8(&mut self) -> u8 {
-        self.read8(self.cpu.pc.wrapping_add(1))
+        self.fetch8(self.cpu.pc.wrapping_add(1))
     }
 
     /// Get a 16-bit argument for the current CPU instruction.
     pub fn arg16(&mut self) -> u16 {
-        self.read16(self.cpu.pc.wrapping_add(1))
+        let low = self.fetch8(self.cpu.pc.wrapping_add(1));
+        let high = self.fetch8(self.cpu.pc.wrapping_add(2));
+        hword(low, high)
     }
 
     /// Pop the current value off the SP.
     pub fn pop_stack(&mut self) -> u16 {
-        let val = self.read16(self.cpu.sp);
+        let low = self.read8_inc(self.cpu.sp);
+        let high = self.read8_inc(self.cpu.sp.wrapping_add(1));
+        let val = hword(low, high);
         self.cpu.sp = self.cpu.sp.wrapping_add(2);
         val
     }
 
     /// Push the given value to the current SP.
     pub fn push_stack(&mut self, value: u16) {
+        self.idu_used(self.cpu.sp);
         self.cpu.sp = self.cpu.sp.wrapping_sub(2);
         self.write16(self.cpu.sp, value);
     }
@@ -131,7 +210,7 @@ impl GameGirl {
 
         T::from_u8(self.get_inner(addr, |this, addr| {
             match addr {
-                0xA000..=0xBFFF => this.cart.read(addr),
+                0x0000..=0x7FFF | 0xA000..=0xBFFF => this.cart.read(addr),
                 0xFE00..=0xFE9F
                     if !this
                         .mem
@@ -152,13 +231,8 @@ impl GameGirl {
         match addr {
             0x0000..=0x7FFF => {
                 self.cart.write(addr, value);
-                // Refresh page offsets
-                for i in 0..4 {
-                    self.mem.page_offsets[i] = self.cart.rom0_bank.u32() * 0x4000;
-                }
-                for i in 4..8 {
-                    self.mem.page_offsets[i] = self.cart.rom1_bank.u32() * 0x4000;
-                }
+                self.c.set_rumble(self.cart.rumble);
+                self.update_rom_pages();
             }
             0xA000..=0xBFFF => self.cart.write(addr, value),
             0x8000..=0x9FFF => {
@@ -175,6 +249,24 @@ impl GameGirl {
         }
     }
 
+    /// Refresh the page offsets of ROM to the currently mapped banks.
+    /// Blocks with ROM cheats are left unmapped, so that reads go to
+    /// [Cartridge::read], which applies them.
+    pub(crate) fn update_rom_pages(&mut self) {
+        for i in 0..8 {
+            let bank = if i < 4 {
+                self.cart.rom0_bank
+            } else {
+                self.cart.rom1_bank
+            };
+            self.mem.page_offsets[i] = if self.cart.has_cheats_in(i) {
+                0
+            } else {
+                bank.u32() * 0x4000
+            };
+        }
+    }
+
     fn get_high(&self, addr: u16) -> u8 {
         match addr {
             DMA => self.dma,
@@ -219,7 +311,7 @@ impl GameGirl {
                     let time = self
                         .scheduler
                         .cancel_with_remaining(|e| matches!(e, GGEvent::PpuEvent(_)));
-                    self.ppu.resume_data = Some(time);
+                    self.ppu.resume_data = Some(self.ppu.lcd_disabled(time));
                 }
                 if !was_on && is_on {
                     let data = self.ppu.resume_data.take();
@@ -228,12 +320,21 @@ impl GameGirl {
                     }
                 }
             }
-            STAT => self[STAT] = value | 0x80, // Bit 7 unavailable
+            STAT => {
+                if self.c.game_hacks.stat_write_quirk && !self.cgb {
+                    Ppu::stat_write_quirk(self);
+                }
+                self[STAT] = value | 0x80; // Bit 7 unavailable
+            }
             DMA => dma::dma_written(self, value),
             BCPS..=OPRI => self.ppu.write_high(addr, value),
             NR10..=WAV_END => self.apu.write_register_gg(HIGH_START + addr, value),
 
-            SB => self.c.debugger.serial_output.push(value as char),
+            SB => {
+                self[SB] = value;
+                self.c.debugger.serial_output.push(value as char);
+            }
+            SC => Serial::sc_written(self, value),
 
             VRAM_SELECT if self.cgb => {
                 self.mem.vram_bank = value & 1;
@@ -248,7 +349,7 @@ impl GameGirl {
             HDMA_SRC_HIGH..=HDMA_START if self.cgb => Hdma::set(self, addr, value),
 
             // Last 3 are unmapped regions.
-            KEY1 | LY | SC | 0x03 | 0x08..=0x0E | 0x4C..=0x7F => (),
+            KEY1 | LY | 0x03 | 0x08..=0x0E | 0x4C..=0x7F => (),
             _ => self[addr] = value,
         }
     }
@@ -259,7 +360,7 @@ impl GameGirl {
             CgbMode::Prefer => cart.supports_cgb(),
             CgbMode::Never => cart.requires_cgb(),
         };
-        self.ppu.configure(self.cgb, conf.cgb_colour_correction);
+        self.ppu.configure(self.cgb, conf);
         self.apu = Apu::new(!self.cgb);
         self.cart = cart;
         MemoryMapper::init_pages(self);
@@ -393,6 +494,7 @@ impl MemoryMappedSystem<256> for GameGirl {
                 self.c.config.get_bios("cgb").unwrap_or(CGB_BOOTROM),
                 a - 0x0100,
             ),
+            0x0000..=0x7FFF if self.cart.has_cheats_in(a >> 12) => ptr::null::<u8>() as *mut u8,
             0x0000..=0x3FFF => offs(&self.cart.rom, a),
             0x4000..=0x7FFF => offs(&self.cart.rom, a - 0x4000),
 
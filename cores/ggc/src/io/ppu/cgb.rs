@@ -6,7 +6,7 @@
 // If a copy of these licenses was not distributed with this file, you can
 // obtain them at https://mozilla.org/MPL/2.0/ and http://www.gnu.org/licenses/.
 
-use common::{numutil::NumExt, Colour};
+use common::{common::options::ColourGrading, numutil::NumExt, Colour};
 
 use crate::{
     io::{
@@ -27,6 +27,10 @@ pub struct Cgb {
     pub(super) obj_palettes: [CgbColour; 32],
 
     pub(super) colour_correction: bool,
+    #[cfg_attr(feature = "serde", serde(default))]
+    bg_grading: ColourGrading,
+    #[cfg_attr(feature = "serde", serde(default))]
+    obj_grading: ColourGrading,
     #[cfg_attr(feature = "serde", serde(skip, default))]
     pub(super) dmg_used_x_obj_cords: Option<[Option<u8>; 10]>,
     #[cfg_attr(feature = "serde", serde(skip, default = "super::serde_bool_arr"))]
@@ -34,7 +38,11 @@ pub struct Cgb {
 }
 
 impl Cgb {
-    pub fn new(colour_correction: bool) -> Self {
+    pub fn new(
+        colour_correction: bool,
+        bg_grading: ColourGrading,
+        obj_grading: ColourGrading,
+    ) -> Self {
         Self {
             bg_palette_idx: 0,
             bg_palette_inc: false,
@@ -43,10 +51,17 @@ impl Cgb {
             obj_palette_inc: false,
             obj_palettes: [CgbColour::default(); 32],
             colour_correction,
+            bg_grading,
+            obj_grading,
             dmg_used_x_obj_cords: None,
             unavailable_pixels: [false; 160],
         }
     }
+
+    /// Get a colour of the given background palette.
+    pub(super) fn bg_palette_colour(&self, palette: u8, colour: usize) -> Colour {
+        self.bg_palettes[(palette.us() * 4) + colour].colour
+    }
 }
 
 /// A CGB palette colour.
@@ -59,7 +74,7 @@ pub struct CgbColour {
 }
 
 impl CgbColour {
-    fn recalculate(&mut self, colour_correction: bool) {
+    fn recalculate(&mut self, colour_correction: bool, grading: ColourGrading) {
         self.colour[0] = self.raw_low & 0x1F;
         self.colour[1] = ((self.raw_high & 3) << 3) | self.raw_low >> 5;
         self.colour[2] = (self.raw_high >> 2) & 0x1F;
@@ -83,10 +98,37 @@ impl CgbColour {
                 *col = (*col << 3) | (*col >> 2);
             }
         }
+        self.colour = grading.apply(self.colour);
     }
 }
 
 impl Ppu {
+    /// Change colour correction and grading, recalculating all palettes.
+    pub fn set_colour_correction(
+        &mut self,
+        enabled: bool,
+        bg_grading: ColourGrading,
+        obj_grading: ColourGrading,
+    ) {
+        if let PpuKind::Cgb(cgb) = &mut self.kind {
+            if cgb.colour_correction == enabled
+                && cgb.bg_grading == bg_grading
+                && cgb.obj_grading == obj_grading
+            {
+                return;
+            }
+            cgb.colour_correction = enabled;
+            cgb.bg_grading = bg_grading;
+            cgb.obj_grading = obj_grading;
+            for palette in cgb.bg_palettes.iter_mut() {
+                palette.recalculate(enabled, bg_grading);
+            }
+            for palette in cgb.obj_palettes.iter_mut() {
+                palette.recalculate(enabled, obj_grading);
+            }
+        }
+    }
+
     pub fn read_high(&self, addr: u16) -> u8 {
         match (&self.kind, addr) {
             (PpuKind::Cgb(cgb), BCPS) => Self::read_cps(cgb.bg_palette_idx, cgb.bg_palette_inc),
@@ -125,6 +167,7 @@ impl Ppu {
                 cgb.bg_palette_inc,
                 &mut cgb.bg_palettes,
                 cgb.colour_correction,
+                cgb.bg_grading,
                 value,
             ),
             (PpuKind::Cgb(cgb), OCPD) => Self::write_cpd(
@@ -132,6 +175,7 @@ impl Ppu {
                 cgb.obj_palette_inc,
                 &mut cgb.obj_palettes,
                 cgb.colour_correction,
+                cgb.obj_grading,
                 value,
             ),
             (PpuKind::Cgb(cgb), OPRI) if value.is_bit(0) => {
@@ -147,6 +191,7 @@ impl Ppu {
         inc: bool,
         palettes: &mut [CgbColour],
         colour_correction: bool,
+        grading: ColourGrading,
         value: u8,
     ) {
         let palette = &mut palettes[(index.us() >> 1) & 0x1F];
@@ -155,7 +200,7 @@ impl Ppu {
         } else {
             palette.raw_low = value;
         };
-        palette.recalculate(colour_correction);
+        palette.recalculate(colour_correction, grading);
         if inc {
             *index += 1;
         }
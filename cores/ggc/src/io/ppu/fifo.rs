@@ -0,0 +1,530 @@
+// Copyright (c) 2024 Leela Aurelia, git@elia.garden
+//
+// Unless otherwise noted, this file is released and thus subject to the
+// terms of the Mozilla Public License Version 2.0 (MPL-2.0) or the
+// GNU General Public License Version 3 (GPL-3).
+// If a copy of these licenses was not distributed with this file, you can
+// obtain them at https://mozilla.org/MPL/2.0/ and http://www.gnu.org/licenses/.
+
+use std::collections::VecDeque;
+
+use common::{numutil::NumExt, Colour};
+
+use super::{
+    Ppu, PpuKind, Sprite, BG_EN, BG_MAP, BIG_OBJS, CGB_BANK, COLOURS, DMG_PAL, OBJ_EN, PRIORITY,
+    WIN_EN, WIN_MAP, X_FLIP, Y_FLIP,
+};
+use crate::{
+    io::addr::{BGP, OBP0, SCX, SCY, WX, WY},
+    GameGirl,
+};
+
+/// Dots the fetcher takes for a tile, and the PPU stalls for an object.
+const FETCH_DOTS: u8 = 6;
+
+/// A background or window pixel waiting in the FIFO.
+#[derive(Copy, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+struct BgPixel {
+    colour: u8,
+    /// CGB palette.
+    palette: u8,
+    /// CGB BG-to-OBJ priority.
+    priority: bool,
+}
+
+/// An object pixel waiting in the FIFO. Colour 0 is transparent.
+#[derive(Copy, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+struct ObjPixel {
+    colour: u8,
+    /// OBP0/OBP1 on DMG, palette 0-7 on CGB.
+    palette: u8,
+    /// If the object is behind background colours 1-3.
+    priority: bool,
+    oam_idx: u8,
+}
+
+/// State of the pixel FIFO renderer, used instead of rendering whole lines
+/// at once when `gb_accurate_ppu` is enabled. It draws the line dot by dot
+/// while in mode 3, reading registers as it goes, so mid-scanline changes
+/// to scrolling, palettes and LCDC take effect at the right pixel.
+#[derive(Default)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct Fifo {
+    /// If the current line is being drawn by the FIFO.
+    pub(super) active: bool,
+    /// Dots elapsed in mode 3 on this line, in steps of the scheduler event.
+    pub(super) elapsed: u16,
+    /// X coordinate of the next pixel to be output.
+    x: u8,
+    /// Pixels still to be dropped at the start of the line, for
+    /// fine scrolling with SCX or the window with WX < 7.
+    discard: u8,
+    /// Dots the fetcher has spent on the current tile.
+    fetch_step: u8,
+    /// Tile column the fetcher is at, relative to the start of the
+    /// background or window.
+    fetch_x: u8,
+    /// If the window is being fetched.
+    window: bool,
+    /// If the window was reached on this line.
+    window_drawn: bool,
+    /// Dots left until output resumes after fetching an object.
+    stall: u8,
+    bg: VecDeque<BgPixel>,
+    obj: VecDeque<ObjPixel>,
+    /// OAM indices of the objects on this line that were not fetched yet.
+    sprites: Vec<u8>,
+}
+
+impl Ppu {
+    /// Start drawing a line at the end of OAM scan.
+    pub(super) fn fifo_start_line(gg: &mut GameGirl) {
+        let height = 8 + gg.lcdc(BIG_OBJS) as i16 * 8;
+        let ly = gg.ppu.line as i16;
+        let sprites = (0..40)
+            .filter(|idx| {
+                let y = Sprite::from(&gg.mem, *idx).y;
+                y <= ly && (y + height) > ly
+            })
+            .take(10)
+            .collect();
+
+        gg.ppu.fifo = Fifo {
+            active: true,
+            discard: gg[SCX] & 7,
+            sprites,
+            ..Fifo::default()
+        };
+    }
+
+    /// Run the FIFO for the given amount of dots.
+    /// Returns if the line is finished, ending mode 3.
+    pub(super) fn fifo_step(gg: &mut GameGirl, dots: u16) -> bool {
+        gg.ppu.fifo.elapsed += dots;
+        for _ in 0..dots {
+            Self::fifo_dot(gg);
+            if gg.ppu.fifo.x == 160 {
+                if gg.ppu.fifo.window_drawn {
+                    gg.ppu.window_line += 1;
+                }
+                return true;
+            }
+        }
+        false
+    }
+
+    fn fifo_dot(gg: &mut GameGirl) {
+        let fifo = &mut gg.ppu.fifo;
+        if fifo.stall > 0 {
+            fifo.stall -= 1;
+            return;
+        }
+
+        let fifo = &gg.ppu.fifo;
+        if !fifo.window_drawn
+            && fifo.discard == 0
+            && gg.lcdc(WIN_EN)
+            && gg[WY] <= gg.ppu.line
+            && gg[WX] <= 166
+            && fifo.x as u16 + 7 >= gg[WX] as u16
+        {
+            gg.ppu.fifo.window_drawn = true;
+            if !Self::hidden_layers(gg).is_bit(1) {
+                let wx = gg[WX];
+                let fifo = &mut gg.ppu.fifo;
+                fifo.window = true;
+                fifo.bg.clear();
+                fifo.fetch_step = 0;
+                fifo.fetch_x = 0;
+                // With WX < 7, the window starts left of the screen, so
+                // its first pixels are not shown
+                fifo.discard = 7u8.saturating_sub(wx);
+            }
+        }
+
+        if gg.ppu.fifo.discard == 0 && gg.lcdc(OBJ_EN) && !Self::hidden_layers(gg).is_bit(2) {
+            let x = gg.ppu.fifo.x as i16;
+            let mut fetched = false;
+            let mut i = 0;
+            while i < gg.ppu.fifo.sprites.len() {
+                let idx = gg.ppu.fifo.sprites[i];
+                let sprite = Sprite::from(&gg.mem, idx);
+                if sprite.x > -8 && sprite.x.max(0) == x {
+                    Self::fifo_fetch_obj(gg, idx, sprite);
+                    gg.ppu.fifo.sprites.remove(i);
+                    fetched = true;
+                } else {
+                    i += 1;
+                }
+            }
+            if fetched {
+                gg.ppu.fifo.stall = FETCH_DOTS - 1;
+                return;
+            }
+        }
+
+        let fifo = &mut gg.ppu.fifo;
+        fifo.fetch_step = (fifo.fetch_step + 1).min(FETCH_DOTS);
+        if fifo.fetch_step == FETCH_DOTS && fifo.bg.is_empty() {
+            Self::fifo_fetch_bg(gg);
+        }
+
+        let fifo = &mut gg.ppu.fifo;
+        let Some(bg) = fifo.bg.pop_front() else {
+            return;
+        };
+        let obj = fifo.obj.pop_front();
+        if fifo.discard > 0 {
+            fifo.discard -= 1;
+            return;
+        }
+
+        let colour = Self::fifo_mix(gg, bg, obj);
+        let (x, y) = (gg.ppu.fifo.x, gg.ppu.line);
+        gg.ppu.set_pixel(x, y, colour);
+        gg.ppu.fifo.x += 1;
+    }
+
+    /// Fetch the next 8 pixels of background or window into the FIFO.
+    /// Registers are read at this point, which is what makes mid-scanline
+    /// changes work.
+    fn fifo_fetch_bg(gg: &mut GameGirl) {
+        let fifo = &gg.ppu.fifo;
+        let (map_addr, map_x, map_y) = if fifo.window {
+            (gg.map_addr(WIN_MAP), fifo.fetch_x, gg.ppu.window_line)
+        } else {
+            (
+                gg.map_addr(BG_MAP),
+                ((gg[SCX] >> 3) + fifo.fetch_x) & 31,
+                gg[SCY].wrapping_add(gg.ppu.line),
+            )
+        };
+
+        let tile_addr = map_addr + ((map_y / 8).u16() * 0x20) + map_x.u16();
+        let attributes = if gg.cgb {
+            gg.mem.vram[0x2000 + tile_addr.us()]
+        } else {
+            0
+        };
+        let tile_y = if attributes.is_bit(6) {
+            7 - (map_y & 7)
+        } else {
+            map_y & 7
+        };
+        let tile_data_addr = Self::bg_tile_data_addr(gg, gg.mem.vram[tile_addr.us()])
+            + (tile_y.u16() * 2)
+            + attributes.bit(3).u16() * 0x2000;
+        let high = gg.mem.vram[tile_data_addr.us() + 1];
+        let low = gg.mem.vram[tile_data_addr.us()];
+
+        let fifo = &mut gg.ppu.fifo;
+        for tile_x in 0..8 {
+            let bit = if attributes.is_bit(5) {
+                tile_x
+            } else {
+                7 - tile_x
+            };
+            fifo.bg.push_back(BgPixel {
+                colour: (high.bit(bit) << 1) + low.bit(bit),
+                palette: attributes & 7,
+                priority: attributes.is_bit(7),
+            });
+        }
+        fifo.fetch_x += 1;
+        fifo.fetch_step = 0;
+    }
+
+    /// Fetch an object and merge it into the object FIFO.
+    fn fifo_fetch_obj(gg: &mut GameGirl, oam_idx: u8, sprite: Sprite) {
+        let line = gg.ppu.line as i16;
+        let tile_y_op = (line - sprite.y) & 0x07;
+        let tile_y = if sprite.opt.is_bit(Y_FLIP) {
+            7 - tile_y_op
+        } else {
+            tile_y_op
+        };
+        let tile_num = match () {
+            _ if gg.lcdc(BIG_OBJS) && (((line - sprite.y) <= 7) != sprite.opt.is_bit(Y_FLIP)) => {
+                sprite.tile_num & 0xFE
+            }
+            _ if gg.lcdc(BIG_OBJS) => sprite.tile_num | 0x01,
+            _ => sprite.tile_num,
+        };
+        let tile_data_addr = (tile_num.u16() * 0x10)
+            + (tile_y as u16 * 2)
+            + ((gg.cgb && sprite.opt.is_bit(CGB_BANK)) as u16) * 0x2000;
+        let high = gg.mem.vram[tile_data_addr.us() + 1];
+        let low = gg.mem.vram[tile_data_addr.us()];
+
+        // On CGB, objects earlier in OAM are in front. On DMG, the one
+        // with the lower X coordinate is, which is the one fetched first.
+        let oam_priority = matches!(
+            &gg.ppu.kind,
+            PpuKind::Cgb(cgb) if cgb.dmg_used_x_obj_cords.is_none()
+        );
+        let palette = if gg.cgb {
+            sprite.opt & 7
+        } else {
+            sprite.opt.bit(DMG_PAL)
+        };
+
+        let fifo = &mut gg.ppu.fifo;
+        for tile_x in 0..8 {
+            let screen_x = sprite.x + tile_x as i16;
+            if screen_x < fifo.x as i16 {
+                continue;
+            }
+            let bit = if sprite.opt.is_bit(X_FLIP) {
+                tile_x
+            } else {
+                7 - tile_x
+            };
+            let colour = (high.bit(bit) << 1) + low.bit(bit);
+            let slot = (screen_x - fifo.x as i16) as usize;
+            while fifo.obj.len() <= slot {
+                fifo.obj.push_back(ObjPixel::default());
+            }
+
+            let existing = &mut fifo.obj[slot];
+            if colour != 0 && (existing.colour == 0 || (oam_priority && oam_idx < existing.oam_idx))
+            {
+                *existing = ObjPixel {
+                    colour,
+                    palette,
+                    priority: sprite.opt.is_bit(PRIORITY),
+                    oam_idx,
+                };
+            }
+        }
+    }
+
+    /// Mix a background and object pixel into the final colour.
+    fn fifo_mix(gg: &GameGirl, bg: BgPixel, obj: Option<ObjPixel>) -> Colour {
+        let bg_en = gg.lcdc(BG_EN);
+        let white = [COLOURS[0], COLOURS[0], COLOURS[0], 255];
+        let obj = obj.filter(|o| o.colour != 0);
+
+        match &gg.ppu.kind {
+            PpuKind::Dmg { .. } => {
+                let bg_off = !bg_en || Self::hidden_layers(gg).is_bit(0);
+                let bg_colour = if bg_off { 0 } else { bg.colour };
+                match obj {
+                    Some(obj) if !obj.priority || bg_colour == 0 => {
+                        Self::get_colour(gg[OBP0 + obj.palette.u16()], obj.colour)
+                    }
+                    _ if bg_off => white,
+                    _ => Self::get_colour(gg[BGP], bg_colour),
+                }
+            }
+
+            PpuKind::Cgb(cgb) => {
+                // In DMG compatibility mode, BG_EN disables the background
+                // like on DMG; on CGB, it removes background priority.
+                let compat = cgb.dmg_used_x_obj_cords.is_some();
+                let bg_off = (compat && !bg_en) || Self::hidden_layers(gg).is_bit(0);
+                let bg_colour = if bg_off { 0 } else { bg.colour };
+                match obj {
+                    Some(obj) if bg_colour == 0 || !bg_en || (!bg.priority && !obj.priority) => {
+                        cgb.obj_palettes[((obj.palette * 4) + obj.colour).us()].colour
+                    }
+                    _ if bg_off => white,
+                    _ => cgb.bg_palette_colour(bg.palette, bg_colour.us()),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use common::{
+        common::options::{CgbMode, SystemConfig},
+        Core,
+    };
+
+    use super::*;
+    use crate::{
+        io::{
+            addr::{HIGH_START, LCDC, STAT},
+            scheduling::{GGEvent, PpuEvent},
+        },
+        tests::mbc3_rom,
+    };
+
+    /// A DMG with the background made of alternating columns of
+    /// colour 0 and colour 3 tiles, starting with colour 0.
+    fn striped_gg() -> Box<GameGirl> {
+        let mut rom = mbc3_rom();
+        // JR -2
+        rom[0x100] = 0x18;
+        rom[0x101] = 0xFE;
+        let config = SystemConfig {
+            mode: CgbMode::Never,
+            gb_accurate_ppu: true,
+            ..SystemConfig::default()
+        };
+        let mut gg = GameGirl::with_cart(rom, None, &config).unwrap();
+        gg.skip_bootrom();
+
+        for addr in 0x8010..0x8020 {
+            gg.set(addr, 0xFF);
+        }
+        for column in 0..0x400 {
+            gg.set(0x9800 + column, (column & 1) as u8);
+        }
+        gg.set(HIGH_START + BGP, 0xE4);
+        gg.set(HIGH_START + SCX, 0);
+        // LCD, tile data 0x8000, objects, BG
+        gg.set(HIGH_START + LCDC, 0x93);
+        gg
+    }
+
+    /// Draw line 0 dot by dot, running `mid_line` once the first 81
+    /// pixels were output. Returns the length of mode 3 in dots.
+    fn draw_line(gg: &mut GameGirl, mid_line: impl FnOnce(&mut GameGirl)) -> u16 {
+        gg.ppu.line = 0;
+        Ppu::fifo_start_line(gg);
+        let mut mid_line = Some(mid_line);
+        loop {
+            if gg.ppu.fifo.x == 81 {
+                if let Some(mid_line) = mid_line.take() {
+                    mid_line(gg);
+                }
+            }
+            if Ppu::fifo_step(gg, 1) {
+                return gg.ppu.fifo.elapsed;
+            }
+        }
+    }
+
+    /// Colour index of the given pixel on line 0, with BGP 0xE4.
+    fn shade(gg: &GameGirl, x: usize) -> usize {
+        let colour = gg.ppu.pixels[x][0];
+        COLOURS.iter().position(|c| *c == colour).unwrap()
+    }
+
+    #[test]
+    fn mode_3_length() {
+        let mut gg = striped_gg();
+        // The first fetch, then one pixel every dot
+        let base = draw_line(&mut gg, |_| ());
+        assert_eq!(base, FETCH_DOTS as u16 + 159);
+
+        // Fine scrolling discards pixels at the start of the line
+        gg.set(HIGH_START + SCX, 5);
+        assert_eq!(draw_line(&mut gg, |_| ()), base + 5);
+        gg.set(HIGH_START + SCX, 0);
+
+        // Every object stalls for its fetch
+        gg.mem.oam[..4].copy_from_slice(&[16, 48, 1, 0]);
+        assert_eq!(draw_line(&mut gg, |_| ()), base + FETCH_DOTS as u16);
+        gg.mem.oam[4..8].copy_from_slice(&[16, 100, 1, 0]);
+        assert_eq!(draw_line(&mut gg, |_| ()), base + 2 * FETCH_DOTS as u16);
+        assert_eq!(shade(&gg, 40), 3);
+        assert_eq!(shade(&gg, 92), 3);
+
+        gg.set(HIGH_START + SCX, 5);
+        assert_eq!(draw_line(&mut gg, |_| ()), base + 5 + 2 * FETCH_DOTS as u16);
+    }
+
+    #[test]
+    fn mid_line_bgp_change() {
+        let mut gg = striped_gg();
+        // Swap colours 0 and 3
+        draw_line(&mut gg, |gg| gg.set(HIGH_START + BGP, 0x27));
+        for x in 0..160 {
+            let stripe = if (x / 8) % 2 == 1 { 3 } else { 0 };
+            let expected = if x < 81 { stripe } else { 3 - stripe };
+            assert_eq!(shade(&gg, x), expected, "{x}");
+        }
+    }
+
+    #[test]
+    fn mid_line_scx_change() {
+        let mut gg = striped_gg();
+        // Tiles already fetched keep the old scroll
+        draw_line(&mut gg, |gg| gg.set(HIGH_START + SCX, 8));
+        for x in 0..160 {
+            let tile = if x < 88 { x / 8 } else { x / 8 + 1 };
+            let expected = if tile % 2 == 1 { 3 } else { 0 };
+            assert_eq!(shade(&gg, x), expected, "{x}");
+        }
+    }
+
+    #[test]
+    fn lcd_off_mid_line() {
+        let mut gg = striped_gg();
+        gg.c.debugger.running = true;
+        while gg[STAT] & 3 != 3 {
+            gg.advance();
+        }
+        gg.advance();
+        assert!(gg.ppu.fifo.active && gg.ppu.fifo.x < 160);
+
+        gg.set(HIGH_START + LCDC, 0x13);
+        assert!(!gg.ppu.fifo.active);
+        assert!(matches!(
+            gg.ppu.resume_data,
+            Some((_, GGEvent::PpuEvent(PpuEvent::UploadEnd)))
+        ));
+
+        // The line is finished without the FIFO once it is back on
+        gg.set(HIGH_START + LCDC, 0x93);
+        let line = gg.ppu.line;
+        while gg.ppu.line == line {
+            gg.advance();
+        }
+        assert!(!gg.ppu.fifo.active);
+        assert_eq!(gg[STAT] & 3, 2);
+    }
+
+    /// Render a frame with the window at the given WX covering the screen.
+    /// Every window tile has its first column in colour 3.
+    fn window_frame(wx: u8) -> Vec<Colour> {
+        let mut rom = mbc3_rom();
+        // JR -2
+        rom[0x100] = 0x18;
+        rom[0x101] = 0xFE;
+        let config = SystemConfig {
+            gb_accurate_ppu: true,
+            ..SystemConfig::default()
+        };
+        let mut gg = GameGirl::with_cart(rom, None, &config).unwrap();
+        gg.skip_bootrom();
+
+        for row in 0..8 {
+            gg.set(0x8010 + row * 2, 0x80);
+            gg.set(0x8011 + row * 2, 0x80);
+        }
+        for addr in 0x9C00..0xA000 {
+            gg.set(addr, 1);
+        }
+        gg.set(HIGH_START + BGP, 0xE4);
+        gg.set(HIGH_START + WY, 0);
+        gg.set(HIGH_START + WX, wx);
+        // LCD, window map 1, window, tile data 0x8000, BG
+        gg.set(HIGH_START + LCDC, 0xF1);
+
+        gg.render_one_frame();
+        gg.render_one_frame()
+    }
+
+    #[test]
+    fn window_left_of_screen() {
+        // WX 7 is the left edge of the screen
+        let frame = window_frame(7);
+        let line = &frame[160 * 10..160 * 11];
+        assert_ne!(line[0], line[1]);
+        assert_eq!(line[0], line[8]);
+
+        // With WX 3, the first 4 pixels of the window are cut off
+        let frame = window_frame(3);
+        let line = &frame[160 * 10..160 * 11];
+        assert_eq!(line[0], line[3]);
+        assert_ne!(line[3], line[4]);
+        assert_eq!(line[4], line[12]);
+        assert_eq!(line[4][..3], [COLOURS[3]; 3]);
+    }
+}
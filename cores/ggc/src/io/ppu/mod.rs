@@ -6,8 +6,14 @@
 // If a copy of these licenses was not distributed with this file, you can
 // obtain them at https://mozilla.org/MPL/2.0/ and http://www.gnu.org/licenses/.
 
-use common::{numutil::NumExt, Colour, Time, TimeS};
+use common::{
+    common::{debugger::SpriteInfo, options::SystemConfig},
+    numutil::NumExt,
+    Colour, Core, Time, TimeS,
+};
 pub use dmg::COLOURS;
+use fifo::Fifo;
+pub use oam_bug::OamCorruption;
 
 use crate::{
     cpu::Interrupt,
@@ -22,6 +28,9 @@ use crate::{
 
 mod cgb;
 mod dmg;
+mod fifo;
+mod oam_bug;
+mod tile_map;
 
 // LCDC
 const BG_EN: u16 = 0;
@@ -31,7 +40,7 @@ const BG_MAP: u16 = 3;
 const ALT_BG_TILE: u16 = 4;
 const WIN_EN: u16 = 5;
 const WIN_MAP: u16 = 6;
-pub(super) const DISP_EN: u16 = 7;
+pub(crate) const DISP_EN: u16 = 7;
 
 // OAM sprites 'option' byte
 const DMG_PAL: u16 = 4;
@@ -49,9 +58,23 @@ pub struct Ppu {
     window_line: u8,
     line: u8,
     kind: PpuKind,
+    /// Time the current OAM scan started at, for the OAM corruption bug.
+    #[cfg_attr(feature = "serde", serde(default))]
+    oam_scan_start: Time,
+    #[cfg_attr(feature = "serde", serde(default))]
+    fifo: Fifo,
 
     #[cfg_attr(feature = "serde", serde(skip, default = "serde_colour_arr"))]
     pixels: [Colour; 160 * 144],
+    /// Last frame output, for LCD ghosting. Seeded from the first frame
+    /// after ghosting is enabled.
+    #[cfg_attr(feature = "serde", serde(skip, default))]
+    last_frame: Option<Vec<Colour>>,
+
+    /// Layers hidden for debugging: Bit 0 is the background,
+    /// bit 1 the window, bit 2 the objects.
+    #[cfg_attr(feature = "serde", serde(skip, default))]
+    pub hidden_layers: u8,
 
     /// Info about what event to schedule in case of LCDC bit 7 being
     /// unset, disabling the PPU.
@@ -66,19 +89,41 @@ impl Ppu {
         }
 
         let (next_mode, time) = match evt {
+            PpuEvent::OamScanEnd if gg.c.config.gb_accurate_ppu => {
+                Self::fifo_start_line(gg);
+                (PpuEvent::PixelTransfer, 4)
+            }
             PpuEvent::OamScanEnd => (PpuEvent::UploadEnd, 176),
 
-            PpuEvent::UploadEnd => {
-                if gg.c.video_buffer.should_render_this_frame() {
-                    Self::render_line(gg);
+            PpuEvent::PixelTransfer => {
+                if Self::fifo_step(gg, 4) {
+                    (PpuEvent::UploadEnd, 0)
+                } else {
+                    (PpuEvent::PixelTransfer, 4)
                 }
+            }
+
+            PpuEvent::UploadEnd => {
+                // The FIFO already drew the line, and mode 3 took a
+                // variable amount of time
+                let hblank = if gg.ppu.fifo.active {
+                    gg.ppu.fifo.active = false;
+                    // Mode 3 can run over with many objects and a window
+                    376u16.saturating_sub(gg.ppu.fifo.elapsed) as TimeS
+                } else {
+                    // Ghosting needs every frame, including skipped ones
+                    if gg.c.video_buffer.should_render_this_frame() || gg.c.config.gb_lcd_ghosting {
+                        Self::render_line(gg);
+                    }
+                    200
+                };
 
                 gg.ppu.bg_occupied_pixels = [false; 160];
                 if gg.cgb && gg.hdma.hblank_transferring {
                     gg.scheduler.schedule(GGEvent::HdmaTransferStep, 2);
                 }
                 Self::stat_interrupt(gg, 3);
-                (PpuEvent::HblankEnd, 200)
+                (PpuEvent::HblankEnd, hblank)
             }
 
             PpuEvent::HblankEnd => {
@@ -92,10 +137,21 @@ impl Ppu {
                     Self::stat_interrupt(gg, 4);
                     gg.request_interrupt(Interrupt::VBlank);
 
+                    if gg.c.config.gb_lcd_ghosting {
+                        gg.ppu.blend_frame();
+                    } else {
+                        gg.ppu.last_frame = None;
+                    }
                     if gg.c.video_buffer.should_render_this_frame() {
-                        gg.c.video_buffer.push(gg.ppu.pixels.to_vec());
+                        let frame = match &gg.ppu.last_frame {
+                            Some(frame) => frame.clone(),
+                            None => gg.ppu.pixels.to_vec(),
+                        };
+                        gg.c.video_buffer.push(frame);
                     }
                     gg.c.video_buffer.start_next_frame();
+                    gg.scheduler.end_frame();
+                    gg.apply_cheats();
 
                     (PpuEvent::VblankEnd, 456)
                 } else {
@@ -127,6 +183,9 @@ impl Ppu {
         };
 
         gg[STAT] = gg[STAT] & 0xFC | next_mode.ordinal();
+        if next_mode == PpuEvent::OamScanEnd {
+            gg.ppu.oam_scan_start = gg.scheduler.now() - late_by as Time;
+        }
 
         gg.scheduler
             .schedule(GGEvent::PpuEvent(next_mode), time - late_by);
@@ -138,6 +197,15 @@ impl Ppu {
         }
     }
 
+    /// On DMG, writing STAT acts as if all interrupt sources were enabled
+    /// for a moment, requesting a STAT interrupt outside of mode 3 or
+    /// while LY=LYC.
+    pub fn stat_write_quirk(gg: &mut GameGirl) {
+        if gg.lcdc(DISP_EN) && (gg[STAT] & 3 != 3 || gg[STAT].is_bit(2)) {
+            gg.request_interrupt(Interrupt::Stat);
+        }
+    }
+
     fn lyc_interrupt(gg: &mut GameGirl) {
         if gg[LYC] == gg[LY] {
             Self::stat_interrupt(gg, 6);
@@ -170,7 +238,7 @@ impl Ppu {
             }
         }
 
-        if gg.lcdc(OBJ_EN) {
+        if gg.lcdc(OBJ_EN) && !Self::hidden_layers(gg).is_bit(2) {
             Self::render_objs(gg);
         }
 
@@ -188,9 +256,21 @@ impl Ppu {
         }
     }
 
+    /// Layers that should not be rendered, either hidden for debugging
+    /// or by a game hack.
+    fn hidden_layers(gg: &GameGirl) -> u8 {
+        gg.ppu.hidden_layers | gg.c.game_hacks.hidden_layers
+    }
+
     fn render_bg(gg: &mut GameGirl) {
+        if Self::hidden_layers(gg).is_bit(0) {
+            Self::clear_line(gg);
+            return;
+        }
+
         // Only render until the point where the window starts, should it be active
-        let end_x = if gg.lcdc(WIN_EN) && (7u8..166u8).contains(&gg[WX]) && gg[WY] <= gg.ppu.line {
+        let window_visible = gg.lcdc(WIN_EN) && !Self::hidden_layers(gg).is_bit(1);
+        let end_x = if window_visible && (7u8..166u8).contains(&gg[WX]) && gg[WY] <= gg.ppu.line {
             gg[WX] - 7
         } else {
             160
@@ -211,6 +291,11 @@ impl Ppu {
         if !(0..=159).contains(&wx) || gg[WY] > gg.ppu.line {
             return;
         }
+        if Self::hidden_layers(gg).is_bit(1) {
+            // Still count lines, to not break the window when shown again
+            gg.ppu().window_line += 1;
+            return;
+        }
 
         Self::render_bg_or_window(
             gg,
@@ -377,13 +462,73 @@ impl Ppu {
                 used_x_obj_coords: [None; 10],
             },
             pixels: [[0; 4]; 160 * 144],
+            last_frame: None,
+            hidden_layers: 0,
+            oam_scan_start: 0,
+            fifo: Fifo::default(),
             resume_data: None,
         }
     }
 
-    pub(super) fn configure(&mut self, cgb: bool, colour_correction: bool) {
+    /// Get all 40 sprites in OAM, for debugging.
+    pub fn sprites(gg: &GameGirl) -> Vec<SpriteInfo> {
+        (0..40)
+            .map(|idx| Sprite::from(&gg.mem, idx).info(gg.cgb))
+            .collect()
+    }
+
+    /// Write a sprite back to OAM, for debugging.
+    pub fn set_sprite(gg: &mut GameGirl, idx: usize, sprite: SpriteInfo) {
+        if idx < 40 {
+            Sprite::write(&mut gg.mem, idx, sprite, gg.cgb);
+        }
+    }
+
+    /// Called when the LCD is turned off, with the PPU event that will be
+    /// resumed once it is turned on again. The pixel FIFO does not keep
+    /// its state across this: If it was in the middle of a line, the line
+    /// is instead drawn at once when resuming.
+    pub(super) fn lcd_disabled(&mut self, resume: (Time, GGEvent)) -> (Time, GGEvent) {
+        match resume {
+            (time, GGEvent::PpuEvent(PpuEvent::PixelTransfer)) => {
+                self.fifo.active = false;
+                (time, GGEvent::PpuEvent(PpuEvent::UploadEnd))
+            }
+            resume => resume,
+        }
+    }
+
+    /// Blend the finished frame into the previous output for LCD ghosting.
+    /// The LCD's pixels only move part of the way to their new colour each
+    /// frame; blending with the previous output instead of only the
+    /// previous frame gives the resulting exponential response. Pixels
+    /// flickering every frame do not settle, but keep alternating between
+    /// two shades close to the middle (about 70 and 185 when flickering
+    /// between black and white), which looks like the shade in between.
+    /// Called for every frame, including ones skipped by frameskip.
+    fn blend_frame(&mut self) {
+        /// Part of the way a pixel moves towards its new colour each
+        /// frame, out of 8.
+        const RESPONSE: u16 = 5;
+        let Some(last_frame) = &mut self.last_frame else {
+            self.last_frame = Some(self.pixels.to_vec());
+            return;
+        };
+        for (last, new) in last_frame.iter_mut().zip(self.pixels.iter()) {
+            for (l, n) in last.iter_mut().zip(new.iter()).take(3) {
+                *l = ((n.u16() * RESPONSE + l.u16() * (8 - RESPONSE) + 4) / 8) as u8;
+            }
+            last[3] = 255;
+        }
+    }
+
+    pub(super) fn configure(&mut self, cgb: bool, conf: &SystemConfig) {
         self.kind = if cgb {
-            PpuKind::Cgb(Cgb::new(colour_correction))
+            PpuKind::Cgb(Cgb::new(
+                conf.cgb_colour_correction,
+                conf.cgb_bg_grading,
+                conf.cgb_obj_grading,
+            ))
         } else {
             PpuKind::Dmg {
                 used_x_obj_coords: [None; 10],
@@ -410,6 +555,42 @@ struct Sprite {
 }
 
 impl Sprite {
+    /// Convert to the system-independent sprite info.
+    fn info(self, cgb: bool) -> SpriteInfo {
+        SpriteInfo {
+            x: self.x,
+            y: self.y,
+            tile: self.tile_num.u16(),
+            x_flip: self.opt.is_bit(X_FLIP),
+            y_flip: self.opt.is_bit(Y_FLIP),
+            priority: self.opt.is_bit(PRIORITY) as u8,
+            palette: if cgb {
+                self.opt & 7
+            } else {
+                self.opt.is_bit(DMG_PAL) as u8
+            },
+        }
+    }
+
+    /// Write the given sprite info into OAM.
+    fn write(mmu: &mut Memory, idx: usize, sprite: SpriteInfo, cgb: bool) {
+        let base = idx * 4;
+        let mut opt = mmu.oam[base + 3]
+            .set_bit(X_FLIP, sprite.x_flip)
+            .set_bit(Y_FLIP, sprite.y_flip)
+            .set_bit(PRIORITY, sprite.priority != 0);
+        opt = if cgb {
+            (opt & !7) | (sprite.palette & 7)
+        } else {
+            opt.set_bit(DMG_PAL, sprite.palette != 0)
+        };
+
+        mmu.oam[base] = (sprite.y + 16) as u8;
+        mmu.oam[base + 1] = (sprite.x + 8) as u8;
+        mmu.oam[base + 2] = sprite.tile as u8;
+        mmu.oam[base + 3] = opt;
+    }
+
     fn from(mmu: &Memory, idx: u8) -> Self {
         let base = idx.us() * 4;
         Self {
@@ -450,3 +631,65 @@ fn serde_bool_arr() -> [bool; 160] {
 fn serde_colour_arr() -> [Colour; 160 * 144] {
     [[0, 0, 0, 255]; 160 * 144]
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::mbc3_rom;
+
+    #[test]
+    fn ghosting_keeps_flicker_between_shades() {
+        let mut ppu = Ppu::new();
+        ppu.pixels = [[0xFF; 4]; 160 * 144];
+        ppu.blend_frame();
+        assert_eq!(ppu.last_frame.as_ref().unwrap()[0], [0xFF; 4]);
+
+        let mut flicker = |frame: usize| {
+            let shade = if frame % 2 == 0 { 0 } else { 0xFF };
+            ppu.pixels = [[shade, shade, shade, 0xFF]; 160 * 144];
+            ppu.blend_frame();
+            let [r, g, b, a] = ppu.last_frame.as_ref().unwrap()[0];
+            assert_eq!([r, r, 0xFF], [g, b, a]);
+            r
+        };
+        for frame in 0..30 {
+            flicker(frame);
+        }
+        // Once steady, it alternates around the middle shade every frame
+        for frame in (30..60).step_by(2) {
+            let (dark, light) = (flicker(frame), flicker(frame + 1));
+            assert!((0x40..0x50).contains(&dark), "{dark}");
+            assert!((0xB0..0xC0).contains(&light), "{light}");
+            assert!((dark as u16 + light as u16).abs_diff(0xFF) <= 2);
+        }
+
+        // Small differences still make it through with rounding
+        ppu.pixels = [[0, 0, 0, 0xFF]; 160 * 144];
+        for _ in 0..30 {
+            ppu.blend_frame();
+        }
+        ppu.pixels = [[1, 1, 1, 0xFF]; 160 * 144];
+        ppu.blend_frame();
+        assert_eq!(ppu.last_frame.as_ref().unwrap()[0], [1, 1, 1, 0xFF]);
+    }
+
+    #[test]
+    fn stat_write_quirk_from_header() {
+        let mut rom = mbc3_rom();
+        rom[0x134..0x13D].copy_from_slice(b"ROAD RASH");
+        for (rom, quirk) in [(mbc3_rom(), false), (rom, true)] {
+            let mut gg = GameGirl::with_cart(rom, None, &SystemConfig::default()).unwrap();
+            gg.skip_bootrom();
+            assert_eq!(gg.c.game_hacks.stat_write_quirk, quirk);
+
+            // Turn on the LCD and wait for VBlank
+            gg.set(HIGH_START + LCDC, 0x91);
+            while gg[STAT] & 3 != 1 {
+                gg.advance_clock(1);
+            }
+            gg[IF] = 0;
+            gg.set(HIGH_START + STAT, 0);
+            assert_eq!(gg[IF].is_bit(Interrupt::Stat.to_index()), quirk);
+        }
+    }
+}
@@ -0,0 +1,199 @@
+// Copyright (c) 2024 Leela Aurelia, git@elia.garden
+//
+// Unless otherwise noted, this file is released and thus subject to the
+// terms of the Mozilla Public License Version 2.0 (MPL-2.0) or the
+// GNU General Public License Version 3 (GPL-3).
+// If a copy of these licenses was not distributed with this file, you can
+// obtain them at https://mozilla.org/MPL/2.0/ and http://www.gnu.org/licenses/.
+
+//! The DMG OAM corruption bug: When the CPU puts an address in OAM range on
+//! the bus while the PPU is scanning OAM, the row of OAM the PPU is currently
+//! reading gets corrupted with data from the row before it.
+//! OAM is split into 20 rows of 8 bytes, which are in turn split into 4
+//! 16-bit words. See https://gbdev.io/pandocs/OAM_Corruption_Bug.html.
+
+use common::numutil::NumExt;
+
+use super::Ppu;
+use crate::{io::addr::STAT, GameGirl};
+
+/// Length of a single row of OAM in bytes.
+const ROW: usize = 8;
+
+/// The kind of CPU access that caused the corruption.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum OamCorruption {
+    /// A read of an address in OAM.
+    Read,
+    /// A write to an address in OAM, or the CPU's increment/decrement
+    /// unit operating on a register containing such an address.
+    Write,
+    /// A read of an address in OAM while the register containing
+    /// it is also incremented/decremented, like with `LD A, [HL+]` or `POP`.
+    ReadIncrease,
+}
+
+impl Ppu {
+    /// Apply the OAM corruption bug for an access of the given address, if
+    /// it is in OAM, the bug is enabled and the PPU is scanning OAM.
+    pub fn oam_bug(gg: &mut GameGirl, addr: u16, kind: OamCorruption) {
+        if !(0xFE00..=0xFEFF).contains(&addr) {
+            return;
+        }
+        let Some(row) = Self::oam_bug_row(gg) else {
+            return;
+        };
+
+        let oam = &mut gg.mem.oam;
+        if kind == OamCorruption::ReadIncrease && (4..19).contains(&row) {
+            let a = word(oam, row - 2, 0);
+            let b = word(oam, row - 1, 0);
+            let c = word(oam, row, 0);
+            let d = word(oam, row - 2, 2);
+            set_word(oam, row - 1, 0, (b & (a | c | d)) | (a & c & d));
+            copy_row(oam, row - 1, row);
+            copy_row(oam, row - 1, row - 2);
+        }
+
+        let a = word(oam, row, 0);
+        let b = word(oam, row - 1, 0);
+        let c = word(oam, row - 1, 2);
+        let first = match kind {
+            OamCorruption::Write => ((a ^ c) & (b ^ c)) ^ c,
+            OamCorruption::Read | OamCorruption::ReadIncrease => b | (a & c),
+        };
+        set_word(oam, row, 0, first);
+        oam.copy_within((row - 1) * ROW + 2..row * ROW, row * ROW + 2);
+    }
+
+    /// The row of OAM the PPU is currently reading, if it is scanning OAM
+    /// and the row is one that can be corrupted.
+    fn oam_bug_row(gg: &GameGirl) -> Option<usize> {
+        if gg.cgb || !gg.c.config.gb_oam_bug || !gg.lcdc(super::DISP_EN) || gg[STAT] & 3 != 2 {
+            return None;
+        }
+        let elapsed = gg.scheduler.now().saturating_sub(gg.ppu.oam_scan_start);
+        let row = (elapsed / 4) as usize;
+        // The first row is never corrupted.
+        (1..20).contains(&row).then_some(row)
+    }
+}
+
+fn word(oam: &[u8; 160], row: usize, word: usize) -> u16 {
+    let idx = row * ROW + word * 2;
+    oam[idx].u16() | (oam[idx + 1].u16() << 8)
+}
+
+fn set_word(oam: &mut [u8; 160], row: usize, word: usize, value: u16) {
+    let idx = row * ROW + word * 2;
+    oam[idx] = value.u8();
+    oam[idx + 1] = (value >> 8).u8();
+}
+
+fn copy_row(oam: &mut [u8; 160], from: usize, to: usize) {
+    oam.copy_within(from * ROW..(from + 1) * ROW, to * ROW);
+}
+
+#[cfg(test)]
+mod tests {
+    use common::common::options::SystemConfig;
+
+    use super::*;
+    use crate::{io::addr::LCDC, tests::mbc3_rom};
+
+    /// A DMG in the middle of scanning OAM row 5, with the first words of
+    /// rows 3-5 and the third word of rows 3 and 4 set to the given values.
+    /// Everything else in OAM is 0x11.
+    fn scanning_row_5(words: [u16; 5]) -> Box<GameGirl> {
+        let config = SystemConfig {
+            gb_oam_bug: true,
+            ..SystemConfig::default()
+        };
+        let mut gg = GameGirl::with_cart(mbc3_rom(), None, &config).unwrap();
+        gg.advance_clock(10);
+        gg[LCDC] |= 0x80;
+        gg[STAT] = (gg[STAT] & !3) | 2;
+        gg.ppu.oam_scan_start = gg.scheduler.now() - 5 * 4;
+
+        gg.mem.oam = [0x11; 160];
+        let [row3, row4, row5, row3_2, row4_2] = words;
+        set_word(&mut gg.mem.oam, 3, 0, row3);
+        set_word(&mut gg.mem.oam, 4, 0, row4);
+        set_word(&mut gg.mem.oam, 5, 0, row5);
+        set_word(&mut gg.mem.oam, 3, 2, row3_2);
+        set_word(&mut gg.mem.oam, 4, 2, row4_2);
+        gg
+    }
+
+    #[test]
+    fn write_corruption() {
+        let mut gg = scanning_row_5([0, 0xCCCC, 0xF0F0, 0, 0xAAAA]);
+        let mut expected = gg.mem.oam;
+        gg.idu_used(0xFE40);
+        // ((a ^ c) & (b ^ c)) ^ c, the rest of the row is copied from above
+        set_word(&mut expected, 5, 0, 0xE8E8);
+        set_word(&mut expected, 5, 2, 0xAAAA);
+        assert_eq!(gg.mem.oam, expected);
+    }
+
+    #[test]
+    fn read_corruption() {
+        let mut gg = scanning_row_5([0, 0xCCCC, 0xF0F0, 0, 0xAAAA]);
+        let mut expected = gg.mem.oam;
+        // Reads take a cycle before they reach OAM
+        gg.ppu.oam_scan_start += 4;
+        gg.read8(0xFE00);
+        // b | (a & c)
+        set_word(&mut expected, 5, 0, 0xECEC);
+        set_word(&mut expected, 5, 2, 0xAAAA);
+        assert_eq!(gg.mem.oam, expected);
+    }
+
+    #[test]
+    fn read_increase_corruption() {
+        let mut gg = scanning_row_5([0x00FF, 0xCCCC, 0x0F0F, 0x3333, 0xAAAA]);
+        let mut expected = gg.mem.oam;
+        gg.ppu.oam_scan_start += 4;
+        gg.read8_inc(0xFE9F);
+        // (b & (a | c | d)) | (a & c & d), copied to the rows around it
+        set_word(&mut expected, 4, 0, 0x0CCF);
+        copy_row(&mut expected, 4, 3);
+        copy_row(&mut expected, 4, 5);
+        assert_eq!(gg.mem.oam, expected);
+    }
+
+    #[test]
+    fn no_corruption() {
+        let words = [0, 0xCCCC, 0xF0F0, 0, 0xAAAA];
+        let untouched = scanning_row_5(words).mem.oam;
+
+        // Outside of OAM
+        let mut gg = scanning_row_5(words);
+        gg.idu_used(0xFF00);
+        assert_eq!(gg.mem.oam, untouched);
+
+        // Disabled
+        let mut gg = scanning_row_5(words);
+        gg.c.config.gb_oam_bug = false;
+        gg.idu_used(0xFE00);
+        assert_eq!(gg.mem.oam, untouched);
+
+        // CGB
+        let mut gg = scanning_row_5(words);
+        gg.cgb = true;
+        gg.idu_used(0xFE00);
+        assert_eq!(gg.mem.oam, untouched);
+
+        // Not scanning OAM
+        let mut gg = scanning_row_5(words);
+        gg[STAT] &= !3;
+        gg.idu_used(0xFE00);
+        assert_eq!(gg.mem.oam, untouched);
+
+        // First row
+        let mut gg = scanning_row_5(words);
+        gg.ppu.oam_scan_start = gg.scheduler.now();
+        gg.idu_used(0xFE00);
+        assert_eq!(gg.mem.oam, untouched);
+    }
+}
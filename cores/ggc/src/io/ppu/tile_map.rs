@@ -0,0 +1,99 @@
+// Copyright (c) 2024 Leela Aurelia, git@elia.garden
+//
+// Unless otherwise noted, this file is released and thus subject to the
+// terms of the Mozilla Public License Version 2.0 (MPL-2.0) or the
+// GNU General Public License Version 3 (GPL-3).
+// If a copy of these licenses was not distributed with this file, you can
+// obtain them at https://mozilla.org/MPL/2.0/ and http://www.gnu.org/licenses/.
+
+use common::{common::debugger::TileMap, numutil::NumExt};
+
+use super::{Ppu, PpuKind, BG_MAP, WIN_MAP};
+use crate::{
+    io::addr::{SCX, SCY, WX, WY},
+    GameGirl,
+};
+
+/// Size of a tile map in pixels.
+const SIZE: usize = 256;
+
+impl Ppu {
+    /// Render the full 32x32 tile map of the background or window,
+    /// for debugging. For the background, the visible area is the one
+    /// selected by SCX/SCY; for the window, it is the part of the map
+    /// that fits on screen at WX/WY.
+    pub fn tile_map(gg: &GameGirl, window: bool) -> TileMap {
+        let map_addr = gg.map_addr(if window { WIN_MAP } else { BG_MAP });
+        let dmg_colours = Self::get_bg_colours(gg);
+
+        let mut pixels = vec![[0; 4]; SIZE * SIZE];
+        for tile in 0..(32 * 32) {
+            let addr = (map_addr + tile).us();
+            // Attributes are always 0 on DMG, since VRAM bank 1 is never written
+            let attributes = gg.mem.vram[0x2000 + (addr & 0x1FFF)];
+            let data_addr = Self::bg_tile_data_addr(gg, gg.mem.vram[addr]).us()
+                + attributes.bit(3).us() * 0x2000;
+
+            for line in 0..8 {
+                let tile_y = if attributes.is_bit(6) { 7 - line } else { line };
+                let low = gg.mem.vram[data_addr + tile_y * 2];
+                let high = gg.mem.vram[data_addr + tile_y * 2 + 1];
+                for pixel in 0..8 {
+                    let bit = if attributes.is_bit(5) {
+                        pixel
+                    } else {
+                        7 - pixel
+                    };
+                    let colour_idx = ((high.bit(bit.u16()) << 1) + low.bit(bit.u16())).us();
+                    let colour = match &gg.ppu.kind {
+                        PpuKind::Dmg { .. } => dmg_colours[colour_idx],
+                        PpuKind::Cgb(cgb) => cgb.bg_palette_colour(attributes & 7, colour_idx),
+                    };
+
+                    let x = (tile.us() % 32) * 8 + pixel;
+                    let y = (tile.us() / 32) * 8 + line;
+                    pixels[y * SIZE + x] = colour;
+                }
+            }
+        }
+
+        let (scroll, visible) = if window {
+            let wx = gg[WX].us().saturating_sub(7).min(160);
+            let wy = gg[WY].us().min(144);
+            ([0, 0], [160 - wx, 144 - wy])
+        } else {
+            ([gg[SCX].us(), gg[SCY].us()], [160, 144])
+        };
+        TileMap {
+            width: SIZE,
+            height: SIZE,
+            pixels,
+            scroll,
+            visible,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use common::common::options::SystemConfig;
+
+    use super::*;
+    use crate::tests::mbc3_rom;
+
+    #[test]
+    fn window_visible_area() {
+        let mut gg = GameGirl::with_cart(mbc3_rom(), None, &SystemConfig::default()).unwrap();
+
+        // Partially on screen, starting at 100,80
+        gg[WX] = 107;
+        gg[WY] = 80;
+        let map = Ppu::tile_map(&gg, true);
+        assert_eq!(map.visible_rects(), [[0, 0, 60, 64]]);
+
+        // Off the right edge of the screen
+        gg[WX] = 200;
+        let map = Ppu::tile_map(&gg, true);
+        assert!(map.visible_rects().is_empty());
+    }
+}
@@ -9,7 +9,7 @@
 use common::{components::scheduler::Kind, TimeS};
 use GGEvent::*;
 
-use super::joypad::Joypad;
+use super::{joypad::Joypad, serial::Serial};
 use crate::{
     io::{dma, dma::Hdma, ppu::Ppu},
     GameGirl,
@@ -34,6 +34,10 @@ pub enum GGEvent {
     HdmaTransferStep,
     /// A GDMA transfer.
     GdmaTransfer,
+    /// A serial transfer finished.
+    SerialTransferEnd,
+    /// Check if the other end of the link cable sent a byte.
+    SerialLinkPoll,
 }
 
 impl GGEvent {
@@ -46,11 +50,24 @@ impl GGEvent {
             DMAFinish => dma::do_oam_dma(gg),
             HdmaTransferStep => Hdma::handle_hdma(gg),
             GdmaTransfer => Hdma::handle_gdma(gg),
+            SerialTransferEnd => Serial::transfer_end(gg),
+            SerialLinkPoll => Serial::link_poll(gg),
         }
     }
 }
 
-impl Kind for GGEvent {}
+impl Kind for GGEvent {
+    fn name(&self) -> &'static str {
+        match self {
+            PauseEmulation => "Pause",
+            UpdateKeypad => "Keypad",
+            PpuEvent(_) => "PPU",
+            DMAFinish => "OAM DMA",
+            HdmaTransferStep | GdmaTransfer => "HDMA",
+            SerialTransferEnd | SerialLinkPoll => "Serial",
+        }
+    }
+}
 
 /// Events the PPU generates.
 #[derive(Copy, Clone, Eq, PartialEq)]
@@ -63,6 +80,8 @@ pub enum PpuEvent {
     VblankEnd,
     // This happens a little after HBlank end
     LYIncrement,
+    /// Drawing with the pixel FIFO, see `Ppu::fifo_step`.
+    PixelTransfer,
 }
 
 impl PpuEvent {
@@ -72,7 +91,7 @@ impl PpuEvent {
             PpuEvent::HblankEnd => 0,
             PpuEvent::VblankEnd => 1,
             PpuEvent::OamScanEnd => 2,
-            PpuEvent::UploadEnd => 3,
+            PpuEvent::UploadEnd | PpuEvent::PixelTransfer => 3,
             PpuEvent::LYIncrement => panic!("Not applicable!"),
         }
     }
@@ -0,0 +1,289 @@
+// Copyright (c) 2024 Leela Aurelia, git@elia.garden
+//
+// Unless otherwise noted, this file is released and thus subject to the
+// terms of the Mozilla Public License Version 2.0 (MPL-2.0) or the
+// GNU General Public License Version 3 (GPL-3).
+// If a copy of these licenses was not distributed with this file, you can
+// obtain them at https://mozilla.org/MPL/2.0/ and http://www.gnu.org/licenses/.
+
+use std::sync::{Arc, Mutex};
+
+use common::{numutil::NumExt, TimeS};
+
+use super::scheduling::GGEvent;
+use crate::{
+    cpu::Interrupt,
+    io::addr::{SB, SC},
+    GameGirl,
+};
+
+/// Cycles a transfer takes using the normal 8192Hz internal clock.
+const SLOW_CYCLES: TimeS = 8 * 512;
+/// Cycles a transfer takes using the CGB's fast 262144Hz internal clock.
+const FAST_CYCLES: TimeS = 8 * 16;
+/// Cycles between checks for a byte from the other console while
+/// waiting on the external clock.
+const LINK_POLL_CYCLES: TimeS = 512;
+
+/// The serial port. Without a [LinkPort] connected, transfers
+/// always shift in 0xFF.
+pub struct Serial;
+
+impl Serial {
+    /// SC was written. Starts a transfer if requested. With the internal
+    /// clock, it finishes after 8 bits worth of time. With the external
+    /// clock, it finishes once the console on the other end of the link
+    /// cable runs a transfer, or never if there is none.
+    pub fn sc_written(gg: &mut GameGirl, value: u8) {
+        let unused = if gg.cgb { 0x7C } else { 0x7E };
+        gg[SC] = value | unused;
+        gg.scheduler.cancel(GGEvent::SerialTransferEnd);
+        gg.scheduler.cancel(GGEvent::SerialLinkPoll);
+        if let Some(link) = &gg.link {
+            link.set_ready(None);
+        }
+
+        if value.is_bit(7) && !value.is_bit(0) {
+            if let Some(link) = &gg.link {
+                link.set_ready(Some(gg[SB]));
+                gg.scheduler
+                    .schedule(GGEvent::SerialLinkPoll, LINK_POLL_CYCLES);
+            }
+        } else if value.is_bit(7) {
+            let cycles = if gg.cgb && value.is_bit(1) {
+                FAST_CYCLES
+            } else {
+                SLOW_CYCLES
+            };
+            // The serial clock is derived from the CPU clock,
+            // so it runs twice as fast in double speed mode.
+            let cycles = cycles >> (2 - gg.t_shift);
+            gg.scheduler.schedule(GGEvent::SerialTransferEnd, cycles);
+        }
+    }
+
+    /// The running transfer using the internal clock completed.
+    pub fn transfer_end(gg: &mut GameGirl) {
+        gg[SB] = match &gg.link {
+            Some(link) => link.exchange(gg[SB]),
+            None => 0xFF,
+        };
+        gg[SC] &= 0x7F;
+        gg.request_interrupt(Interrupt::Serial);
+    }
+
+    /// Check if the other console ran a transfer while we are waiting on
+    /// the external clock.
+    pub fn link_poll(gg: &mut GameGirl) {
+        let Some(link) = &gg.link else {
+            return;
+        };
+        match link.take_received() {
+            Some(value) => {
+                gg[SB] = value;
+                gg[SC] &= 0x7F;
+                gg.request_interrupt(Interrupt::Serial);
+            }
+            None => gg
+                .scheduler
+                .schedule(GGEvent::SerialLinkPoll, LINK_POLL_CYCLES),
+        }
+    }
+}
+
+/// One end of an in-process link cable, connecting the serial ports of two
+/// systems. Transfers are byte-wise: When the console providing the clock
+/// finishes a transfer, it exchanges bytes with the other console, if that
+/// one is waiting on the external clock. This is enough for longer
+/// protocols like trading, since they are made up of single-byte transfers.
+#[derive(Clone)]
+pub struct LinkPort {
+    ends: Arc<Mutex<[LinkEnd; 2]>>,
+    /// Index of this end in `ends`.
+    side: usize,
+}
+
+/// State of one end of a link cable.
+#[derive(Default)]
+struct LinkEnd {
+    /// Byte this console will send, if it is waiting on the external clock.
+    ready: Option<u8>,
+    /// Byte this console received from the other end, if it did not pick it
+    /// up yet.
+    received: Option<u8>,
+}
+
+impl LinkPort {
+    /// Create a new link cable, returning both of its ends.
+    pub fn pair() -> (Self, Self) {
+        let ends = Arc::new(Mutex::new(Default::default()));
+        (
+            Self {
+                ends: ends.clone(),
+                side: 0,
+            },
+            Self { ends, side: 1 },
+        )
+    }
+
+    /// Set the byte this end will send when the other end runs a transfer.
+    fn set_ready(&self, value: Option<u8>) {
+        let mut ends = self.ends.lock().unwrap();
+        ends[self.side] = LinkEnd {
+            ready: value,
+            received: None,
+        };
+    }
+
+    /// Run a transfer from this end, sending the given byte and returning the
+    /// byte received. If the other end is not waiting for a transfer, this
+    /// receives 0xFF and the other end receives nothing.
+    fn exchange(&self, value: u8) -> u8 {
+        let mut ends = self.ends.lock().unwrap();
+        let other = &mut ends[self.side ^ 1];
+        match other.ready.take() {
+            Some(received) => {
+                other.received = Some(value);
+                received
+            }
+            None => 0xFF,
+        }
+    }
+
+    /// Take the byte received from the other end, if there is one.
+    fn take_received(&self) -> Option<u8> {
+        self.ends.lock().unwrap()[self.side].received.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use common::common::options::SystemConfig;
+
+    use super::*;
+    use crate::{
+        io::addr::{HIGH_START, IF},
+        tests::mbc3_rom,
+    };
+
+    /// Run a transfer with the given SC value, returning how long it took
+    /// in scheduler time.
+    fn transfer_time(gg: &mut GameGirl, sc: u8) -> u64 {
+        gg.set(HIGH_START + SB, 0x42);
+        gg.set(HIGH_START + SC, sc);
+        let start = gg.scheduler.now();
+        while !gg[IF].is_bit(Interrupt::Serial.to_index()) {
+            gg.advance_clock(1);
+            assert!(
+                gg.scheduler.now() - start < 10_000,
+                "transfer never finished"
+            );
+        }
+        assert_eq!(gg[SB], 0xFF);
+        assert!(!gg[SC].is_bit(7));
+        gg[IF] = 0;
+        gg.scheduler.now() - start
+    }
+
+    #[test]
+    fn transfer_timing() {
+        let mut gg = GameGirl::with_cart(mbc3_rom(), None, &SystemConfig::default()).unwrap();
+        gg.cgb = false;
+        assert_eq!(transfer_time(&mut gg, 0x81), SLOW_CYCLES as u64);
+        // No fast clock on DMG
+        assert_eq!(transfer_time(&mut gg, 0x83), SLOW_CYCLES as u64);
+
+        gg.cgb = true;
+        assert_eq!(transfer_time(&mut gg, 0x81), SLOW_CYCLES as u64);
+        assert_eq!(transfer_time(&mut gg, 0x83), FAST_CYCLES as u64);
+        // Double speed
+        gg.t_shift = 1;
+        assert_eq!(transfer_time(&mut gg, 0x81), SLOW_CYCLES as u64 / 2);
+        assert_eq!(transfer_time(&mut gg, 0x83), FAST_CYCLES as u64 / 2);
+    }
+
+    fn serial_irq(gg: &mut GameGirl) -> bool {
+        let bit = Interrupt::Serial.to_index();
+        let fired = gg[IF].is_bit(bit);
+        gg[IF] = gg[IF].set_bit(bit, false);
+        fired
+    }
+
+    /// Exchange a byte over the link cable, with the first console providing
+    /// the clock. Returns the bytes each console received.
+    fn exchange(master: &mut GameGirl, slave: &mut GameGirl, bytes: (u8, u8)) -> (u8, u8) {
+        slave.set(HIGH_START + SB, bytes.1);
+        slave.set(HIGH_START + SC, 0x80);
+        master.set(HIGH_START + SB, bytes.0);
+        master.set(HIGH_START + SC, 0x81);
+        let (mut master_done, mut slave_done) = (false, false);
+        for _ in 0..10_000 {
+            master.advance_clock(1);
+            slave.advance_clock(1);
+            master_done |= serial_irq(master);
+            slave_done |= serial_irq(slave);
+            if master_done && slave_done {
+                return (master[SB], slave[SB]);
+            }
+        }
+        panic!("transfer never finished");
+    }
+
+    #[test]
+    fn link_cable_handshake() {
+        let (a, b) = LinkPort::pair();
+        let mut master = GameGirl::with_cart(mbc3_rom(), None, &SystemConfig::default()).unwrap();
+        let mut slave = GameGirl::with_cart(mbc3_rom(), None, &SystemConfig::default()).unwrap();
+        master.link = Some(a);
+        slave.link = Some(b);
+
+        // Connection request and acknowledgement, then a block of data
+        // in both directions
+        assert_eq!(
+            exchange(&mut master, &mut slave, (0x01, 0x02)),
+            (0x02, 0x01)
+        );
+        for i in 0..8 {
+            let bytes = (0xA0 + i, 0xB0 + i);
+            assert_eq!(exchange(&mut master, &mut slave, bytes), (bytes.1, bytes.0));
+        }
+    }
+
+    #[test]
+    fn link_cable_other_end_not_waiting() {
+        let (a, b) = LinkPort::pair();
+        let mut master = GameGirl::with_cart(mbc3_rom(), None, &SystemConfig::default()).unwrap();
+        let mut slave = GameGirl::with_cart(mbc3_rom(), None, &SystemConfig::default()).unwrap();
+        master.link = Some(a);
+        slave.link = Some(b);
+
+        // The master shifts in 0xFF, the slave receives nothing
+        assert_eq!(transfer_time(&mut master, 0x81), SLOW_CYCLES as u64);
+        slave.set(HIGH_START + SB, 0x42);
+        slave.set(HIGH_START + SC, 0x80);
+        for _ in 0..10_000 {
+            slave.advance_clock(1);
+        }
+        assert!(!serial_irq(&mut slave));
+        assert_eq!(slave[SB], 0x42);
+
+        // Once it waits, the next transfer goes through
+        assert_eq!(
+            exchange(&mut master, &mut slave, (0x11, 0x22)),
+            (0x22, 0x11)
+        );
+    }
+
+    #[test]
+    fn external_clock_never_finishes_alone() {
+        let mut gg = GameGirl::with_cart(mbc3_rom(), None, &SystemConfig::default()).unwrap();
+        gg.set(HIGH_START + SB, 0x42);
+        gg.set(HIGH_START + SC, 0x80);
+        for _ in 0..10_000 {
+            gg.advance_clock(1);
+        }
+        assert!(!gg[IF].is_bit(Interrupt::Serial.to_index()));
+        assert_eq!(gg[SB], 0x42);
+        assert!(gg[SC].is_bit(7));
+    }
+}
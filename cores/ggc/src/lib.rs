@@ -10,7 +10,9 @@ use std::{cmp::Ordering, mem, path::PathBuf};
 
 use common::{
     common::{
-        debugger::{self, Width},
+        audio::{PsgChannelState, PsgState},
+        cheats::CheatWrite,
+        debugger::{self, InterruptState, MemoryRegion, SpriteInfo, TileMap, Width},
         options::SystemConfig,
     },
     common_functions,
@@ -20,20 +22,21 @@ use common::{
         storage::{GameSave, Storage},
     },
     numutil::{hword, word, NumExt},
-    Common, Core, Time,
+    Common, Core, CoreKind, Time,
 };
 use io::addr::DIV;
 
 use crate::{
     cpu::{Cpu, Interrupt},
     io::{
-        addr::{BOOTROM_DISABLE, IF, KEY1},
+        addr::{BOOTROM_DISABLE, HIGH_START, IE, IF, KEY1, NAMED_REGISTERS},
         apu::Apu,
         cartridge::Cartridge,
         dma::Hdma,
         joypad::Joypad,
         ppu::Ppu,
         scheduling::GGEvent,
+        serial::LinkPort,
         timer::Timer,
         Memory,
     },
@@ -43,6 +46,8 @@ pub mod cpu;
 pub mod io;
 
 const T_CLOCK_HZ: u32 = 4_194_304;
+/// M-cycles the CPU stays stopped for after a CGB speed switch.
+const SPEED_SWITCH_CYCLES: u16 = 2050;
 
 /// The system and it's state.
 /// Represents the entire console.
@@ -67,12 +72,19 @@ pub struct GameGirl {
     /// mode. Regular: 2, CGB 2x: 1.
     t_shift: u8,
 
+    /// Link cable connected to the serial port, if any.
+    #[cfg_attr(feature = "serde", serde(skip, default))]
+    pub link: Option<LinkPort>,
     pub c: Common,
 }
 
 impl Core for GameGirl {
     common_functions!(T_CLOCK_HZ, GGEvent::PauseEmulation, [160, 144]);
 
+    fn kind(&self) -> Option<CoreKind> {
+        Some(CoreKind::Ggc)
+    }
+
     fn advance(&mut self) {
         Cpu::exec_next_inst(self);
     }
@@ -85,19 +97,41 @@ impl Core for GameGirl {
             self.cart.load_save(save);
         }
 
+        self.link = old_self.link;
         self.c.restore_from(old_self.c);
         MemoryMapper::init_pages(self);
     }
 
     fn skip_bootrom(&mut self) {
         self.cpu.pc = 0x100;
-        self.set(BOOTROM_DISABLE, 1u8);
+        self.set(HIGH_START + BOOTROM_DISABLE, 1u8);
     }
 
     fn make_save(&self) -> Option<GameSave> {
         self.cart.make_save()
     }
 
+    fn load_save(&mut self, save: GameSave) {
+        self.cart.load_save(save);
+        self.cart.ram_dirty = true;
+    }
+
+    fn rtc_time(&self) -> Option<u64> {
+        self.cart.rtc_time()
+    }
+
+    fn set_rtc_time(&mut self, unix_seconds: u64) {
+        self.cart.set_rtc_time(unix_seconds);
+    }
+
+    fn save_is_dirty(&self) -> bool {
+        self.cart.ram_dirty
+    }
+
+    fn clear_save_dirty(&mut self) {
+        self.cart.ram_dirty = false;
+    }
+
     fn get_memory(&self, addr: u32, width: Width) -> u32 {
         match width {
             Width::Byte => self.get(addr.u16()),
@@ -109,6 +143,27 @@ impl Core for GameGirl {
         }
     }
 
+    fn set_memory(&mut self, addr: u32, value: u32, width: Width) {
+        self.c
+            .debugger
+            .memory_accessed(addr, width.size() as u32, value, true);
+        for i in 0..width.size() {
+            let addr = addr.u16().wrapping_add(i as u16);
+            self.set(addr, (value >> (i * 8)).u8());
+        }
+    }
+
+    fn set_rom_cheats(&mut self, writes: Vec<CheatWrite>) {
+        self.cart.cheats = writes;
+        MemoryMapper::init_pages(self);
+        self.update_rom_pages();
+    }
+
+    fn is_writable_memory(&self, addr: u32) -> bool {
+        // Everything below is cartridge ROM, where writes go to the MBC
+        (0x8000..=0xFFFF).contains(&addr)
+    }
+
     fn search_memory(&self, value: u32, width: Width, kind: Ordering) -> Vec<u32> {
         let mut values = Vec::new();
         debugger::search_array(&mut values, &self.mem.vram, 0x8000, value, width, kind);
@@ -118,41 +173,145 @@ impl Core for GameGirl {
         values
     }
 
+    fn memory_regions(&self) -> Vec<MemoryRegion> {
+        vec![
+            MemoryRegion::new("ROM", 0x0000, 0x8000, false),
+            MemoryRegion::new("VRAM", 0x8000, 0x2000, true),
+            MemoryRegion::new("Cart RAM", 0xA000, 0x2000, true),
+            MemoryRegion::new("WRAM", 0xC000, 0x2000, true),
+            MemoryRegion::new("OAM", 0xFE00, 0xA0, true),
+            MemoryRegion::new("I/O", 0xFF00, 0x80, true),
+            MemoryRegion::new("HRAM", 0xFF80, 0x80, true),
+        ]
+    }
+
     fn get_registers(&self) -> Vec<usize> {
         self.cpu.regs.iter().map(|r| *r as usize).collect()
     }
 
+    fn take_event_counts(&mut self) -> Vec<(&'static str, u32)> {
+        self.scheduler.take_event_counts()
+    }
+
+    fn stack_trace(&self) -> Vec<u32> {
+        Cpu::stack_trace(self)
+    }
+
     fn get_rom(&self) -> Vec<u8> {
         self.cart.rom.clone()
     }
+
+    fn apply_config(&mut self, config: &SystemConfig) {
+        self.c.config.apply_live(config);
+        self.ppu.set_colour_correction(
+            config.cgb_colour_correction,
+            config.cgb_bg_grading,
+            config.cgb_obj_grading,
+        );
+    }
+
+    fn sprites(&self) -> Vec<SpriteInfo> {
+        Ppu::sprites(self)
+    }
+
+    fn set_sprite(&mut self, idx: usize, sprite: SpriteInfo) {
+        Ppu::set_sprite(self, idx, sprite);
+    }
+
+    fn tile_map(&self, layer: usize) -> Option<TileMap> {
+        (layer < 2).then(|| Ppu::tile_map(self, layer == 1))
+    }
+
+    fn interrupt_state(&self) -> InterruptState {
+        InterruptState {
+            master_enable: self.cpu.ime,
+            cpu_masked: false,
+            halted: self.cpu.halted,
+            sources: InterruptState::sources_from_bits(
+                &["VBlank", "STAT", "Timer", "Serial", "Joypad"],
+                self[IE].u32(),
+                self[IF].u32(),
+            ),
+        }
+    }
+
+    fn io_register_table(&self) -> &'static [(&'static str, u32, Width)] {
+        NAMED_REGISTERS
+    }
+
+    fn psg_state(&self) -> Option<PsgState> {
+        Some(self.apu.snapshot())
+    }
+
+    fn audio_channels(&self) -> &'static [&'static str] {
+        &PsgChannelState::NAMES
+    }
+
+    fn layers(&self) -> &'static [&'static str] {
+        &["Background", "Window", "Objects"]
+    }
+
+    fn hidden_layers(&self) -> u32 {
+        self.ppu.hidden_layers.u32()
+    }
+
+    fn set_hidden_layers(&mut self, mask: u32) {
+        self.ppu.hidden_layers = mask.u8() & 0x7;
+    }
 }
 
 impl GameGirl {
     /// Advance the scheduler, which controls everything except the CPU.
     fn advance_clock(&mut self, m_cycles: u16) {
+        self.advance_scheduler(m_cycles);
+        for _ in 0..m_cycles {
+            Timer::step(self);
+            self.clock_apu();
+        }
+    }
+
+    /// Advance the system while the CPU is stopped during a speed switch.
+    /// The PPU and APU keep running, but DIV does not tick.
+    fn advance_clock_stopped(&mut self, m_cycles: u16) {
+        self.advance_scheduler(m_cycles);
+        for _ in 0..m_cycles {
+            self.clock_apu();
+        }
+    }
+
+    fn advance_scheduler(&mut self, m_cycles: u16) {
         self.scheduler.advance((m_cycles << self.t_shift) as Time);
         while let Some(event) = self.scheduler.get_next_pending() {
             event.kind.dispatch(self, event.late_by);
         }
+    }
 
-        for _ in 0..m_cycles {
-            Timer::step(self);
-            self.apu.clock(
-                self.t_shift == 1,
-                Timer::read(self, DIV),
-                &mut self.c.audio_buffer.input,
-            )
-        }
+    fn clock_apu(&mut self) {
+        self.apu.clock(
+            self.t_shift == 1,
+            Timer::read(self, DIV),
+            &mut self.c.audio_buffer,
+            self.c.options.channel_mask,
+        )
     }
 
     /// Switch between CGB 2x and normal speed mode.
+    /// STOP resets DIV, after which the CPU stays stopped for
+    /// [SPEED_SWITCH_CYCLES] while the rest of the system keeps running.
     fn switch_speed(&mut self) {
         self.t_shift = if self.t_shift == 2 { 1 } else { 2 };
         self.speed = if self.t_shift == 1 { 2 } else { 1 };
         self[KEY1] = (self.speed - 1) << 7;
-
-        for _ in 0..64 {
-            self.advance_clock(2048 / 64);
+        Timer::write(self, DIV, 0);
+
+        if self.c.config.gb_speed_switch_timing {
+            // Advance in steps to keep events and the APU from lagging behind.
+            let mut remaining = SPEED_SWITCH_CYCLES;
+            while remaining > 0 {
+                let step = remaining.min(32);
+                self.advance_clock_stopped(step);
+                remaining -= step;
+            }
         }
     }
 
@@ -169,9 +328,13 @@ impl GameGirl {
         if let Some(save) = save {
             self.cart.load_save(save);
         }
+        self.cart.ram_dirty = old_self.cart.ram_dirty;
+        self.cart.cheats = old_self.cart.cheats;
 
+        self.link = old_self.link;
         self.c.restore_from(old_self.c);
         MemoryMapper::init_pages(self);
+        self.update_rom_pages();
     }
 
     /// Load the given cartridge.
@@ -181,20 +344,27 @@ impl GameGirl {
             let old_self = mem::take(self);
             self.c.debugger = old_self.c.debugger;
         }
+        self.c.game_hacks = cart.game_hacks();
         self.load_cart_mem(cart, config);
         self.c.config = config.clone();
+        self.c.video_buffer.set_history_len(config.frame_history);
     }
 
     /// Create a system with a cart already loaded.
-    pub fn with_cart(cart: Vec<u8>, path: Option<PathBuf>, config: &SystemConfig) -> Box<Self> {
-        let mut cart = Cartridge::from_rom(cart);
+    /// Returns `None` if the ROM is invalid, see [Cartridge::from_rom].
+    pub fn with_cart(
+        cart: Vec<u8>,
+        path: Option<PathBuf>,
+        config: &SystemConfig,
+    ) -> Option<Box<Self>> {
+        let mut cart = Cartridge::from_rom(cart)?;
         if let Some(save) = Storage::load(path, cart.title(true)) {
             cart.load_save(save);
         }
 
         let mut ggc = Box::<Self>::default();
         ggc.load_cart(cart, config, false);
-        ggc
+        Some(ggc)
     }
 }
 
@@ -218,7 +388,228 @@ impl Default for GameGirl {
             speed: 1,
             t_shift: 2,
 
+            link: None,
             c: Common::default(),
         }
     }
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use common::{
+        common::{debugger::Watchpoint, options::CgbMode},
+        components::storage::SaveError,
+        Core,
+    };
+
+    use super::*;
+    use crate::cpu::Reg;
+
+    /// A blank 32K MBC3 ROM with 32K of battery-backed RAM.
+    pub fn mbc3_rom() -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        rom[0x147] = 0x13;
+        rom[0x149] = 3;
+        rom
+    }
+
+    #[test]
+    fn rtc_time_survives_save() {
+        let mut rom = mbc3_rom();
+        rom[0x147] = 0x10;
+        let mut gg = GameGirl::with_cart(rom.clone(), None, &SystemConfig::default()).unwrap();
+        let target = gg.rtc_time().unwrap() - 86400;
+        gg.set_rtc_time(target);
+
+        let mut other = GameGirl::with_cart(rom, None, &SystemConfig::default()).unwrap();
+        other.load_save(gg.make_save().unwrap());
+        // Time might have passed between setting and reading it
+        assert!(other.rtc_time().unwrap().abs_diff(target) <= 1);
+    }
+
+    #[test]
+    fn save_ram_round_trip() {
+        let mut gg = GameGirl::with_cart(mbc3_rom(), None, &SystemConfig::default()).unwrap();
+        let ram: Vec<u8> = (0..0x8000).map(|i| i as u8).collect();
+        gg.load_save(GameSave {
+            ram: ram.clone(),
+            rtc: None,
+            rtc_offset: 0,
+            title: String::new(),
+        });
+        let exported = gg.export_save_ram().unwrap();
+        assert_eq!(exported, ram);
+
+        let mut other = GameGirl::with_cart(mbc3_rom(), None, &SystemConfig::default()).unwrap();
+        other.import_save_ram(&exported).unwrap();
+        assert_eq!(other.make_save().unwrap().ram, ram);
+        assert!(other.save_is_dirty());
+    }
+
+    #[test]
+    fn import_rejects_wrong_size() {
+        let mut gg = GameGirl::with_cart(mbc3_rom(), None, &SystemConfig::default()).unwrap();
+        let result = gg.import_save_ram(&[0xFF; 0x2000]);
+        assert_eq!(
+            result,
+            Err(SaveError::SizeMismatch {
+                expected: 0x8000,
+                actual: 0x2000
+            })
+        );
+        assert!(!gg.save_is_dirty());
+    }
+
+    #[test]
+    fn game_genie_patch_is_undone() {
+        let mut rom = mbc3_rom();
+        rom[0x150] = 0x12;
+        let mut gg = GameGirl::with_cart(rom, None, &SystemConfig::default()).unwrap();
+
+        let id = gg.add_cheat("341-50F").unwrap();
+        gg.apply_cheats();
+        assert_eq!(gg.get_memory(0x150, Width::Byte), 0x34);
+        // Applying again must not lose the original value
+        gg.apply_cheats();
+        gg.remove_cheat(id);
+        assert_eq!(gg.get_memory(0x150, Width::Byte), 0x12);
+    }
+
+    #[test]
+    fn game_genie_compare_in_switched_bank() {
+        let mut rom = mbc3_rom();
+        rom.resize(0x10000, 0);
+        rom[0x148] = 1;
+        rom[0x8050] = 0x12;
+        rom[0xC050] = 0x56;
+        let mut gg = GameGirl::with_cart(rom, None, &SystemConfig::default()).unwrap();
+
+        // Replace 0x12 at 4050 with 0x34, which is only there in bank 2
+        let id = gg.add_cheat("340-50B-A02").unwrap();
+        for (bank, value) in [(1, 0x00), (2, 0x34), (3, 0x56), (2, 0x34)] {
+            gg.set_memory(0x2000, bank, Width::Byte);
+            gg.apply_cheats();
+            assert_eq!(gg.get_memory(0x4050, Width::Byte), value, "bank {bank}");
+        }
+        // ROM itself is never modified
+        assert_eq!(gg.cart.rom[0x8050], 0x12);
+
+        gg.remove_cheat(id);
+        assert_eq!(gg.get_memory(0x4050, Width::Byte), 0x12);
+    }
+
+    #[test]
+    fn frame_history_on_load() {
+        let config = SystemConfig {
+            frame_history: 2,
+            ..SystemConfig::default()
+        };
+        let mut gg = GameGirl::with_cart(mbc3_rom(), None, &config).unwrap();
+        for shade in 0..3 {
+            gg.c.video_buffer.push(vec![[shade; 4]]);
+        }
+        assert_eq!(gg.frame_history(), [vec![[1; 4]], vec![[2; 4]]]);
+    }
+
+    #[test]
+    fn invalid_roms_are_rejected() {
+        let config = SystemConfig::default();
+        assert!(GameGirl::with_cart(vec![0; 0x100], None, &config).is_none());
+        let mut rom = mbc3_rom();
+        rom[0x149] = 0x42;
+        assert!(GameGirl::with_cart(rom, None, &config).is_none());
+
+        // Header claims more banks than the ROM has
+        let mut rom = mbc3_rom();
+        rom[0x148] = 5;
+        let mut gg = GameGirl::with_cart(rom, None, &config).unwrap();
+        gg.set_memory(0x2000, 0x20, Width::Byte);
+        assert_eq!(gg.get_memory(0x4000, Width::Byte), 0xFF);
+    }
+
+    /// Run a CGB speed switch, returning how long the STOP took in
+    /// scheduler time.
+    fn speed_switch(timing: bool) -> Time {
+        let mut rom = mbc3_rom();
+        rom[0x100..0x107].copy_from_slice(&[
+            0x3E, 0x01, // LD A, 1
+            0xE0, 0x4D, // LDH (KEY1), A
+            0x10, // STOP
+            0x18, 0xFE, // JR -2
+        ]);
+        let config = SystemConfig {
+            mode: CgbMode::Always,
+            gb_speed_switch_timing: timing,
+            ..SystemConfig::default()
+        };
+        let mut gg = GameGirl::with_cart(rom, None, &config).unwrap();
+        gg.skip_bootrom();
+        gg.c.debugger.running = true;
+        while gg.cpu.pc != 0x104 {
+            gg.advance();
+        }
+        let start = gg.scheduler.now();
+        gg.advance();
+        assert_eq!(gg.t_shift, 1);
+        assert_eq!(gg[KEY1] & 0x81, 0x80);
+        // DIV was reset and did not tick while the CPU was stopped
+        assert_eq!(Timer::read(&gg, DIV), 0);
+        gg.scheduler.now() - start
+    }
+
+    #[test]
+    fn speed_switch_timing() {
+        // 2050 M-cycles at double speed
+        assert_eq!(speed_switch(true) - speed_switch(false), 2050 * 2);
+    }
+
+    #[test]
+    fn watchpoints_stop_stepping() {
+        let mut rom = mbc3_rom();
+        rom[0x100..0x10B].copy_from_slice(&[
+            0x3E, 0x42, // LD A, 0x42
+            0xEA, 0x00, 0xC0, // LD (0xC000), A
+            0x3C, // INC A
+            0xFA, 0x00, 0xC0, // LD A, (0xC000)
+            0x18, 0xFE, // JR -2
+        ]);
+        let mut gg = GameGirl::with_cart(rom, None, &SystemConfig::default()).unwrap();
+        gg.skip_bootrom();
+        gg.c.debugger.watchpoints = vec![
+            Watchpoint {
+                start: 0xC000,
+                end: 0xC000,
+                read: true,
+                write: true,
+                value: None,
+            },
+            // Instruction fetches are not watched
+            Watchpoint {
+                start: 0x100,
+                end: 0x10A,
+                read: true,
+                write: false,
+                value: None,
+            },
+        ];
+
+        gg.c.debugger.running = true;
+        assert!(gg.step_cycles(10_000) < 10_000);
+        let hit = gg.c.debugger.watchpoint_hit.take().unwrap();
+        assert_eq!(
+            (hit.index, hit.addr, hit.value, hit.write),
+            (0, 0xC000, 0x42, true)
+        );
+        assert_eq!(gg.cpu.pc, 0x105);
+
+        gg.c.debugger.running = true;
+        gg.step_cycles(10_000);
+        let hit = gg.c.debugger.watchpoint_hit.take().unwrap();
+        assert_eq!(
+            (hit.index, hit.addr, hit.value, hit.write),
+            (0, 0xC000, 0x42, false)
+        );
+        assert_eq!(gg.cpu.pc, 0x109);
+        assert_eq!(gg.cpu.reg(Reg::A), 0x42);
+    }
+}
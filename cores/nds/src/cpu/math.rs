@@ -8,7 +8,10 @@
 
 use std::num;
 
-use common::numutil::{hword, word, NumExt, U16Ext, U32Ext};
+use common::{
+    numutil::{hword, word, NumExt, U16Ext, U32Ext},
+    Time,
+};
 use modular_bitfield::{bitfield, specifiers::*, BitfieldSpecifier};
 
 #[derive(Default)]
@@ -19,10 +22,27 @@ pub struct Div {
     pub denom: u64,
     pub result: u64,
     pub rem: u64,
+    /// Time the current division finishes at. The result is available
+    /// immediately, this is only used for the busy bit.
+    #[cfg_attr(feature = "serde", serde(default))]
+    busy_until: Time,
 }
 
 impl Div {
-    pub fn update(&mut self) {
+    /// Get DIVCNT, with the busy bit set if a division is still running.
+    pub fn ctrl(&self, now: Time) -> DivControl {
+        self.ctrl.with_busy(now < self.busy_until)
+    }
+
+    /// Start a new division after a register was written.
+    pub fn update(&mut self, now: Time) {
+        // 18 or 34 bus cycles; the scheduler runs at twice that rate
+        self.busy_until = now
+            + match self.ctrl.mode() {
+                DivMode::All32 => 36,
+                _ => 68,
+            };
+        // This flag checks all 64 bits, even in 32-bit mode
         self.ctrl.set_by_zero(self.denom == 0);
         match self.ctrl.mode() {
             DivMode::All32 if self.numer.u32() == 0x80000000 && self.denom.u32() == u32::MAX => {
@@ -30,13 +50,14 @@ impl Div {
                 self.rem = 0;
             }
             DivMode::All32 if self.denom.u32() == 0 => {
+                // Result is -1 or 1 (opposite sign of the numerator), with
+                // the upper 32 bits inverted
                 if (self.numer as i32) < 0 {
                     self.result = 1 | ((u32::MAX as u64) << 32);
-                    self.rem = self.numer | ((u32::MAX as u64) << 32);
                 } else {
                     self.result = u32::MAX as u64;
-                    self.rem = self.numer;
                 }
+                self.rem = self.numer.u32() as i32 as i64 as u64;
             }
             DivMode::All32 => {
                 self.result =
@@ -46,7 +67,8 @@ impl Div {
                     .unwrap_or(0) as i64 as u64;
             }
 
-            DivMode::Partial1 if self.denom.u32() == 0 => {
+            // Mode 3 is reserved, but acts like mode 1
+            DivMode::Partial1 | DivMode::Reserved if self.denom.u32() == 0 => {
                 if (self.numer as i64) < 0 {
                     self.result = 1;
                 } else {
@@ -54,7 +76,7 @@ impl Div {
                 }
                 self.rem = self.numer;
             }
-            DivMode::Partial1 => {
+            DivMode::Partial1 | DivMode::Reserved => {
                 self.result =
                     (self.numer as i64).wrapping_div(self.denom.u32() as i32 as i64) as u64;
                 self.rem = (self.numer as i64)
@@ -62,7 +84,7 @@ impl Div {
                     .unwrap_or(0) as i64 as u64;
             }
 
-            DivMode::All64 | DivMode::Reserved if self.denom == 0 => {
+            DivMode::All64 if self.denom == 0 => {
                 if (self.numer as i64) < 0 {
                     self.result = 1;
                 } else {
@@ -70,7 +92,7 @@ impl Div {
                 }
                 self.rem = self.numer;
             }
-            DivMode::All64 | DivMode::Reserved => {
+            DivMode::All64 => {
                 self.result = (self.numer as i64).wrapping_div(self.denom as i64) as u64;
                 self.rem = (self.numer as i64)
                     .checked_rem(self.denom as i64)
@@ -108,10 +130,21 @@ pub struct Sqrt {
     pub ctrl: SqrtControl,
     pub input: u64,
     pub result: u32,
+    /// Time the current square root finishes at, for the busy bit.
+    #[cfg_attr(feature = "serde", serde(default))]
+    busy_until: Time,
 }
 
 impl Sqrt {
-    pub fn update(&mut self) {
+    /// Get SQRTCNT, with the busy bit set if a calculation is still running.
+    pub fn ctrl(&self, now: Time) -> SqrtControl {
+        self.ctrl.with_busy(now < self.busy_until)
+    }
+
+    /// Start a new calculation after a register was written.
+    pub fn update(&mut self, now: Time) {
+        // 13 bus cycles
+        self.busy_until = now + 26;
         match self.ctrl.mode() {
             SqrtMode::Bit32 => self.result = self.input.u32().isqrt(),
             SqrtMode::Bit64 => self.result = self.input.isqrt() as u32,
@@ -137,3 +170,114 @@ pub enum SqrtMode {
     Bit32 = 0,
     Bit64 = 1,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn divide(mode: DivMode, numer: u64, denom: u64) -> Div {
+        let mut div = Div {
+            ctrl: DivControl::new().with_mode(mode),
+            numer,
+            denom,
+            ..Default::default()
+        };
+        div.update(0);
+        div
+    }
+
+    fn sqrt(mode: SqrtMode, input: u64) -> u32 {
+        let mut sqrt = Sqrt {
+            ctrl: SqrtControl::new().with_mode(mode),
+            input,
+            ..Default::default()
+        };
+        sqrt.update(0);
+        sqrt.result
+    }
+
+    #[test]
+    fn div_32() {
+        // Upper halves of the inputs are ignored
+        let div = divide(DivMode::All32, 0xAAAA_AAAA_FFFF_FFF9, 0x5555_5555_0000_0002);
+        assert_eq!(div.result as i64, -3);
+        assert_eq!(div.rem as i64, -1);
+        assert!(!div.ctrl.by_zero());
+
+        let div = divide(DivMode::All32, 0x8000_0000, 0xFFFF_FFFF);
+        assert_eq!(div.result, 0x8000_0000);
+        assert_eq!(div.rem, 0);
+    }
+
+    #[test]
+    fn div_64_by_32() {
+        let div = divide(
+            DivMode::Partial1,
+            (-(1i64 << 40) - 5) as u64,
+            0xFFFF_FFFF_0000_0100,
+        );
+        assert_eq!(div.result as i64, -(1 << 32));
+        assert_eq!(div.rem as i64, -5);
+
+        // Mode 3 behaves the same
+        let div = divide(DivMode::Reserved, 1 << 40, 0xFFFF_FFFF_0000_0100);
+        assert_eq!(div.result, 1 << 32);
+    }
+
+    #[test]
+    fn div_64() {
+        let div = divide(DivMode::All64, (-(1i64 << 40) - 5) as u64, 1 << 36);
+        assert_eq!(div.result as i64, -16);
+        assert_eq!(div.rem as i64, -5);
+
+        let div = divide(DivMode::All64, i64::MIN as u64, u64::MAX);
+        assert_eq!(div.result, i64::MIN as u64);
+        assert_eq!(div.rem, 0);
+    }
+
+    #[test]
+    fn div_by_zero() {
+        let div = divide(DivMode::All32, 0x1234_5678_0000_0005, 0);
+        assert_eq!(div.result, 0x0000_0000_FFFF_FFFF);
+        assert_eq!(div.rem, 5);
+        assert!(div.ctrl.by_zero());
+        let div = divide(DivMode::All32, 0xFFFF_FFFB, 0);
+        assert_eq!(div.result, 0xFFFF_FFFF_0000_0001);
+        assert_eq!(div.rem as i64, -5);
+
+        let div = divide(DivMode::All64, 1 << 40, 0);
+        assert_eq!(div.result, u64::MAX);
+        assert_eq!(div.rem, 1 << 40);
+        let div = divide(DivMode::All64, -5i64 as u64, 0);
+        assert_eq!(div.result, 1);
+        assert_eq!(div.rem as i64, -5);
+
+        // 64/32 divides by zero here, but the flag checks all 64 bits
+        let div = divide(DivMode::Partial1, 1 << 40, 1 << 32);
+        assert_eq!(div.result, u64::MAX);
+        assert_eq!(div.rem, 1 << 40);
+        assert!(!div.ctrl.by_zero());
+    }
+
+    #[test]
+    fn div_busy() {
+        let div = divide(DivMode::All32, 10, 3);
+        assert!(div.ctrl(35).busy());
+        assert!(!div.ctrl(36).busy());
+        let div = divide(DivMode::All64, 10, 3);
+        assert!(div.ctrl(67).busy());
+        assert!(!div.ctrl(68).busy());
+    }
+
+    #[test]
+    fn sqrt_32() {
+        assert_eq!(sqrt(SqrtMode::Bit32, 0xFFFF_FFFF_0000_0011), 4);
+        assert_eq!(sqrt(SqrtMode::Bit32, 0xFFFF_FFFF), 0xFFFF);
+    }
+
+    #[test]
+    fn sqrt_64() {
+        assert_eq!(sqrt(SqrtMode::Bit64, (1 << 40) + 1), 1 << 20);
+        assert_eq!(sqrt(SqrtMode::Bit64, u64::MAX), 0xFFFF_FFFF);
+    }
+}
@@ -85,6 +85,7 @@ impl Gpu {
                             Self::push_output(ds);
                         }
                         ds.c.video_buffer.start_next_frame();
+                        ds.scheduler.end_frame();
                     }
                     _ => (),
                 }
@@ -363,19 +363,22 @@ impl Window {
 
     #[inline]
     pub fn bottom(&self) -> usize {
-        let top = self.top as usize;
-        let mut bottom = self.bottom as usize;
-        if bottom > WIDTH || bottom < top {
-            bottom = WIDTH;
-        }
-        bottom
+        self.bottom as usize
     }
 
+    /// If the given line is inside the window. The hardware turns the window
+    /// on at the top line and off at the bottom line, so if bottom is above
+    /// top, the window wraps around: it covers everything from top to the
+    /// end of the screen and from the start of the screen to bottom.
     #[inline]
     pub fn contains_y(&self, y: usize) -> bool {
         let top = self.top();
         let bottom = self.bottom();
-        y >= top && y < bottom
+        if top <= bottom {
+            y >= top && y < bottom
+        } else {
+            y >= top || y < bottom
+        }
     }
 }
 
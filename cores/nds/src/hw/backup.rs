@@ -0,0 +1,358 @@
+// Copyright (c) 2024 Leela Aurelia, git@elia.garden
+//
+// Unless otherwise noted, this file is released and thus subject to the
+// terms of the Mozilla Public License Version 2.0 (MPL-2.0) or the
+// GNU General Public License Version 3 (GPL-3).
+// If a copy of these licenses was not distributed with this file, you can
+// obtain them at https://mozilla.org/MPL/2.0/ and http://www.gnu.org/licenses/.
+
+use common::{components::storage::GameSave, numutil::NumExt};
+
+use crate::memory::{KB, MB};
+
+/// Kind of backup memory on the cartridge. They all use similar SPI
+/// commands, the main difference being the amount of address bytes.
+/// The cartridge does not say which one it has, so it is detected
+/// from the size of an existing save, or from the amount of address bytes
+/// in the first read command the game sends.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub enum BackupKind {
+    #[default]
+    Undetected,
+    /// 512 byte EEPROM, with a single address byte. The 9th address bit
+    /// is part of the command.
+    Eeprom512,
+    /// EEPROM up to 64K, including FRAM, with 2 address bytes.
+    Eeprom,
+    /// FLASH (and large EEPROM) with 3 address bytes.
+    Flash,
+}
+
+impl BackupKind {
+    /// Pick the kind that fits a save of the given size.
+    pub fn from_size(size: usize) -> Self {
+        match size {
+            0 => BackupKind::Undetected,
+            1..=512 => BackupKind::Eeprom512,
+            513..=0x10000 => BackupKind::Eeprom,
+            _ => BackupKind::Flash,
+        }
+    }
+
+    /// Size of the smallest chip of this kind, used when no save
+    /// existed yet.
+    pub fn min_size(self) -> usize {
+        match self {
+            BackupKind::Undetected => 0,
+            BackupKind::Eeprom512 => 512,
+            BackupKind::Eeprom => 8 * KB,
+            BackupKind::Flash => 256 * KB,
+        }
+    }
+
+    /// Size of the largest chip of this kind.
+    pub fn max_size(self) -> usize {
+        match self {
+            BackupKind::Undetected => 0,
+            BackupKind::Eeprom512 => 512,
+            BackupKind::Eeprom => 64 * KB,
+            BackupKind::Flash => 8 * MB,
+        }
+    }
+
+    fn addr_len(self) -> u8 {
+        match self {
+            BackupKind::Undetected => 0,
+            BackupKind::Eeprom512 => 1,
+            BackupKind::Eeprom => 2,
+            BackupKind::Flash => 3,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub enum BackupState {
+    #[default]
+    AwaitingCmd,
+    /// Command was not understood or takes no further bytes.
+    Ignore,
+    ReadStatus,
+    WaitingAddr {
+        cmd: u8,
+        addr: u32,
+        left: u8,
+    },
+    Read {
+        addr: u32,
+    },
+    Write {
+        addr: u32,
+    },
+    /// Backup kind is not known yet, counting the bytes of the first
+    /// read command until the game reads back data to find out.
+    Detecting {
+        addr: u32,
+        bytes: u8,
+    },
+}
+
+/// Backup memory of a cartridge, accessed using AUXSPIDATA.
+#[derive(Default)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct Backup {
+    pub kind: BackupKind,
+    pub ram: Vec<u8>,
+    state: BackupState,
+    write_en: bool,
+    /// If the size of the chip is not known yet. The memory then starts
+    /// at the smallest size for its kind and grows to fit the highest
+    /// address the game accesses.
+    #[cfg_attr(feature = "serde", serde(default))]
+    growing: bool,
+    /// If the memory was modified since the last time the flag was cleared.
+    #[cfg_attr(feature = "serde", serde(skip, default))]
+    pub ram_dirty: bool,
+}
+
+impl Backup {
+    /// Transfer a byte to the chip, returning the byte it sent back.
+    pub fn transfer(&mut self, value: u8) -> u8 {
+        match &mut self.state {
+            BackupState::AwaitingCmd => {
+                self.command(value);
+                0xFF
+            }
+            BackupState::Ignore => 0xFF,
+            BackupState::ReadStatus => (self.write_en as u8) << 1,
+
+            BackupState::WaitingAddr { cmd, addr, left } => {
+                *addr = (*addr << 8) | value.u32();
+                *left -= 1;
+                if *left == 0 {
+                    let (cmd, mut addr) = (*cmd, *addr);
+                    if self.kind == BackupKind::Eeprom512 && cmd & 0x8 != 0 {
+                        // READ/WRITE high: access the upper 256 bytes
+                        addr |= 0x100;
+                    }
+                    self.fit_size(addr);
+                    self.state = match cmd {
+                        0x03 | 0x0B => BackupState::Read { addr },
+                        0x02 | 0x0A => BackupState::Write { addr },
+                        _ => {
+                            self.erase(cmd, addr);
+                            BackupState::Ignore
+                        }
+                    };
+                }
+                0xFF
+            }
+            BackupState::Read { addr } => {
+                let value = self.ram[addr.us() % self.ram.len()];
+                *addr += 1;
+                value
+            }
+            BackupState::Write { addr } => {
+                if self.write_en {
+                    let len = self.ram.len();
+                    self.ram[addr.us() % len] = value;
+                    self.ram_dirty = true;
+                }
+                *addr += 1;
+                0xFF
+            }
+
+            BackupState::Detecting { addr, bytes } => {
+                *addr = (*addr << 8) | value.u32();
+                *bytes = bytes.saturating_add(1);
+                0xFF
+            }
+        }
+    }
+
+    /// The game read back the byte returned by the last transfer.
+    /// Games only do this for data bytes, so during detection, all bytes
+    /// before it were the address.
+    pub fn data_read(&mut self) {
+        if let BackupState::Detecting {
+            addr,
+            bytes: bytes @ 2..,
+        } = self.state
+        {
+            self.detect(bytes - 1);
+            // The byte just read came from a blank chip
+            let addr = (addr >> 8) & ((1 << (8 * self.kind.addr_len())) - 1);
+            self.fit_size(addr);
+            self.state = BackupState::Read { addr: addr + 1 };
+        }
+    }
+
+    /// The chip was deselected, ending the current command.
+    pub fn deselect(&mut self) {
+        match self.state {
+            // Writes end by clearing the write enable latch
+            BackupState::Write { .. } => self.write_en = false,
+            // The game did not read back anything before the last byte,
+            // which was the only data byte
+            BackupState::Detecting { bytes, .. } if bytes > 1 => self.detect(bytes - 1),
+            _ => (),
+        }
+        self.state = BackupState::AwaitingCmd;
+    }
+
+    /// Load an existing save, using its size to detect the backup kind.
+    pub fn load(&mut self, save: GameSave) {
+        self.kind = BackupKind::from_size(save.ram.len());
+        self.ram = save.ram;
+        self.growing = false;
+    }
+
+    /// Set the backup kind from the amount of address bytes the game sent,
+    /// creating a blank memory of the smallest size for it.
+    fn detect(&mut self, addr_bytes: u8) {
+        self.kind = match addr_bytes {
+            1 => BackupKind::Eeprom512,
+            2 => BackupKind::Eeprom,
+            _ => BackupKind::Flash,
+        };
+        log::info!("Detected cartridge backup as {:?}", self.kind);
+        self.ram = vec![0xFF; self.kind.min_size()];
+        self.growing = true;
+    }
+
+    /// Grow a memory of unknown size to fit the given address.
+    fn fit_size(&mut self, addr: u32) {
+        if !self.growing {
+            return;
+        }
+        let size = (addr.us() + 1)
+            .next_power_of_two()
+            .min(self.kind.max_size());
+        if size > self.ram.len() {
+            self.ram.resize(size, 0xFF);
+        }
+    }
+
+    fn command(&mut self, cmd: u8) {
+        let addr_len = self.kind.addr_len();
+        self.state = match cmd {
+            0x06 => {
+                self.write_en = true;
+                BackupState::Ignore
+            }
+            0x04 => {
+                self.write_en = false;
+                BackupState::Ignore
+            }
+            0x05 => BackupState::ReadStatus,
+
+            0x03 if self.kind == BackupKind::Undetected => {
+                BackupState::Detecting { addr: 0, bytes: 0 }
+            }
+            _ if self.kind == BackupKind::Undetected => {
+                log::warn!("Backup command 0x{cmd:X} before backup kind is known, ignoring");
+                BackupState::Ignore
+            }
+
+            // READ / WRITE, or their high variants on 512B EEPROM.
+            // On FLASH, 0x0A is a page write, which works the same.
+            0x03 | 0x02 | 0x0A => BackupState::WaitingAddr {
+                cmd,
+                addr: 0,
+                left: addr_len,
+            },
+            0x0B if self.kind == BackupKind::Eeprom512 => BackupState::WaitingAddr {
+                cmd,
+                addr: 0,
+                left: addr_len,
+            },
+            // FLASH sector and page erase
+            0xD8 | 0xDB if self.kind == BackupKind::Flash => BackupState::WaitingAddr {
+                cmd,
+                addr: 0,
+                left: addr_len,
+            },
+
+            _ => {
+                log::warn!("Unknown backup command 0x{cmd:X}");
+                BackupState::Ignore
+            }
+        };
+    }
+
+    fn erase(&mut self, cmd: u8, addr: u32) {
+        if !self.write_en {
+            return;
+        }
+        let size = if cmd == 0xD8 { 64 * KB } else { 256 };
+        let start = (addr.us() % self.ram.len()) & !(size - 1);
+        let end = (start + size).min(self.ram.len());
+        self.ram[start..end].fill(0xFF);
+        self.ram_dirty = true;
+        self.write_en = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Send a command and its address bytes, then read back `read` bytes
+    /// like games do, by writing a dummy byte and reading the result.
+    fn command(backup: &mut Backup, bytes: &[u8], read: usize) -> Vec<u8> {
+        for byte in bytes {
+            backup.transfer(*byte);
+        }
+        let data = (0..read)
+            .map(|_| {
+                let value = backup.transfer(0);
+                backup.data_read();
+                value
+            })
+            .collect();
+        backup.deselect();
+        data
+    }
+
+    #[test]
+    fn detect_and_round_trip() {
+        let mut backup = Backup::default();
+        // 8K EEPROM: 2 address bytes
+        assert_eq!(command(&mut backup, &[0x03, 0x00, 0x10], 4), [0xFF; 4]);
+        assert_eq!(backup.kind, BackupKind::Eeprom);
+        assert_eq!(backup.ram.len(), 8 * KB);
+
+        command(&mut backup, &[0x06], 0);
+        command(&mut backup, &[0x02, 0x12, 0x34, 0xAB, 0xCD], 0);
+        assert_eq!(backup.ram.len(), 8 * KB);
+        assert!(backup.ram_dirty);
+
+        let save = GameSave {
+            ram: backup.ram.clone(),
+            rtc: None,
+            rtc_offset: 0,
+            title: String::new(),
+        };
+        let mut loaded = Backup::default();
+        loaded.load(save);
+        assert_eq!(loaded.kind, BackupKind::Eeprom);
+        assert_eq!(command(&mut loaded, &[0x03, 0x12, 0x34], 2), [0xAB, 0xCD]);
+    }
+
+    #[test]
+    fn detect_other_kinds() {
+        let mut backup = Backup::default();
+        command(&mut backup, &[0x03, 0x00], 16);
+        assert_eq!(backup.kind, BackupKind::Eeprom512);
+        assert_eq!(backup.ram.len(), 512);
+
+        let mut backup = Backup::default();
+        command(&mut backup, &[0x03, 0x00, 0x00, 0x00], 1);
+        assert_eq!(backup.kind, BackupKind::Flash);
+        assert_eq!(backup.ram.len(), 256 * KB);
+        // Accessing higher addresses means the chip is larger
+        command(&mut backup, &[0x03, 0x07, 0x00, 0x00], 1);
+        assert_eq!(backup.ram.len(), 512 * KB);
+    }
+}
@@ -10,11 +10,12 @@ use std::default;
 
 use arm_cpu::{Cpu, Interrupt};
 use common::{
-    components::{io::IoSection, scheduler::Scheduler},
+    components::{io::IoSection, scheduler::Scheduler, storage::GameSave},
     numutil::{dword, word, ByteArrayExt, NumExt},
 };
 use modular_bitfield::{bitfield, specifiers::*, BitfieldSpecifier};
 
+use super::backup::Backup;
 use crate::{
     scheduling::{CartEvent, NdsEvent},
     Nds, NdsCpu,
@@ -69,6 +70,9 @@ pub struct Cartridge {
     pub romctrl: u32,
     pub rom_read_addr: RomRead,
     pub rom_read_left: u32,
+
+    // Backup
+    pub backup: Backup,
 }
 
 impl Cartridge {
@@ -80,6 +84,24 @@ impl Cartridge {
         self.rom.get_exact(0)
     }
 
+    pub fn title(&self) -> String {
+        let title = self.header().game_title;
+        let len = title.iter().position(|c| *c == 0).unwrap_or(title.len());
+        String::from_utf8_lossy(&title[..len]).to_string()
+    }
+
+    pub fn make_save(&self) -> Option<GameSave> {
+        if self.backup.ram.is_empty() {
+            return None;
+        }
+        Some(GameSave {
+            ram: self.backup.ram.clone(),
+            rtc: None,
+            rtc_offset: 0,
+            title: self.title(),
+        })
+    }
+
     pub fn handle_evt(&mut self, evt: CartEvent) -> bool {
         match evt {
             CartEvent::SpiDataComplete => {
@@ -99,7 +121,20 @@ impl Cartridge {
     pub fn data_write(&mut self, sched: &mut Scheduler<NdsEvent>, value: u16) {
         self.spictrl.set_busy(true);
         sched.schedule(NdsEvent::CartEvent(CartEvent::SpiDataComplete), 20); // TODO timing
-        log::error!("CART:SPI Write 0x{value:X}");
+        if self.spictrl.slot_mode() != SlotMode::Serial {
+            log::error!("CART:SPI Write 0x{value:X} in ROM mode");
+            return;
+        }
+
+        self.spidata = self.backup.transfer(value.u8()).u16();
+        if !self.spictrl.hold_chipselect() {
+            self.backup.deselect();
+        }
+    }
+
+    pub fn data_read(&mut self) -> u16 {
+        self.backup.data_read();
+        self.spidata
     }
 
     pub fn cmd_write(&mut self, value: IoSection<u32>, low: bool) {
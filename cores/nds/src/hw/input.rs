@@ -39,8 +39,8 @@ impl Nds {
 
     pub fn keyinput_ext(&self) -> u16 {
         // NDS input is active low
-        // TODO Touchscreen
-        0b0111_1100 | (0x3 ^ (self.c.input.state(self.scheduler.now()).0 >> 10))
+        let pen_up = (self.spi.touch.is_none() as u16) << 6;
+        0b0011_1100 | pen_up | (0x3 ^ ((self.c.input.state(self.scheduler.now()).0 >> 10) & 0x3))
     }
 
     /// Check if KEYCNT should cause a joypad IRQ.
@@ -148,6 +148,8 @@ impl IpcFifo {
             .into()
     }
 
+    /// Write IPCSYNC. Returns if the remote CPU should get an IPC sync IRQ,
+    /// which requires it to have enabled them.
     pub(crate) fn sync_write(&mut self, i: usize, value: IoSection<u16>) -> bool {
         let new = SyncRegister::from(value.with(self.sync_read(i)));
         let (a, b) = self.cpu.split_at_mut(1);
@@ -159,11 +161,15 @@ impl IpcFifo {
 
         local.sync_out = new.data_out();
         local.irqs.sync = new.irq_en();
-        new.send_irq()
+        new.send_irq() && remote.irqs.sync
     }
 
-    pub(crate) fn cnt_write(&mut self, i: usize, value: IoSection<u16>) {
-        let new = ControlRegister::from(value.with(self.sync_read(i)));
+    /// Write IPCFIFOCNT. Returns an IRQ for the local CPU if one of the
+    /// FIFO IRQs got enabled while its condition is already met.
+    pub(crate) fn cnt_write(&mut self, i: usize, value: IoSection<u16>) -> Option<Interrupt> {
+        // Error bit is masked, so that writes not covering it do not
+        // acknowledge it
+        let new = ControlRegister::from(value.with(self.cnt_read(i) & !(1 << 14)));
         let (a, b) = self.cpu.split_at_mut(1);
         let (local, remote) = if i == 0 {
             (&mut a[0], &mut b[0])
@@ -171,8 +177,11 @@ impl IpcFifo {
             (&mut b[0], &mut a[0])
         };
 
+        let send_irq_enabled = !local.irqs.send_empty && new.send_fifo_empty_irq();
+        let recv_irq_enabled = !local.irqs.recv_not_empty && new.recv_fifo_not_empty_irq();
         local.irqs.send_empty = new.send_fifo_empty_irq();
         local.irqs.recv_not_empty = new.recv_fifo_not_empty_irq();
+        // The error flag is acknowledged by writing 1
         local.error &= !new.error();
         local.fifo_en = new.enable();
 
@@ -180,5 +189,95 @@ impl IpcFifo {
             remote.fifo.clear();
             remote.last = 0;
         }
+
+        if send_irq_enabled && remote.fifo.is_empty() {
+            Some(Interrupt::IpcSendFifoEmpty)
+        } else if recv_irq_enabled && !local.fifo.is_empty() {
+            Some(Interrupt::IpcRecvFifoNotEmpty)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::section;
+
+    const ARM7: usize = 0;
+    const ARM9: usize = 1;
+
+    fn write(value: u16) -> IoSection<u16> {
+        section::<u16>(0, value.into(), 0xFFFF)
+    }
+
+    fn write_low(value: u8) -> IoSection<u16> {
+        section::<u16>(0, value.into(), 0xFF)
+    }
+
+    #[test]
+    fn handshake() {
+        let mut ipc = IpcFifo::default();
+        // ARM7 waits for IPC sync and received data, ARM9 only sends
+        assert!(ipc.cnt_write(ARM7, write(0x8400)).is_none());
+        assert!(ipc.cnt_write(ARM9, write(0x8000)).is_none());
+        // No sync IRQ before the ARM7 enabled them
+        assert!(!ipc.sync_write(ARM9, write(0x2100)));
+        assert!(!ipc.sync_write(ARM7, write(0x4000)));
+
+        // Sync value and IRQ
+        assert!(ipc.sync_write(ARM9, write(0x2200)));
+        assert_eq!(ipc.sync_read(ARM7) & 0xF, 2);
+        assert!(!ipc.sync_write(ARM7, write(0x4300)));
+        assert_eq!(ipc.sync_read(ARM9) & 0xF, 3);
+
+        // Only the first value received raises an IRQ
+        assert!(matches!(
+            ipc.send(ARM9, 0xDEAD_BEEF),
+            Some(Interrupt::IpcRecvFifoNotEmpty)
+        ));
+        assert!(ipc.send(ARM9, 2).is_none());
+        assert_eq!(ipc.cnt_read(ARM9) & 1, 0);
+        assert_eq!(ipc.cnt_read(ARM7) & 0x100, 0);
+
+        // Emptying the FIFO raises the ARM9's send-empty IRQ once enabled
+        assert!(matches!(ipc.receive(ARM7), (0xDEAD_BEEF, None)));
+        assert!(ipc.cnt_write(ARM9, write(0x8004)).is_none());
+        assert!(matches!(
+            ipc.receive(ARM7),
+            (2, Some(Interrupt::IpcSendFifoEmpty))
+        ));
+        assert_eq!(ipc.cnt_read(ARM9) & 1, 1);
+        assert_eq!(ipc.cnt_read(ARM7) & 0x100, 0x100);
+    }
+
+    #[test]
+    fn error_latches_until_acknowledged() {
+        let mut ipc = IpcFifo::default();
+        ipc.cnt_write(ARM7, write(0x8000));
+        ipc.cnt_write(ARM9, write(0x8000));
+
+        // Underflow keeps returning the last value
+        ipc.send(ARM9, 5);
+        assert_eq!(ipc.receive(ARM7).0, 5);
+        assert_eq!(ipc.receive(ARM7).0, 5);
+        assert_ne!(ipc.cnt_read(ARM7) & 0x4000, 0);
+        ipc.cnt_write(ARM7, write(0x8000));
+        assert_ne!(ipc.cnt_read(ARM7) & 0x4000, 0);
+        ipc.cnt_write(ARM7, write_low(0x00));
+        assert_ne!(ipc.cnt_read(ARM7) & 0x4000, 0);
+        ipc.cnt_write(ARM7, write(0xC000));
+        assert_eq!(ipc.cnt_read(ARM7) & 0x4000, 0);
+
+        // Overflow drops the value
+        for i in 0..17 {
+            ipc.send(ARM9, i);
+        }
+        assert_ne!(ipc.cnt_read(ARM9) & 0x4000, 0);
+        assert_eq!(ipc.cnt_read(ARM9) & 2, 2);
+        for i in 0..16 {
+            assert_eq!(ipc.receive(ARM7).0, i);
+        }
     }
 }
@@ -48,6 +48,11 @@ pub struct SpiBus {
     pub(crate) firm_data: Box<[u8]>,
     firm: FirmwareState,
     firm_write_en: bool,
+
+    /// Position currently touched on the bottom screen, in pixels.
+    pub(crate) touch: Option<[u16; 2]>,
+    tsc_result: u16,
+    tsc_pos: u8,
 }
 
 #[derive(Default, Debug)]
@@ -111,7 +116,19 @@ impl SpiBus {
                 }
             }
 
-            DevSelect::Touchscreen => log::error!("TSC: Write 0x{value:X}"),
+            DevSelect::Touchscreen => {
+                self.data_out = match self.tsc_pos {
+                    1 => (self.tsc_result >> 5) & 0xFF,
+                    2 => (self.tsc_result << 3) & 0xFF,
+                    _ => 0,
+                };
+                if value.is_bit(7) {
+                    self.tsc_result = self.touch_adc((value >> 4) & 7);
+                    self.tsc_pos = 1;
+                } else {
+                    self.tsc_pos = self.tsc_pos.saturating_add(1);
+                }
+            }
             DevSelect::Reserved => (),
         }
 
@@ -122,4 +139,51 @@ impl SpiBus {
             };
         }
     }
+
+    /// Get the 12-bit ADC value of the given touchscreen controller channel.
+    fn touch_adc(&self, channel: u16) -> u16 {
+        match (channel, self.touch) {
+            (1, Some([_, y])) => self.pixel_to_adc(y, 1),
+            (1, None) => 0xFFF,
+            (5, Some([x, _])) => self.pixel_to_adc(x, 0),
+            _ => 0,
+        }
+    }
+
+    /// Convert a screen position on the given axis to an ADC value, using
+    /// the calibration stored in the firmware user settings. Without
+    /// firmware, an uncalibrated linear mapping is used.
+    fn pixel_to_adc(&self, pixel: u16, axis: usize) -> u16 {
+        let Some(cal) = self.calibration() else {
+            return (pixel << 4).min(0xFFF);
+        };
+        let (adc1, adc2) = (cal.adc1[axis] as i32, cal.adc2[axis] as i32);
+        let (scr1, scr2) = (cal.scr1[axis] as i32, cal.scr2[axis] as i32);
+        if scr1 == scr2 {
+            return (pixel << 4).min(0xFFF);
+        }
+        let adc = adc1 + (pixel as i32 - scr1) * (adc2 - adc1) / (scr2 - scr1);
+        adc.clamp(0, 0xFFF) as u16
+    }
+
+    /// Read touchscreen calibration from the firmware user settings.
+    fn calibration(&self) -> Option<Calibration> {
+        let offset = self.firm_data.get(0x20..0x22)?;
+        let user = hword(offset[0], offset[1]).us() * 8;
+        let cal = self.firm_data.get((user + 0x58)..(user + 0x64))?;
+        Some(Calibration {
+            adc1: [hword(cal[0], cal[1]), hword(cal[2], cal[3])],
+            scr1: [cal[4], cal[5]],
+            adc2: [hword(cal[6], cal[7]), hword(cal[8], cal[9])],
+            scr2: [cal[10], cal[11]],
+        })
+    }
+}
+
+/// Touchscreen calibration points, indexed by axis (X, Y).
+struct Calibration {
+    adc1: [u16; 2],
+    scr1: [u8; 2],
+    adc2: [u16; 2],
+    scr2: [u8; 2],
 }
@@ -6,7 +6,7 @@
 // If a copy of these licenses was not distributed with this file, you can
 // obtain them at https://mozilla.org/MPL/2.0/ and http://www.gnu.org/licenses/.
 
-use std::{marker::PhantomData, mem};
+use std::marker::PhantomData;
 
 use arm_cpu::{Cpu, Interrupt};
 pub use common::components::io::*;
@@ -16,6 +16,7 @@ use crate::{
     addr::*,
     graphics::vram::*,
     hw::{cartridge::Cartridge, dma::Dmas},
+    memory::WramStatus,
     Nds, Nds7, Nds9, NdsCpu,
 };
 
@@ -59,7 +60,7 @@ impl Nds {
 
         // SPI / Cart
         io16!(a, AUXSPICNT, self.cart.spictrl.into());
-        io16!(a, AUXSPIDATA, self.cart.spidata);
+        io16!(a, AUXSPIDATA, self.cart.data_read());
         io32!(a, ROMCTRL, self.cart.romctrl.set_bit(23, true));
         io32!(a, AUXSPIIN, Cartridge::data_in_read(&mut DS::mk(self)));
 
@@ -67,7 +68,7 @@ impl Nds {
         io08!(a, POSTFLG, self.memory.postflg as u8);
         io16!(a, EXMEM, self.memory.exmem);
 
-        log::info!("Read from unknown IO register 0x{a:X}");
+        log::trace!("Read from unknown IO register 0x{a:X}");
         FAILED_READ
     }
 
@@ -83,7 +84,11 @@ impl Nds {
                 self.send_irq(DS::I ^ 1, Interrupt::IpcSync);
             }
         });
-        iow16!(a, IPCFIFOCNT, self.fifo.cnt_write(DS::I, s16));
+        iow16!(a, IPCFIFOCNT, {
+            if let Some(intr) = self.fifo.cnt_write(DS::I, s16) {
+                self.send_irq(DS::I, intr);
+            }
+        });
         iow32!(a, IPCFIFOSEND_L, {
             let intr = self.fifo.send(DS::I, s32.with(0));
             self.maybe_irq_to_other(DS::I, intr);
@@ -166,7 +171,7 @@ impl Nds {
         // Misc
         iow08!(a, POSTFLG, self.memory.postflg = true);
 
-        log::info!("Write to unknown IO register 0x{a:X}, value 0x{v:X}");
+        log::trace!("Write to unknown IO register 0x{a:X}, value 0x{v:X}");
         FAILED_WRITE
     }
 }
@@ -261,7 +266,7 @@ impl Nds9 {
             io08!(a, VRAMCNT_I, self.gpu.vram.ctrls[I].into());
 
             // DIV
-            io16!(a, DIVCNT_L, self.div.ctrl.into());
+            io16!(a, DIVCNT_L, self.div.ctrl(self.scheduler.now()).into());
             io32!(a, DIV_NUMER, self.div.numer as u32);
             io32!(a, DIV_NUMER_H, (self.div.numer >> 32) as u32);
             io32!(a, DIV_DENOM, self.div.denom as u32);
@@ -271,7 +276,7 @@ impl Nds9 {
             io32!(a, DIV_REM, self.div.rem as u32);
             io32!(a, DIV_REM_H, (self.div.rem >> 32) as u32);
             // SQRT
-            io16!(a, SQRTCNT_L, self.sqrt.ctrl.into());
+            io16!(a, SQRTCNT_L, self.sqrt.ctrl(self.scheduler.now()).into());
             io32!(a, SQRT_RESULT, self.sqrt.result);
             io32!(a, SQRT_INPUT, self.sqrt.input as u32);
             io32!(a, SQRT_INPUT_H, (self.sqrt.input >> 32) as u32);
@@ -325,7 +330,7 @@ impl Nds9 {
                 );
             }
             iow08!(a, WRAMCNT, {
-                dsx.memory.wram_status = unsafe { mem::transmute(s8.raw() & 3) };
+                dsx.memory.wram_status = WramStatus::from_wramcnt(s8.raw());
                 dsx.update_wram();
             });
             iow16!(a, EXMEM, s16.mask(0xE8FF).apply(&mut self.memory.exmem));
@@ -345,39 +350,50 @@ impl Nds9 {
             // Math
             iow16!(a, DIVCNT_L, {
                 s16.mask(0x3).apply_io(&mut self.div.ctrl);
-                self.div.update();
+                let now = self.scheduler.now();
+                self.div.update(now);
             });
             iow32!(a, DIV_NUMER, {
                 self.div.numer =
                     (self.div.numer & !0xFFFF_FFFF) | s32.with(self.div.numer as u32) as u64;
-                self.div.update();
+                let now = self.scheduler.now();
+                self.div.update(now);
             });
             iow32!(a, DIV_NUMER_H, {
                 self.div.numer = (self.div.numer & 0xFFFF_FFFF)
                     | (s32.with((self.div.numer >> 32) as u32) as u64) << 32;
-                self.div.update();
+                let now = self.scheduler.now();
+                self.div.update(now);
             });
             iow32!(a, DIV_DENOM, {
                 self.div.denom =
                     (self.div.denom & !0xFFFF_FFFF) | s32.with(self.div.denom as u32) as u64;
-                self.div.update();
+                let now = self.scheduler.now();
+                self.div.update(now);
             });
             iow32!(a, DIV_DENOM_H, {
                 self.div.denom = (self.div.denom & 0xFFFF_FFFF)
                     | (s32.with((self.div.denom >> 32) as u32) as u64) << 32;
-                self.div.update();
+                let now = self.scheduler.now();
+                self.div.update(now);
             });
             // SQRT
-            iow16!(a, SQRTCNT_L, s16.apply_io(&mut self.sqrt.ctrl));
+            iow16!(a, SQRTCNT_L, {
+                s16.mask(0x1).apply_io(&mut self.sqrt.ctrl);
+                let now = self.scheduler.now();
+                self.sqrt.update(now);
+            });
             iow32!(a, SQRT_INPUT, {
                 self.sqrt.input =
                     (self.sqrt.input & !0xFFFF_FFFF) | s32.with(self.sqrt.input as u32) as u64;
-                self.sqrt.update();
+                let now = self.scheduler.now();
+                self.sqrt.update(now);
             });
             iow32!(a, SQRT_INPUT_H, {
                 self.sqrt.input = (self.sqrt.input & 0xFFFF_FFFF)
                     | (s32.with((self.sqrt.input >> 32) as u32) as u64) << 32;
-                self.sqrt.update();
+                let now = self.scheduler.now();
+                self.sqrt.update(now);
             });
             self.set_mmio_shared::<Self>(a, v, m)
         })
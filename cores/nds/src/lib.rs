@@ -7,10 +7,8 @@
 // obtain them at https://mozilla.org/MPL/2.0/ and http://www.gnu.org/licenses/.
 
 // Things left to do:
-// - IPC FIFO implementation
 // - Video stuff
 // - Audio
-// - Math registers implementation
 // - Link port
 // - Touchscreen
 // - RTC
@@ -39,16 +37,26 @@ use std::{
 };
 
 use addr::{BIOSPROT, SOUNDBIAS};
-use arm_cpu::{interface::ArmSystem, registers::Flag, Cpu, Interrupt};
+use arm_cpu::{
+    interface::{ArmSystem, RwType},
+    registers::Flag,
+    Cpu, Interrupt,
+};
 use common::{
-    common::options::{EmulateOptions, SystemConfig},
+    common::{
+        debugger::{MemoryRegion, Width},
+        options::{EmulateOptions, SystemConfig},
+    },
     common_functions,
-    components::{scheduler::Scheduler, storage::GameSave},
-    numutil::NumExt,
-    Colour, Common, Core, Time, TimeS,
+    components::{
+        scheduler::Scheduler,
+        storage::{GameSave, Storage},
+    },
+    numutil::{ByteArrayExt, NumExt},
+    Colour, Common, Core, CoreKind, Time, TimeS,
 };
 use cpu::{
-    cp15::Cp15,
+    cp15::{Cp15, TcmState},
     math::{Div, Sqrt},
 };
 use hw::{input::Input, ipc::IpcFifo, spi::SpiBus};
@@ -59,7 +67,7 @@ use crate::{
     cpu::NDS9_CLOCK,
     graphics::Gpu,
     hw::{audio::Apu, cartridge::Cartridge, dma::Dmas, timer::Timers},
-    memory::Memory,
+    memory::{Memory, KB, MB},
     scheduling::{ApuEvent, NdsEvent},
 };
 
@@ -110,6 +118,11 @@ macro_rules! nds_wrapper {
 nds_wrapper!(Nds7, 0);
 nds_wrapper!(Nds9, 1);
 
+/// Start of the ARM7's address space in [Core::get_memory] and
+/// [Core::set_memory]. The ARM7 ignores the upper 4 address bits, so this
+/// is otherwise unused; addresses below it are the ARM9's.
+pub const ARM7_SPACE: u32 = 0x1000_0000;
+
 #[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
 pub struct Nds {
     cpu7: Cpu<Nds7>,
@@ -138,6 +151,90 @@ pub struct Nds {
 impl Core for Nds {
     common_functions!(NDS9_CLOCK, NdsEvent::PauseEmulation, [256, 192 * 2]);
 
+    fn kind(&self) -> Option<CoreKind> {
+        Some(CoreKind::Nds)
+    }
+
+    fn set_touch(&mut self, pos: Option<[usize; 2]>) {
+        // Only the bottom screen can be touched
+        self.spi.touch = pos
+            .filter(|[x, y]| *x < 256 && (192..384).contains(y))
+            .map(|[x, y]| [x as u16, (y - 192) as u16]);
+    }
+
+    fn apply_config(&mut self, config: &SystemConfig) {
+        self.c.config.apply_live(config);
+        self.cpu7.apply_optimizations(config);
+        self.cpu9.apply_optimizations(config);
+    }
+
+    fn get_memory(&self, addr: u32, width: Width) -> u32 {
+        match width {
+            Width::Byte => self.debug_get::<u8>(addr).u32(),
+            Width::Halfword => self.debug_get::<u16>(addr).u32(),
+            Width::Word => self.debug_get::<u32>(addr),
+        }
+    }
+
+    fn set_memory(&mut self, addr: u32, value: u32, width: Width) {
+        if let Some(addr) = addr.checked_sub(ARM7_SPACE).filter(|a| *a < ARM7_SPACE) {
+            let mut ds = self.nds7();
+            match width {
+                Width::Byte => ds.set(addr, value.u8()),
+                Width::Halfword => ds.set(addr, value.u16()),
+                Width::Word => ds.set(addr, value),
+            }
+        } else {
+            let mut ds = self.nds9();
+            match width {
+                Width::Byte => ds.set(addr, value.u8()),
+                Width::Halfword => ds.set(addr, value.u16()),
+                Width::Word => ds.set(addr, value),
+            }
+        }
+    }
+
+    fn is_writable_memory(&self, addr: u32) -> bool {
+        match addr.checked_sub(ARM7_SPACE) {
+            // Everything below is BIOS
+            Some(addr) if addr < ARM7_SPACE => (0x200_0000..0x800_0000).contains(&addr),
+            _ => addr < 0x800_0000,
+        }
+    }
+
+    fn memory_regions(&self) -> Vec<MemoryRegion> {
+        // DTCM is left out, since its location is set by the game.
+        // I/O is left out since reading it has side effects, see
+        // [Nds::debug_get].
+        vec![
+            MemoryRegion::new("ARM9 ITCM", 0x000_0000, 32 * KB as u32, true),
+            MemoryRegion::new("ARM9 Main RAM", 0x200_0000, 4 * MB as u32, true),
+            MemoryRegion::new("ARM9 Shared WRAM", 0x300_0000, 32 * KB as u32, true),
+            MemoryRegion::new("ARM9 Palette", 0x500_0000, 2 * KB as u32, true),
+            MemoryRegion::new("ARM9 VRAM (LCDC)", 0x680_0000, 656 * KB as u32, true),
+            MemoryRegion::new("ARM9 OAM", 0x700_0000, 2 * KB as u32, true),
+            MemoryRegion::new("ARM7 BIOS", ARM7_SPACE, 16 * KB as u32, false),
+            MemoryRegion::new(
+                "ARM7 Main RAM",
+                ARM7_SPACE + 0x200_0000,
+                4 * MB as u32,
+                true,
+            ),
+            MemoryRegion::new(
+                "ARM7 Shared WRAM",
+                ARM7_SPACE + 0x300_0000,
+                32 * KB as u32,
+                true,
+            ),
+            MemoryRegion::new("ARM7 WRAM", ARM7_SPACE + 0x380_0000, 64 * KB as u32, true),
+            MemoryRegion::new("ARM9 BIOS", 0xFFFF_0000, 4 * KB as u32, false),
+        ]
+    }
+
+    fn take_event_counts(&mut self) -> Vec<(&'static str, u32)> {
+        self.scheduler.take_event_counts()
+    }
+
     fn advance(&mut self) {
         // Run the ARM9, then keep running the ARM7
         // until it has caught up
@@ -218,8 +315,20 @@ impl Core for Nds {
     }
 
     fn make_save(&self) -> Option<GameSave> {
-        // TODO
-        None
+        self.cart.make_save()
+    }
+
+    fn load_save(&mut self, save: GameSave) {
+        self.cart.backup.load(save);
+        self.cart.backup.ram_dirty = true;
+    }
+
+    fn save_is_dirty(&self) -> bool {
+        self.cart.backup.ram_dirty
+    }
+
+    fn clear_save_dirty(&mut self) {
+        self.cart.backup.ram_dirty = false;
     }
 
     fn get_rom(&self) -> Vec<u8> {
@@ -228,6 +337,40 @@ impl Core for Nds {
 }
 
 impl Nds {
+    /// Read memory without side effects, for debugging. Addresses starting
+    /// at [ARM7_SPACE] are read from the ARM7's address space, the rest
+    /// from the ARM9's. I/O registers and unmapped memory read as 0.
+    fn debug_get<T: RwType>(&self, addr: u32) -> T {
+        let addr = addr & !(T::WIDTH - 1);
+        if let Some(addr) = addr.checked_sub(ARM7_SPACE).filter(|a| *a < ARM7_SPACE) {
+            return self.memory.pager7.read(addr).unwrap_or(T::from_u32(0));
+        }
+
+        if addr <= 0xFFF_FFFF {
+            for tcm in 0..2 {
+                if self.cp15.tcm_state[tcm] == TcmState::Rw
+                    && self.cp15.tcm_range[tcm].contains(&addr)
+                {
+                    return self.memory.tcm[tcm]
+                        .get_wrap(addr.us() - self.cp15.tcm_range[tcm].start.us());
+                }
+            }
+            if let Some(read) = self.memory.pager9.read(addr) {
+                return read;
+            }
+        }
+
+        let a = addr.us();
+        match addr >> 24 {
+            0xFF if (0xFFFF_0000..0xFFFF_1000).contains(&addr) => {
+                self.memory.bios9.get_exact(a & 0xFFFF)
+            }
+            0x05 => self.gpu.ppus[a.bit(10)].palette.get_wrap(a),
+            0x07 => self.gpu.ppus[a.bit(10)].oam.get_wrap(a),
+            _ => T::from_u32(0),
+        }
+    }
+
     #[inline]
     pub fn nds7(&mut self) -> Nds7 {
         Nds7(self as *mut Nds)
@@ -245,13 +388,21 @@ impl Nds {
     /// Restore state after a savestate load. `old_self` should be the
     /// system state before the state was loaded.
     pub fn restore_from(&mut self, old_self: Self) {
+        let save = old_self.cart.make_save();
+        self.cart.load_rom(old_self.cart.rom);
+        if let Some(save) = save {
+            self.cart.backup.load(save);
+        }
+        self.cart.backup.ram_dirty = old_self.cart.backup.ram_dirty;
+
         self.c.restore_from(old_self.c);
         self.init_memory();
     }
 
-    pub fn with_cart(cart: Vec<u8>, _path: Option<PathBuf>, config: &SystemConfig) -> Box<Self> {
+    pub fn with_cart(cart: Vec<u8>, path: Option<PathBuf>, config: &SystemConfig) -> Box<Self> {
         let mut nds = Box::<Self>::default();
         nds.c.config = config.clone();
+        nds.c.video_buffer.set_history_len(config.frame_history);
         if let Some(bios) = config.get_bios("nds7") {
             nds.memory.bios7 = bios.into();
         }
@@ -262,6 +413,9 @@ impl Nds {
             nds.spi.firm_data = fw.into();
         }
         nds.cart.load_rom(cart);
+        if let Some(save) = Storage::load(path, nds.cart.title()) {
+            nds.cart.backup.load(save);
+        }
 
         log::error!("{:#?}", nds.cart.header());
         nds.init_memory();
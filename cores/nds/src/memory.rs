@@ -37,16 +37,33 @@ use crate::{
 pub const KB: usize = 1024;
 pub const MB: usize = KB * KB;
 
+/// Mapping of the shared WRAM between the CPUs, as set by WRAMCNT.
 #[repr(u8)]
 #[derive(Copy, Clone, PartialEq, Eq)]
 #[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
 pub enum WramStatus {
+    /// All 32KB to ARM9; ARM7 sees its own WRAM in the shared region.
     All9 = 0,
+    /// First 16KB to ARM7, second 16KB to ARM9.
     First7 = 1,
+    /// First 16KB to ARM9, second 16KB to ARM7.
     First9 = 2,
+    /// All 32KB to ARM7; ARM9 sees nothing.
     All7 = 3,
 }
 
+impl WramStatus {
+    /// Get the status from the value written to WRAMCNT.
+    pub fn from_wramcnt(value: u8) -> Self {
+        match value & 3 {
+            0 => Self::All9,
+            1 => Self::First7,
+            2 => Self::First9,
+            _ => Self::All7,
+        }
+    }
+}
+
 /// Memory struct containing the NDS's memory regions along with page tables
 /// and other auxiliary cached information relating to memory.
 /// A lot is separated by the 2 CPUs.
@@ -90,10 +107,8 @@ impl Nds {
         self.gpu.vram.init_mappings(p7, p9);
         self.update_wram();
 
-        if self.c.config.cached_interpreter {
-            self.cpu7.cache.init();
-            self.cpu9.cache.init();
-        }
+        self.cpu7.apply_optimizations(&self.c.config);
+        self.cpu9.apply_optimizations(&self.c.config);
     }
 
     /// Evict and recreate WRAM mappings.
@@ -132,6 +147,12 @@ impl Nds {
                     .map(&self.memory.wram7, 0x300_0000..0x380_0000, RW)
             }
         }
+
+        // Code in the shared region is now different
+        for addr in (0x300_0000..0x400_0000).step_by(0x4000) {
+            self.cpu7.cache.invalidate_address(addr);
+            self.cpu9.cache.invalidate_address(addr);
+        }
     }
 
     pub(super) fn maybe_irq_to_other(&mut self, cpu: usize, intr: Option<Interrupt>) {
@@ -184,7 +205,7 @@ impl Nds7 {
             // MMIO
             0x04 => self.set_mmio(addr, value),
             _ => {
-                log::info!("Invalid write: {addr:X}");
+                log::trace!("Invalid write: {addr:X}");
             }
         }
     }
@@ -224,7 +245,7 @@ impl Nds9 {
             0x04 => self.get_mmio(addr),
 
             _ => {
-                log::info!("Invalid read: {addr:X}");
+                log::trace!("Invalid read: {addr:X}");
                 T::from_u32(0)
             }
         }
@@ -260,7 +281,7 @@ impl Nds9 {
             0x04 => self.set_mmio(addr, value),
 
             _ => {
-                log::info!("Invalid write: {addr:X}");
+                log::trace!("Invalid write: {addr:X}");
             }
         }
     }
@@ -289,3 +310,73 @@ impl Default for Memory {
 }
 
 unsafe impl Send for Memory {}
+
+#[cfg(test)]
+mod tests {
+    use common::{common::debugger::Width, Core};
+
+    use super::*;
+
+    fn wramcnt(ds: &mut Nds, value: u8) {
+        ds.nds9().set::<u8>(0x0400_0247, value);
+    }
+
+    #[test]
+    fn memory_regions_round_trip() {
+        let mut ds = Box::<Nds>::default();
+        ds.init_memory();
+        // Split shared WRAM, so both CPUs see some of it, and map all
+        // VRAM banks to the LCDC
+        wramcnt(&mut ds, 1);
+        for vramcnt in (0x0400_0240..=0x0400_0249).filter(|a| *a != 0x0400_0247) {
+            ds.nds9().set::<u8>(vramcnt, 0x80);
+        }
+        for region in ds.memory_regions() {
+            for addr in [region.start, region.end() - 3] {
+                let value = ds.get_memory(addr, Width::Word);
+                if region.writable {
+                    ds.set_memory(addr, 0x1234_5678, Width::Word);
+                    let read = ds.get_memory(addr, Width::Word);
+                    assert_eq!(read, 0x1234_5678, "{} at {addr:08X}", region.name);
+                    ds.set_memory(addr, value, Width::Word);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn wramcnt_modes() {
+        let mut ds = Box::<Nds>::default();
+        ds.init_memory();
+        ds.nds7().set::<u32>(0x0380_0000, 0x7777);
+
+        // All to ARM9, ARM7 sees its own WRAM instead
+        wramcnt(&mut ds, 0);
+        ds.nds9().set::<u32>(0x0300_0000, 0x1111);
+        ds.nds9().set::<u32>(0x0300_4000, 0x2222);
+        assert_eq!(ds.nds7().get::<u32>(0x0300_0000), 0x7777);
+
+        // All to ARM7, ARM9 sees nothing
+        wramcnt(&mut ds, 3);
+        assert_eq!(ds.nds7().get::<u32>(0x0300_0000), 0x1111);
+        assert_eq!(ds.nds7().get::<u32>(0x0300_4000), 0x2222);
+        ds.nds9().set::<u32>(0x0300_0000, 0xDEAD);
+        assert_eq!(ds.nds9().get::<u32>(0x0300_0000), 0);
+        assert_eq!(ds.nds7().get::<u32>(0x0300_0000), 0x1111);
+
+        // First half to ARM7, second half to ARM9; both mirrored
+        wramcnt(&mut ds, 1);
+        assert_eq!(ds.nds7().get::<u32>(0x0300_4000), 0x1111);
+        assert_eq!(ds.nds9().get::<u32>(0x0300_0000), 0x2222);
+        ds.nds9().set::<u32>(0x0300_0000, 0x3333);
+        assert_eq!(ds.nds7().get::<u32>(0x0300_0000), 0x1111);
+
+        // First half to ARM9, second half to ARM7
+        wramcnt(&mut ds, 2);
+        assert_eq!(ds.nds7().get::<u32>(0x0300_0000), 0x3333);
+        assert_eq!(ds.nds9().get::<u32>(0x0300_4000), 0x1111);
+        ds.nds9().set::<u32>(0x0300_0000, 0x4444);
+        assert_eq!(ds.nds7().get::<u32>(0x0300_4000), 0x3333);
+        assert_eq!(ds.nds9().get::<u8>(0x0400_0247), 2);
+    }
+}
@@ -72,7 +72,18 @@ impl NdsEvent {
     }
 }
 
-impl Kind for NdsEvent {}
+impl Kind for NdsEvent {
+    fn name(&self) -> &'static str {
+        match self {
+            PauseEmulation => "Pause",
+            PpuEvent(_) => "PPU",
+            ApuEvent(_) => "APU",
+            TimerOverflow { .. } => "Timer",
+            UpdateKeypad => "Keypad",
+            CartEvent(_) => "Cart",
+        }
+    }
+}
 
 impl Default for NdsEvent {
     fn default() -> Self {
@@ -0,0 +1,119 @@
+// Copyright (c) 2024 Leela Aurelia, git@elia.garden
+//
+// Unless otherwise noted, this file is released and thus subject to the
+// terms of the Mozilla Public License Version 2.0 (MPL-2.0) or the
+// GNU General Public License Version 3 (GPL-3).
+// If a copy of these licenses was not distributed with this file, you can
+// obtain them at https://mozilla.org/MPL/2.0/ and http://www.gnu.org/licenses/.
+
+use common::numutil::NumExt;
+
+use crate::Nes;
+
+/// Timer periods in CPU cycles (NTSC).
+const RATES: [u16; 16] = [
+    428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54,
+];
+
+/// The delta modulation channel, playing 1-bit delta samples from memory.
+#[derive(Default)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct DmcChannel {
+    looping: bool,
+    period: u16,
+    timer: u16,
+    level: u8,
+
+    sample_addr: u16,
+    sample_length: u16,
+    current_addr: u16,
+    bytes_remaining: u16,
+    buffer: Option<u8>,
+
+    shift_register: u8,
+    bits_remaining: u8,
+    silence: bool,
+}
+
+impl DmcChannel {
+    pub fn write(&mut self, reg: u16, value: u8) {
+        match reg {
+            0 => {
+                // IRQs are not implemented yet, so bit 7 is ignored
+                self.looping = value.is_bit(6);
+                self.period = RATES[value.us() & 0xF];
+            }
+            1 => self.level = value & 0x7F,
+            2 => self.sample_addr = 0xC000 | (value.u16() << 6),
+            _ => self.sample_length = (value.u16() << 4) | 1,
+        }
+    }
+
+    /// Enable or disable the channel through 0x4015. Enabling restarts the
+    /// sample if it has finished.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        if !enabled {
+            self.bytes_remaining = 0;
+        } else if self.bytes_remaining == 0 {
+            self.restart();
+        }
+    }
+
+    pub fn active(&self) -> bool {
+        self.bytes_remaining > 0
+    }
+
+    fn restart(&mut self) {
+        self.current_addr = self.sample_addr;
+        self.bytes_remaining = self.sample_length;
+    }
+
+    /// Clock the timer, done every CPU cycle.
+    pub fn clock(nes: &mut Nes) {
+        if nes.apu.dmc.buffer.is_none() && nes.apu.dmc.bytes_remaining > 0 {
+            let addr = nes.apu.dmc.current_addr;
+            let value = nes.get(addr);
+            let dmc = &mut nes.apu.dmc;
+            dmc.buffer = Some(value);
+            dmc.current_addr = dmc.current_addr.checked_add(1).unwrap_or(0x8000);
+            dmc.bytes_remaining -= 1;
+            if dmc.bytes_remaining == 0 && dmc.looping {
+                dmc.restart();
+            }
+        }
+
+        let dmc = &mut nes.apu.dmc;
+        if dmc.timer != 0 {
+            dmc.timer -= 1;
+            return;
+        }
+        dmc.timer = dmc.period;
+
+        if !dmc.silence {
+            if dmc.shift_register.is_bit(0) {
+                if dmc.level <= 125 {
+                    dmc.level += 2;
+                }
+            } else if dmc.level >= 2 {
+                dmc.level -= 2;
+            }
+        }
+        dmc.shift_register >>= 1;
+
+        dmc.bits_remaining = dmc.bits_remaining.saturating_sub(1);
+        if dmc.bits_remaining == 0 {
+            dmc.bits_remaining = 8;
+            match dmc.buffer.take() {
+                Some(value) => {
+                    dmc.silence = false;
+                    dmc.shift_register = value;
+                }
+                None => dmc.silence = true,
+            }
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        self.level
+    }
+}
@@ -0,0 +1,351 @@
+// Copyright (c) 2024 Leela Aurelia, git@elia.garden
+//
+// Unless otherwise noted, this file is released and thus subject to the
+// terms of the Mozilla Public License Version 2.0 (MPL-2.0) or the
+// GNU General Public License Version 3 (GPL-3).
+// If a copy of these licenses was not distributed with this file, you can
+// obtain them at https://mozilla.org/MPL/2.0/ and http://www.gnu.org/licenses/.
+
+mod dmc;
+mod noise;
+mod pulse;
+mod triangle;
+
+use common::{
+    components::psg::{Channel, EnvelopGenerator, LengthCountedChannel},
+    numutil::NumExt,
+    TimeS,
+};
+use dmc::DmcChannel;
+use noise::NoiseChannel;
+use pulse::PulseChannel;
+use triangle::TriangleChannel;
+
+use crate::{
+    scheduling::{ApuEvent, NesEvent},
+    Nes, CLOCK_HZ,
+};
+
+const SAMPLE_EVERY_N_CLOCKS: TimeS = CLOCK_HZ as TimeS / 48000;
+/// Actual rate samples are produced at.
+pub const SAMPLE_RATE: usize = CLOCK_HZ as usize / SAMPLE_EVERY_N_CLOCKS as usize;
+/// CPU cycles between frame counter steps, roughly a quarter frame.
+const FRAME_COUNTER_STEP: TimeS = 7457;
+
+const LENGTH_TABLE: [u8; 32] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14, 12, 16, 24, 18, 48, 20, 96, 22,
+    192, 24, 72, 26, 16, 28, 32, 30,
+];
+
+/// A channel with a length counter. These share the layout of the length
+/// counter in their registers, see [Apu::write_channel].
+trait NesChannel: Channel {
+    /// Write one of the channel's 4 registers.
+    fn write(&mut self, reg: u16, value: u8);
+    /// If the length counter is halted, set through register 0.
+    fn length_halted(&self) -> bool;
+}
+
+/// Set up the envelope from register 0 of the pulse and noise channels.
+/// Returns the volume if the envelope is not used.
+fn write_envelope(envelope: &mut EnvelopGenerator, value: u8) -> Option<u8> {
+    let volume = value & 0xF;
+    // The divider is reloaded with the volume and counts down to 0,
+    // so it takes one more clock than the volume
+    envelope.set_parameters(15, false, volume + 1, value.is_bit(5));
+    value.is_bit(4).then_some(volume)
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct Apu {
+    pulse1: LengthCountedChannel<PulseChannel>,
+    pulse2: LengthCountedChannel<PulseChannel>,
+    triangle: LengthCountedChannel<TriangleChannel>,
+    noise: LengthCountedChannel<NoiseChannel>,
+    dmc: DmcChannel,
+    /// Channels enabled through 0x4015. Length counters of disabled
+    /// channels cannot be loaded.
+    enabled: u8,
+
+    /// If the frame counter uses the 5-step sequence.
+    five_step: bool,
+    frame_step: u8,
+    /// If the pulse channels are on an odd CPU cycle, since their
+    /// timers are only clocked every other cycle.
+    odd_cycle: bool,
+    /// Charge of the output capacitor, to remove DC offset.
+    capacitor: f32,
+}
+
+impl Apu {
+    pub fn handle_event(nes: &mut Nes, event: ApuEvent, late_by: TimeS) {
+        match event {
+            ApuEvent::PushSample => {
+                Self::step(nes, SAMPLE_EVERY_N_CLOCKS);
+                let sample = nes.apu.sample();
+                nes.c.audio_buffer.input[0].push(sample);
+                nes.c.audio_buffer.input[1].push(sample);
+                nes.scheduler.schedule(
+                    NesEvent::ApuEvent(ApuEvent::PushSample),
+                    SAMPLE_EVERY_N_CLOCKS - late_by,
+                )
+            }
+            ApuEvent::FrameCounter => {
+                nes.apu.clock_frame_counter();
+                nes.scheduler.schedule(
+                    NesEvent::ApuEvent(ApuEvent::FrameCounter),
+                    FRAME_COUNTER_STEP - late_by,
+                )
+            }
+        }
+    }
+
+    /// Start the APU's events. To be called once on system creation.
+    pub fn init_scheduler(nes: &mut Nes) {
+        nes.c.audio_buffer.set_input_sr(SAMPLE_RATE);
+        nes.scheduler.schedule(
+            NesEvent::ApuEvent(ApuEvent::PushSample),
+            SAMPLE_EVERY_N_CLOCKS,
+        );
+        nes.scheduler.schedule(
+            NesEvent::ApuEvent(ApuEvent::FrameCounter),
+            FRAME_COUNTER_STEP,
+        );
+    }
+
+    pub fn read_status(nes: &Nes) -> u8 {
+        let apu = &nes.apu;
+        ((apu.pulse1.length_counter() > 0) as u8)
+            | (((apu.pulse2.length_counter() > 0) as u8) << 1)
+            | (((apu.triangle.length_counter() > 0) as u8) << 2)
+            | (((apu.noise.length_counter() > 0) as u8) << 3)
+            | ((apu.dmc.active() as u8) << 4)
+    }
+
+    pub fn write(nes: &mut Nes, addr: u16, value: u8) {
+        let apu = &mut nes.apu;
+        match addr {
+            0x4000..=0x4003 => {
+                Self::write_channel(&mut apu.pulse1, apu.enabled.is_bit(0), addr, value)
+            }
+            0x4004..=0x4007 => {
+                Self::write_channel(&mut apu.pulse2, apu.enabled.is_bit(1), addr, value)
+            }
+            0x4008..=0x400B => {
+                Self::write_channel(&mut apu.triangle, apu.enabled.is_bit(2), addr, value)
+            }
+            0x400C..=0x400F => {
+                Self::write_channel(&mut apu.noise, apu.enabled.is_bit(3), addr, value)
+            }
+            0x4010..=0x4013 => apu.dmc.write(addr & 3, value),
+            0x4015 => {
+                apu.enabled = value;
+                Self::set_length_enabled(&mut apu.pulse1, value.is_bit(0));
+                Self::set_length_enabled(&mut apu.pulse2, value.is_bit(1));
+                Self::set_length_enabled(&mut apu.triangle, value.is_bit(2));
+                Self::set_length_enabled(&mut apu.noise, value.is_bit(3));
+                apu.dmc.set_enabled(value.is_bit(4));
+            }
+            0x4017 => {
+                // Frame counter IRQs are not implemented yet, so bit 6
+                // is ignored
+                apu.five_step = value.is_bit(7);
+                apu.frame_step = 0;
+                if apu.five_step {
+                    apu.clock_quarter_frame();
+                    apu.clock_half_frame();
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// Write to a channel's register. The length counter is loaded from
+    /// the upper bits of register 3, which also restarts the channel.
+    fn write_channel<C: NesChannel>(
+        ch: &mut LengthCountedChannel<C>,
+        enabled: bool,
+        addr: u16,
+        value: u8,
+    ) {
+        let reg = addr & 3;
+        ch.channel_mut().write(reg, value);
+        match reg {
+            0 => ch.write_length_enable(!ch.channel().length_halted()),
+            3 if enabled => {
+                ch.load_length(LENGTH_TABLE[value.us() >> 3].u16());
+                ch.trigger();
+            }
+            3 => ch.channel_mut().trigger(),
+            _ => (),
+        }
+    }
+
+    /// Enable or disable a channel through 0x4015. Disabling clears the
+    /// length counter, silencing the channel.
+    fn set_length_enabled<C: Channel>(ch: &mut LengthCountedChannel<C>, enabled: bool) {
+        if !enabled {
+            ch.load_length(0);
+            ch.set_enable(false);
+        }
+    }
+
+    /// Run the channel timers for the given amount of CPU cycles.
+    fn step(nes: &mut Nes, cycles: TimeS) {
+        for _ in 0..cycles {
+            let apu = &mut nes.apu;
+            if apu.odd_cycle {
+                apu.pulse1.channel_mut().clock();
+                apu.pulse2.channel_mut().clock();
+            }
+            apu.odd_cycle = !apu.odd_cycle;
+            apu.triangle.channel_mut().clock();
+            apu.noise.channel_mut().clock();
+            DmcChannel::clock(nes);
+        }
+    }
+
+    fn clock_frame_counter(&mut self) {
+        let (quarter, half) = match (self.five_step, self.frame_step) {
+            (_, 0 | 2) => (true, false),
+            (false, 1 | 3) | (true, 1 | 4) => (true, true),
+            _ => (false, false),
+        };
+        if quarter {
+            self.clock_quarter_frame();
+        }
+        if half {
+            self.clock_half_frame();
+        }
+        let steps = if self.five_step { 5 } else { 4 };
+        self.frame_step = (self.frame_step + 1) % steps;
+    }
+
+    fn clock_quarter_frame(&mut self) {
+        self.pulse1.channel_mut().envelope.clock();
+        self.pulse2.channel_mut().envelope.clock();
+        self.triangle.channel_mut().clock_linear();
+        self.noise.channel_mut().envelope.clock();
+    }
+
+    fn clock_half_frame(&mut self) {
+        self.pulse1.clock_length_counter();
+        self.pulse1.channel_mut().clock_sweep();
+        self.pulse2.clock_length_counter();
+        self.pulse2.channel_mut().clock_sweep();
+        self.triangle.clock_length_counter();
+        self.noise.clock_length_counter();
+    }
+
+    /// Mix all channels into a sample, using the usual approximation of the
+    /// non-linear NES mixer.
+    fn sample(&mut self) -> f32 {
+        let pulse = (Self::output(&self.pulse1) + Self::output(&self.pulse2)) as f32;
+        let pulse_out = if pulse == 0.0 {
+            0.0
+        } else {
+            95.88 / ((8128.0 / pulse) + 100.0)
+        };
+
+        // The triangle holds its level when silenced, see its `output`
+        let tnd = (self.triangle.channel().output() as f32 / 8227.0)
+            + (Self::output(&self.noise) as f32 / 12241.0)
+            + (self.dmc.output() as f32 / 22638.0);
+        let tnd_out = if tnd == 0.0 {
+            0.0
+        } else {
+            159.79 / ((1.0 / tnd) + 100.0)
+        };
+
+        let output = pulse_out + tnd_out;
+        let sample = output - self.capacitor;
+        self.capacitor = output - sample * 0.996;
+        sample
+    }
+
+    fn output<C: Channel>(ch: &LengthCountedChannel<C>) -> u8 {
+        if ch.muted() {
+            0
+        } else {
+            ch.output()
+        }
+    }
+}
+
+impl Default for Apu {
+    fn default() -> Self {
+        Self {
+            pulse1: LengthCountedChannel::new(PulseChannel::new(true), 0),
+            pulse2: LengthCountedChannel::new(PulseChannel::new(false), 0),
+            triangle: LengthCountedChannel::new(TriangleChannel::default(), 0),
+            noise: LengthCountedChannel::new(NoiseChannel::default(), 0),
+            dmc: DmcChannel::default(),
+            enabled: 0,
+            five_step: false,
+            frame_step: 0,
+            odd_cycle: false,
+            capacitor: 0.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use common::{common::options::SystemConfig, Core};
+
+    use super::*;
+    use crate::tests::nrom;
+
+    /// Program playing a 440Hz square wave on pulse 1 at full volume.
+    #[rustfmt::skip]
+    const PROGRAM: &[u8] = &[
+        0xA9, 0x01, 0x8D, 0x15, 0x40, // Enable pulse 1
+        0xA9, 0xBF, 0x8D, 0x00, 0x40, // 50% duty, halt length, volume 15
+        0xA9, 0xFD, 0x8D, 0x02, 0x40, // Period 253
+        0xA9, 0x00, 0x8D, 0x03, 0x40,
+        0x4C, 0x14, 0x80,             // JMP to itself
+    ];
+
+    /// Play the program for a few frames and return the left channel.
+    fn play(program: &[u8]) -> Vec<f32> {
+        let mut nes = Nes::with_cart(nrom(program, &[]), None, &SystemConfig::default()).unwrap();
+        // Let the DC filter settle
+        for _ in 0..10 {
+            nes.produce_av_frame();
+        }
+        let mut samples = Vec::new();
+        for _ in 0..30 {
+            samples.extend(nes.produce_av_frame().1.into_iter().step_by(2));
+        }
+        samples
+    }
+
+    #[test]
+    fn pulse_tone() {
+        let samples = play(PROGRAM);
+        let seconds = samples.len() as f32 / SAMPLE_RATE as f32;
+        assert!(seconds > 0.4, "only got {seconds}s of audio");
+
+        let crossings = samples
+            .windows(2)
+            .filter(|w| w[0] < 0.0 && w[1] >= 0.0)
+            .count();
+        let frequency = crossings as f32 / seconds;
+        assert!(
+            (frequency - 440.0).abs() < 5.0,
+            "frequency is {frequency}Hz"
+        );
+
+        let peak = samples.iter().fold(0.0f32, |acc, s| acc.max(s.abs()));
+        assert!(peak > 0.05, "peak is only {peak}");
+    }
+
+    #[test]
+    fn disabled_channel_is_silent() {
+        let mut program = PROGRAM.to_vec();
+        // Do not enable pulse 1 in 0x4015
+        program[1] = 0;
+        let samples = play(&program);
+        assert!(samples.iter().all(|s| s.abs() < 0.001));
+    }
+}
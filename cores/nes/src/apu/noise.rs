@@ -0,0 +1,115 @@
+// Copyright (c) 2024 Leela Aurelia, git@elia.garden
+//
+// Unless otherwise noted, this file is released and thus subject to the
+// terms of the Mozilla Public License Version 2.0 (MPL-2.0) or the
+// GNU General Public License Version 3 (GPL-3).
+// If a copy of these licenses was not distributed with this file, you can
+// obtain them at https://mozilla.org/MPL/2.0/ and http://www.gnu.org/licenses/.
+
+use common::{
+    components::psg::{Channel, EnvelopGenerator},
+    numutil::NumExt,
+};
+
+use super::{write_envelope, NesChannel};
+
+const PERIODS: [u16; 16] = [
+    4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
+];
+
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct NoiseChannel {
+    /// Short mode, using bit 6 instead of bit 1 for feedback.
+    short_mode: bool,
+    period: u16,
+    timer: u16,
+    shift_register: u16,
+
+    length_halted: bool,
+    /// Volume if not using the envelope.
+    constant_volume: Option<u8>,
+    pub envelope: EnvelopGenerator,
+    enabled: bool,
+}
+
+impl NoiseChannel {
+    /// Clock the timer, done every CPU cycle.
+    pub fn clock(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period;
+            let other = if self.short_mode { 6 } else { 1 };
+            let feedback = self.shift_register.bit(0) ^ self.shift_register.bit(other);
+            self.shift_register = (self.shift_register >> 1) | (feedback << 14);
+        } else {
+            self.timer -= 1;
+        }
+    }
+}
+
+impl NesChannel for NoiseChannel {
+    fn write(&mut self, reg: u16, value: u8) {
+        match reg {
+            0 => {
+                self.length_halted = value.is_bit(5);
+                self.constant_volume = write_envelope(&mut self.envelope, value);
+            }
+            2 => {
+                self.short_mode = value.is_bit(7);
+                self.period = PERIODS[value.us() & 0xF];
+            }
+            _ => (),
+        }
+    }
+
+    fn length_halted(&self) -> bool {
+        self.length_halted
+    }
+}
+
+impl Channel for NoiseChannel {
+    fn output(&self) -> u8 {
+        if self.shift_register.is_bit(0) {
+            0
+        } else {
+            self.constant_volume
+                .unwrap_or(self.envelope.current_volume())
+        }
+    }
+
+    fn muted(&self) -> bool {
+        false
+    }
+
+    fn set_enable(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn set_dac_enable(&mut self, _enabled: bool) {}
+
+    fn dac_enabled(&self) -> bool {
+        true
+    }
+
+    fn trigger(&mut self) {
+        self.envelope.trigger();
+    }
+}
+
+impl Default for NoiseChannel {
+    fn default() -> Self {
+        Self {
+            short_mode: false,
+            period: PERIODS[0],
+            timer: 0,
+            shift_register: 1,
+            length_halted: false,
+            constant_volume: None,
+            envelope: EnvelopGenerator::default(),
+            enabled: false,
+        }
+    }
+}
@@ -0,0 +1,160 @@
+// Copyright (c) 2024 Leela Aurelia, git@elia.garden
+//
+// Unless otherwise noted, this file is released and thus subject to the
+// terms of the Mozilla Public License Version 2.0 (MPL-2.0) or the
+// GNU General Public License Version 3 (GPL-3).
+// If a copy of these licenses was not distributed with this file, you can
+// obtain them at https://mozilla.org/MPL/2.0/ and http://www.gnu.org/licenses/.
+
+use common::{
+    components::psg::{Channel, EnvelopGenerator},
+    numutil::NumExt,
+};
+
+use super::{write_envelope, NesChannel};
+
+const DUTY_SEQUENCES: [[u8; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0],
+    [0, 1, 1, 0, 0, 0, 0, 0],
+    [0, 1, 1, 1, 1, 0, 0, 0],
+    [1, 0, 0, 1, 1, 1, 1, 1],
+];
+
+#[derive(Default)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct PulseChannel {
+    /// If this is the first pulse channel, which negates with
+    /// one's complement in the sweep unit.
+    first: bool,
+
+    duty: u8,
+    sequencer_position: usize,
+    period: u16,
+    timer: u16,
+
+    sweep_enabled: bool,
+    sweep_period: u8,
+    sweep_negate: bool,
+    sweep_shift: u8,
+    sweep_reload: bool,
+    sweep_divider: u8,
+
+    length_halted: bool,
+    /// Volume if not using the envelope.
+    constant_volume: Option<u8>,
+    pub envelope: EnvelopGenerator,
+    enabled: bool,
+}
+
+impl PulseChannel {
+    pub fn new(first: bool) -> Self {
+        Self {
+            first,
+            ..Default::default()
+        }
+    }
+
+    /// Clock the timer, done every APU cycle (2 CPU cycles).
+    pub fn clock(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period;
+            self.sequencer_position = (self.sequencer_position + 1) & 7;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    /// Clock the sweep unit, done by the frame counter every half frame.
+    pub fn clock_sweep(&mut self) {
+        let target = self.sweep_target();
+        if self.sweep_divider == 0
+            && self.sweep_enabled
+            && self.sweep_shift != 0
+            && !self.sweep_muted(target)
+        {
+            self.period = target;
+        }
+        if self.sweep_divider == 0 || self.sweep_reload {
+            self.sweep_divider = self.sweep_period;
+            self.sweep_reload = false;
+        } else {
+            self.sweep_divider -= 1;
+        }
+    }
+
+    fn sweep_target(&self) -> u16 {
+        let change = self.period >> self.sweep_shift;
+        if !self.sweep_negate {
+            self.period + change
+        } else if self.first {
+            self.period.saturating_sub(change + 1)
+        } else {
+            self.period.saturating_sub(change)
+        }
+    }
+
+    /// The sweep unit mutes the channel if the period is too low or the
+    /// target period too high, even when sweeping is disabled.
+    fn sweep_muted(&self, target: u16) -> bool {
+        self.period < 8 || target > 0x7FF
+    }
+}
+
+impl NesChannel for PulseChannel {
+    fn write(&mut self, reg: u16, value: u8) {
+        match reg {
+            0 => {
+                self.duty = value >> 6;
+                self.length_halted = value.is_bit(5);
+                self.constant_volume = write_envelope(&mut self.envelope, value);
+            }
+            1 => {
+                self.sweep_enabled = value.is_bit(7);
+                self.sweep_period = (value >> 4) & 7;
+                self.sweep_negate = value.is_bit(3);
+                self.sweep_shift = value & 7;
+                self.sweep_reload = true;
+            }
+            2 => self.period = (self.period & 0x700) | value.u16(),
+            _ => self.period = (self.period & 0xFF) | ((value.u16() & 7) << 8),
+        }
+    }
+
+    fn length_halted(&self) -> bool {
+        self.length_halted
+    }
+}
+
+impl Channel for PulseChannel {
+    fn output(&self) -> u8 {
+        if DUTY_SEQUENCES[self.duty.us()][self.sequencer_position] == 0 {
+            0
+        } else {
+            self.constant_volume
+                .unwrap_or(self.envelope.current_volume())
+        }
+    }
+
+    fn muted(&self) -> bool {
+        self.sweep_muted(self.sweep_target())
+    }
+
+    fn set_enable(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn set_dac_enable(&mut self, _enabled: bool) {}
+
+    fn dac_enabled(&self) -> bool {
+        true
+    }
+
+    fn trigger(&mut self) {
+        self.sequencer_position = 0;
+        self.envelope.trigger();
+    }
+}
@@ -0,0 +1,114 @@
+// Copyright (c) 2024 Leela Aurelia, git@elia.garden
+//
+// Unless otherwise noted, this file is released and thus subject to the
+// terms of the Mozilla Public License Version 2.0 (MPL-2.0) or the
+// GNU General Public License Version 3 (GPL-3).
+// If a copy of these licenses was not distributed with this file, you can
+// obtain them at https://mozilla.org/MPL/2.0/ and http://www.gnu.org/licenses/.
+
+use common::{components::psg::Channel, numutil::NumExt};
+
+use super::NesChannel;
+
+const SEQUENCE: [u8; 32] = [
+    15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12,
+    13, 14, 15,
+];
+
+#[derive(Default)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct TriangleChannel {
+    sequencer_position: usize,
+    period: u16,
+    timer: u16,
+
+    /// Also the length counter halt flag.
+    linear_control: bool,
+    linear_reload_value: u8,
+    linear_reload: bool,
+    linear_counter: u8,
+
+    enabled: bool,
+}
+
+impl TriangleChannel {
+    /// Clock the timer, done every CPU cycle. The sequencer only advances
+    /// while both the length and linear counter are running.
+    pub fn clock(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period;
+            if self.enabled && self.linear_counter > 0 {
+                self.sequencer_position = (self.sequencer_position + 1) & 31;
+            }
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    /// Clock the linear counter, done by the frame counter every
+    /// quarter frame.
+    pub fn clock_linear(&mut self) {
+        if self.linear_reload {
+            self.linear_counter = self.linear_reload_value;
+        } else if self.linear_counter > 0 {
+            self.linear_counter -= 1;
+        }
+        if !self.linear_control {
+            self.linear_reload = false;
+        }
+    }
+}
+
+impl NesChannel for TriangleChannel {
+    fn write(&mut self, reg: u16, value: u8) {
+        match reg {
+            0 => {
+                self.linear_control = value.is_bit(7);
+                self.linear_reload_value = value & 0x7F;
+            }
+            2 => self.period = (self.period & 0x700) | value.u16(),
+            3 => self.period = (self.period & 0xFF) | ((value.u16() & 7) << 8),
+            _ => (),
+        }
+    }
+
+    fn length_halted(&self) -> bool {
+        self.linear_control
+    }
+}
+
+impl Channel for TriangleChannel {
+    /// The triangle keeps outputting its current level when stopped,
+    /// so this does not depend on the length counter.
+    fn output(&self) -> u8 {
+        // Very low periods produce ultrasonic frequencies that would only
+        // cause popping, silence them like many emulators do
+        if self.period < 2 {
+            7
+        } else {
+            SEQUENCE[self.sequencer_position]
+        }
+    }
+
+    fn muted(&self) -> bool {
+        false
+    }
+
+    fn set_enable(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn set_dac_enable(&mut self, _enabled: bool) {}
+
+    fn dac_enabled(&self) -> bool {
+        true
+    }
+
+    fn trigger(&mut self) {
+        self.linear_reload = true;
+    }
+}
@@ -46,9 +46,15 @@ pub struct Nes2Header {
 #[derive(Default)]
 #[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
 pub struct Cartridge {
+    /// The entire ROM file, including the header.
+    #[cfg_attr(feature = "serde", serde(skip, default))]
+    pub rom: Vec<u8>,
     prg_rom: Vec<u8>,
     prg_ram: Vec<u8>,
     chr_rom: Vec<u8>,
+    /// If `chr_rom` is CHR RAM instead, since the cart has no CHR ROM.
+    chr_is_ram: bool,
+    mirroring: Mirroring,
     mapper: Mapper,
 }
 
@@ -65,33 +71,88 @@ impl Cartridge {
 
     pub fn write(_: &Nes, _: u16, _value: u8) {}
 
-    pub fn from_rom(rom: Vec<u8>) -> Self {
-        let prg_size = rom[4].us() * 16_384;
-        let chr_size = rom[5].us() * 8_192;
-        let header = INesHeader::from_bytes(rom[6..8].try_into().unwrap());
+    /// Read from the pattern tables, PPU 0x0000-0x1FFF.
+    pub fn chr_read(&self, addr: u16) -> u8 {
+        self.chr_rom[addr.us() & (self.chr_rom.len() - 1)]
+    }
+
+    /// Write to the pattern tables, if they are CHR RAM.
+    pub fn chr_write(&mut self, addr: u16, value: u8) {
+        if self.chr_is_ram {
+            let len = self.chr_rom.len();
+            self.chr_rom[addr.us() & (len - 1)] = value;
+        }
+    }
+
+    /// Get the address inside the console's 2KB of nametable RAM
+    /// for the given PPU address in 0x2000-0x3EFF.
+    pub fn nametable_addr(&self, addr: u16) -> usize {
+        let addr = addr.us() & 0xFFF;
+        let table = match self.mirroring {
+            Mirroring::Horizontal => (addr >> 11) & 1,
+            Mirroring::Vertical => (addr >> 10) & 1,
+        };
+        (table << 10) | (addr & 0x3FF)
+    }
+
+    /// Create a cartridge from the given iNES file. Returns `None` if the
+    /// file is shorter than its header claims or uses an unsupported mapper.
+    pub fn from_rom(rom: Vec<u8>) -> Option<Self> {
+        let prg_size = rom.get(4)?.us() * 16_384;
+        if prg_size == 0 {
+            return None;
+        }
+        let chr_size = rom.get(5)?.us() * 8_192;
+        let header = INesHeader::from_bytes(rom.get(6..8)?.try_into().unwrap());
 
         let addr = 16 + (header.has_trainer() as usize * 512);
-        let prg_rom = rom[addr..(addr + prg_size)].to_vec();
+        let prg_rom = rom.get(addr..(addr + prg_size))?.to_vec();
         let addr = addr + prg_size;
-        let chr_rom = rom[addr..(addr + chr_size)].to_vec();
+        let chr_rom = rom.get(addr..(addr + chr_size))?.to_vec();
+        let chr_is_ram = chr_size == 0;
+        let chr_rom = if chr_is_ram { vec![0; 0x2000] } else { chr_rom };
+        let mirroring = if header.mirror_is_vertical() {
+            Mirroring::Vertical
+        } else {
+            Mirroring::Horizontal
+        };
 
         let mapper = header.mapper_lower() | (header.mapper_higher() << 4);
         let mapper = match mapper {
             0 => Mapper::Nrom,
-            _ => panic!("Unknown mapper!"),
+            _ => {
+                log::error!("Unsupported mapper {mapper}");
+                return None;
+            }
         };
 
         let mut cart = Self {
+            rom,
             prg_rom,
             prg_ram: Vec::new(),
             chr_rom,
+            chr_is_ram,
+            mirroring,
             mapper,
         };
         cart.prg_ram.extend(iter::repeat(0).take(0x2000));
-        cart
+        Some(cart)
     }
 }
 
+/// Nametable mirroring, hardwired on the cartridge for NROM.
+#[derive(Default, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub enum Mirroring {
+    /// 0x2000 and 0x2400 are the same, as are 0x2800 and 0x2C00.
+    /// Used for vertical scrolling.
+    #[default]
+    Horizontal,
+    /// 0x2000 and 0x2800 are the same, as are 0x2400 and 0x2C00.
+    /// Used for horizontal scrolling.
+    Vertical,
+}
+
 #[derive(Default)]
 #[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
 pub enum Mapper {
@@ -7,7 +7,7 @@
 // obtain them at https://mozilla.org/MPL/2.0/ and http://www.gnu.org/licenses/.
 
 mod inst;
-use common::numutil::{NumExt, U16Ext};
+use common::numutil::{hword, NumExt, U16Ext};
 use modular_bitfield::bitfield;
 
 use self::inst::Inst;
@@ -47,10 +47,16 @@ pub struct Cpu {
     pub pc: u16,
     regs: [u8; 4],
     status: CpuStatus,
+    /// Set by the PPU on VBlank, handled before the next instruction.
+    pub nmi_pending: bool,
 }
 
 impl Cpu {
     pub fn exec_next_inst(nes: &mut Nes) {
+        if nes.cpu.nmi_pending {
+            nes.cpu.nmi_pending = false;
+            Self::trigger_nmi(nes);
+        }
         let inst = nes.read_imm();
         inst::execute(nes, Inst(inst));
     }
@@ -63,6 +69,14 @@ impl Cpu {
         nes.cpu.pc = 0xFFFE;
     }
 
+    pub fn trigger_nmi(nes: &mut Nes) {
+        nes.push(nes.cpu.pc.high());
+        nes.push(nes.cpu.pc.low());
+        nes.push(nes.cpu.status.with_break_cmd(false).into());
+        nes.cpu.status.set_interrupt_disable(true);
+        nes.cpu.pc = hword(nes.read(0xFFFA), nes.read(0xFFFB));
+    }
+
     pub fn get(&self, reg: Reg) -> u8 {
         self.regs[reg as usize]
     }
@@ -6,15 +6,26 @@
 // If a copy of these licenses was not distributed with this file, you can
 // obtain them at https://mozilla.org/MPL/2.0/ and http://www.gnu.org/licenses/.
 
-use common::{components::input::Button, numutil::NumExt};
+use common::{common::input::Button, numutil::NumExt};
 
 use crate::Nes;
 
+/// Order the buttons are shifted out in.
+const BUTTON_ORDER: [Button; 8] = [
+    Button::A,
+    Button::B,
+    Button::Select,
+    Button::Start,
+    Button::Up,
+    Button::Down,
+    Button::Left,
+    Button::Right,
+];
+
 /// Joypad 1 of the console.
 #[derive(Default)]
 #[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
 pub struct Joypad {
-    key_states: [bool; 8],
     register: u8,
     strobe: bool,
 }
@@ -23,38 +34,19 @@ impl Joypad {
     pub fn read(&mut self) -> u8 {
         let value = self.register & 1;
         self.register >>= 1;
-        self.register.set_bit(7, true);
+        self.register = self.register.set_bit(7, true);
         value
     }
 
-    pub fn write(&mut self, value: u8) {
-        self.strobe = value.is_bit(0);
-        if self.strobe {
-            self.register = 0;
-            self.register
-                .set_bit(0, self.key_states[Button::A as usize]);
-            self.register
-                .set_bit(1, self.key_states[Button::B as usize]);
-            self.register
-                .set_bit(2, self.key_states[Button::Select as usize]);
-            self.register
-                .set_bit(3, self.key_states[Button::Start as usize]);
-            self.register
-                .set_bit(4, self.key_states[Button::Up as usize]);
-            self.register
-                .set_bit(5, self.key_states[Button::Down as usize]);
-            self.register
-                .set_bit(6, self.key_states[Button::Left as usize]);
-            self.register
-                .set_bit(7, self.key_states[Button::Right as usize]);
-        }
-    }
-
-    /// To be called by GUI code; sets the state of a given button.
-    pub fn set(nes: &mut Nes, button: Button, state: bool) {
-        if button as usize >= 8 {
-            return; // GGA buttons
+    pub fn write(nes: &mut Nes, value: u8) {
+        nes.joypad.strobe = value.is_bit(0);
+        if nes.joypad.strobe {
+            let state = nes.c.input.state(nes.scheduler.now());
+            nes.joypad.register = 0;
+            for (bit, button) in BUTTON_ORDER.into_iter().enumerate() {
+                let pressed = state.0.is_bit(button as u16);
+                nes.joypad.register = nes.joypad.register.set_bit(bit as u16, pressed);
+            }
         }
-        nes.joypad.key_states[button as usize] = state;
     }
 }
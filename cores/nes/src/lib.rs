@@ -22,11 +22,11 @@ use std::{mem, path::PathBuf};
 use apu::Apu;
 use cartridge::Cartridge;
 use common::{
+    common::options::SystemConfig,
     common_functions,
-    components::{debugger::Debugger, scheduler::Scheduler, storage::GameSave},
-    misc::{EmulateOptions, SystemConfig},
-    numutil::NumExt,
-    produce_samples_buffered, Core, Time,
+    components::{scheduler::Scheduler, storage::GameSave},
+    numutil::{hword, NumExt},
+    Common, Core, CoreKind, Time,
 };
 use cpu::Cpu;
 use joypad::Joypad;
@@ -36,8 +36,6 @@ use scheduling::NesEvent;
 
 const CLOCK_HZ: u32 = 1_789_773;
 
-pub type NesDebugger = Debugger<u16>;
-
 /// The system and it's state.
 /// Represents the entire console.
 #[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
@@ -48,24 +46,16 @@ pub struct Nes {
     pub apu: Apu,
     pub cart: Cartridge,
     pub joypad: Joypad,
-
-    #[cfg_attr(feature = "serde", serde(skip, default))]
-    pub debugger: NesDebugger,
     scheduler: Scheduler<NesEvent>,
-
-    /// Temporary used by [advance_delta]. Will be true until the scheduled
-    /// PauseEmulation event fires.
-    ticking: bool,
-
-    /// System config.
-    pub config: SystemConfig,
-    /// Emulation options.
-    pub options: EmulateOptions,
+    pub c: Common,
 }
 
 impl Core for Nes {
     common_functions!(CLOCK_HZ, NesEvent::PauseEmulation, [256, 240]);
-    produce_samples_buffered!(48000);
+
+    fn kind(&self) -> Option<CoreKind> {
+        Some(CoreKind::Nes)
+    }
 
     fn advance(&mut self) {
         Cpu::exec_next_inst(self);
@@ -73,20 +63,19 @@ impl Core for Nes {
 
     fn reset(&mut self) {
         let old_self = mem::take(self);
-
-        self.options = old_self.options;
-        self.config = old_self.config;
-        self.debugger = old_self.debugger;
+        self.cart = old_self.cart;
+        self.c.restore_from(old_self.c);
+        self.power_on();
     }
 
     fn skip_bootrom(&mut self) {}
 
     fn make_save(&self) -> Option<GameSave> {
-        todo!();
+        None
     }
 
     fn get_rom(&self) -> Vec<u8> {
-        unimplemented!();
+        self.cart.rom.clone()
     }
 }
 
@@ -102,16 +91,30 @@ impl Nes {
     /// Restore state after a savestate load. `old_self` should be the
     /// system state before the state was loaded.
     pub fn restore_from(&mut self, old_self: Self) {
-        self.options = old_self.options;
-        self.config = old_self.config;
-        self.debugger = old_self.debugger;
+        self.cart.rom = old_self.cart.rom;
+        self.c.restore_from(old_self.c);
     }
 
     /// Create a system with a cart already loaded.
-    pub fn with_cart(cart: Vec<u8>, _: Option<PathBuf>, _: &SystemConfig) -> Box<Self> {
+    /// Returns `None` if the ROM is invalid, see [Cartridge::from_rom].
+    pub fn with_cart(
+        cart: Vec<u8>,
+        _: Option<PathBuf>,
+        config: &SystemConfig,
+    ) -> Option<Box<Self>> {
         let mut nes = Box::<Self>::default();
-        nes.cart = Cartridge::from_rom(cart);
-        nes
+        nes.cart = Cartridge::from_rom(cart)?;
+        nes.c.config = config.clone();
+        nes.c.video_buffer.set_history_len(config.frame_history);
+        nes.power_on();
+        Some(nes)
+    }
+
+    /// Jump to the reset vector and start the PPU and APU.
+    fn power_on(&mut self) {
+        self.cpu.pc = hword(self.get(0xFFFC), self.get(0xFFFD));
+        Ppu::schedule_line(self, 0);
+        Apu::init_scheduler(self);
     }
 }
 
@@ -124,12 +127,62 @@ impl Default for Nes {
             apu: Apu::default(),
             cart: Cartridge::default(),
             joypad: Joypad::default(),
-            debugger: Default::default(),
             scheduler: Default::default(),
+            c: Common::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Build an NROM image with the given program at 0x8000, which is
+    /// also the reset vector, and the given CHR ROM data.
+    pub fn nrom(program: &[u8], chr: &[u8]) -> Vec<u8> {
+        let mut prg = vec![0xEA; 0x4000];
+        prg[..program.len()].copy_from_slice(program);
+        prg[0x3FFC] = 0x00;
+        prg[0x3FFD] = 0x80;
+        let mut chr = chr.to_vec();
+        chr.resize(0x2000, 0);
+
+        let mut rom = vec![b'N', b'E', b'S', 0x1A, 1, 1, 0, 0];
+        rom.resize(16, 0);
+        rom.extend(prg);
+        rom.extend(chr);
+        rom
+    }
 
-            ticking: false,
-            config: Default::default(),
-            options: Default::default(),
+    #[test]
+    fn load_rom() {
+        let nes = Nes::with_cart(nrom(&[], &[]), None, &SystemConfig::default()).unwrap();
+        assert_eq!(nes.cpu.pc, 0x8000);
+        assert!(Nes::with_cart(
+            nrom(&[], &[])[..0x1000].to_vec(),
+            None,
+            &SystemConfig::default()
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn frame_history_on_load() {
+        let config = SystemConfig {
+            frame_history: 2,
+            ..SystemConfig::default()
+        };
+        let mut nes = Nes::with_cart(nrom(&[], &[]), None, &config).unwrap();
+        for shade in 0..3 {
+            nes.c.video_buffer.push(vec![[shade; 4]]);
         }
+        assert_eq!(nes.frame_history(), [vec![[1; 4]], vec![[2; 4]]]);
+    }
+
+    #[test]
+    fn unknown_mapper() {
+        let mut rom = nrom(&[], &[]);
+        rom[6] = 0x10;
+        assert!(Nes::with_cart(rom, None, &SystemConfig::default()).is_none());
     }
 }
@@ -8,14 +8,12 @@
 
 use common::numutil::{hword, NumExt};
 
-use crate::{cartridge::Cartridge, cpu::Reg::*, Nes};
+use crate::{apu::Apu, cartridge::Cartridge, cpu::Reg::*, joypad::Joypad, ppu::Ppu, Nes};
 
 #[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
 pub struct Memory {
     #[cfg_attr(feature = "serde", serde(with = "serde_arrays"))]
     iram: [u8; 0x800],
-    ppu_regs: [u8; 0x8],
-    other_regs: [u8; 0x15],
 }
 
 impl Nes {
@@ -52,8 +50,8 @@ impl Nes {
     pub fn get(&mut self, addr: u16) -> u8 {
         match addr {
             0x0000..=0x1FFF => self.mem.iram[addr.us() & 0x7FF],
-            0x2000..=0x3FFF => self.mem.ppu_regs[addr.us() & 0x8],
-            0x4000..=0x4015 => self.mem.other_regs[addr.us() - 0x4000],
+            0x2000..=0x3FFF => Ppu::read(self, addr),
+            0x4015 => Apu::read_status(self),
             0x4016 => self.joypad.read() | 0x40,
             0x4020..=0xFFFF => Cartridge::read(self, addr),
             _ => 0xFF,
@@ -63,9 +61,10 @@ impl Nes {
     pub fn set(&mut self, addr: u16, value: u8) {
         match addr {
             0x0000..=0x1FFF => self.mem.iram[addr.us() & 0x7FF] = value,
-            0x2000..=0x3FFF => self.mem.ppu_regs[addr.us() & 0x8] = value,
-            0x4000..=0x4015 => self.mem.other_regs[addr.us() - 0x4000] = value,
-            0x4016 => self.joypad.write(value),
+            0x2000..=0x3FFF => Ppu::write(self, addr, value),
+            0x4014 => Ppu::oam_dma(self, value),
+            0x4000..=0x4013 | 0x4015 | 0x4017 => Apu::write(self, addr, value),
+            0x4016 => Joypad::write(self, value),
             0x4020..=0xFFFF => Cartridge::write(self, addr, value),
             _ => (),
         }
@@ -74,10 +73,6 @@ impl Nes {
 
 impl Default for Memory {
     fn default() -> Self {
-        Self {
-            iram: [0; 0x800],
-            ppu_regs: [0; 0x8],
-            other_regs: [0; 0x15],
-        }
+        Self { iram: [0; 0x800] }
     }
 }
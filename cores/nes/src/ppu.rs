@@ -6,10 +6,609 @@
 // If a copy of these licenses was not distributed with this file, you can
 // obtain them at https://mozilla.org/MPL/2.0/ and http://www.gnu.org/licenses/.
 
-use common::Colour;
+use std::mem;
 
-#[derive(Default)]
+use common::{numutil::NumExt, Colour, TimeS};
+
+use crate::{
+    scheduling::{NesEvent, PpuEvent},
+    Nes,
+};
+
+const WIDTH: usize = 256;
+const HEIGHT: usize = 240;
+/// PPU dots per scanline. The PPU runs at 3 dots per CPU cycle.
+const DOTS_PER_LINE: u16 = 341;
+/// Line on which VBlank starts.
+const VBLANK_LINE: u16 = 241;
+/// Last line of the frame, the pre-render line.
+const PRE_RENDER_LINE: u16 = 261;
+
+// PPUCTRL
+const CTRL_INCREMENT_32: u16 = 2;
+const CTRL_SPRITE_TABLE: u16 = 3;
+const CTRL_BG_TABLE: u16 = 4;
+const CTRL_SPRITE_8X16: u16 = 5;
+const CTRL_NMI: u16 = 7;
+// PPUMASK
+const MASK_GREYSCALE: u16 = 0;
+const MASK_BG_LEFT: u16 = 1;
+const MASK_SPRITES_LEFT: u16 = 2;
+const MASK_BG: u16 = 3;
+const MASK_SPRITES: u16 = 4;
+// PPUSTATUS
+const STATUS_OVERFLOW: u16 = 5;
+const STATUS_SPRITE_0_HIT: u16 = 6;
+const STATUS_VBLANK: u16 = 7;
+
+/// The standard 2C02 palette.
+const PALETTE: [[u8; 3]; 64] = [
+    [84, 84, 84],
+    [0, 30, 116],
+    [8, 16, 144],
+    [48, 0, 136],
+    [68, 0, 100],
+    [92, 0, 48],
+    [84, 4, 0],
+    [60, 24, 0],
+    [32, 42, 0],
+    [8, 58, 0],
+    [0, 64, 0],
+    [0, 60, 0],
+    [0, 50, 60],
+    [0, 0, 0],
+    [0, 0, 0],
+    [0, 0, 0],
+    [152, 150, 152],
+    [8, 76, 196],
+    [48, 50, 236],
+    [92, 30, 228],
+    [136, 20, 176],
+    [160, 20, 100],
+    [152, 34, 32],
+    [120, 60, 0],
+    [84, 90, 0],
+    [40, 114, 0],
+    [8, 124, 0],
+    [0, 118, 40],
+    [0, 102, 120],
+    [0, 0, 0],
+    [0, 0, 0],
+    [0, 0, 0],
+    [236, 238, 236],
+    [76, 154, 236],
+    [120, 124, 236],
+    [176, 98, 236],
+    [228, 84, 236],
+    [236, 88, 180],
+    [236, 106, 100],
+    [212, 136, 32],
+    [160, 170, 0],
+    [116, 196, 0],
+    [76, 208, 32],
+    [56, 204, 108],
+    [56, 180, 204],
+    [60, 60, 60],
+    [0, 0, 0],
+    [0, 0, 0],
+    [236, 238, 236],
+    [168, 204, 236],
+    [188, 188, 236],
+    [212, 178, 236],
+    [236, 174, 236],
+    [236, 174, 212],
+    [236, 180, 176],
+    [228, 196, 144],
+    [204, 210, 120],
+    [180, 222, 120],
+    [168, 226, 144],
+    [152, 226, 180],
+    [160, 214, 228],
+    [160, 162, 160],
+    [0, 0, 0],
+    [0, 0, 0],
+];
+
+/// The NES PPU (2C02). Renders an entire scanline at once at the end of it,
+/// which is enough for games that only change scroll between lines.
 #[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
 pub struct Ppu {
-    pub last_frame: Option<Vec<Colour>>,
+    #[cfg_attr(feature = "serde", serde(skip, default = "default_pixels"))]
+    pixels: Vec<Colour>,
+
+    ctrl: u8,
+    mask: u8,
+    status: u8,
+    oam_addr: u8,
+    /// Current VRAM address.
+    v: u16,
+    /// Temporary VRAM address, the top-left of the screen.
+    t: u16,
+    /// Fine X scroll.
+    x: u8,
+    /// Write toggle for PPUSCROLL and PPUADDR.
+    w: bool,
+    /// Buffer for delayed PPUDATA reads.
+    read_buffer: u8,
+    /// Last value written to any register, returned on open bus.
+    open_bus: u8,
+
+    line: u16,
+    /// Leftover thirds of a CPU cycle from the last scanline.
+    dot_remainder: u16,
+
+    #[cfg_attr(feature = "serde", serde(with = "serde_arrays"))]
+    ciram: [u8; 0x800],
+    palette: [u8; 0x20],
+    #[cfg_attr(feature = "serde", serde(with = "serde_arrays"))]
+    pub oam: [u8; 0x100],
+}
+
+impl Ppu {
+    pub fn handle_event(nes: &mut Nes, event: PpuEvent, late_by: TimeS) {
+        match event {
+            PpuEvent::EndOfLine => {
+                match nes.ppu.line {
+                    0..=239 => Self::render_line(nes),
+                    VBLANK_LINE => {
+                        nes.ppu.status = nes.ppu.status.set_bit(STATUS_VBLANK, true);
+                        if nes.ppu.ctrl.is_bit(CTRL_NMI) {
+                            nes.cpu.nmi_pending = true;
+                        }
+                        if nes.c.video_buffer.should_render_this_frame() {
+                            nes.c.video_buffer.push(nes.ppu.pixels.clone());
+                        }
+                        nes.c.video_buffer.start_next_frame();
+                    }
+                    PRE_RENDER_LINE => {
+                        nes.ppu.status = 0;
+                        if nes.ppu.rendering_enabled() {
+                            // Copy vertical scroll bits, as well as the
+                            // horizontal ones like on every other line
+                            nes.ppu.v = (nes.ppu.v & 0x041F) | (nes.ppu.t & 0x7BE0);
+                            nes.ppu.v = (nes.ppu.v & !0x041F) | (nes.ppu.t & 0x041F);
+                        }
+                    }
+                    _ => (),
+                }
+
+                nes.ppu.line = (nes.ppu.line + 1) % (PRE_RENDER_LINE + 1);
+                Self::schedule_line(nes, late_by);
+            }
+        }
+    }
+
+    /// Schedule the end of the next scanline.
+    pub fn schedule_line(nes: &mut Nes, late_by: TimeS) {
+        let dots = DOTS_PER_LINE + nes.ppu.dot_remainder;
+        nes.ppu.dot_remainder = dots % 3;
+        nes.scheduler.schedule(
+            NesEvent::PpuEvent(PpuEvent::EndOfLine),
+            (dots / 3) as TimeS - late_by,
+        );
+    }
+
+    pub fn read(nes: &mut Nes, addr: u16) -> u8 {
+        let ppu = &mut nes.ppu;
+        let value = match addr & 7 {
+            // PPUSTATUS
+            2 => {
+                let value = (ppu.status & 0xE0) | (ppu.open_bus & 0x1F);
+                ppu.status = ppu.status.set_bit(STATUS_VBLANK, false);
+                ppu.w = false;
+                value
+            }
+            // OAMDATA
+            4 => ppu.oam[ppu.oam_addr.us()],
+            // PPUDATA
+            7 => {
+                let addr = ppu.v & 0x3FFF;
+                let data = Self::vram_read(nes, addr);
+                let value = if addr >= 0x3F00 {
+                    // Palette reads are not delayed, but the buffer gets
+                    // the nametable byte 'below' the palette
+                    nes.ppu.read_buffer = Self::vram_read(nes, addr - 0x1000);
+                    data
+                } else {
+                    mem::replace(&mut nes.ppu.read_buffer, data)
+                };
+                nes.ppu.increment_v();
+                value
+            }
+            _ => ppu.open_bus,
+        };
+        nes.ppu.open_bus = value;
+        value
+    }
+
+    pub fn write(nes: &mut Nes, addr: u16, value: u8) {
+        let ppu = &mut nes.ppu;
+        ppu.open_bus = value;
+        match addr & 7 {
+            // PPUCTRL
+            0 => {
+                let nmi_was_on = ppu.ctrl.is_bit(CTRL_NMI);
+                ppu.ctrl = value;
+                ppu.t = (ppu.t & !0x0C00) | ((value.u16() & 3) << 10);
+                // Enabling NMI during VBlank fires it immediately
+                if !nmi_was_on && value.is_bit(CTRL_NMI) && ppu.status.is_bit(STATUS_VBLANK) {
+                    nes.cpu.nmi_pending = true;
+                }
+            }
+            // PPUMASK
+            1 => ppu.mask = value,
+            // OAMADDR
+            3 => ppu.oam_addr = value,
+            // OAMDATA
+            4 => {
+                ppu.oam[ppu.oam_addr.us()] = value;
+                ppu.oam_addr = ppu.oam_addr.wrapping_add(1);
+            }
+            // PPUSCROLL
+            5 if !ppu.w => {
+                ppu.t = (ppu.t & !0x001F) | (value.u16() >> 3);
+                ppu.x = value & 7;
+                ppu.w = true;
+            }
+            5 => {
+                ppu.t = (ppu.t & !0x73E0) | ((value.u16() & 7) << 12) | ((value.u16() >> 3) << 5);
+                ppu.w = false;
+            }
+            // PPUADDR
+            6 if !ppu.w => {
+                ppu.t = (ppu.t & 0x00FF) | ((value.u16() & 0x3F) << 8);
+                ppu.w = true;
+            }
+            6 => {
+                ppu.t = (ppu.t & 0xFF00) | value.u16();
+                ppu.v = ppu.t;
+                ppu.w = false;
+            }
+            // PPUDATA
+            7 => {
+                let addr = nes.ppu.v & 0x3FFF;
+                Self::vram_write(nes, addr, value);
+                nes.ppu.increment_v();
+            }
+            _ => (),
+        }
+    }
+
+    /// Copy a page of CPU memory into OAM. Takes 513 cycles.
+    pub fn oam_dma(nes: &mut Nes, page: u8) {
+        nes.advance_clock(1);
+        for low in 0..=0xFF {
+            let value = nes.read(((page.u16()) << 8) | low);
+            nes.advance_clock(1);
+            let addr = nes.ppu.oam_addr;
+            nes.ppu.oam[addr.us()] = value;
+            nes.ppu.oam_addr = addr.wrapping_add(1);
+        }
+    }
+
+    fn vram_read(nes: &Nes, addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x1FFF => nes.cart.chr_read(addr),
+            0x2000..=0x3EFF => nes.ppu.ciram[nes.cart.nametable_addr(addr)],
+            _ => nes.ppu.palette[Self::palette_addr(addr)],
+        }
+    }
+
+    fn vram_write(nes: &mut Nes, addr: u16, value: u8) {
+        match addr {
+            0x0000..=0x1FFF => nes.cart.chr_write(addr, value),
+            0x2000..=0x3EFF => nes.ppu.ciram[nes.cart.nametable_addr(addr)] = value,
+            _ => nes.ppu.palette[Self::palette_addr(addr)] = value & 0x3F,
+        }
+    }
+
+    fn palette_addr(addr: u16) -> usize {
+        let addr = addr.us() & 0x1F;
+        // Backdrop entries of the sprite palettes mirror the ones of the
+        // background palettes
+        if addr & 0x13 == 0x10 {
+            addr & 0x0F
+        } else {
+            addr
+        }
+    }
+
+    fn increment_v(&mut self) {
+        let step = if self.ctrl.is_bit(CTRL_INCREMENT_32) {
+            32
+        } else {
+            1
+        };
+        self.v = self.v.wrapping_add(step) & 0x7FFF;
+    }
+
+    fn rendering_enabled(&self) -> bool {
+        self.mask.is_bit(MASK_BG) || self.mask.is_bit(MASK_SPRITES)
+    }
+
+    fn render_line(nes: &mut Nes) {
+        let line = nes.ppu.line.us();
+        let mut bg = [0u8; WIDTH];
+        let mut sprites = [None; WIDTH];
+
+        if nes.ppu.mask.is_bit(MASK_BG) {
+            Self::render_bg(nes, &mut bg);
+        }
+        if nes.ppu.mask.is_bit(MASK_SPRITES) {
+            Self::render_sprites(nes, &mut sprites);
+        }
+
+        let ppu = &mut nes.ppu;
+        for x in 0..WIDTH {
+            let bg_idx = if x < 8 && !ppu.mask.is_bit(MASK_BG_LEFT) {
+                0
+            } else {
+                bg[x]
+            };
+            let sprite = if x < 8 && !ppu.mask.is_bit(MASK_SPRITES_LEFT) {
+                None
+            } else {
+                sprites[x]
+            };
+
+            let palette_idx = match sprite {
+                Some(sprite) if bg_idx & 3 != 0 => {
+                    if sprite.zero && x != 255 {
+                        ppu.status = ppu.status.set_bit(STATUS_SPRITE_0_HIT, true);
+                    }
+                    if sprite.behind_bg {
+                        bg_idx
+                    } else {
+                        sprite.palette_idx
+                    }
+                }
+                Some(sprite) => sprite.palette_idx,
+                None if bg_idx & 3 != 0 => bg_idx,
+                None => 0,
+            };
+
+            let mut colour = ppu.palette[palette_idx.us()];
+            if ppu.mask.is_bit(MASK_GREYSCALE) {
+                colour &= 0x30;
+            }
+            let [r, g, b] = PALETTE[colour.us() & 0x3F];
+            ppu.pixels[line * WIDTH + x] = [r, g, b, 255];
+        }
+
+        if nes.ppu.rendering_enabled() {
+            nes.ppu.increment_y();
+            // Copy horizontal scroll bits
+            nes.ppu.v = (nes.ppu.v & !0x041F) | (nes.ppu.t & 0x041F);
+        }
+    }
+
+    /// Render the background for the current line into palette indices,
+    /// following the scroll position in `v`.
+    fn render_bg(nes: &Nes, out: &mut [u8; WIDTH]) {
+        let ppu = &nes.ppu;
+        let mut v = ppu.v;
+        let fine_y = v >> 12;
+        let table = ppu.ctrl.bit(CTRL_BG_TABLE).u16() * 0x1000;
+
+        // One extra tile, since fine X scroll can shift it into view
+        for tile in 0..33 {
+            let tile_idx = Self::vram_read(nes, 0x2000 | (v & 0x0FFF));
+            let attr_addr = 0x23C0 | (v & 0x0C00) | ((v >> 4) & 0x38) | ((v >> 2) & 0x07);
+            let attr = Self::vram_read(nes, attr_addr);
+            let shift = ((v >> 4) & 4) | (v & 2);
+            let palette = ((attr >> shift) & 3) << 2;
+
+            let pattern = table + tile_idx.u16() * 16 + fine_y;
+            let low = nes.cart.chr_read(pattern);
+            let high = nes.cart.chr_read(pattern + 8);
+            for pixel in 0..8u16 {
+                let x = (tile * 8 + pixel as usize).wrapping_sub(ppu.x.us());
+                if x >= WIDTH {
+                    continue;
+                }
+                let bit = 7 - pixel;
+                let colour = (high.bit(bit) << 1) | low.bit(bit);
+                if colour != 0 {
+                    out[x] = palette | colour;
+                }
+            }
+
+            // Increment coarse X, switching horizontal nametable on wrap
+            if v & 0x001F == 31 {
+                v = (v & !0x001F) ^ 0x0400;
+            } else {
+                v += 1;
+            }
+        }
+    }
+
+    /// Evaluate and render the sprites on the current line.
+    /// Only the first 8 sprites are drawn, later ones set the
+    /// overflow flag instead.
+    fn render_sprites(nes: &mut Nes, out: &mut [Option<SpritePixel>; WIDTH]) {
+        let line = nes.ppu.line as i32;
+        let tall = nes.ppu.ctrl.is_bit(CTRL_SPRITE_8X16);
+        let height = if tall { 16 } else { 8 };
+
+        let mut found = 0;
+        for idx in 0..64 {
+            let sprite = &nes.ppu.oam[idx * 4..idx * 4 + 4];
+            let (y, tile, attr, sprite_x) = (sprite[0], sprite[1], sprite[2], sprite[3]);
+            // Sprites are displayed one line below their Y coordinate
+            let mut row = line - y as i32 - 1;
+            if !(0..height).contains(&row) {
+                continue;
+            }
+            found += 1;
+            if found > 8 {
+                nes.ppu.status = nes.ppu.status.set_bit(STATUS_OVERFLOW, true);
+                break;
+            }
+
+            if attr.is_bit(7) {
+                row = height - 1 - row;
+            }
+            let pattern = if tall {
+                let table = (tile & 1).u16() * 0x1000;
+                let tile = (tile & 0xFE).u16() + (row >= 8) as u16;
+                table + tile * 16 + (row as u16 & 7)
+            } else {
+                let table = nes.ppu.ctrl.bit(CTRL_SPRITE_TABLE).u16() * 0x1000;
+                table + tile.u16() * 16 + row as u16
+            };
+            let low = nes.cart.chr_read(pattern);
+            let high = nes.cart.chr_read(pattern + 8);
+
+            for pixel in 0..8u16 {
+                let x = sprite_x.us() + pixel.us();
+                // Lower OAM indices have priority
+                if x >= WIDTH || out[x].is_some() {
+                    continue;
+                }
+                let bit = if attr.is_bit(6) { pixel } else { 7 - pixel };
+                let colour = (high.bit(bit) << 1) | low.bit(bit);
+                if colour != 0 {
+                    out[x] = Some(SpritePixel {
+                        palette_idx: 0x10 | ((attr & 3) << 2) | colour,
+                        behind_bg: attr.is_bit(5),
+                        zero: idx == 0,
+                    });
+                }
+            }
+        }
+    }
+
+    /// Increment the fine and coarse Y scroll in `v`, switching vertical
+    /// nametable when going past the last row of tiles.
+    fn increment_y(&mut self) {
+        if self.v & 0x7000 != 0x7000 {
+            self.v += 0x1000;
+            return;
+        }
+        self.v &= !0x7000;
+        let coarse_y = (self.v & 0x03E0) >> 5;
+        let coarse_y = match coarse_y {
+            29 => {
+                self.v ^= 0x0800;
+                0
+            }
+            31 => 0,
+            _ => coarse_y + 1,
+        };
+        self.v = (self.v & !0x03E0) | (coarse_y << 5);
+    }
+}
+
+impl Default for Ppu {
+    fn default() -> Self {
+        Self {
+            pixels: default_pixels(),
+            ctrl: 0,
+            mask: 0,
+            status: 0,
+            oam_addr: 0,
+            v: 0,
+            t: 0,
+            x: 0,
+            w: false,
+            read_buffer: 0,
+            open_bus: 0,
+            line: 0,
+            dot_remainder: 0,
+            ciram: [0; 0x800],
+            palette: [0; 0x20],
+            oam: [0; 0x100],
+        }
+    }
+}
+
+fn default_pixels() -> Vec<Colour> {
+    vec![[0, 0, 0, 255]; WIDTH * HEIGHT]
+}
+
+/// A sprite pixel on the current line.
+#[derive(Copy, Clone)]
+struct SpritePixel {
+    palette_idx: u8,
+    behind_bg: bool,
+    /// If this pixel is from sprite 0, for sprite 0 hit detection.
+    zero: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use common::{common::options::SystemConfig, Core};
+
+    use super::*;
+    use crate::tests::nrom;
+
+    /// Program that sets up a palette, fills the first row of the
+    /// nametable with tile 1, places sprite 0 (tile 2) at 128/64 and
+    /// turns on rendering.
+    #[rustfmt::skip]
+    const PROGRAM: &[u8] = &[
+        0x78,                   // SEI
+        0xA2, 0xFF, 0x9A,       // LDX #$FF; TXS
+        0x2C, 0x02, 0x20,       // BIT $2002
+        0x10, 0xFB,             // BPL -5
+        0x2C, 0x02, 0x20,       // BIT $2002
+        0x10, 0xFB,             // BPL -5
+        // Background palette: black, red
+        0xA9, 0x3F, 0x8D, 0x06, 0x20, 0xA9, 0x00, 0x8D, 0x06, 0x20,
+        0xA9, 0x0F, 0x8D, 0x07, 0x20, 0xA9, 0x16, 0x8D, 0x07, 0x20,
+        // Sprite palette 0, colour 3: white
+        0xA9, 0x3F, 0x8D, 0x06, 0x20, 0xA9, 0x13, 0x8D, 0x06, 0x20,
+        0xA9, 0x30, 0x8D, 0x07, 0x20,
+        // First nametable row
+        0xA9, 0x20, 0x8D, 0x06, 0x20, 0xA9, 0x00, 0x8D, 0x06, 0x20,
+        0xA9, 0x01, 0xA2, 0x20,       // LDA #1; LDX #32
+        0x8D, 0x07, 0x20, 0xCA,       // STA $2007; DEX
+        0xD0, 0xFA,                   // BNE -6
+        // Sprite 0
+        0xA9, 0x00, 0x8D, 0x03, 0x20,
+        0xA9, 0x3F, 0x8D, 0x04, 0x20, 0xA9, 0x02, 0x8D, 0x04, 0x20,
+        0xA9, 0x00, 0x8D, 0x04, 0x20, 0xA9, 0x80, 0x8D, 0x04, 0x20,
+        // Scroll, PPUCTRL, PPUMASK
+        0xA9, 0x00, 0x8D, 0x05, 0x20, 0x8D, 0x05, 0x20, 0x8D, 0x00, 0x20,
+        0xA9, 0x1E, 0x8D, 0x01, 0x20,
+        0x4C, 0x6E, 0x80,             // JMP to itself
+    ];
+
+    fn chr() -> Vec<u8> {
+        let mut chr = vec![0; 0x30];
+        // Tile 1: colour 1
+        chr[0x10..0x18].fill(0xFF);
+        // Tile 2: colour 3
+        chr[0x20..0x30].fill(0xFF);
+        chr
+    }
+
+    fn colour(idx: usize) -> Colour {
+        let [r, g, b] = PALETTE[idx];
+        [r, g, b, 255]
+    }
+
+    #[test]
+    fn nrom_screenshot() {
+        let mut nes =
+            Nes::with_cart(nrom(PROGRAM, &chr()), None, &SystemConfig::default()).unwrap();
+        for _ in 0..4 {
+            nes.render_one_frame();
+        }
+        let frame = nes.render_one_frame();
+        assert_eq!(frame.len(), WIDTH * HEIGHT);
+
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                let expected = match (x, y) {
+                    (_, 0..=7) => colour(0x16),
+                    (128..=135, 64..=71) => colour(0x30),
+                    _ => colour(0x0F),
+                };
+                assert_eq!(frame[y * WIDTH + x], expected, "pixel at {x}/{y}");
+            }
+        }
+        // Sprite 0 is only over transparent background
+        assert!(!nes.ppu.status.is_bit(STATUS_SPRITE_0_HIT));
+    }
 }
@@ -9,7 +9,7 @@
 use common::{components::scheduler::Kind, TimeS};
 use NesEvent::*;
 
-use crate::{apu::Apu, Nes};
+use crate::{apu::Apu, ppu::Ppu, Nes};
 
 /// All scheduler events on the NES.
 #[derive(Copy, Clone, Eq, PartialEq, Default)]
@@ -21,19 +21,29 @@ pub enum NesEvent {
     #[default]
     PauseEmulation,
     ApuEvent(ApuEvent),
+    PpuEvent(PpuEvent),
 }
 
 impl NesEvent {
     /// Handle the event by delegating to the appropriate handler.
     pub fn dispatch(&self, nes: &mut Nes, late_by: TimeS) {
         match self {
-            PauseEmulation => nes.ticking = false,
+            PauseEmulation => nes.c.in_tick = false,
             ApuEvent(event) => Apu::handle_event(nes, *event, late_by),
+            PpuEvent(event) => Ppu::handle_event(nes, *event, late_by),
         }
     }
 }
 
-impl Kind for NesEvent {}
+impl Kind for NesEvent {
+    fn name(&self) -> &'static str {
+        match self {
+            PauseEmulation => "Pause",
+            ApuEvent(_) => "APU",
+            PpuEvent(_) => "PPU",
+        }
+    }
+}
 
 /// Events the APU generates.
 #[derive(Copy, Clone, Eq, PartialEq)]
@@ -42,4 +52,16 @@ impl Kind for NesEvent {}
 pub enum ApuEvent {
     // Push a sample to the output.
     PushSample,
+    /// Step the frame counter, which clocks envelopes, sweeps and
+    /// length counters.
+    FrameCounter,
+}
+
+/// Events the PPU generates.
+#[derive(Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+#[repr(u16)]
+pub enum PpuEvent {
+    /// End of a scanline; render it or handle VBlank.
+    EndOfLine,
 }
@@ -23,7 +23,7 @@ use common::{
         storage::{GameSave, Storage},
     },
     misc::{EmulateOptions, SystemConfig},
-    produce_samples_buffered, Colour, Core, Time, TimeS,
+    produce_samples_buffered, Colour, Core, CoreKind, Time, TimeS,
 };
 use glow::Context;
 use iso::Iso;
@@ -67,6 +67,10 @@ pub struct PlayStation {
 
 impl Core for PlayStation {
     common_functions!(CPU_CLOCK, PsxEvent::PauseEmulation, [1024, 512]);
+
+    fn kind(&self) -> Option<CoreKind> {
+        Some(CoreKind::Psx)
+    }
     produce_samples_buffered!(48000);
 
     fn advance(&mut self) {
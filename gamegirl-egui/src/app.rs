@@ -7,28 +7,37 @@
 // obtain them at https://mozilla.org/MPL/2.0/ and http://www.gnu.org/licenses/.
 
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
+    mem,
     path::PathBuf,
     sync::{mpsc, Arc, Mutex},
     time::Duration,
 };
 
-use common::{common::options::SystemConfig, Colour as RColour, Core};
+#[cfg(feature = "savestates")]
+use common::common::replay::ReplayBundle;
+use common::{
+    common::{input::Button, options::SystemConfig, video},
+    Colour as RColour, Core, CoreKind,
+};
 use cpal::Stream;
 use eframe::{
-    egui::{Context, Event, TextureOptions},
+    egui::{Context, Event, TextureOptions, ViewportCommand},
     emath::History,
     epaint::{ColorImage, ImageData, ImageDelta, TextureId},
     glow::{self},
     CreationContext, Frame, Storage,
 };
 use egui_notify::{Anchor, Toasts};
-use gilrs::{Axis, EventType, Gilrs};
+use gilrs::{Axis, EventType, GamepadId, Gilrs};
+use log::Level;
 
 use crate::{
+    debug::DebugLayout,
+    emu_thread::EmuThread,
     filter::{Blend, Filter, ScreenBuffer},
     gui::{self, cheat::CheatEngineState, options, APP_WINDOW_COUNT},
-    input::{self, File, Input, InputAction, InputSource},
+    input::{self, latency::InputLatency, rumble::Rumble, File, Input, InputAction, InputSource},
     rewind::Rewinder,
     Colour,
 };
@@ -41,13 +50,25 @@ pub enum AxisState {
 }
 
 impl AxisState {
-    fn new(value: f32) -> Self {
+    fn new(value: f32, deadzone: f32) -> Self {
         match value {
-            ..=-0.5 => Self::Negative,
-            0.5.. => Self::Positive,
+            _ if value <= -deadzone => Self::Negative,
+            _ if value >= deadzone => Self::Positive,
             _ => Self::Neutral,
         }
     }
+
+    /// The input source for this state, `None` for neutral.
+    fn source(self, axis: Axis) -> Option<InputSource> {
+        match self {
+            Self::Negative => Some(InputSource::Axis { axis, is_neg: true }),
+            Self::Neutral => None,
+            Self::Positive => Some(InputSource::Axis {
+                axis,
+                is_neg: false,
+            }),
+        }
+    }
 }
 
 /// The main app struct used by the GUI.
@@ -61,8 +82,13 @@ pub struct App {
     pub rewinder: Rewinder,
     /// Screen buffer state.
     pub screen_buffer: ScreenBuffer,
-    /// If the emulator is fast-forwarding using the toggle hotkey.
-    pub fast_forward_toggled: bool,
+    /// Last frame produced by the system, for screenshots.
+    last_frame: Option<Vec<RColour>>,
+    /// Hash and filter of the last frame uploaded to the screen texture, used
+    /// to skip identical frames.
+    last_frame_hash: Option<(u64, Filter)>,
+    /// Remaining and total time of fast boot, see [Options::fast_boot].
+    fast_boot: Option<(f32, f32)>,
     /// Dynamic loading state, to be used for debugging
     #[cfg(feature = "dynamic")]
     pub dyn_ctx: gamegirl::dynamic::DynamicContext,
@@ -71,18 +97,28 @@ pub struct App {
     pub textures: Vec<TextureId>,
     /// Game controller state
     pub gil: Gilrs,
-    /// States for controller axes
-    controller_axes: HashMap<Axis, AxisState>,
+    /// States for controller axes, per controller
+    controller_axes: HashMap<(GamepadId, Axis), AxisState>,
+    /// Controller buttons currently held, to release them should the
+    /// controller holding them get disconnected.
+    controller_buttons: HashSet<(GamepadId, gilrs::Button)>,
+    /// Controller vibration for games with a rumble motor
+    rumble: Rumble,
     /// Message channel for reacting to some async events, see [Message].
     pub message_channel: (mpsc::Sender<Message>, mpsc::Receiver<Message>),
     /// Frame times.
     pub frame_times: History<f32>,
+    /// Measured input latency, see [Options::show_input_latency].
+    pub input_latency: InputLatency,
     /// Stream for audio.
     audio_stream: Option<Stream>,
+    /// Thread running the core, if emulation runs on a separate thread.
+    /// See [Options::threaded_emulation].
+    emu_thread: Option<EmuThread>,
     /// App window states.
     pub app_window_states: [bool; APP_WINDOW_COUNT],
-    /// Debugger window states.
-    pub debugger_window_states: Vec<bool>,
+    /// Least severe level of log records shown in the log window.
+    pub log_level: Level,
     /// Cheat engine state
     pub cheat: CheatEngineState,
     /// State of OSI
@@ -91,6 +127,11 @@ pub struct App {
     pub open_option: options::Panel,
     /// Toasts
     pub toasts: Toasts,
+    /// If the user was already warned about unsaved changes when closing.
+    exit_warned: bool,
+    /// Replay bundle currently being recorded.
+    #[cfg(feature = "savestates")]
+    pub replay_bundle: Option<ReplayBundle>,
 
     /// The App state, which is persisted on reboot.
     pub state: State,
@@ -98,6 +139,7 @@ pub struct App {
 
 impl eframe::App for App {
     fn update(&mut self, ctx: &Context, frame: &mut Frame) {
+        self.check_close(ctx);
         let size = self.update_gg(ctx);
         self.process_messages(frame.gl());
         gui::draw(self, ctx, frame, size);
@@ -119,6 +161,20 @@ impl App {
     fn update_gg(&mut self, ctx: &Context) -> [usize; 2] {
         let (frame, size) = self.get_frame(ctx);
         if let Some(pixels) = frame {
+            // Only hashed when needed, once for both the input latency and
+            // skipping identical frames
+            let options = &self.state.options;
+            let hash = (options.show_input_latency || options.skip_identical_frames)
+                .then(|| video::frame_hash(&pixels));
+            if let Some(hash) = hash.filter(|_| self.state.options.show_input_latency) {
+                // Before skipping, since it needs to see unchanged frames
+                self.input_latency.frame(hash);
+            }
+            if self.is_identical_frame(hash) {
+                return size;
+            }
+            self.last_frame = Some(pixels.clone());
+
             let (img, filter) = self.screen_buffer.next_frame(
                 size,
                 pixels,
@@ -135,6 +191,24 @@ impl App {
         size
     }
 
+    /// Check if the frame with the given hash is identical to the last one
+    /// and can be skipped. Only done when enabled and no blending is used,
+    /// since blending still changes the output between identical frames.
+    fn is_identical_frame(&mut self, hash: Option<u64>) -> bool {
+        let Some(hash) = hash.filter(|_| {
+            self.state.options.skip_identical_frames
+                && self.state.options.screen_blend == Blend::None
+        }) else {
+            self.last_frame_hash = None;
+            return false;
+        };
+
+        let last = Some((hash, self.state.options.tex_filter));
+        let identical = self.last_frame_hash == last;
+        self.last_frame_hash = last;
+        identical
+    }
+
     /// Process keyboard inputs and return the GG's next frame, if one was
     /// produced.
     fn get_frame(&mut self, ctx: &Context) -> (Option<Vec<RColour>>, [usize; 2]) {
@@ -150,28 +224,38 @@ impl App {
                     self.handle_evt(InputSource::Key(*key), *pressed);
                 }
             }
-            while let Some(gilrs::Event { event, .. }) = self.gil.next_event() {
+            while let Some(gilrs::Event { id, event, .. }) = self.gil.next_event() {
                 match event {
-                    EventType::ButtonPressed(b, _) => self.handle_evt(InputSource::Button(b), true),
+                    EventType::ButtonPressed(b, _) => {
+                        self.controller_buttons.insert((id, b));
+                        self.handle_evt(InputSource::Button(b), true)
+                    }
                     EventType::ButtonReleased(b, _) => {
+                        self.controller_buttons.remove(&(id, b));
                         self.handle_evt(InputSource::Button(b), false)
                     }
                     EventType::AxisChanged(axis, value, _) => {
-                        let prev = self
-                            .controller_axes
-                            .get(&axis)
-                            .unwrap_or(&AxisState::Neutral);
-                        let curr = AxisState::new(value);
-                        if *prev != curr {
-                            self.handle_evt(
-                                InputSource::Axis {
-                                    axis,
-                                    is_neg: value < 0.0,
-                                },
-                                value.abs() > 0.5,
-                            );
+                        let deadzone = self.state.options.controller_deadzone;
+                        self.set_axis(id, axis, AxisState::new(value, deadzone));
+                    }
+                    EventType::Connected => {
+                        let name = self.gil.gamepad(id).name().to_string();
+                        self.toasts.info(format!("Controller connected: {name}"));
+                    }
+                    EventType::Disconnected => {
+                        // Release everything this controller held, so no
+                        // buttons get stuck
+                        let held = self.controller_buttons.iter().filter(|(pad, _)| *pad == id);
+                        for (pad, button) in held.copied().collect::<Vec<_>>() {
+                            self.controller_buttons.remove(&(pad, button));
+                            self.handle_evt(InputSource::Button(button), false);
+                        }
+                        let axes = self.controller_axes.keys().filter(|(pad, _)| *pad == id);
+                        for (pad, axis) in axes.copied().collect::<Vec<_>>() {
+                            self.set_axis(pad, axis, AxisState::Neutral);
+                            self.controller_axes.remove(&(pad, axis));
                         }
-                        self.controller_axes.insert(axis, curr);
+                        self.toasts.info("Controller disconnected");
                     }
                     _ => (),
                 }
@@ -180,12 +264,26 @@ impl App {
         });
         let delta = raw_delta.clamp(0.001, 0.016) - 0.0009;
 
+        if let Some(thread) = &self.emu_thread {
+            // Rewinding drives the core from this thread
+            thread.set_active(!self.rewinder.rewinding);
+        }
         let mut core = self.core.lock().unwrap();
         let size = core.screen_size();
+        self.rumble.update(
+            &mut self.gil,
+            core.c_mut().take_rumble(),
+            self.state.options.rumble_intensity,
+            Duration::from_millis(self.state.options.rumble_min_pulse_ms),
+        );
 
         if self.rewinder.rewinding {
-            let frame = if let Some(state) = self.rewinder.rewind_buffer.pop() {
-                core.load_state(state);
+            let load = |state: &[u8]| {
+                if let Err(err) = core.load_state(state) {
+                    log::error!("Failed to load rewind state: {err}");
+                }
+            };
+            let frame = if self.rewinder.pop(load) {
                 core.c_mut().options.invert_audio_samples = true;
                 core.produce_frame()
             } else {
@@ -195,27 +293,86 @@ impl App {
             };
             (frame, size)
         } else {
-            core.advance_delta(delta);
-            let frame = core.c_mut().video_buffer.pop();
+            if let Some((remaining, total)) = &mut self.fast_boot {
+                *remaining -= delta;
+                Self::fast_boot_input(&mut **core, *total - *remaining, *remaining <= 0.0);
+                if *remaining <= 0.0 {
+                    self.fast_boot = None;
+                }
+            }
+
+            let frame = match &self.emu_thread {
+                Some(thread) => thread.take_frame(),
+                None => {
+                    core.advance_delta(delta);
+                    core.c_mut().video_buffer.pop()
+                }
+            };
             if frame.is_some() && self.state.options.enable_rewind {
-                let state = core.save_state();
-                self.rewinder.rewind_buffer.push(state);
+                if self.rewinder.is_threaded() {
+                    // Leave compression to the capture thread
+                    let compress =
+                        mem::replace(&mut core.c_mut().config.compress_savestates, false);
+                    let state = core.save_state();
+                    core.c_mut().config.compress_savestates = compress;
+                    self.rewinder.push(state, compress);
+                } else {
+                    self.rewinder.push(core.save_state(), false);
+                }
             }
             (frame, size)
         }
     }
 
+    /// Press Start and A in turns, at the given time since fast boot
+    /// started. Both are released once done.
+    fn fast_boot_input(core: &mut dyn Core, elapsed: f32, done: bool) {
+        let phase = elapsed.fract();
+        let time = core.get_time();
+        let input = &mut core.c_mut().input;
+        input.set(time, Button::Start, !done && phase < 0.25);
+        input.set(time, Button::A, !done && (0.5..0.75).contains(&phase));
+    }
+
+    /// Start fast boot, if enabled.
+    pub fn start_fast_boot(&mut self) {
+        let seconds = self.state.options.fast_boot_seconds as f32;
+        self.fast_boot = self.state.options.fast_boot.then_some((seconds, seconds));
+    }
+
+    /// Update the state of a controller axis, releasing the direction it
+    /// was held in before and pressing the new one.
+    fn set_axis(&mut self, pad: GamepadId, axis: Axis, state: AxisState) {
+        let prev = self
+            .controller_axes
+            .insert((pad, axis), state)
+            .unwrap_or(AxisState::Neutral);
+        if prev == state {
+            return;
+        }
+        if let Some(src) = prev.source(axis) {
+            self.handle_evt(src, false);
+        }
+        if let Some(src) = state.source(axis) {
+            self.handle_evt(src, true);
+        }
+    }
+
     fn handle_evt(&mut self, src: InputSource, pressed: bool) {
         if let Some(action) = self.state.options.input.pending.take() {
             self.state.options.input.set(src, action);
             return;
         }
 
-        match self.state.options.input.get(src) {
+        let kind = self.core.lock().unwrap().kind();
+        match self.state.options.input.get(src, kind) {
             Some(InputAction::Button(btn)) => {
                 let mut core = self.core.lock().unwrap();
                 let time = core.get_time();
                 core.c_mut().input.set(time, btn, pressed);
+                if pressed && self.state.options.show_input_latency {
+                    self.input_latency.press();
+                }
             }
             Some(InputAction::Hotkey(idx)) => input::HOTKEYS[idx as usize].1(self, pressed),
             None => (),
@@ -254,6 +411,8 @@ impl App {
                     }
 
                     self.audio_stream = crate::setup_cpal(self.core.clone());
+                    self.last_frame_hash = None;
+                    self.start_fast_boot();
 
                     self.current_rom_path = file.path.clone();
                     if let Some(path) = file.path {
@@ -286,6 +445,48 @@ impl App {
                         .set_duration(Some(Duration::from_secs(5)));
                 }
 
+                Message::RetroArchSaveOpen(file) => {
+                    let mut core = self.core.lock().unwrap();
+                    match core.import_save_ram(&file.content) {
+                        Ok(()) => {
+                            core.reset();
+                            self.toasts.info("Imported RetroArch save")
+                        }
+                        Err(err) => self.toasts.error(format!("Failed to import save: {err}")),
+                    }
+                    .set_duration(Some(Duration::from_secs(5)));
+                }
+
+                Message::MemoryDumpOpen { file, addr } => {
+                    let written = self
+                        .core
+                        .lock()
+                        .unwrap()
+                        .load_memory_region(addr, &file.content);
+                    let skipped = file.content.len() - written;
+                    let toast = if skipped == 0 {
+                        self.toasts
+                            .info(format!("Loaded {written} bytes at 0x{addr:X}"))
+                    } else {
+                        self.toasts.warning(format!(
+                            "Loaded {written} bytes at 0x{addr:X}, skipped {skipped} bytes of ROM"
+                        ))
+                    };
+                    toast.set_duration(Some(Duration::from_secs(5)));
+                }
+
+                #[cfg(feature = "savestates")]
+                Message::BundleOpen(file) => {
+                    self.save_game();
+                    let result = ReplayBundle::deserialize(&file.content)
+                        .and_then(|bundle| bundle.verify(&mut **self.core.lock().unwrap()));
+                    match result {
+                        Ok(()) => self.toasts.success("Replay bundle verified"),
+                        Err(err) => self.toasts.error(format!("Replay bundle failed: {err}")),
+                    }
+                    .set_duration(Some(Duration::from_secs(5)));
+                }
+
                 Message::BiosOpen { file, console_id } => {
                     self.state
                         .options
@@ -333,9 +534,88 @@ impl App {
         }
     }
 
-    /// Save the system cart RAM, if a cart is loaded and it has RAM.
+    /// Save the system cart RAM, if a cart is loaded and it has RAM that was
+    /// modified since the last save.
     pub fn save_game(&self) {
-        gamegirl::save_game(&**self.core.lock().unwrap(), self.current_rom_path.clone());
+        let mut core = self.core.lock().unwrap();
+        if core.save_is_dirty() && (cfg!(target_arch = "wasm32") || self.current_rom_path.is_some())
+        {
+            gamegirl::save_game(&**core, self.current_rom_path.clone());
+            core.clear_save_dirty();
+        }
+    }
+
+    /// Save the last frame as a PNG, without filters or blending. Named
+    /// after the ROM and the current time and put into
+    /// [Options::screenshot_dir]; on WASM, the user is asked where to put it.
+    pub fn screenshot(&mut self) {
+        let Some(frame) = self.last_frame.as_ref() else {
+            self.toasts
+                .error("No frame to take a screenshot of")
+                .set_duration(Some(Duration::from_secs(3)));
+            return;
+        };
+        let (size, title) = {
+            let core = self.core.lock().unwrap();
+            (core.screen_size(), core.make_save().map(|s| s.title))
+        };
+        let title = self
+            .current_rom_path
+            .as_ref()
+            .and_then(|p| p.file_stem())
+            .map(|s| s.to_string_lossy().to_string())
+            .or(title)
+            .unwrap_or_else(|| "gamegirl".to_string());
+
+        let mut png = Vec::new();
+        if let Err(err) = video::write_png(&mut png, frame, size) {
+            self.toasts
+                .error(format!("Failed to encode screenshot: {err}"))
+                .set_duration(Some(Duration::from_secs(5)));
+            return;
+        }
+
+        #[cfg(target_arch = "wasm32")]
+        input::file_dialog::save_screenshot(format!("{title}.png"), png);
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let time = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map_or(0, |d| d.as_millis());
+            let dir = &self.state.options.screenshot_dir;
+            let path = dir.join(format!("{title}-{time}.png"));
+            let result = std::fs::create_dir_all(dir).and_then(|_| std::fs::write(&path, png));
+            match result {
+                Ok(()) => self
+                    .toasts
+                    .info(format!("Saved screenshot to {}", path.display())),
+                Err(err) => self
+                    .toasts
+                    .error(format!("Failed to save screenshot: {err}")),
+            }
+            .set_duration(Some(Duration::from_secs(3)));
+        }
+    }
+
+    /// Flush the save when the user closes the app. If the save cannot be
+    /// written since the ROM has no path, warn once and cancel the close.
+    fn check_close(&mut self, ctx: &Context) {
+        if !ctx.input(|i| i.viewport().close_requested()) {
+            return;
+        }
+
+        self.save_game();
+        let unsaved = {
+            let core = self.core.lock().unwrap();
+            core.save_is_dirty() && core.make_save().is_some()
+        };
+        if unsaved && !self.exit_warned {
+            self.exit_warned = true;
+            ctx.send_viewport_cmd(ViewportCommand::CancelClose);
+            self.toasts
+                .warning("The game has unsaved changes that cannot be written to disk. Close again to discard them.")
+                .set_duration(Some(Duration::from_secs(10)));
+        }
     }
 
     pub fn new(ctx: &CreationContext<'_>) -> Box<Self> {
@@ -345,6 +625,8 @@ impl App {
             .unwrap_or_default();
         let core = gamegirl::dummy_core();
         let core = Arc::new(Mutex::new(core));
+        let emu_thread = (state.options.threaded_emulation && !cfg!(target_arch = "wasm32"))
+            .then(|| EmuThread::start(core.clone()));
         let textures = vec![App::make_screen_texture(
             &ctx.egui_ctx,
             [160, 144],
@@ -359,27 +641,39 @@ impl App {
             core,
             current_rom_path: None,
 
-            rewinder: Rewinder::new(state.options.rewind_buffer_size),
+            rewinder: Rewinder::new(
+                state.options.rewind_buffer_size,
+                state.options.threaded_rewind,
+            ),
             screen_buffer: ScreenBuffer::default(),
-            fast_forward_toggled: false,
+            last_frame: None,
+            last_frame_hash: None,
+            fast_boot: None,
             #[cfg(feature = "dynamic")]
             dyn_ctx: gamegirl::dynamic::DynamicContext::watch_dir(move |path| {
                 tx2.send(Message::CoreLoad(path)).unwrap();
             }),
 
             app_window_states: [false; APP_WINDOW_COUNT],
-            debugger_window_states: Vec::from([false; 10]),
+            log_level: Level::Warn,
             cheat: CheatEngineState::default(),
             on_screen_input: false,
             open_option: options::Panel::About,
             toasts: Toasts::default().with_anchor(Anchor::BottomLeft),
+            exit_warned: false,
+            #[cfg(feature = "savestates")]
+            replay_bundle: None,
 
             textures,
             gil: Gilrs::new().unwrap(),
             controller_axes: HashMap::with_capacity(6),
+            controller_buttons: HashSet::new(),
+            rumble: Rumble::default(),
             message_channel: (tx, rx),
             frame_times: History::new(0..120, 2.0),
+            input_latency: InputLatency::default(),
             audio_stream: None,
+            emu_thread,
 
             state,
         })
@@ -409,6 +703,9 @@ pub struct State {
     pub last_opened: Vec<PathBuf>,
     /// User configuration options.
     pub options: Options,
+    /// Layout of the debugger windows.
+    #[serde(default)]
+    pub debug_layout: DebugLayout,
 }
 
 /// A message that can be sent from some async context.
@@ -418,6 +715,14 @@ pub enum Message {
     RomOpen(File),
     /// A file picked by the user to be opened as a replay.
     ReplayOpen(File),
+    /// A RetroArch save file picked by the user to be imported.
+    RetroArchSaveOpen(File),
+    /// A raw memory dump picked by the user to be loaded at the given
+    /// address.
+    MemoryDumpOpen { file: File, addr: u32 },
+    /// A file picked by the user to be verified as a replay bundle.
+    #[cfg(feature = "savestates")]
+    BundleOpen(File),
     /// An error occured.
     Error(String),
     /// A BIOS file was picked.
@@ -430,25 +735,57 @@ pub enum Message {
 
 /// User-configurable options.
 #[derive(serde::Deserialize, serde::Serialize)]
+#[serde(default)]
 pub struct Options {
     /// Options passed to the system when loading a ROM.
     pub sys: SystemConfig,
     /// Input configuration.
     pub input: Input,
+    /// Use the mouse to touch the screen on systems with a touchscreen.
+    pub mouse_touch: bool,
 
     /// Fast forward speed for the hold button.
-    pub fast_forward_hold_speed: usize,
+    pub fast_forward_hold_speed: f32,
     /// Fast forward speed for the toggle button.
-    pub fast_forward_toggle_speed: usize,
+    pub fast_forward_toggle_speed: f32,
+    /// Per-system fast forward speeds, used instead of the ones above
+    /// for systems that have an entry.
+    pub system_fast_forward: HashMap<CoreKind, FastForwardSpeed>,
     /// Enable rewinding.
     pub enable_rewind: bool,
     /// Rewind buffer size (if enabled), in seconds.
     pub rewind_buffer_size: usize,
+    /// Capture rewind states on a separate thread, which does compression
+    /// and buffer insertion instead of the UI thread.
+    pub threaded_rewind: bool,
+    /// Run the core on a separate thread at a fixed rate, instead of
+    /// advancing it in the UI's update loop.
+    pub threaded_emulation: bool,
+    /// Strength of controller vibration for games with a rumble motor,
+    /// from 0 (off) to 1.
+    pub rumble_intensity: f32,
+    /// Shortest time controllers vibrate for, in milliseconds. Games often
+    /// pulse the motor too briefly to be felt otherwise.
+    pub rumble_min_pulse_ms: u64,
+    /// How far analog sticks need to be moved to count as a direction,
+    /// from 0 to 1.
+    pub controller_deadzone: f32,
+    /// Press Start and A for a while after boot to skip intros.
+    pub fast_boot: bool,
+    /// How long to do fast boot for (if enabled), in seconds.
+    pub fast_boot_seconds: usize,
 
     /// Texture filter applied to the display.
     pub tex_filter: Filter,
     /// Blending applied to the display.
     pub screen_blend: Blend,
+    /// Skip uploading frames identical to the previous one.
+    pub skip_identical_frames: bool,
+    /// Show the measured time from a button press to the first frame it
+    /// changed next to the emulation speed, see [InputLatency].
+    pub show_input_latency: bool,
+    /// Directory screenshots taken with the hotkey are saved to.
+    pub screenshot_dir: PathBuf,
     /// Require pixel perfect scaling.
     pub pixel_perfect: bool,
     /// Always preserve aspect ratio.
@@ -462,12 +799,25 @@ impl Default for Options {
         Self {
             sys: Default::default(),
             input: Input::new(),
-            fast_forward_hold_speed: 2,
-            fast_forward_toggle_speed: 2,
+            mouse_touch: true,
+            fast_forward_hold_speed: 2.0,
+            fast_forward_toggle_speed: 2.0,
+            system_fast_forward: HashMap::new(),
             enable_rewind: true,
             rewind_buffer_size: 10,
+            // WASM doesn't do threads
+            threaded_rewind: !cfg!(target_arch = "wasm32"),
+            threaded_emulation: false,
+            rumble_intensity: 0.75,
+            rumble_min_pulse_ms: 50,
+            controller_deadzone: 0.5,
+            fast_boot: false,
+            fast_boot_seconds: 10,
             tex_filter: Filter::Nearest,
             screen_blend: Blend::None,
+            skip_identical_frames: false,
+            show_input_latency: false,
+            screenshot_dir: PathBuf::from("screenshots"),
             pixel_perfect: false,
             preserve_aspect_ratio: true,
             #[cfg(target_arch = "wasm32")]
@@ -478,6 +828,28 @@ impl Default for Options {
     }
 }
 
+impl Options {
+    /// Get the fast forward speeds to use for the given system, falling back
+    /// to the global ones if the system has none set.
+    pub fn fast_forward_for(&self, kind: Option<CoreKind>) -> FastForwardSpeed {
+        kind.and_then(|k| self.system_fast_forward.get(&k))
+            .copied()
+            .unwrap_or(FastForwardSpeed {
+                hold: self.fast_forward_hold_speed,
+                toggle: self.fast_forward_toggle_speed,
+            })
+    }
+}
+
+/// Fast forward speeds for a single system.
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct FastForwardSpeed {
+    /// Speed for the hold button.
+    pub hold: f32,
+    /// Speed for the toggle button.
+    pub toggle: f32,
+}
+
 #[derive(serde::Deserialize, serde::Serialize, Debug, PartialEq)]
 pub enum GuiStyle {
     OnTop,
@@ -501,10 +873,41 @@ impl App {
         }
     }
 
+    /// Render a single frame while paused.
+    pub fn advance_frame(&mut self) {
+        let mut core = self.core.lock().unwrap();
+        if !core.c().debugger.running {
+            let frame = core.render_one_frame();
+            core.c_mut().video_buffer.requeue(frame);
+        }
+    }
+
     pub fn reset(&mut self) {
         self.core.lock().unwrap().reset();
+        self.start_fast_boot();
         self.toasts
             .warning("Console reset")
             .set_duration(Some(Duration::from_secs(5)));
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn axis_deadzone() {
+        let deadzone = 0.5;
+        for (value, state) in [
+            (0.0, AxisState::Neutral),
+            (0.49, AxisState::Neutral),
+            (-0.49, AxisState::Neutral),
+            (0.5, AxisState::Positive),
+            (-0.5, AxisState::Negative),
+            (1.0, AxisState::Positive),
+            (-1.0, AxisState::Negative),
+        ] {
+            assert!(AxisState::new(value, deadzone) == state, "{value}");
+        }
+    }
+}
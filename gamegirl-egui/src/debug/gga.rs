@@ -10,43 +10,58 @@ use std::iter;
 
 use common::{numutil::NumExt, Core};
 use eframe::{
-    egui::{load::SizedTexture, Context, Label, RichText, TextureOptions, Ui},
+    egui::{load::SizedTexture, Context, Label, RichText, ScrollArea, Slider, TextureOptions, Ui},
     epaint::{vec2, ColorImage, ImageData, ImageDelta, TextureId},
 };
 use gamegirl::gga::{
-    hw::timer::{self},
+    hw::{
+        gpio::GpioDevice,
+        timer::{self},
+    },
     ppu::registers::{Window, WindowCtrl},
     GameGirlAdv,
 };
 
-use super::Windows;
+use super::{window_button, Windows};
 use crate::{App, Colour};
 
 pub fn ui_menu(app: &mut App, ui: &mut eframe::egui::Ui) {
-    app.debugger_window_states[0] ^= ui.button("Debugger").clicked();
-    app.debugger_window_states[1] ^= ui.button("Cartridge Viewer").clicked();
+    window_button(app, ui, "Debugger");
+    window_button(app, ui, "Memory Viewer");
+    window_button(app, ui, "Cartridge Viewer");
     if cfg!(all(feature = "remote-debugger", target_family = "unix")) {
-        app.debugger_window_states[2] ^= ui.button("Remote Debugger").clicked();
+        window_button(app, ui, "Remote Debugger");
     }
     ui.separator();
-    app.debugger_window_states[7] ^= ui.button("PPU Register Viewer").clicked();
-    app.debugger_window_states[3] ^= ui.button("BG Tileset Viewer").clicked();
-    app.debugger_window_states[4] ^= ui.button("OBJ Tileset Viewer").clicked();
+    window_button(app, ui, "PPU Register Viewer");
+    window_button(app, ui, "BG Tileset Viewer");
+    window_button(app, ui, "OBJ Tileset Viewer");
+    window_button(app, ui, "BG Map Viewer");
     ui.separator();
-    app.debugger_window_states[5] ^= ui.button("Timer Status").clicked();
-    app.debugger_window_states[6] ^= ui.button("DMA Status").clicked();
+    window_button(app, ui, "Timer Status");
+    window_button(app, ui, "DMA Status");
+    window_button(app, ui, "PSG Viewer");
+    window_button(app, ui, "IO Registers");
+    window_button(app, ui, "Interrupts");
+    window_button(app, ui, "Sprites");
 }
 
 pub fn get_windows() -> Windows<GameGirlAdv> {
     &[
         ("Debugger", debugger),
-        ("Cartridge", cart_info),
+        ("Memory Viewer", memory),
+        ("Cartridge Viewer", cart_info),
         ("Remote Debugger", remote_debugger),
         ("BG Tileset Viewer", bg_tileset_viewer),
         ("OBJ Tileset Viewer", obj_tileset_viewer),
+        ("BG Map Viewer", bg_map_viewer),
         ("Timer Status", timer_status),
         ("DMA Status", dma_status),
         ("PPU Register Viewer", ppu_registers),
+        ("PSG Viewer", psg_viewer),
+        ("IO Registers", io_registers),
+        ("Interrupts", interrupts),
+        ("Sprites", sprites),
     ]
 }
 
@@ -92,6 +107,12 @@ fn debugger(gg: &mut GameGirlAdv, ui: &mut Ui, _: &mut App, _: &Context) {
                 );
                 sp = sp.wrapping_add(4);
             }
+
+            ui.add(Label::new(RichText::new("Backtrace:").monospace()).extend())
+                .on_hover_text("Approximate, found by looking for return addresses on the stack.");
+            for addr in gg.stack_trace() {
+                ui.monospace(format!("0x{addr:08X}"));
+            }
         });
         ui.separator();
 
@@ -136,14 +157,36 @@ fn debugger(gg: &mut GameGirlAdv, ui: &mut Ui, _: &mut App, _: &Context) {
         }
     });
 
-    super::debugger_footer(&mut gg.c.debugger, ui);
+    super::debugger_footer(
+        &mut gg.c,
+        ui,
+        &["Pulse 1", "Pulse 2", "Wave", "Noise", "FIFO A", "FIFO B"],
+    );
 }
 
 /// Window showing information about the loaded ROM/cart.
-pub fn cart_info(gg: &mut GameGirlAdv, ui: &mut Ui, _: &mut App, _: &Context) {
+pub fn cart_info(gg: &mut GameGirlAdv, ui: &mut Ui, app: &mut App, _: &Context) {
     ui.label(format!("Reported Title: {}", gg.cart.title()));
     ui.label(format!("Reported Game Code: AGB-{}", gg.cart.game_code()));
     ui.label(format!("Detected Save Type: {:?}", gg.cart.save_type));
+    ui.separator();
+
+    let gpio = &gg.cart.gpio;
+    ui.label("GPIO");
+    ui.monospace(format!("Data:      {:04b}", gpio.data));
+    ui.monospace(format!("Direction: {:04b}", gpio.direction));
+    ui.label(format!("Readable: {}", gpio.readable));
+    ui.label(format!("Device: {:?}", gpio.device));
+
+    if let GpioDevice::Solar(_) = gpio.device {
+        let level = &mut gg.c.config.solar_level;
+        if ui
+            .add(Slider::new(level, 0..=255).text("Light level"))
+            .changed()
+        {
+            app.state.options.sys.solar_level = *level;
+        }
+    }
 }
 
 /// Window showing status of the remote debugger.
@@ -198,10 +241,12 @@ pub(super) fn remote_debugger(_: &mut GameGirlAdv, _: &mut Ui, _: &mut App, _: &
 const BG_VRAM_TEX: [usize; 4] = [1, 2, 3, 4];
 /// Texture IDs for OBJ VRAM viewer
 const OBJ_VRAM_TEX: [usize; 2] = [4, 5];
+/// Texture IDs for BG map viewer
+const BG_MAP_TEX: [usize; 4] = [6, 7, 8, 9];
 
 /// Window showing current tilesets for all background layers.
 fn bg_tileset_viewer(gg: &mut GameGirlAdv, ui: &mut Ui, app: &mut App, ctx: &Context) {
-    fn draw_bg_layer(gg: &GameGirlAdv, bg: u32, ui: &mut Ui, app: &mut App, ctx: &Context) {
+    fn draw_bg_layer(gg: &mut GameGirlAdv, bg: u32, ui: &mut Ui, app: &mut App, ctx: &Context) {
         let mut buf = make_buffer(32, 32);
         let cnt = gg.ppu.regs.bg_cnt[bg.us()];
         let tile_base_addr = cnt.character_base_block().us() * 0x4000;
@@ -229,10 +274,16 @@ fn bg_tileset_viewer(gg: &mut GameGirlAdv, ui: &mut Ui, app: &mut App, ctx: &Con
             TextureOptions::NEAREST,
         );
         ui.vertical(|ui| {
-            ui.label(format!(
-                "Tileset for BG{bg} ({}bpp)",
-                if bpp8 { "8" } else { "4" }
-            ));
+            ui.horizontal(|ui| {
+                ui.label(format!(
+                    "Tileset for BG{bg} ({}bpp)",
+                    if bpp8 { "8" } else { "4" }
+                ));
+                let mut shown = gg.ppu.layer_enabled(bg.us());
+                if ui.checkbox(&mut shown, "Show on screen").changed() {
+                    gg.ppu.set_layer_enabled(bg.us(), shown);
+                }
+            });
             ui.image(Into::<SizedTexture>::into((
                 tex,
                 vec2(32. * 16., 32. * 16.),
@@ -310,6 +361,10 @@ fn obj_tileset_viewer(gg: &mut GameGirlAdv, ui: &mut Ui, app: &mut App, ctx: &Co
 
     let mode = gg.ppu.regs.dispcnt.bg_mode();
     ui.label(format!("Current PPU mode: {mode:?}"));
+    let mut shown = gg.ppu.layer_enabled(4);
+    if ui.checkbox(&mut shown, "Show objects on screen").changed() {
+        gg.ppu.set_layer_enabled(4, shown);
+    }
     ui.separator();
     ui.vertical(|ui| {
         ui.horizontal(|ui| {
@@ -320,6 +375,20 @@ fn obj_tileset_viewer(gg: &mut GameGirlAdv, ui: &mut Ui, app: &mut App, ctx: &Co
     });
 }
 
+/// Window showing the tile maps of all text backgrounds, with the area
+/// currently on screen outlined.
+fn bg_map_viewer(gg: &mut GameGirlAdv, ui: &mut Ui, app: &mut App, _: &Context) {
+    super::layer_toggles(gg, ui);
+    ui.separator();
+
+    ScrollArea::both().show(ui, |ui| {
+        for bg in 0..4 {
+            super::tile_map_view(gg, bg, BG_MAP_TEX[bg], ui, app);
+            ui.separator();
+        }
+    });
+}
+
 /// Create a buffer with the given size in tiles (8x8 tiles)
 fn make_buffer(x: usize, y: usize) -> Vec<Colour> {
     let count = (x * 8) * (y * 8);
@@ -443,8 +512,36 @@ fn dma_status(gg: &mut GameGirlAdv, ui: &mut Ui, _: &mut App, _: &Context) {
     }
 }
 
+/// Memory viewer for all regions of the GGA's address space.
+fn memory(gg: &mut GameGirlAdv, ui: &mut Ui, _: &mut App, _: &Context) {
+    super::memory_viewer(gg, ui);
+}
+
+/// Window showing PSG state.
+fn psg_viewer(gg: &mut GameGirlAdv, ui: &mut Ui, _: &mut App, _: &Context) {
+    super::psg_viewer(gg, ui);
+}
+
+/// Window showing named IO registers.
+fn io_registers(gg: &mut GameGirlAdv, ui: &mut Ui, _: &mut App, _: &Context) {
+    super::io_registers(gg, ui);
+}
+
+/// Window showing the state of all interrupts.
+fn interrupts(gg: &mut GameGirlAdv, ui: &mut Ui, _: &mut App, _: &Context) {
+    super::interrupt_state(gg, ui);
+}
+
+/// Window showing an editable table of all objects.
+fn sprites(gg: &mut GameGirlAdv, ui: &mut Ui, _: &mut App, _: &Context) {
+    super::sprite_table(gg, ui);
+}
+
 /// Window showing PPU state.
 fn ppu_registers(gg: &mut GameGirlAdv, ui: &mut Ui, _: &mut App, _: &Context) {
+    super::layer_toggles(gg, ui);
+    ui.separator();
+
     let cnt = gg.ppu.regs.dispcnt;
     ui.collapsing("Display Control", |ui| {
         ui.label(format!("BG Mode: {:?}", cnt.bg_mode()));
@@ -6,13 +6,13 @@
 // If a copy of these licenses was not distributed with this file, you can
 // obtain them at https://mozilla.org/MPL/2.0/ and http://www.gnu.org/licenses/.
 
-use std::{fmt::Write, iter};
+use std::iter;
 
 use common::{numutil::NumExt, Core};
 use eframe::{
     egui::{
-        load::SizedTexture, vec2, Align, ColorImage, Context, ImageData, Label, RichText,
-        ScrollArea, TextureId, TextureOptions, Ui,
+        load::SizedTexture, vec2, ColorImage, Context, ImageData, Label, RichText, TextureId,
+        TextureOptions, Ui,
     },
     epaint::ImageDelta,
 };
@@ -20,30 +20,39 @@ use gamegirl::ggc::{
     cpu::{inst, DReg},
     io::{
         addr::{IE, IF, VRAM_SELECT, WRAM_SELECT},
-        ppu::{self, Ppu},
+        ppu,
     },
     GameGirl,
 };
 
-use super::Windows;
+use super::{window_button, Windows};
 use crate::{app::App, Colour};
 
 pub fn ui_menu(app: &mut App, ui: &mut eframe::egui::Ui) {
-    app.debugger_window_states[0] ^= ui.button("Debugger").clicked();
-    app.debugger_window_states[1] ^= ui.button("Memory Viewer").clicked();
-    app.debugger_window_states[2] ^= ui.button("Cartridge Viewer").clicked();
+    window_button(app, ui, "Debugger");
+    window_button(app, ui, "Memory Viewer");
+    window_button(app, ui, "Cartridge Viewer");
     ui.separator();
-    app.debugger_window_states[3] ^= ui.button("VRAM Viewer").clicked();
-    app.debugger_window_states[4] ^= ui.button("Background Map Viewer").clicked();
+    window_button(app, ui, "VRAM Viewer");
+    window_button(app, ui, "Background Map Viewer");
+    ui.separator();
+    window_button(app, ui, "APU Viewer");
+    window_button(app, ui, "IO Registers");
+    window_button(app, ui, "Interrupts");
+    window_button(app, ui, "Sprites");
 }
 
 pub fn get_windows() -> Windows<GameGirl> {
     &[
         ("Debugger", debugger),
-        ("Memory", memory),
-        ("Cartridge", cart_info),
+        ("Memory Viewer", memory),
+        ("Cartridge Viewer", cart_info),
         ("VRAM Viewer", vram_viewer),
         ("Background Map Viewer", bg_map_viewer),
+        ("APU Viewer", apu_viewer),
+        ("IO Registers", io_registers),
+        ("Interrupts", interrupts),
+        ("Sprites", sprites),
     ]
 }
 
@@ -92,6 +101,12 @@ fn debugger(gg: &mut GameGirl, ui: &mut Ui, _: &mut App, _: &Context) {
                 );
                 sp = sp.wrapping_add(2);
             }
+
+            ui.add(Label::new(RichText::new("Backtrace:").monospace()).extend())
+                .on_hover_text("Approximate, found by looking for return addresses on the stack.");
+            for addr in gg.stack_trace() {
+                ui.monospace(format!("0x{addr:04X}"));
+            }
         });
         ui.separator();
 
@@ -127,52 +142,12 @@ fn debugger(gg: &mut GameGirl, ui: &mut Ui, _: &mut App, _: &Context) {
         ui.checkbox(&mut gg.c.debugger.running, "Running");
     });
 
-    super::debugger_footer(&mut gg.c.debugger, ui);
+    super::debugger_footer(&mut gg.c, ui, &["Pulse 1", "Pulse 2", "Wave", "Noise"]);
 }
 
-/// Memory viewer showing the entire GG's address space.
+/// Memory viewer for all regions of the GG's address space.
 fn memory(gg: &mut GameGirl, ui: &mut Ui, _: &mut App, _: &Context) {
-    let mut position = None;
-
-    ui.horizontal(|ui| {
-        ui.label("Jump to:");
-        const POS: &[(&str, u16)] = &[
-            ("Cart", 0),
-            ("VRAM", 0x8000),
-            ("CRAM", 0xA000),
-            ("WRAM", 0xC000),
-            ("OAM", 0xFE00),
-            ("I/O", 0xFF00),
-            ("ZRAM", 0xFF80),
-        ];
-        for (name, pos) in POS.iter() {
-            if ui.button(*name).clicked() {
-                position = Some(*pos);
-            }
-        }
-    });
-    ui.separator();
-
-    ui.horizontal(|ui| {
-        ui.label("   "); // Padding
-        ui.monospace("     0  1  2  3  4  5  6  7  8  9  A  B  C  D  E  F");
-    });
-    ScrollArea::vertical().show(ui, |ui| {
-        let mut buf = String::with_capacity(100);
-        for row_start in 0..0x1000 {
-            let row_start = row_start * 0x10;
-            write!(&mut buf, "{:04X} -", row_start).unwrap();
-            for offset in 0..0x10 {
-                write!(&mut buf, " {:02X}", gg.get::<u8>(row_start + offset)).unwrap();
-            }
-
-            let label = ui.add(Label::new(RichText::new(&buf).monospace()).extend());
-            if position == Some(row_start) {
-                ui.scroll_to_rect(label.rect, Some(Align::Min));
-            }
-            buf.clear();
-        }
-    });
+    super::memory_viewer(gg, ui);
 }
 
 /// Window showing information about the loaded ROM/cart.
@@ -234,48 +209,36 @@ fn vram_viewer(gg: &mut GameGirl, ui: &mut Ui, app: &mut App, ctx: &Context) {
     )));
 }
 
-/// Window showing 32x32 tile map of background and window.
-fn bg_map_viewer(gg: &mut GameGirl, ui: &mut Ui, app: &mut App, ctx: &Context) {
-    fn render_tiles(
-        ctx: &Context,
-        gg: &GameGirl,
-        app: &mut App,
-        window: bool,
-        id: usize,
-    ) -> TextureId {
-        let mut buf = make_buffer(32, 32);
-        for tile_idx_addr in 0..(32 * 32) {
-            let data_addr = Ppu::bg_idx_tile_data_addr(gg, window, tile_idx_addr);
-            draw_tile(
-                gg,
-                &mut buf,
-                (tile_idx_addr % 0x20).u8(),
-                (tile_idx_addr / 0x20).u8(),
-                data_addr,
-            );
-        }
-        upload_texture(ctx, 32, 32, app, id, buf, TextureOptions::NEAREST)
-    }
+/// Window showing APU state.
+fn apu_viewer(gg: &mut GameGirl, ui: &mut Ui, _: &mut App, _: &Context) {
+    super::psg_viewer(gg, ui);
+}
+
+/// Window showing named IO registers.
+fn io_registers(gg: &mut GameGirl, ui: &mut Ui, _: &mut App, _: &Context) {
+    super::io_registers(gg, ui);
+}
+
+/// Window showing the state of all interrupts.
+fn interrupts(gg: &mut GameGirl, ui: &mut Ui, _: &mut App, _: &Context) {
+    super::interrupt_state(gg, ui);
+}
+
+/// Window showing an editable table of all sprites.
+fn sprites(gg: &mut GameGirl, ui: &mut Ui, _: &mut App, _: &Context) {
+    super::sprite_table(gg, ui);
+}
 
-    let bg_id = render_tiles(ctx, gg, app, false, BG_TEX);
-    let win_id = render_tiles(ctx, gg, app, true, WIN_TEX);
+/// Window showing 32x32 tile map of background and window, with the area
+/// currently on screen outlined.
+fn bg_map_viewer(gg: &mut GameGirl, ui: &mut Ui, app: &mut App, _: &Context) {
+    super::layer_toggles(gg, ui);
+    ui.separator();
 
     ui.horizontal(|ui| {
-        ui.vertical(|ui| {
-            ui.label("Background Map");
-            ui.image(Into::<SizedTexture>::into((
-                bg_id,
-                vec2(32. * 16., 32. * 16.),
-            )));
-        });
+        ui.vertical(|ui| super::tile_map_view(gg, 0, BG_TEX, ui, app));
         ui.separator();
-        ui.vertical(|ui| {
-            ui.label("Window Map");
-            ui.image(Into::<SizedTexture>::into((
-                win_id,
-                vec2(32. * 16., 32. * 16.),
-            )));
-        });
+        ui.vertical(|ui| super::tile_map_view(gg, 1, WIN_TEX, ui, app));
     });
 }
 
@@ -12,14 +12,22 @@ mod nds;
 // #[cfg(not(target_arch = "wasm32"))]
 // mod psx;
 
-use std::any::Any;
+use std::{any::Any, collections::HashMap};
 
 use common::{
-    common::debugger::{Breakpoint, Debugger, Severity},
-    Core,
+    common::{
+        audio::{ChannelMask, PsgChannelState},
+        debugger::{Breakpoint, Debugger, Severity, Watchpoint, Width},
+    },
+    numutil::NumExt,
+    Common, Core, IoRegister, Pointer,
 };
-use eframe::egui::{
-    self, Align, Color32, ComboBox, Context, Layout, RichText, ScrollArea, TextEdit, Ui,
+use eframe::{
+    egui::{
+        self, load::SizedTexture, vec2, Align, Color32, ColorImage, ComboBox, Context, DragValue,
+        Id, ImageData, Layout, Rect, RichText, ScrollArea, Stroke, TextEdit, TextureOptions, Ui,
+    },
+    epaint::ImageDelta,
 };
 use gamegirl::{gga::GameGirlAdv, ggc::GameGirl, nds::Nds};
 
@@ -28,6 +36,34 @@ use crate::app::{App, GuiStyle};
 type DbgFn<T> = fn(&mut T, &mut Ui, &mut App, &Context);
 type Windows<T> = &'static [(&'static str, DbgFn<T>)];
 
+/// Layout of all debugger windows, persisted as part of the app state.
+#[derive(Default, serde::Deserialize, serde::Serialize)]
+pub struct DebugLayout {
+    /// Layout of each window, keyed by window title.
+    windows: HashMap<String, WindowLayout>,
+    /// Incremented on every layout reset. Used as part of the window IDs,
+    /// so that egui forgets its own stored positions and sizes.
+    generation: u32,
+}
+
+impl DebugLayout {
+    /// Close all windows and return them to their default position and size.
+    pub fn reset(&mut self) {
+        self.windows.clear();
+        self.generation = self.generation.wrapping_add(1);
+    }
+}
+
+/// Layout of a single debugger window.
+#[derive(Copy, Clone, Default, serde::Deserialize, serde::Serialize)]
+struct WindowLayout {
+    /// If the window is open.
+    open: bool,
+    /// Position of the window and size of its contents, if it was shown
+    /// before.
+    rect: Option<Rect>,
+}
+
 pub fn menu(app: &mut App, ui: &mut Ui) {
     let lock = app.core.clone();
     let mut core = lock.lock().unwrap();
@@ -39,6 +75,21 @@ pub fn menu(app: &mut App, ui: &mut Ui) {
     // #[cfg(not(target_arch = "wasm32"))]
     // maybe_system::<gamegirl::psx::PlayStation>(core, |_| psx::ui_menu(app,
     // ui));
+
+    ui.separator();
+    if ui.button("Reset Layout").clicked() {
+        app.state.debug_layout.reset();
+        ui.close_menu();
+    }
+}
+
+/// Menu button that toggles the debugger window with the given title.
+fn window_button(app: &mut App, ui: &mut Ui, title: &str) {
+    if ui.button(title).clicked() {
+        let layout = app.state.debug_layout.windows.entry(title.to_string());
+        let layout = layout.or_default();
+        layout.open = !layout.open;
+    }
 }
 
 pub fn render(app: &mut App, ctx: &Context) {
@@ -56,11 +107,18 @@ pub fn render(app: &mut App, ctx: &Context) {
 }
 
 fn render_inner<T: Core>(windows: Windows<T>, core: &mut T, app: &mut App, ctx: &Context) {
-    let mut states = app.debugger_window_states.clone();
-    for ((name, runner), state) in windows.iter().zip(states.iter_mut()) {
-        make_window(app, ctx, name, state, core, *runner);
+    for (name, runner) in windows {
+        let Some(mut layout) = app.state.debug_layout.windows.get(*name).copied() else {
+            continue;
+        };
+        if layout.open {
+            make_window(app, ctx, name, &mut layout, core, *runner);
+            app.state
+                .debug_layout
+                .windows
+                .insert(name.to_string(), layout);
+        }
     }
-    app.debugger_window_states = states;
 }
 
 fn maybe_system<T: Core + 'static>(core: &mut dyn Any, mut apply: impl FnMut(&mut T)) {
@@ -73,37 +131,62 @@ fn make_window<T>(
     app: &mut App,
     ctx: &Context,
     title: &str,
-    open: &mut bool,
+    layout: &mut WindowLayout,
     core: &mut T,
     content: DbgFn<T>,
 ) {
+    let generation = app.state.debug_layout.generation;
     match app.state.options.gui_style {
         GuiStyle::OnTop | GuiStyle::AllWindows => {
-            egui::Window::new(title)
-                .open(open)
-                .show(ctx, |ui| content(core, ui, app, ctx));
+            let mut window = egui::Window::new(title)
+                .id(Id::new(title).with(generation))
+                .open(&mut layout.open);
+            if let Some(rect) = layout.rect {
+                window = window.default_rect(rect);
+            }
+            let mut size = None;
+            let response = window.show(ctx, |ui| {
+                size = Some(ui.max_rect().size());
+                content(core, ui, app, ctx)
+            });
+            // Position of the window frame, but size of only the contents,
+            // which is what `default_rect` expects
+            if let (Some(response), Some(size)) = (response, size) {
+                layout.rect = Some(Rect::from_min_size(response.response.rect.min, size));
+            }
         }
         GuiStyle::MultiWindow => {
-            if *open {
-                ctx.show_viewport_immediate(
-                    egui::ViewportId::from_hash_of(title),
-                    egui::ViewportBuilder::default().with_title(title),
-                    |ctx, _| {
-                        egui::CentralPanel::default().show(ctx, |ui| content(core, ui, app, ctx));
-                        *open &= !ctx.input(|i| i.viewport().close_requested());
-                    },
-                )
+            let mut builder = egui::ViewportBuilder::default().with_title(title);
+            if let Some(rect) = layout.rect {
+                builder = builder.with_position(rect.min).with_inner_size(rect.size());
             }
+            ctx.show_viewport_immediate(
+                egui::ViewportId::from_hash_of((title, generation)),
+                builder,
+                |ctx, _| {
+                    egui::CentralPanel::default().show(ctx, |ui| content(core, ui, app, ctx));
+                    let viewport = ctx.input(|i| i.viewport().clone());
+                    layout.rect = viewport.inner_rect.or(layout.rect);
+                    layout.open &= !viewport.close_requested();
+                },
+            )
         }
     }
 }
 
-fn debugger_footer(dbg: &mut Debugger, ui: &mut Ui) {
+fn debugger_footer(c: &mut Common, ui: &mut Ui, channels: &[&str]) {
+    let dbg = &mut c.debugger;
     ui.add_space(10.0);
     inst_dump(ui, dbg);
     ui.add_space(10.0);
     breakpoints(dbg, ui);
     ui.add_space(10.0);
+    watchpoints(dbg, ui);
+    if !channels.is_empty() {
+        ui.add_space(10.0);
+        audio_channels(&mut c.options.channel_mask, ui, channels);
+    }
+    ui.add_space(10.0);
     event_log(dbg, ui);
 }
 
@@ -128,6 +211,19 @@ fn inst_dump(ui: &mut Ui, debugger: &mut Debugger) {
             }
         });
     });
+
+    ui.horizontal(|ui| {
+        let mut enabled = debugger.trace_range.is_some();
+        if ui.checkbox(&mut enabled, "Only trace PC range").changed() {
+            debugger.trace_range = enabled.then_some((0, Pointer::MAX));
+        }
+        if let Some((start, end)) = debugger.trace_range.as_mut() {
+            ui.label("0x");
+            ui.add(DragValue::new(start).hexadecimal(8, false, true));
+            ui.label("- 0x");
+            ui.add(DragValue::new(end).hexadecimal(8, false, true));
+        }
+    });
 }
 
 fn breakpoints(dbg: &mut Debugger, ui: &mut Ui) {
@@ -175,6 +271,377 @@ fn breakpoints(dbg: &mut Debugger, ui: &mut Ui) {
     });
 }
 
+fn watchpoints(dbg: &mut Debugger, ui: &mut Ui) {
+    ui.horizontal(|ui| {
+        ui.heading("Watchpoints");
+        ui.with_layout(Layout::right_to_left(Align::Center), |ui| {
+            if ui.button("Clear").clicked() {
+                dbg.watchpoints.clear();
+                dbg.watchpoint_hit = None;
+            }
+            if ui.button("Add").clicked() {
+                dbg.watchpoints.push(Watchpoint::default());
+            }
+        });
+    });
+    ui.indent(2413, |ui| {
+        let mut del = None;
+        for (i, wp) in dbg.watchpoints.iter_mut().enumerate() {
+            ui.horizontal(|ui| {
+                ui.label("0x");
+                if ui
+                    .add(DragValue::new(&mut wp.start).hexadecimal(8, false, true))
+                    .changed()
+                {
+                    wp.end = wp.end.max(wp.start);
+                }
+                ui.label("- 0x");
+                ui.add(DragValue::new(&mut wp.end).hexadecimal(8, false, true));
+                ui.checkbox(&mut wp.read, "Read");
+                ui.checkbox(&mut wp.write, "Write");
+
+                let mut has_value = wp.value.is_some();
+                if ui.checkbox(&mut has_value, "Value").changed() {
+                    wp.value = has_value.then_some(0);
+                }
+                if let Some(value) = wp.value.as_mut() {
+                    ui.add(DragValue::new(value).hexadecimal(1, false, true));
+                }
+
+                if let Some(hit) = dbg.watchpoint_hit.filter(|h| h.index == i) {
+                    let kind = if hit.write { "Write" } else { "Read" };
+                    ui.colored_label(
+                        Color32::RED,
+                        format!("{kind} of 0x{:X} at 0x{:X}", hit.value, hit.addr),
+                    );
+                }
+
+                ui.with_layout(Layout::right_to_left(Align::Center), |ui| {
+                    if ui.button("Delete").clicked() {
+                        del = Some(i);
+                    }
+                });
+            });
+        }
+
+        if let Some(i) = del {
+            dbg.watchpoints.remove(i);
+            dbg.watchpoint_hit = None;
+        }
+    });
+}
+
+/// Mute and solo controls for the core's audio channels.
+/// These are kept across savestate loads and rewinding.
+fn audio_channels(mask: &mut ChannelMask, ui: &mut Ui, channels: &[&str]) {
+    ui.horizontal(|ui| {
+        ui.heading("Audio Channels");
+        ui.with_layout(Layout::right_to_left(Align::Center), |ui| {
+            if ui.button("Reset").clicked() {
+                *mask = ChannelMask::default();
+            }
+        });
+    });
+    if mask.solo.is_some() {
+        ui.label("A channel is soloed, muting is ignored.");
+    }
+
+    egui::Grid::new("audio_channels").show(ui, |ui| {
+        for (i, name) in channels.iter().enumerate() {
+            let ch = i as u8;
+            ui.label(*name);
+            let mut muted = mask.muted.is_bit(ch.u16());
+            if ui.checkbox(&mut muted, "Mute").changed() {
+                mask.set_muted(ch, muted);
+            }
+            if ui.selectable_label(mask.solo == Some(ch), "Solo").clicked() {
+                mask.toggle_solo(ch);
+            }
+            ui.end_row();
+        }
+    });
+}
+
+/// Register and channel state of the core's PSG.
+fn psg_viewer(core: &dyn Core, ui: &mut Ui) {
+    let Some(state) = core.psg_state() else {
+        ui.label("This system has no PSG.");
+        return;
+    };
+
+    ui.label(format!("Power: {}", state.power));
+    ui.label(format!(
+        "Frame Sequencer Position: {}",
+        state.sequencer_position
+    ));
+    ui.add_space(10.0);
+
+    egui::Grid::new("psg_channels")
+        .striped(true)
+        .show(ui, |ui| {
+            for name in [
+                "",
+                "Enabled",
+                "DAC",
+                "Length",
+                "Frequency",
+                "Volume",
+                "Output",
+            ] {
+                ui.strong(name);
+            }
+            ui.end_row();
+
+            for (name, ch) in PsgChannelState::NAMES.iter().zip(state.channels.iter()) {
+                ui.label(*name);
+                ui.label(if ch.enabled { "Yes" } else { "No" });
+                ui.label(if ch.dac_enabled { "On" } else { "Off" });
+                if ch.length_enabled {
+                    ui.monospace(format!("{:3}", ch.length_counter));
+                } else {
+                    ui.label("-");
+                }
+                ui.monospace(format!("{:03X}", ch.frequency));
+                ui.monospace(format!("{:2}", ch.volume));
+                ui.monospace(format!("{:2}", ch.output));
+                ui.end_row();
+            }
+        });
+    ui.add_space(10.0);
+
+    ui.collapsing("Registers", |ui| {
+        for (addr, value) in &state.registers {
+            ui.monospace(format!("{addr:08X}: {value:02X}"));
+        }
+    });
+}
+
+/// Hex viewer for one of the core's memory regions, selected with a combo box.
+/// Values are read with [Core::get_memory], so reads with side effects
+/// should be avoided by the core there.
+fn memory_viewer(core: &mut dyn Core, ui: &mut Ui) {
+    let regions = core.memory_regions();
+    if regions.is_empty() {
+        ui.label("(This core does not support viewing memory)");
+        return;
+    }
+    let id = Id::new("memory_viewer_region");
+    let mut selected = ui.data(|d| d.get_temp::<usize>(id)).unwrap_or(0);
+    selected = selected.min(regions.len() - 1);
+
+    ComboBox::from_label("Region")
+        .selected_text(regions[selected].name)
+        .show_ui(ui, |ui| {
+            for (i, region) in regions.iter().enumerate() {
+                let label = format!(
+                    "{} ({:08X}-{:08X})",
+                    region.name,
+                    region.start,
+                    region.end()
+                );
+                ui.selectable_value(&mut selected, i, label);
+            }
+        });
+    ui.data_mut(|d| d.insert_temp(id, selected));
+    let region = regions[selected];
+    if !region.writable {
+        ui.label("(Read-only)");
+    }
+    ui.separator();
+
+    ui.horizontal(|ui| {
+        ui.monospace("            0  1  2  3  4  5  6  7  8  9  A  B  C  D  E  F");
+    });
+    let row_height = ui.text_style_height(&egui::TextStyle::Monospace);
+    let rows = region.len.div_ceil(0x10).us();
+    ScrollArea::vertical()
+        .id_source(region.name)
+        .show_rows(ui, row_height, rows, |ui, range| {
+            let mut buf = String::with_capacity(80);
+            for row in range {
+                let row_start = region.start + row.u32() * 0x10;
+                buf.push_str(&format!("{row_start:08X} -"));
+                for addr in row_start..=(row_start + 0xF).min(region.end()) {
+                    let value = core.get_memory(addr, Width::Byte);
+                    buf.push_str(&format!(" {value:02X}"));
+                }
+                ui.monospace(&buf);
+                buf.clear();
+            }
+        });
+}
+
+/// Table of the core's named IO registers and their current values, as
+/// reported by the core. Values can be edited, which writes them like the
+/// running program would.
+fn io_registers(core: &mut dyn Core, ui: &mut Ui) {
+    ScrollArea::vertical().show(ui, |ui| {
+        egui::Grid::new("io_registers")
+            .striped(true)
+            .show(ui, |ui| {
+                for name in ["Name", "Address", "Value"] {
+                    ui.strong(name);
+                }
+                ui.end_row();
+
+                for (name, addr, mut value) in core.io_registers() {
+                    let width = core
+                        .io_register_table()
+                        .iter()
+                        .find(|reg| reg.1 == addr)
+                        .map_or(Width::Word, |reg| reg.2);
+                    ui.label(name);
+                    ui.monospace(format!("{addr:08X}"));
+                    let drag = DragValue::new(&mut value)
+                        .hexadecimal(width.size() * 2, false, true)
+                        .range(0..=width.mask());
+                    if ui.add(drag).changed() {
+                        core.set_io_register(IoRegister::Addr(addr), value);
+                    }
+                    ui.end_row();
+                }
+            });
+    });
+}
+
+/// Table of the core's sprites in OAM. Attributes can be edited,
+/// which writes them back to OAM.
+fn sprite_table(core: &mut dyn Core, ui: &mut Ui) {
+    ScrollArea::vertical().show(ui, |ui| {
+        egui::Grid::new("sprites").striped(true).show(ui, |ui| {
+            for name in [
+                "#", "X", "Y", "Tile", "X Flip", "Y Flip", "Priority", "Palette",
+            ] {
+                ui.strong(name);
+            }
+            ui.end_row();
+
+            for (idx, mut sprite) in core.sprites().into_iter().enumerate() {
+                let old = sprite;
+                ui.monospace(format!("{idx:3}"));
+                ui.add(DragValue::new(&mut sprite.x));
+                ui.add(DragValue::new(&mut sprite.y));
+                ui.add(DragValue::new(&mut sprite.tile).hexadecimal(3, false, true));
+                ui.checkbox(&mut sprite.x_flip, "");
+                ui.checkbox(&mut sprite.y_flip, "");
+                ui.add(DragValue::new(&mut sprite.priority));
+                ui.add(DragValue::new(&mut sprite.palette));
+                if sprite != old {
+                    core.set_sprite(idx, sprite);
+                }
+                ui.end_row();
+            }
+        });
+    });
+}
+
+/// The full tile map of the given layer, with the area currently shown on
+/// screen outlined. The outline is split where the screen wraps around the
+/// edges of the map. The map is uploaded to the texture with the given index.
+fn tile_map_view(core: &dyn Core, layer: usize, tex: usize, ui: &mut Ui, app: &mut App) {
+    let name = core.layers()[layer];
+    let Some(map) = core.tile_map(layer) else {
+        ui.label(format!("({name} is not a tile map in the current mode)"));
+        return;
+    };
+
+    while app.textures.len() <= tex {
+        app.textures.push(App::make_screen_texture(
+            ui.ctx(),
+            [0, 0],
+            TextureOptions::NEAREST,
+        ));
+    }
+    let pixels = map
+        .pixels
+        .iter()
+        .map(|[r, g, b, a]| Color32::from_rgba_premultiplied(*r, *g, *b, *a))
+        .collect();
+    let img = ImageDelta::full(
+        ImageData::Color(
+            ColorImage {
+                size: [map.width, map.height],
+                pixels,
+            }
+            .into(),
+        ),
+        TextureOptions::NEAREST,
+    );
+    ui.ctx().tex_manager().write().set(app.textures[tex], img);
+
+    let scale = 2.0;
+    ui.label(format!(
+        "{name}: {}x{}, scrolled to ({}, {})",
+        map.width, map.height, map.scroll[0], map.scroll[1]
+    ));
+    let size = vec2(map.width as f32, map.height as f32) * scale;
+    let response = ui.image(SizedTexture::new(app.textures[tex], size));
+    let painter = ui.painter_at(response.rect);
+    for [x, y, w, h] in map.visible_rects() {
+        let rect = Rect::from_min_size(
+            response.rect.min + vec2(x as f32, y as f32) * scale,
+            vec2(w as f32, h as f32) * scale,
+        );
+        painter.rect_stroke(rect, 0.0, Stroke::new(2.0_f32, Color32::RED));
+    }
+}
+
+/// Table of the core's interrupt sources, with IME and CPU state.
+/// Sources that are requested and enabled are highlighted.
+fn interrupt_state(core: &dyn Core, ui: &mut Ui) {
+    let state = core.interrupt_state();
+    ui.monospace(format!("IME: {}", state.master_enable));
+    if state.cpu_masked {
+        ui.monospace("IRQs masked by CPU");
+    }
+    if state.halted {
+        ui.monospace("CPU halted");
+    }
+    ui.separator();
+
+    egui::Grid::new("interrupts").striped(true).show(ui, |ui| {
+        for name in ["Source", "IE", "IF"] {
+            ui.strong(name);
+        }
+        ui.end_row();
+
+        for source in &state.sources {
+            let text = RichText::new(source.name).monospace();
+            if source.enabled && source.requested {
+                ui.label(text.color(Color32::GREEN));
+            } else {
+                ui.label(text);
+            }
+            ui.monospace(if source.enabled { "1" } else { "0" });
+            ui.monospace(if source.requested { "1" } else { "0" });
+            ui.end_row();
+        }
+    });
+
+    let pending = state.pending().map(|s| s.name).collect::<Vec<_>>();
+    ui.separator();
+    if pending.is_empty() {
+        ui.label("No interrupts pending.");
+    } else {
+        ui.label(format!("Pending: {}", pending.join(", ")));
+    }
+}
+
+/// Checkboxes for showing/hiding the core's graphics layers.
+fn layer_toggles(core: &mut dyn Core, ui: &mut Ui) {
+    let mut hidden = core.hidden_layers();
+    ui.horizontal(|ui| {
+        ui.label("Show layers:");
+        for (i, name) in core.layers().iter().enumerate() {
+            let mut shown = !hidden.is_bit(i as u16);
+            if ui.checkbox(&mut shown, *name).changed() {
+                hidden ^= 1 << i;
+            }
+        }
+    });
+    core.set_hidden_layers(hidden);
+}
+
 fn event_log(dbg: &mut Debugger, ui: &mut Ui) {
     ui.horizontal(|ui| {
         ui.heading("Event Log");
@@ -199,6 +666,10 @@ fn event_log(dbg: &mut Debugger, ui: &mut Ui) {
             if ui.button("Clear").clicked() {
                 dbg.diagnostic_events.lock().unwrap().clear();
             }
+            ui.checkbox(&mut dbg.pause_on_invalid, "Pause on invalid")
+                .on_hover_text(
+                    "Pause and log the CPU state when the system hits an unknown opcode or a prohibited PPU mode.",
+                );
         });
     });
 
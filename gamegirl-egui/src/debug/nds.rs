@@ -10,20 +10,22 @@ use common::Core;
 use eframe::egui::{Context, Label, RichText, Ui};
 use gamegirl::nds::{Nds, NdsCpu};
 
-use super::Windows;
+use super::{window_button, Windows};
 use crate::{App, Colour};
 
 pub fn ui_menu(app: &mut App, ui: &mut eframe::egui::Ui) {
-    app.debugger_window_states[0] ^= ui.button("Debugger ARM9").clicked();
-    app.debugger_window_states[1] ^= ui.button("Debugger ARM7").clicked();
-    app.debugger_window_states[2] ^= ui.button("Cartridge Viewer").clicked();
+    window_button(app, ui, "Debugger ARM9");
+    window_button(app, ui, "Debugger ARM7");
+    window_button(app, ui, "Memory Viewer");
+    window_button(app, ui, "Cartridge Viewer");
 }
 
 pub fn get_windows() -> Windows<Nds> {
     &[
         ("Debugger ARM9", debugger9),
         ("Debugger ARM7", debugger7),
-        ("Cartridge", cart_info),
+        ("Memory Viewer", memory),
+        ("Cartridge Viewer", cart_info),
     ]
 }
 
@@ -36,6 +38,11 @@ fn debugger9(ds: &mut Nds, ui: &mut Ui, a: &mut App, c: &Context) {
     debugger(ds, ui, a, c);
 }
 
+/// Memory viewer for the address spaces of both CPUs.
+fn memory(ds: &mut Nds, ui: &mut Ui, _: &mut App, _: &Context) {
+    super::memory_viewer(ds, ui);
+}
+
 /// Debugger window with instruction view, stack inspection and register
 /// inspection. Allows for inst-by-inst advancing.
 fn debugger(ds: &mut impl NdsCpu, ui: &mut Ui, _: &mut App, _: &Context) {
@@ -122,7 +129,7 @@ fn debugger(ds: &mut impl NdsCpu, ui: &mut Ui, _: &mut App, _: &Context) {
         }
     });
 
-    super::debugger_footer(&mut ds.c.debugger, ui);
+    super::debugger_footer(&mut ds.c, ui, &[]);
 }
 
 /// Window showing information about the loaded ROM/cart.
@@ -10,20 +10,20 @@ use common::{components::debugger::Breakpoint, Core};
 use eframe::egui::{Context, Label, RichText, TextEdit, Ui};
 use gamegirl::psx::PlayStation;
 
-use super::Windows;
+use super::{window_button, Windows};
 use crate::{App, Colour};
 
 pub fn ui_menu(app: &mut App, ui: &mut eframe::egui::Ui) {
-    app.debugger_window_states[0] ^= ui.button("Debugger").clicked();
-    app.debugger_window_states[1] ^= ui.button("Breakpoints").clicked();
-    app.debugger_window_states[2] ^= ui.button("ISO Viewer").clicked();
+    window_button(app, ui, "Debugger");
+    window_button(app, ui, "Breakpoints");
+    window_button(app, ui, "ISO Viewer");
 }
 
 pub fn get_windows() -> Windows<PlayStation> {
     &[
         ("Debugger", debugger),
         ("Breakpoints", breakpoints),
-        ("ISO", cart_info),
+        ("ISO Viewer", cart_info),
     ]
 }
 
@@ -0,0 +1,121 @@
+// Copyright (c) 2024 Leela Aurelia, git@elia.garden
+//
+// Unless otherwise noted, this file is released and thus subject to the
+// terms of the Mozilla Public License Version 2.0 (MPL-2.0) or the
+// GNU General Public License Version 3 (GPL-3).
+// If a copy of these licenses was not distributed with this file, you can
+// obtain them at https://mozilla.org/MPL/2.0/ and http://www.gnu.org/licenses/.
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use common::{Colour, Core};
+
+/// Interval at which the emulation thread advances the core.
+const TICK: Duration = Duration::from_micros(16_667);
+/// Longest time the thread advances by in a single tick, to prevent
+/// the core from trying to catch up after a stall.
+const MAX_TICK: Duration = Duration::from_millis(50);
+
+/// Harness for running the core on a separate thread at a fixed rate,
+/// instead of inside the UI's update loop. This decouples emulation from
+/// egui's repaint timing, which reduces frame pacing jitter.
+/// The UI takes the most recent frame from a buffer shared with the thread.
+pub struct EmuThread {
+    /// The most recent frame produced by the core, if the UI did not take it
+    /// yet. Older frames get replaced.
+    frame: Arc<Mutex<Option<Vec<Colour>>>>,
+    /// If the thread should currently advance the core. Cleared while the
+    /// UI thread drives the core itself, like when rewinding.
+    active: Arc<AtomicBool>,
+    /// Set to stop the thread.
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl EmuThread {
+    /// Start running the given core on a new thread.
+    pub fn start(core: Arc<Mutex<Box<dyn Core>>>) -> Self {
+        let frame = Arc::new(Mutex::new(None));
+        let active = Arc::new(AtomicBool::new(true));
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let handle = {
+            let frame = frame.clone();
+            let active = active.clone();
+            let stop = stop.clone();
+            thread::spawn(move || Self::run(&core, &frame, &active, &stop))
+        };
+
+        Self {
+            frame,
+            active,
+            stop,
+            handle: Some(handle),
+        }
+    }
+
+    /// Take the most recent frame, if a new one was produced since the last
+    /// call.
+    pub fn take_frame(&self) -> Option<Vec<Colour>> {
+        self.frame.lock().unwrap().take()
+    }
+
+    /// Set if the thread should advance the core.
+    pub fn set_active(&self, active: bool) {
+        self.active.store(active, Ordering::Relaxed);
+    }
+
+    fn run(
+        core: &Mutex<Box<dyn Core>>,
+        frame: &Mutex<Option<Vec<Colour>>>,
+        active: &AtomicBool,
+        stop: &AtomicBool,
+    ) {
+        let mut last = Instant::now();
+        let mut next = last + TICK;
+        while !stop.load(Ordering::Relaxed) {
+            let now = Instant::now();
+            let delta = (now - last).min(MAX_TICK);
+            last = now;
+
+            if active.load(Ordering::Relaxed) {
+                let mut core = core.lock().unwrap();
+                core.advance_delta(delta.as_secs_f32());
+                let mut newest = None;
+                while let Some(next) = core.c_mut().video_buffer.pop() {
+                    newest = Some(next);
+                }
+                drop(core);
+                if newest.is_some() {
+                    *frame.lock().unwrap() = newest;
+                }
+            }
+
+            // Sleep until the next tick. If we fell behind, start over from
+            // now instead of running several ticks back to back.
+            let now = Instant::now();
+            if next > now {
+                thread::sleep(next - now);
+                next += TICK;
+            } else {
+                next = now + TICK;
+            }
+        }
+    }
+}
+
+impl Drop for EmuThread {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            handle.join().ok();
+        }
+    }
+}
@@ -4,7 +4,7 @@ use common::common::debugger::Width;
 use egui::{Button, Checkbox, Color32, Context, Separator, TextEdit, Ui};
 use egui_extras::{Column, TableBuilder};
 
-use crate::App;
+use crate::{input::file_dialog, App};
 
 #[derive(Default)]
 pub struct CheatEngineState {
@@ -13,6 +13,8 @@ pub struct CheatEngineState {
     pub searches: Vec<u32>,
     pub is_first: bool,
     pub typ: Width,
+    /// Address to load a memory dump to, in hex.
+    pub dump_addr: String,
 }
 
 #[derive(Clone)]
@@ -67,7 +69,7 @@ pub fn ui(app: &mut App, _ctx: &Context, ui: &mut Ui) {
                         {
                             if let Ok(value) = entry.value_text.parse::<u32>() {
                                 entry.value = value;
-                                app.core.lock().unwrap().set_memory(
+                                app.core.lock().unwrap().edit_memory(
                                     entry.address,
                                     value,
                                     app.cheat.typ,
@@ -145,4 +147,34 @@ pub fn ui(app: &mut App, _ctx: &Context, ui: &mut Ui) {
             ui.radio_value(&mut app.cheat.typ, Width::Word, "Word / 32-bit");
         });
     });
+
+    ui.add(Separator::default().spacing(10.));
+    ui.heading("Memory Dump");
+    ui.horizontal(|ui| {
+        let addr = u32::from_str_radix(app.cheat.dump_addr.trim_start_matches("0x"), 16);
+        let edit = TextEdit::singleline(&mut app.cheat.dump_addr)
+            .hint_text("Address")
+            .desired_width(80.0);
+        let edit = if addr.is_ok() {
+            edit
+        } else {
+            edit.text_color(Color32::RED)
+        };
+        ui.add(edit);
+
+        let writable = addr
+            .as_ref()
+            .is_ok_and(|addr| app.core.lock().unwrap().is_writable_memory(*addr));
+        if ui
+            .add_enabled(writable, Button::new("Load Dump..."))
+            .on_hover_text(
+                "Write a raw memory dump to memory at the given address. ROM is skipped.",
+            )
+            .clicked()
+        {
+            if let Ok(addr) = addr {
+                file_dialog::open_memory_dump(app.message_channel.0.clone(), addr);
+            }
+        }
+    });
 }
@@ -13,18 +13,25 @@ pub mod options;
 use std::{fs, time::Duration};
 
 use common::common::input::{InputReplay, ReplayState};
+#[cfg(feature = "savestates")]
+use common::common::replay::ReplayBundle;
 use eframe::{
-    egui::{self, load::SizedTexture, vec2, widgets, Context, Image, Layout, Ui, ViewportCommand},
+    egui::{
+        self, load::SizedTexture, vec2, widgets, Color32, Context, Image, Layout, Response,
+        RichText, Sense, Ui, ViewportCommand,
+    },
     emath::Align,
     epaint::Vec2,
     Frame,
 };
 use file_dialog::File;
+use log::Level;
 
 use crate::{
     app::{App, GuiStyle, Message},
     debug,
     input::file_dialog,
+    logger,
 };
 
 const DEMO_APP_URLS: &[(&str, &str, &str)] = &[
@@ -48,12 +55,14 @@ const DEMO_APP_URLS: &[(&str, &str, &str)] = &[
 /// Function signature for an app window
 type AppFn = fn(&mut App, &Context, &mut Ui);
 /// Count of GUI windows that take the App as a parameter.
-pub const APP_WINDOW_COUNT: usize = 3;
+pub const APP_WINDOW_COUNT: usize = 5;
 /// GUI windows that take the App as a parameter.
 const APP_WINDOWS: [(&str, AppFn); APP_WINDOW_COUNT] = [
     ("Options", options::options),
     ("Replays", replays),
     ("Cheat Engine", cheat::ui),
+    ("Audio Stats", audio_stats),
+    ("Log", log_viewer),
 ];
 
 pub fn draw(app: &mut App, ctx: &Context, frame: &Frame, size: [usize; 2]) {
@@ -160,6 +169,23 @@ fn navbar_content(app: &mut App, now: f64, frame: &Frame, ctx: &Context, ui: &mu
             }
             ui.close_menu();
         }
+        ui.menu_button("⇄ RetroArch", |ui| {
+            if ui.button("Export Save...").clicked() {
+                let save = { app.core.lock().unwrap().make_save() };
+                if let Some(save) = save {
+                    file_dialog::save_retroarch_save(save.title.clone(), save.to_retroarch_srm());
+                }
+                ui.close_menu();
+            }
+            if ui.button("Import Save...").clicked() {
+                file_dialog::open_retroarch_save(app.message_channel.0.clone());
+                ui.close_menu();
+            }
+        });
+        if ui.button("📷 Save Screenshot").clicked() {
+            app.screenshot();
+            ui.close_menu();
+        }
 
         let text = if app.core.lock().unwrap().c().debugger.running {
             "⏸ Pause"
@@ -196,6 +222,16 @@ fn navbar_content(app: &mut App, now: f64, frame: &Frame, ctx: &Context, ui: &mu
             ui.close_menu();
         }
 
+        if ui.button("🔊 Audio Stats").clicked() {
+            app.app_window_states[3] ^= true;
+            ui.close_menu();
+        }
+
+        if ui.button("📜 Log").clicked() {
+            app.app_window_states[4] ^= true;
+            ui.close_menu();
+        }
+
         ui.menu_button("🖴 Savestates", |ui| {
             for (i, state) in app.rewinder.save_states.iter_mut().enumerate() {
                 if ui.button(format!("↘ Save State {}", i + 1)).clicked() {
@@ -217,11 +253,17 @@ fn navbar_content(app: &mut App, now: f64, frame: &Frame, ctx: &Context, ui: &mu
             {
                 if ui.button(format!("↗ Load State {}", i + 1)).clicked() {
                     let mut core = app.core.lock().unwrap();
-                    app.rewinder.before_last_ss_load = Some(core.save_state());
-                    core.load_state(state);
-                    app.toasts
-                        .info(format!("Loaded state {}", i + 1))
-                        .set_duration(Some(Duration::from_secs(3)));
+                    let before = core.save_state();
+                    match core.load_state(state) {
+                        Ok(()) => {
+                            app.rewinder.before_last_ss_load = Some(before);
+                            app.toasts.info(format!("Loaded state {}", i + 1))
+                        }
+                        Err(err) => app
+                            .toasts
+                            .error(format!("Failed to load state {}: {err}", i + 1)),
+                    }
+                    .set_duration(Some(Duration::from_secs(3)));
                     ui.close_menu();
                 }
             }
@@ -247,13 +289,82 @@ fn navbar_content(app: &mut App, now: f64, frame: &Frame, ctx: &Context, ui: &mu
     ui.with_layout(Layout::right_to_left(Align::Center), |ui| {
         let time = frame.info().cpu_usage.unwrap_or(0.0);
         app.frame_times.add(now, time);
-        // Backwards because we're in RTL layout
-        ui.monospace(format!(
-            "{:.3}ms",
+        let frame_time = format!(
+            "Frame time: {:.3}ms",
             app.frame_times.average().unwrap_or(0.0) * 1000.0
-        ));
-        ui.label("Frame time: ");
+        );
+        let speed = match app.core.lock().unwrap().emulation_speed_percent() {
+            Some(percent) => format!("{percent:.0}%"),
+            None => "-".into(),
+        };
+        // Backwards because we're in RTL layout
+        ui.monospace(speed).on_hover_text(frame_time);
+        ui.label("Speed: ");
+        if app.state.options.show_input_latency {
+            let latency = match app.input_latency.average_ms() {
+                Some(ms) => format!("{ms:.1}ms"),
+                None => "-".into(),
+            };
+            ui.separator();
+            ui.monospace(latency);
+            ui.label("Input lag: ");
+        }
+    });
+}
+
+/// Audio buffer diagnostics, for tuning audio settings.
+fn audio_stats(app: &mut App, _ctx: &Context, ui: &mut Ui) {
+    let core = app.core.lock().unwrap();
+    let stats = core.audio_stats();
+    let sample_rate = core.c().audio_buffer.input_sr();
+    drop(core);
+
+    ui.label(format!(
+        "Buffered: {} samples ({:.1}ms)",
+        stats.buffered,
+        stats.buffered as f32 * 1000.0 / sample_rate as f32
+    ));
+    ui.label(format!("Estimated latency: {:.1}ms", stats.latency_ms));
+    ui.label(format!("Underruns: {}", stats.underruns));
+}
+
+fn log_viewer(app: &mut App, _ctx: &Context, ui: &mut Ui) {
+    ui.horizontal(|ui| {
+        egui::ComboBox::from_label("Level")
+            .selected_text(format!("{}", app.log_level))
+            .show_ui(ui, |ui| {
+                for level in [Level::Error, Level::Warn, Level::Info] {
+                    ui.selectable_value(&mut app.log_level, level, format!("{level}"));
+                }
+            });
+        logger::set_capture_level(app.log_level);
+        if ui.button("Clear").clicked() {
+            logger::clear();
+        }
     });
+    ui.separator();
+
+    egui::ScrollArea::vertical()
+        .stick_to_bottom(true)
+        .show(ui, |ui| {
+            logger::with_records(|records| {
+                for record in records.iter().filter(|r| r.level <= app.log_level) {
+                    let colour = match record.level {
+                        Level::Error => Color32::RED,
+                        Level::Warn => Color32::YELLOW,
+                        _ => ui.visuals().text_color(),
+                    };
+                    ui.label(
+                        RichText::new(format!(
+                            "[{} {}] {}",
+                            record.level, record.target, record.message
+                        ))
+                        .monospace()
+                        .color(colour),
+                    );
+                }
+            });
+        });
 }
 
 fn replays(app: &mut App, _ctx: &Context, ui: &mut Ui) {
@@ -267,12 +378,40 @@ fn replays(app: &mut App, _ctx: &Context, ui: &mut Ui) {
         (ReplayState::None, Some(file)) => {
             ui.label("Status: Not currently recording replay");
             if ui.button("Restart system and start recording").clicked() {
-                core.c_mut().input.replay = ReplayState::Recording(InputReplay::empty(file));
+                core.c_mut().input.replay =
+                    ReplayState::Recording(InputReplay::empty(file.clone()));
                 core.reset();
             }
             if ui.button("Load recording and restart").clicked() {
                 file_dialog::open_replay(app.message_channel.0.clone());
             }
+
+            #[cfg(feature = "savestates")]
+            {
+                ui.separator();
+                ui.label("Bundles contain a savestate and can reproduce a replay exactly.");
+                if ui.button("Start recording bundle").clicked() {
+                    app.replay_bundle = Some(ReplayBundle::start(&mut **core, file));
+                }
+                if ui.button("Load and verify bundle").clicked() {
+                    file_dialog::open_bundle(app.message_channel.0.clone());
+                }
+            }
+        }
+
+        #[cfg(feature = "savestates")]
+        (ReplayState::Recording(ir), _) if app.replay_bundle.is_some() => {
+            ui.label("Status: Recording replay bundle");
+            ui.label(&format!("Recorded {} states!", ir.states.len()));
+            if ui.button("End & Save Bundle").clicked() {
+                let bundle = app.replay_bundle.take().unwrap();
+                match bundle.finish(&mut **core) {
+                    Some(bundle) => file_dialog::save_bundle(bundle.serialize()),
+                    None => {
+                        app.toasts.error("Failed to finish replay bundle");
+                    }
+                }
+            }
         }
 
         (ReplayState::Recording(ir), _) => {
@@ -294,22 +433,48 @@ fn replays(app: &mut App, _ctx: &Context, ui: &mut Ui) {
 }
 
 fn game_screen(app: &App, ctx: &Context, size: [usize; 2]) {
-    match app.state.options.gui_style {
-        GuiStyle::AllWindows => {
-            egui::Window::new("Screen").show(ctx, |ui| {
+    let response = match app.state.options.gui_style {
+        GuiStyle::AllWindows => egui::Window::new("Screen")
+            .show(ctx, |ui| {
                 ui.add(make_screen_ui(app, size, ui.available_size()))
-            });
-        }
-        GuiStyle::OnTop | GuiStyle::MultiWindow => {
-            egui::CentralPanel::default().show(ctx, |ui| {
-                ui.centered_and_justified(|ui| {
-                    ui.add(make_screen_ui(app, size, ui.available_size()))
-                });
-            });
+            })
+            .and_then(|r| r.inner),
+        GuiStyle::OnTop | GuiStyle::MultiWindow => Some(
+            egui::CentralPanel::default()
+                .show(ctx, |ui| {
+                    ui.centered_and_justified(|ui| {
+                        ui.add(make_screen_ui(app, size, ui.available_size()))
+                    })
+                    .inner
+                })
+                .inner,
+        ),
+    };
+
+    if app.state.options.mouse_touch {
+        if let Some(response) = response {
+            screen_touch(app, &response, size);
         }
     }
 }
 
+/// Forward the mouse to the system as touchscreen input, mapping the
+/// position on the displayed image back to screen pixels.
+fn screen_touch(app: &App, response: &Response, size: [usize; 2]) {
+    let rect = response.rect;
+    let pos = response
+        .interact_pointer_pos()
+        .filter(|pos| response.is_pointer_button_down_on() && rect.contains(*pos))
+        .map(|pos| {
+            let rel = (pos - rect.min) / rect.size();
+            [
+                ((rel.x * size[0] as f32) as usize).min(size[0] - 1),
+                ((rel.y * size[1] as f32) as usize).min(size[1] - 1),
+            ]
+        });
+    app.core.lock().unwrap().set_touch(pos);
+}
+
 fn make_screen_ui(app: &App, size: [usize; 2], avail_size: Vec2) -> Image {
     if app.state.options.pixel_perfect {
         // Find the biggest multiple of the screen size that still fits
@@ -320,7 +485,7 @@ fn make_screen_ui(app: &App, size: [usize; 2], avail_size: Vec2) -> Image {
         }
         size -= orig_size;
 
-        egui::Image::new(Into::<SizedTexture>::into((app.textures[0], size)))
+        egui::Image::new(Into::<SizedTexture>::into((app.textures[0], size))).sense(Sense::drag())
     } else {
         egui::Image::new(Into::<SizedTexture>::into((
             app.textures[0],
@@ -328,6 +493,7 @@ fn make_screen_ui(app: &App, size: [usize; 2], avail_size: Vec2) -> Image {
         )))
         .maintain_aspect_ratio(app.state.options.preserve_aspect_ratio)
         .shrink_to_fit()
+        .sense(Sense::drag())
     }
 }
 
@@ -6,10 +6,17 @@
 // If a copy of these licenses was not distributed with this file, you can
 // obtain them at https://mozilla.org/MPL/2.0/ and http://www.gnu.org/licenses/.
 
-use common::common::{audio::AudioSampler, input::Button, options::CgbMode};
+use common::{
+    common::{
+        audio::AudioSampler,
+        input::Button,
+        options::{AccuracyProfile, CgbMode, ColourExpansion, ColourGrading, SystemVolume},
+    },
+    CoreKind,
+};
 use eframe::{
     egui,
-    egui::{vec2, CollapsingHeader, ComboBox, Context, Slider, Ui},
+    egui::{vec2, Checkbox, CollapsingHeader, ComboBox, Context, DragValue, Slider, Ui},
 };
 use egui::{Color32, RichText, Separator};
 
@@ -82,6 +89,23 @@ pub(super) fn options(app: &mut App, ctx: &Context, ui: &mut Ui) {
             });
             ui.add(Separator::default().spacing(10.));
 
+            ui.heading("Accuracy");
+            ComboBox::from_label("Accuracy profile")
+                .selected_text(format!("{}", opt.sys.accuracy))
+                .show_ui(ui, |ui| {
+                    for option in AccuracyProfile::ALL {
+                        // Picking the current profile again resets options
+                        // that were changed individually
+                        let selected = opt.sys.accuracy == *option;
+                        if ui.selectable_label(selected, format!("{option}")).clicked() {
+                            option.apply(&mut opt.sys);
+                        }
+                    }
+                })
+                .response
+                .on_hover_text("Sets all options below that trade accuracy for speed. They can still be changed individually afterwards.");
+            ui.add(Separator::default().spacing(10.));
+
             ui.heading("GameBoy");
             ComboBox::from_label("GB Color mode")
                 .selected_text(format!("{:?}", opt.sys.mode))
@@ -95,15 +119,71 @@ pub(super) fn options(app: &mut App, ctx: &Context, ui: &mut Ui) {
                 "Enable GBC colour correction",
             )
             .on_hover_text("Adjust colours to be more accurate to a real GBC screen.");
+            for (grading, label) in [
+                (&mut opt.sys.cgb_bg_grading, "GBC background colour grading"),
+                (&mut opt.sys.cgb_obj_grading, "GBC object colour grading"),
+            ] {
+                ComboBox::from_label(label)
+                    .selected_text(format!("{grading}"))
+                    .show_ui(ui, |ui| {
+                        for option in ColourGrading::ALL {
+                            ui.selectable_value(grading, *option, format!("{option}"));
+                        }
+                    });
+            }
+            ui.checkbox(&mut opt.sys.oam_dma_bus_conflict, "Emulate OAM DMA bus conflicts")
+                .on_hover_text("Reads outside of HRAM during OAM DMA return the byte being transferred, like on real hardware. Slightly slower.");
+            ui.checkbox(&mut opt.sys.gb_halt_bug, "Emulate HALT bug")
+                .on_hover_text("Emulate the hardware bug causing the CPU to read a byte twice after some HALT instructions. Some games depend on it.");
+            ui.checkbox(&mut opt.sys.gb_oam_bug, "Emulate OAM corruption bug")
+                .on_hover_text("Emulate the DMG hardware bug corrupting sprite data when accessing OAM during OAM scan. Only affects DMG mode.");
+            ui.checkbox(&mut opt.sys.gb_speed_switch_timing, "Emulate speed switch timing")
+                .on_hover_text("Stop the CPU for about 2000 cycles after switching CGB speed, like on real hardware. Some games time their intros around it.");
+            ui.checkbox(&mut opt.sys.gb_ppu_memory_locking, "Emulate PPU memory locking")
+                .on_hover_text("Block the CPU from accessing VRAM and OAM while the PPU or OAM DMA is using them, like on real hardware. Needs exact PPU timing, so it can cause glitches in some games.");
+            ui.checkbox(&mut opt.sys.gb_lcd_ghosting, "Simulate LCD ghosting")
+                .on_hover_text("Blend frames together like the slow GB LCD does. Needed for the extra shades some games and demos create by flickering.");
+            ui.checkbox(&mut opt.sys.gb_accurate_ppu, "Accurate PPU (pixel FIFO, experimental)")
+                .on_hover_text("Draw lines pixel by pixel like real hardware, so effects that change registers in the middle of a line show correctly. Slower, and still experimental.");
+            ui.checkbox(&mut opt.sys.gb_lenient_detection, "Detect GB ROMs without logo")
+                .on_hover_text("Load ROMs as GB games even if their header is missing the Nintendo logo, as long as the file extension or header checksum suggest they are. For homebrew and test ROMs. Takes effect when loading a ROM.");
             ui.add(Separator::default().spacing(10.));
 
             ui.heading("Gameboy Advance");
             ui.checkbox(&mut opt.sys.cached_interpreter, "Enable Cached Interpreter")
             .on_hover_text("Enables caching in the interpreter. Speeds up emulation at the cost of RAM usage. Also breaks breakpoints.");
+            ui.horizontal(|ui| {
+                ui.add(DragValue::new(&mut opt.sys.cached_interpreter_limit).speed(10_000));
+                ui.label("Cache limit (instructions)")
+                    .on_hover_text("The cache is cleared once it holds more instructions than this, to bound RAM usage. 0 for no limit.");
+            });
+            ui.checkbox(&mut opt.sys.waitloop_detection, "Enable Waitloop Detection")
+            .on_hover_text("Skips ahead when the CPU is waiting in a loop for an interrupt or a memory value. Disable together with caching for pure interpretation, to check if an optimization causes an issue.");
 
             #[cfg(not(target_arch = "wasm32"))]
             ui.checkbox(&mut opt.sys.threaded_ppu, "Enable Threaded Graphics")
                 .on_hover_text("Enables running the GGA PPU in a separate thread. Speeds up emulation a lot, but uses slightly more CPU and RAM and might cause some subtle graphical glitches.");
+            ComboBox::from_label("GBA colour conversion")
+                .selected_text(format!("{}", opt.sys.gba_colour_expansion))
+                .show_ui(ui, |ui| {
+                    for option in ColourExpansion::ALL {
+                        ui.selectable_value(&mut opt.sys.gba_colour_expansion, *option, format!("{option}"));
+                    }
+                })
+                .response
+                .on_hover_text("How colours are converted for display. 'LCD gamma' is darker, closer to captures of real hardware.\nLoad a ROM to apply changes to this.");
+            let simple = opt.sys.gba_colour_expansion == ColourExpansion::Simple;
+            ui.add_enabled(simple, Checkbox::new(&mut opt.sys.gba_colour_correction, "Enable GBA colour correction"))
+                .on_hover_text("Adjust colours to look like on a real GBA screen: darker and less saturated.")
+                .on_disabled_hover_text("Only available with 'Simple' colour conversion, 'LCD gamma' already simulates the screen.");
+            ui.checkbox(&mut opt.sys.gba_bios_protection, "Emulate BIOS read protection")
+                .on_hover_text("Only allow reading the BIOS from code running inside it, like on real hardware. Some games check this as anti-piracy.");
+            ui.checkbox(&mut opt.sys.save_write_latency, "Emulate save write latency")
+                .on_hover_text("Flash and EEPROM saves stay busy for a while after being written, like on real hardware. Slightly less compatible.");
+            ui.add(Slider::new(&mut opt.sys.solar_level, 0..=255).text("Solar sensor light level"))
+                .on_hover_text(
+                    "Amount of sunlight seen by games with a solar sensor, like the Boktai series.",
+                );
         }
 
         Panel::Features => {
@@ -117,24 +197,66 @@ pub(super) fn options(app: &mut App, ctx: &Context, ui: &mut Ui) {
                 "Skip System ROM / BIOS",
             )
             .on_hover_text("Skip any kind of intro the system would usually play (e.g. 'GameBoy' logo splash) and run the game immediately.");
+            ui.checkbox(&mut opt.fast_boot, "Fast boot")
+                .on_hover_text("Repeatedly press Start and A after loading a ROM to skip past the game's own intro and logo screens.");
+            if opt.fast_boot {
+                ui.horizontal(|ui| {
+                    ui.add(Slider::new(&mut opt.fast_boot_seconds, 1..=30));
+                    ui.label("Fast boot time in seconds");
+                });
+            }
+            #[cfg(not(target_arch = "wasm32"))]
+            ui.checkbox(&mut opt.threaded_emulation, "Run emulation on a separate thread")
+                .on_hover_text("Runs the system at a fixed rate on its own thread instead of in sync with the UI, for smoother frame pacing.\nRequires restart to apply changes.");
             ui.add(Separator::default().spacing(10.));
 
             ui.heading("Fast-forward");
             ui.horizontal(|ui| {
-                ui.add(Slider::new(&mut opt.fast_forward_hold_speed, 2..=10));
+                ui.add(Slider::new(&mut opt.fast_forward_hold_speed, 1.25..=10.0).step_by(0.25));
                 ui.label("Fast forward speed (Hold)");
             });
             ui.horizontal(|ui| {
-                ui.add(Slider::new(&mut opt.fast_forward_toggle_speed, 2..=10));
+                ui.add(Slider::new(&mut opt.fast_forward_toggle_speed, 1.25..=10.0).step_by(0.25));
                 ui.label("Fast forward speed (Toggle)");
             });
+            CollapsingHeader::new("Per-system Fast-forward").show(ui, |ui| {
+                ui.label("Systems without their own speeds use the speeds above.");
+                for kind in CoreKind::ALL {
+                    let mut enabled = opt.system_fast_forward.contains_key(&kind);
+                    if ui.checkbox(&mut enabled, kind.name()).changed() {
+                        if enabled {
+                            let speed = opt.fast_forward_for(None);
+                            opt.system_fast_forward.insert(kind, speed);
+                        } else {
+                            opt.system_fast_forward.remove(&kind);
+                        }
+                    }
+
+                    if let Some(speed) = opt.system_fast_forward.get_mut(&kind) {
+                        ui.horizontal(|ui| {
+                            ui.add(Slider::new(&mut speed.hold, 1.25..=10.0).step_by(0.25));
+                            ui.label("Fast forward speed (Hold)");
+                        });
+                        ui.horizontal(|ui| {
+                            ui.add(Slider::new(&mut speed.toggle, 1.25..=10.0).step_by(0.25));
+                            ui.label("Fast forward speed (Toggle)");
+                        });
+                    }
+                }
+            });
+            ui.add(Separator::default().spacing(10.));
+
+            ui.heading("Save States");
+            ui.checkbox(&mut opt.sys.compress_savestates, "Compress save states")
+                .on_hover_text("Heavily reduces rewinding memory usage and the size of save states on disk, but requires a lot of performance.\nStates are loaded regardless of this setting.\nLoad a ROM to apply changes to this.");
             ui.add(Separator::default().spacing(10.));
 
             ui.heading("Rewind");
             ui.checkbox(&mut opt.enable_rewind, "Enable Rewinding");
             if opt.enable_rewind {
-                ui.checkbox(&mut opt.sys.compress_savestates, "Compress rewind data")
-                .on_hover_text("Heavily reduces rewinding memory usage, but requires a lot of performance.\nLoad a ROM to apply changes to this.");
+                #[cfg(not(target_arch = "wasm32"))]
+                ui.checkbox(&mut opt.threaded_rewind, "Capture rewind data on a separate thread")
+                    .on_hover_text("Moves compressing and storing rewind data off the UI thread, reducing frame time.\nRequires restart to apply changes.");
                 ui.horizontal(|ui| {
                     ui.add(Slider::new(&mut opt.rewind_buffer_size, 1..=60))
                         .on_hover_text(format!(
@@ -169,6 +291,12 @@ pub(super) fn options(app: &mut App, ctx: &Context, ui: &mut Ui) {
                     ui.selectable_value(&mut opt.screen_blend, Blend::Accumulate, "Accumulate")
                         .on_hover_text("Accumulatively blend past frames. Can fix flickering in some games.");
                 });
+            if opt.screen_blend == Blend::None {
+                ui.checkbox(&mut opt.skip_identical_frames, "Skip identical frames")
+                    .on_hover_text("Skip uploading frames that are identical to the previous one, like menus or paused games.\nSaves GPU bandwidth at a small CPU cost for hashing each frame.");
+            }
+            ui.checkbox(&mut opt.show_input_latency, "Show input lag")
+                .on_hover_text("Measure the time from a button press to the first frame that changed on screen, averaged over recent presses. Only presses while the screen is not animated are measured. Does not include the latency of the display itself.");
             ui.horizontal(|ui| {
                 let mut core = app.core.lock().unwrap();
                 ui.add(Slider::new(
@@ -177,6 +305,21 @@ pub(super) fn options(app: &mut App, ctx: &Context, ui: &mut Ui) {
                 ));
                 ui.label("Frameskip");
             });
+            ui.horizontal(|ui| {
+                ui.add(Slider::new(&mut opt.sys.frame_history, 0..=600))
+                    .on_hover_text("Keep the most recent frames for debugging, 0 to disable.\nEvery frame kept uses its size in RAM. Load a ROM to apply changes to this.");
+                ui.label("Frame history");
+            });
+
+            #[cfg(not(target_arch = "wasm32"))]
+            ui.horizontal(|ui| {
+                let mut dir = opt.screenshot_dir.to_string_lossy().to_string();
+                if ui.text_edit_singleline(&mut dir).changed() {
+                    opt.screenshot_dir = dir.into();
+                }
+                ui.label("Screenshot directory")
+                    .on_hover_text("Where screenshots taken with the hotkey are saved. Relative paths are relative to the working directory.");
+            });
 
             ui.checkbox(&mut opt.pixel_perfect, "Pixel perfect scaling")
             .on_hover_text("Will only scale the screen to integer multiples, preventing some scaling artifacts at the cost of screen size.\nMainly applicable with 'Nearest' filtering.");
@@ -211,24 +354,55 @@ pub(super) fn options(app: &mut App, ctx: &Context, ui: &mut Ui) {
 
         Panel::Audio => {
             ui.horizontal(|ui| {
-                if ui
-                    .add(Slider::new(&mut opt.sys.volume, 0.0..=1.0))
-                    .changed()
-                {
-                    app.core.lock().unwrap().c_mut().config.volume = opt.sys.volume;
-                }
+                ui.add(Slider::new(&mut opt.sys.volume, 0.0..=1.0));
                 ui.label("Volume");
             });
             ui.horizontal(|ui| {
-                if ui
-                    .add(Slider::new(&mut opt.sys.volume_ff, 0.0..=1.0))
-                    .changed()
-                {
-                    app.core.lock().unwrap().c_mut().config.volume_ff = opt.sys.volume_ff;
-                }
+                ui.add(Slider::new(&mut opt.sys.volume_ff, 0.0..=1.0));
                 ui.label("Volume during Fast-Forward");
             });
 
+            CollapsingHeader::new("Per-system Volume").show(ui, |ui| {
+                ui.label("Systems without their own volume use the volume above.");
+                for kind in CoreKind::ALL {
+                    let mut enabled = opt.sys.system_volume.contains_key(&kind);
+                    if ui.checkbox(&mut enabled, kind.name()).changed() {
+                        if enabled {
+                            opt.sys.system_volume.insert(
+                                kind,
+                                SystemVolume {
+                                    volume: opt.sys.volume,
+                                    volume_ff: opt.sys.volume_ff,
+                                },
+                            );
+                        } else {
+                            opt.sys.system_volume.remove(&kind);
+                        }
+                    }
+
+                    if let Some(vol) = opt.sys.system_volume.get_mut(&kind) {
+                        ui.horizontal(|ui| {
+                            ui.add(Slider::new(&mut vol.volume, 0.0..=1.0));
+                            ui.label("Volume");
+                        });
+                        ui.horizontal(|ui| {
+                            ui.add(Slider::new(&mut vol.volume_ff, 0.0..=1.0));
+                            ui.label("Volume during Fast-Forward");
+                        });
+                    }
+                }
+
+                ui.separator();
+                ui.horizontal(|ui| {
+                    ui.add(Slider::new(&mut opt.sys.volume_min, 0.0..=1.0));
+                    ui.label("Minimum Volume");
+                });
+                ui.horizontal(|ui| {
+                    ui.add(Slider::new(&mut opt.sys.volume_max, 0.0..=1.0));
+                    ui.label("Maximum Volume");
+                });
+            });
+
             ComboBox::from_label("Output Sample Rate")
                 .selected_text(format!("{:.1}kHz", opt.sys.sample_rate / 1000))
                 .show_ui(ui, |ui| {
@@ -267,23 +441,67 @@ pub(super) fn options(app: &mut App, ctx: &Context, ui: &mut Ui) {
         }
 
         Panel::Input => {
+            ui.checkbox(&mut opt.mouse_touch, "Use mouse as touchscreen")
+                .on_hover_text("Clicking on the bottom screen of the DS touches it.");
+            ui.add(Slider::new(&mut opt.rumble_intensity, 0.0..=1.0).text("Rumble intensity"))
+                .on_hover_text("Strength of controller vibration for games with a rumble motor, like Pokémon Pinball. 0 disables it.");
+            ui.add(
+                Slider::new(&mut opt.rumble_min_pulse_ms, 0..=200)
+                    .text("Minimum rumble duration (ms)"),
+            )
+            .on_hover_text("Games often turn the motor on very briefly; this keeps the controller vibrating long enough to be felt.");
+            ui.add(
+                Slider::new(&mut opt.controller_deadzone, 0.05..=0.95)
+                    .text("Analog stick deadzone"),
+            )
+            .on_hover_text("How far analog sticks need to be moved before they press a direction.");
+            ui.separator();
+
+            ui.horizontal(|ui| {
+                let editing = opt.input.editing;
+                ComboBox::from_label("Controller Profile")
+                    .selected_text(editing.map(CoreKind::name).unwrap_or("Shared"))
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(&mut opt.input.editing, None, "Shared");
+                        for kind in CoreKind::ALL {
+                            ui.selectable_value(&mut opt.input.editing, Some(kind), kind.name());
+                        }
+                    });
+                if let Some(kind) = editing {
+                    if opt.input.has_profile(kind) && ui.button("Reset to shared").clicked() {
+                        opt.input.clear_profile(kind);
+                    }
+                }
+            });
+            if opt.input.editing.is_some() {
+                ui.label("Buttons not bound in this profile use the shared profile.");
+            }
+            ui.separator();
+
             ui.horizontal(|ui| {
+                let buttons = opt
+                    .input
+                    .editing
+                    .map(CoreKind::buttons)
+                    .unwrap_or(&Button::BUTTONS);
                 input_section(
                     ui,
                     opt,
-                    Button::BUTTONS
+                    buttons
                         .iter()
                         .map(|btn| (format!("{:?}", btn), InputAction::Button(*btn))),
                 );
-                ui.separator();
-                input_section(
-                    ui,
-                    opt,
-                    HOTKEYS
-                        .iter()
-                        .enumerate()
-                        .map(|(i, (n, _))| (n.to_string(), InputAction::Hotkey(i as u8))),
-                );
+                if opt.input.editing.is_none() {
+                    ui.separator();
+                    input_section(
+                        ui,
+                        opt,
+                        HOTKEYS
+                            .iter()
+                            .enumerate()
+                            .map(|(i, (n, _))| (n.to_string(), InputAction::Hotkey(i as u8))),
+                    );
+                }
             });
         }
 
@@ -311,6 +529,12 @@ pub(super) fn options(app: &mut App, ctx: &Context, ui: &mut Ui) {
             }
         }
     }
+
+    // Cheap enough to do every frame, and catches every widget
+    app.core
+        .lock()
+        .unwrap()
+        .apply_config(&app.state.options.sys);
 }
 
 fn input_section(
@@ -61,6 +61,27 @@ pub fn open_replay(sender: mpsc::Sender<Message>) {
     });
 }
 
+/// Open a file dialog. This operation is async and returns immediately,
+/// sending a [Message] once the user has picked a file.
+#[cfg(feature = "savestates")]
+pub fn open_bundle(sender: mpsc::Sender<Message>) {
+    let task = rfd::AsyncFileDialog::new()
+        .set_title("Open Replay Bundle")
+        .add_filter("GameGirl replay bundles", &["rpb"])
+        .pick_file();
+
+    execute(async move {
+        let file = task.await;
+        if let Some(file) = file {
+            let path = path(&file);
+            let content = file.read().await;
+            sender
+                .send(Message::BundleOpen(File { content, path }))
+                .ok();
+        }
+    });
+}
+
 /// Open a file dialog. This operation is async and returns immediately,
 /// sending a [Message] once the user has picked a file.
 pub fn open_bios(sender: mpsc::Sender<Message>, console_id: String) {
@@ -98,6 +119,98 @@ pub fn save_replay(content: String) {
     });
 }
 
+/// Open a file save dialog. This operation is async and returns immediately.
+#[cfg(feature = "savestates")]
+pub fn save_bundle(content: Vec<u8>) {
+    let task = rfd::AsyncFileDialog::new()
+        .set_title("Save Replay Bundle")
+        .add_filter("GameGirl replay bundles", &["rpb"])
+        .save_file();
+
+    execute(async move {
+        let file = task.await;
+        if let Some(file) = file {
+            file.write(&content).await.unwrap();
+        }
+    });
+}
+
+/// Open a file dialog. This operation is async and returns immediately,
+/// sending a [Message] once the user has picked a file.
+pub fn open_retroarch_save(sender: mpsc::Sender<Message>) {
+    let task = rfd::AsyncFileDialog::new()
+        .set_title("Import RetroArch Save")
+        .add_filter("RetroArch saves", &["srm"])
+        .pick_file();
+
+    execute(async move {
+        let file = task.await;
+        if let Some(file) = file {
+            let path = path(&file);
+            let content = file.read().await;
+            sender
+                .send(Message::RetroArchSaveOpen(File { content, path }))
+                .ok();
+        }
+    });
+}
+
+/// Open a file dialog. This operation is async and returns immediately,
+/// sending a [Message] once the user has picked a file.
+pub fn open_memory_dump(sender: mpsc::Sender<Message>, addr: u32) {
+    let task = rfd::AsyncFileDialog::new()
+        .set_title("Load Memory Dump")
+        .pick_file();
+
+    execute(async move {
+        let file = task.await;
+        if let Some(file) = file {
+            let path = path(&file);
+            let content = file.read().await;
+            sender
+                .send(Message::MemoryDumpOpen {
+                    file: File { content, path },
+                    addr,
+                })
+                .ok();
+        }
+    });
+}
+
+/// Open a file save dialog. This operation is async and returns immediately.
+pub fn save_retroarch_save(name: String, content: Vec<u8>) {
+    let task = rfd::AsyncFileDialog::new()
+        .set_title("Export RetroArch Save")
+        .set_file_name(format!("{name}.srm"))
+        .add_filter("RetroArch saves", &["srm"])
+        .save_file();
+
+    execute(async move {
+        let file = task.await;
+        if let Some(file) = file {
+            file.write(&content).await.unwrap();
+        }
+    });
+}
+
+/// Open a file save dialog. This operation is async and returns immediately.
+/// Only used on WASM, where screenshots cannot be put into a directory.
+#[cfg(target_arch = "wasm32")]
+pub fn save_screenshot(name: String, content: Vec<u8>) {
+    let task = rfd::AsyncFileDialog::new()
+        .set_title("Save Screenshot")
+        .set_file_name(name)
+        .add_filter("PNG images", &["png"])
+        .save_file();
+
+    execute(async move {
+        let file = task.await;
+        if let Some(file) = file {
+            file.write(&content).await.unwrap();
+        }
+    });
+}
+
 /// Open a file save dialog. This operation is async and returns immediately.
 pub fn save_gamesave(name: String, content: Vec<u8>) {
     let task = rfd::AsyncFileDialog::new()
@@ -21,26 +21,14 @@ pub const HOTKEYS: &[(&str, HotkeyFn)] = &[
     ("Save", |a, p| pressed(a, p, |app| app.save_game())),
     ("Fast Forward (Hold)", |app, pressed| {
         let mut core = app.core.lock().unwrap();
-        let c = core.c_mut();
-        if pressed {
-            c.options.speed_multiplier = app.state.options.fast_forward_hold_speed;
-        } else {
-            c.options.speed_multiplier = 1;
-        }
-        c.video_buffer.frameskip = c.options.speed_multiplier - 1;
+        let speed = app.state.options.fast_forward_for(core.kind()).hold;
+        core.c_mut().set_fast_forward_hold(pressed.then_some(speed));
     }),
     ("Fast Forward (Toggle)", |a, p| {
         pressed(a, p, |app| {
             let mut core = app.core.lock().unwrap();
-            let c = core.c_mut();
-
-            app.fast_forward_toggled = !app.fast_forward_toggled;
-            if app.fast_forward_toggled {
-                c.options.speed_multiplier = app.state.options.fast_forward_toggle_speed;
-            } else {
-                c.options.speed_multiplier = 1;
-            }
-            c.video_buffer.frameskip = c.options.speed_multiplier - 1;
+            let speed = app.state.options.fast_forward_for(core.kind()).toggle;
+            core.c_mut().toggle_fast_forward(speed);
         });
     }),
     ("Rewind (Hold)", |app, pressed| {
@@ -52,6 +40,8 @@ pub const HOTKEYS: &[(&str, HotkeyFn)] = &[
             .options
             .invert_audio_samples = pressed;
     }),
+    ("Advance Frame", |a, p| pressed(a, p, App::advance_frame)),
+    ("Screenshot", |a, p| pressed(a, p, App::screenshot)),
 ];
 
 fn pressed(app: &mut App, pressed: bool, inner: fn(&mut App)) {
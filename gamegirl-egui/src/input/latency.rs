@@ -0,0 +1,121 @@
+// Copyright (c) 2024 Leela Aurelia, git@elia.garden
+//
+// Unless otherwise noted, this file is released and thus subject to the
+// terms of the Mozilla Public License Version 2.0 (MPL-2.0) or the
+// GNU General Public License Version 3 (GPL-3).
+// If a copy of these licenses was not distributed with this file, you can
+// obtain them at https://mozilla.org/MPL/2.0/ and http://www.gnu.org/licenses/.
+
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+/// Amount of measurements the average is taken over.
+const SAMPLES: usize = 16;
+/// Time after which a press that did not change the screen is discarded,
+/// since it probably did not do anything visible.
+const TIMEOUT: Duration = Duration::from_secs(1);
+/// Amount of identical frames that need to be uploaded in a row before a
+/// press for the screen to count as static.
+const STATIC_FRAMES: usize = 4;
+
+/// Measurement of input latency: The time from a button press being applied
+/// to the core until the first frame that differs from the one on screen at
+/// the time of the press is uploaded. This covers emulation, frame pacing
+/// and the frontend, but not the display itself.
+/// A changed frame is only caused by the press if the scene is not animated
+/// on its own, so presses are only measured when the screen was static for
+/// the last few frames. Presses in animated scenes and while a measurement
+/// is running are ignored.
+#[derive(Default)]
+pub struct InputLatency {
+    /// Time of the press being measured, and the hash of the frame that was
+    /// on screen at that point.
+    pending: Option<(Instant, u64)>,
+    /// Hash of the last frame uploaded.
+    last_frame: u64,
+    /// Amount of frames in a row that were identical to [Self::last_frame].
+    unchanged_frames: usize,
+    /// Most recent measurements.
+    samples: VecDeque<Duration>,
+}
+
+impl InputLatency {
+    /// A button was pressed and applied to the core.
+    pub fn press(&mut self) {
+        if self.pending.is_none() && self.unchanged_frames >= STATIC_FRAMES {
+            self.pending = Some((Instant::now(), self.last_frame));
+        }
+    }
+
+    /// A frame with the given [common::common::video::frame_hash] was
+    /// produced, including frames identical to the last one that were
+    /// not uploaded again.
+    pub fn frame(&mut self, hash: u64) {
+        if hash == self.last_frame {
+            self.unchanged_frames += 1;
+        } else {
+            self.unchanged_frames = 0;
+        }
+        self.last_frame = hash;
+        let Some((start, hash)) = self.pending else {
+            return;
+        };
+
+        let elapsed = start.elapsed();
+        if elapsed > TIMEOUT {
+            self.pending = None;
+        } else if hash != self.last_frame {
+            self.pending = None;
+            if self.samples.len() == SAMPLES {
+                self.samples.pop_front();
+            }
+            self.samples.push_back(elapsed);
+        }
+    }
+
+    /// Average latency of the recent measurements in milliseconds,
+    /// `None` if there were none yet.
+    pub fn average_ms(&self) -> Option<f32> {
+        if self.samples.is_empty() {
+            return None;
+        }
+        let total: Duration = self.samples.iter().sum();
+        Some(total.as_secs_f32() * 1000.0 / self.samples.len() as f32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use common::common::video;
+
+    use super::*;
+
+    #[test]
+    fn only_static_scenes_are_measured() {
+        let mut latency = InputLatency::default();
+        let frames = [
+            video::frame_hash(&[[0; 4]; 16]),
+            video::frame_hash(&[[0xFF; 4]; 16]),
+        ];
+
+        // Animated: Every frame differs regardless of input
+        for frame in 0..10 {
+            latency.frame(frames[frame % 2]);
+        }
+        latency.press();
+        latency.frame(frames[0]);
+        assert_eq!(latency.average_ms(), None);
+
+        // Static: The first differing frame is the response to the press
+        for _ in 0..STATIC_FRAMES {
+            latency.frame(frames[0]);
+        }
+        latency.press();
+        latency.frame(frames[0]);
+        assert_eq!(latency.average_ms(), None);
+        latency.frame(frames[1]);
+        assert!(latency.average_ms().is_some());
+    }
+}
@@ -8,13 +8,18 @@
 
 use std::{collections::HashMap, fmt::Display};
 
-use common::common::input::{self, Button::*};
+use common::{
+    common::input::{self, Button::*},
+    CoreKind,
+};
 use eframe::egui::Key;
 pub use file_dialog::File;
 use InputAction::*;
 
 pub mod file_dialog;
 mod hotkeys;
+pub mod latency;
+pub mod rumble;
 
 pub use hotkeys::HOTKEYS;
 
@@ -22,38 +27,73 @@ pub use hotkeys::HOTKEYS;
 #[derive(serde::Deserialize, serde::Serialize)]
 pub struct Input {
     mappings: HashMap<InputSource, InputAction>,
+    /// Controller profiles for each system. Mappings in here take priority
+    /// over the shared ones when a core of that kind is loaded.
+    #[serde(default)]
+    profiles: HashMap<CoreKind, HashMap<InputSource, InputAction>>,
     #[serde(skip, default)]
     pub(crate) pending: Option<InputAction>,
+    /// The profile currently being edited in the options, `None` for the
+    /// shared mappings.
+    #[serde(skip, default)]
+    pub(crate) editing: Option<CoreKind>,
 }
 
 impl Input {
-    /// Get a mapping.
-    pub fn get(&self, src: InputSource) -> Option<InputAction> {
-        self.mappings.get(&src).copied()
+    /// Get a mapping, using the profile of the given core kind if it has one.
+    pub fn get(&self, src: InputSource, kind: Option<CoreKind>) -> Option<InputAction> {
+        kind.and_then(|k| self.profiles.get(&k))
+            .and_then(|p| p.get(&src))
+            .or_else(|| self.mappings.get(&src))
+            .copied()
     }
 
-    /// Set a mapping.
+    /// Set a mapping in the profile currently being edited.
     pub fn set(&mut self, src: InputSource, value: InputAction) {
         if src == InputSource::Key(Key::Escape) {
             // ESC: unset all mappings
             for k in self.key_for(value).collect::<Vec<_>>() {
-                self.mappings.remove(&k);
+                self.editing_mappings().remove(&k);
             }
         } else {
-            self.mappings.insert(src, value);
+            self.editing_mappings().insert(src, value);
+        }
+    }
+
+    /// Returns if the given system has its own profile.
+    pub fn has_profile(&self, kind: CoreKind) -> bool {
+        self.profiles.contains_key(&kind)
+    }
+
+    /// Remove the profile of the given system, making it use the shared
+    /// mappings again.
+    pub fn clear_profile(&mut self, kind: CoreKind) {
+        self.profiles.remove(&kind);
+    }
+
+    fn editing_mappings(&mut self) -> &mut HashMap<InputSource, InputAction> {
+        match self.editing {
+            Some(kind) => self.profiles.entry(kind).or_default(),
+            None => &mut self.mappings,
         }
     }
 
-    /// Get the key for a certain action.
-    pub fn key_for(&mut self, action: InputAction) -> impl Iterator<Item = InputSource> + '_ {
-        self.mappings
-            .iter()
+    /// Get the key for a certain action in the profile currently being
+    /// edited.
+    pub fn key_for(&self, action: InputAction) -> impl Iterator<Item = InputSource> + '_ {
+        let mappings = match self.editing {
+            Some(kind) => self.profiles.get(&kind),
+            None => Some(&self.mappings),
+        };
+        mappings
+            .into_iter()
+            .flatten()
             .filter(move |(_, v)| **v == action)
             .map(|(k, _)| *k)
     }
 
     /// Get the key for a certain action, formatted to a string.
-    pub fn key_for_fmt(&mut self, action: InputAction) -> String {
+    pub fn key_for_fmt(&self, action: InputAction) -> String {
         let mut keys = self
             .key_for(action)
             .map(|k| format!("{k}"))
@@ -76,12 +116,33 @@ impl Input {
                 (InputSource::Key(Key::A), Button(L)),
                 (InputSource::Key(Key::S), Button(R)),
                 (InputSource::Key(Key::R), Hotkey(4)),
+                // Nintendo layout: A is the right face button
+                (InputSource::Button(gilrs::Button::East), Button(A)),
+                (InputSource::Button(gilrs::Button::South), Button(B)),
+                (InputSource::Button(gilrs::Button::Start), Button(Start)),
+                (InputSource::Button(gilrs::Button::Select), Button(Select)),
+                (InputSource::Button(gilrs::Button::DPadDown), Button(Down)),
+                (InputSource::Button(gilrs::Button::DPadUp), Button(Up)),
+                (InputSource::Button(gilrs::Button::DPadLeft), Button(Left)),
+                (InputSource::Button(gilrs::Button::DPadRight), Button(Right)),
+                (InputSource::Button(gilrs::Button::LeftTrigger), Button(L)),
+                (InputSource::Button(gilrs::Button::RightTrigger), Button(R)),
+                (axis(gilrs::Axis::LeftStickY, true), Button(Down)),
+                (axis(gilrs::Axis::LeftStickY, false), Button(Up)),
+                (axis(gilrs::Axis::LeftStickX, true), Button(Left)),
+                (axis(gilrs::Axis::LeftStickX, false), Button(Right)),
             ]),
+            profiles: HashMap::new(),
             pending: None,
+            editing: None,
         }
     }
 }
 
+fn axis(axis: gilrs::Axis, is_neg: bool) -> InputSource {
+    InputSource::Axis { axis, is_neg }
+}
+
 impl Default for Input {
     fn default() -> Self {
         Self::new()
@@ -0,0 +1,63 @@
+// Copyright (c) 2024 Leela Aurelia, git@elia.garden
+//
+// Unless otherwise noted, this file is released and thus subject to the
+// terms of the Mozilla Public License Version 2.0 (MPL-2.0) or the
+// GNU General Public License Version 3 (GPL-3).
+// If a copy of these licenses was not distributed with this file, you can
+// obtain them at https://mozilla.org/MPL/2.0/ and http://www.gnu.org/licenses/.
+
+use std::time::{Duration, Instant};
+
+use gilrs::{
+    ff::{BaseEffect, BaseEffectType, Effect, EffectBuilder},
+    Gilrs,
+};
+
+/// Turns the rumble motor state reported by the core into
+/// controller vibration.
+#[derive(Default)]
+pub struct Rumble {
+    /// The effect currently playing and when it started.
+    playing: Option<(Effect, Instant)>,
+}
+
+impl Rumble {
+    /// Start or stop vibration on all controllers that support it to match
+    /// the motor state, as given by [common::Common::take_rumble].
+    /// Vibration is kept for at least `min_pulse`, so that very short pulses
+    /// can still be felt.
+    pub fn update(&mut self, gil: &mut Gilrs, motor: bool, intensity: f32, min_pulse: Duration) {
+        let on = motor && intensity > 0.0;
+        match &self.playing {
+            None if on => self.playing = Self::start(gil, intensity).map(|e| (e, Instant::now())),
+            Some((effect, start)) if !on && start.elapsed() >= min_pulse => {
+                effect.stop().ok();
+                self.playing = None;
+            }
+            _ => (),
+        }
+    }
+
+    fn start(gil: &mut Gilrs, intensity: f32) -> Option<Effect> {
+        let gamepads = gil
+            .gamepads()
+            .filter(|(_, pad)| pad.is_ff_supported())
+            .map(|(id, _)| id)
+            .collect::<Vec<_>>();
+        if gamepads.is_empty() {
+            return None;
+        }
+
+        let magnitude = (intensity.clamp(0.0, 1.0) * u16::MAX as f32) as u16;
+        let effect = EffectBuilder::new()
+            .add_effect(BaseEffect {
+                kind: BaseEffectType::Strong { magnitude },
+                ..Default::default()
+            })
+            .gamepads(&gamepads)
+            .finish(gil)
+            .ok()?;
+        effect.play().ok()?;
+        Some(effect)
+    }
+}
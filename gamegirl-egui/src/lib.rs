@@ -11,9 +11,11 @@
 
 mod app;
 mod debug;
+mod emu_thread;
 mod filter;
 mod gui;
 mod input;
+mod logger;
 mod rewind;
 
 use std::{
@@ -28,6 +30,8 @@ use cpal::{
 };
 use eframe::egui::Color32;
 use gamegirl::Core;
+#[cfg(not(target_arch = "wasm32"))]
+pub use logger::init_logger;
 
 /// Colour type used by the PPU for display output.
 pub type Colour = Color32;
@@ -0,0 +1,164 @@
+// Copyright (c) 2024 Leela Aurelia, git@elia.garden
+//
+// Unless otherwise noted, this file is released and thus subject to the
+// terms of the Mozilla Public License Version 2.0 (MPL-2.0) or the
+// GNU General Public License Version 3 (GPL-3).
+// If a copy of these licenses was not distributed with this file, you can
+// obtain them at https://mozilla.org/MPL/2.0/ and http://www.gnu.org/licenses/.
+
+//! Logger that keeps recent log records in a ring buffer, to show them
+//! in the GUI for users without a terminal.
+
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+use log::Level;
+
+/// Maximum amount of records kept.
+const CAPACITY: usize = 1000;
+
+/// Recent log records, oldest first.
+static RECORDS: Mutex<VecDeque<LogRecord>> = Mutex::new(VecDeque::new());
+
+/// Most verbose level captured into the buffer, as a [Level] cast to
+/// `usize`, independent of the filter set for terminal output with
+/// `RUST_LOG`. Cores log a lot at info level, so only warnings are
+/// captured unless the log window asks for more.
+static CAPTURE_LEVEL: AtomicUsize = AtomicUsize::new(Level::Warn as usize);
+
+/// A single captured log record.
+#[derive(Clone)]
+pub struct LogRecord {
+    pub level: Level,
+    /// Module the record was logged from.
+    pub target: String,
+    pub message: String,
+}
+
+/// Run the given closure with all captured records, oldest first.
+/// It gets a copy, so logging from it or other threads does not block.
+pub fn with_records<T>(f: impl FnOnce(&VecDeque<LogRecord>) -> T) -> T {
+    let records = RECORDS.lock().unwrap().clone();
+    f(&records)
+}
+
+/// Set the most verbose level of records to capture from now on.
+pub fn set_capture_level(level: Level) {
+    if CAPTURE_LEVEL.swap(level as usize, Ordering::Relaxed) != level as usize {
+        #[cfg(not(target_arch = "wasm32"))]
+        native::update_max_level();
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+fn captures(level: Level) -> bool {
+    level as usize <= CAPTURE_LEVEL.load(Ordering::Relaxed)
+}
+
+/// Remove all captured records.
+pub fn clear() {
+    RECORDS.lock().unwrap().clear();
+}
+
+#[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+fn push(record: &log::Record) {
+    let mut records = RECORDS.lock().unwrap();
+    if records.len() == CAPACITY {
+        records.pop_front();
+    }
+    records.push_back(LogRecord {
+        level: record.level(),
+        target: record.target().to_string(),
+        message: record.args().to_string(),
+    });
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use native::init_logger;
+
+#[cfg(not(target_arch = "wasm32"))]
+mod native {
+    use std::sync::OnceLock;
+
+    use log::{Log, Metadata, Record};
+
+    static LOGGER: OnceLock<RingLogger> = OnceLock::new();
+
+    /// Logger writing to both the ring buffer and the terminal.
+    struct RingLogger {
+        terminal: env_logger::Logger,
+    }
+
+    impl Log for RingLogger {
+        fn enabled(&self, metadata: &Metadata) -> bool {
+            super::captures(metadata.level()) || self.terminal.enabled(metadata)
+        }
+
+        fn log(&self, record: &Record) {
+            if super::captures(record.level()) {
+                super::push(record);
+            }
+            if self.terminal.matches(record) {
+                self.terminal.log(record);
+            }
+        }
+
+        fn flush(&self) {
+            self.terminal.flush();
+        }
+    }
+
+    /// Install the logger. Terminal output is configured with `RUST_LOG`,
+    /// like with `env_logger`.
+    pub fn init_logger() {
+        let terminal = env_logger::Builder::from_default_env().build();
+        let logger = LOGGER.get_or_init(|| RingLogger { terminal });
+        if log::set_logger(logger).is_ok() {
+            update_max_level();
+        }
+    }
+
+    /// Let through everything either the terminal or the buffer wants.
+    pub(super) fn update_max_level() {
+        let Some(logger) = LOGGER.get() else {
+            return;
+        };
+        let capture = log::LevelFilter::iter()
+            .nth(super::CAPTURE_LEVEL.load(super::Ordering::Relaxed))
+            .unwrap();
+        log::set_max_level(logger.terminal.filter().max(capture));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn capture_level_and_snapshot() {
+        set_capture_level(Level::Warn);
+        assert!(captures(Level::Error) && captures(Level::Warn));
+        assert!(!captures(Level::Info));
+        set_capture_level(Level::Info);
+        assert!(captures(Level::Info) && !captures(Level::Debug));
+
+        push(
+            &log::Record::builder()
+                .level(Level::Warn)
+                .target("test")
+                .args(format_args!("message"))
+                .build(),
+        );
+        // The buffer is not locked while looking at the records
+        with_records(|records| {
+            assert_eq!(records.back().unwrap().message, "message");
+            clear();
+        });
+        with_records(|records| assert!(records.is_empty()));
+    }
+}
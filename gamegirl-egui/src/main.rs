@@ -13,7 +13,7 @@ use gamegirl_egui::App;
 fn main() {
     use eframe::egui::ViewportBuilder;
 
-    env_logger::init();
+    gamegirl_egui::init_logger();
     let options = eframe::NativeOptions {
         viewport: ViewportBuilder::default().with_transparent(true),
         default_theme: Theme::Dark,
@@ -16,7 +16,12 @@ use std::{
 };
 
 pub use common::{self, Core};
-use common::{common::options::SystemConfig, components::storage::Storage, Common, Time};
+use common::{
+    common::options::SystemConfig,
+    components::storage::{SaveInfo, Storage},
+    serialize::StateError,
+    Common, CoreKind, Time,
+};
 #[cfg(feature = "gga")]
 pub use gga;
 #[cfg(feature = "ggc")]
@@ -24,6 +29,8 @@ pub use ggc;
 use glow::Context;
 #[cfg(feature = "nds")]
 pub use nds;
+#[cfg(feature = "nes")]
+pub use nes;
 // #[cfg(feature = "psx")]
 // pub use psx;
 use thiserror::Error;
@@ -38,6 +45,10 @@ pub mod remote_debugger;
 pub enum GamegirlError {
     #[error("ROM is too small")]
     RomTooSmall,
+    #[error("ROM has an invalid GB cartridge header")]
+    InvalidGbHeader,
+    #[error("ROM is not a valid iNES file or uses an unsupported mapper")]
+    UnsupportedNesRom,
     #[error("Zip error: {0}")]
     ZipError(ZipError),
     #[error("IO error: {0}")]
@@ -91,35 +102,22 @@ pub fn load_cart(
         return Err(GamegirlError::RomTooSmall);
     }
 
-    // We detect GG(C) carts by the first 2 bytes of the "Nintendo" logo header
-    // that is present on every cartridge.
-    let _is_ggc = cart[0x0104] == 0xCE && cart[0x0105] == 0xED;
-    // We detect GGA carts by a zero-filled header region
-    let _is_gga = cart.iter().skip(0xB5).take(6).all(|b| *b == 0);
-    // We detect NDS carts by a zero-filled header region
-    let _is_nds = cart.iter().skip(0x15).take(6).all(|b| *b == 0);
-    // We detect iNES files by the header
-    let _is_nes = cart[0] == b'N' && cart[1] == b'E' && cart[2] == b'S';
-    // We detect PSX games by being ISOs
-    // #[cfg(feature = "psx")]
-    // use std::os::unix::prelude::OsStrExt;
-    // #[cfg(feature = "psx")]
-    // let _is_psx = path
-    //     .as_ref()
-    //     .map(|e| e.extension().unwrap().as_bytes() == b"iso")
-    //     .unwrap_or(false);
-
-    let mut sys: Box<dyn Core> = match () {
+    let mut sys: Box<dyn Core> = match detect_kind(&cart, path.as_ref(), config) {
         #[cfg(feature = "ggc")]
-        _ if _is_ggc => ggc::GameGirl::with_cart(cart, path, config),
+        Some(CoreKind::Ggc) => {
+            ggc::GameGirl::with_cart(cart, path, config).ok_or(GamegirlError::InvalidGbHeader)?
+        }
         #[cfg(feature = "nds")]
-        _ if _is_nds => nds::Nds::with_cart(cart, path, config),
+        Some(CoreKind::Nds) => nds::Nds::with_cart(cart, path, config),
         #[cfg(feature = "gga")]
-        _ if _is_gga => gga::GameGirlAdv::new(Some(cart), path, config),
+        Some(CoreKind::Gga) => gga::GameGirlAdv::new(Some(cart), path, config),
         // #[cfg(feature = "psx")]
-        // _ if _is_psx => psx::PlayStation::with_iso(cart, path, config, _ogl_ctx, _ogl_tex_id),
-        // #[cfg(feature = "nes")]
-        // _ if _is_nes => nes::Nes::with_cart(cart, path, config),
+        // Some(CoreKind::Psx) => psx::PlayStation::with_iso(cart, path, config, _ogl_ctx,
+        // _ogl_tex_id),
+        #[cfg(feature = "nes")]
+        Some(CoreKind::Nes) => {
+            nes::Nes::with_cart(cart, path, config).ok_or(GamegirlError::UnsupportedNesRom)?
+        }
         #[cfg(feature = "gga")]
         _ => {
             log::error!("Failed to detect cart! Guessing GGA.");
@@ -130,6 +128,11 @@ pub fn load_cart(
         _ => return Err(GamegirlError::AutodetectFailed),
     };
 
+    if let Some(hacks) = config.game_hacks.get(&sys.rom_hash()) {
+        log::info!("Applying compatibility hacks for this game: {hacks:?}");
+        sys.c_mut().game_hacks = *hacks;
+    }
+
     sys.c_mut().debugger.running = config.run_on_open;
     if config.skip_bootrom {
         sys.skip_bootrom();
@@ -137,6 +140,75 @@ pub fn load_cart(
     Ok(sys)
 }
 
+/// Detect which system a cart is for. Expects at least 0x120 bytes.
+fn detect_kind(cart: &[u8], path: Option<&PathBuf>, config: &SystemConfig) -> Option<CoreKind> {
+    // We detect GG(C) carts by the first 2 bytes of the "Nintendo" logo header
+    // that is present on every cartridge.
+    let has_logo = cart[0x0104] == 0xCE && cart[0x0105] == 0xED;
+    // We detect GGA carts by a zero-filled header region
+    let is_gga = cart.iter().skip(0xB5).take(6).all(|b| *b == 0);
+    // We detect NDS carts by a zero-filled header region
+    let is_nds = cart.iter().skip(0x15).take(6).all(|b| *b == 0);
+    // We detect iNES files by the header
+    let is_nes = cart[0] == b'N' && cart[1] == b'E' && cart[2] == b'S';
+    // A valid checksum alone is weak evidence, any other match wins over it
+    let is_ggc = has_logo
+        || (config.gb_lenient_detection
+            && (has_gb_extension(path)
+                || (gb_header_checksum_valid(cart) && !is_gga && !is_nds && !is_nes)));
+    // We detect PSX games by being ISOs
+    // #[cfg(feature = "psx")]
+    // use std::os::unix::prelude::OsStrExt;
+    // #[cfg(feature = "psx")]
+    // let is_psx = path
+    //     .map(|e| e.extension().unwrap().as_bytes() == b"iso")
+    //     .unwrap_or(false);
+
+    match () {
+        _ if is_ggc => Some(CoreKind::Ggc),
+        _ if is_nds => Some(CoreKind::Nds),
+        _ if is_gga => Some(CoreKind::Gga),
+        _ if is_nes => Some(CoreKind::Nes),
+        _ => None,
+    }
+}
+
+/// If the file has an extension used for GB(C) ROMs.
+fn has_gb_extension(path: Option<&PathBuf>) -> bool {
+    path.and_then(|p| p.extension())
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| matches!(e.to_ascii_lowercase().as_str(), "gb" | "gbc" | "sgb"))
+}
+
+/// If the GB header checksum at 0x14D matches the header, which homebrew
+/// usually still sets even when leaving out the logo.
+fn gb_header_checksum_valid(cart: &[u8]) -> bool {
+    if cart.len() < 0x150 {
+        return false;
+    }
+    let checksum = cart[0x134..=0x14C]
+        .iter()
+        .fold(0u8, |acc, b| acc.wrapping_sub(*b).wrapping_sub(1));
+    checksum == cart[0x14D]
+}
+
+/// Detect the kind and size of save a ROM uses, without loading it.
+/// The system is detected the same way [load_cart] does.
+/// Returns `None` if the game does not save or the system is not supported.
+pub fn detect_save(cart: &[u8], path: Option<&PathBuf>, config: &SystemConfig) -> Option<SaveInfo> {
+    if cart.len() < 0x150 {
+        return None;
+    }
+
+    match detect_kind(cart, path, config) {
+        #[cfg(feature = "ggc")]
+        Some(CoreKind::Ggc) => ggc::io::cartridge::Cartridge::detect_save_info(cart),
+        #[cfg(feature = "gga")]
+        Some(CoreKind::Gga) => gga::hw::cartridge::Cartridge::detect_save_info(cart),
+        _ => None,
+    }
+}
+
 pub fn dummy_core() -> Box<dyn Core> {
     Box::<Dummy>::default()
 }
@@ -153,7 +225,9 @@ impl Core for Dummy {
         vec![]
     }
 
-    fn load_state(&mut self, _: &[u8]) {}
+    fn load_state(&mut self, _: &[u8]) -> Result<(), StateError> {
+        Ok(())
+    }
 
     fn advance(&mut self) {}
 
@@ -173,6 +247,10 @@ impl Core for Dummy {
         None
     }
 
+    fn save_is_dirty(&self) -> bool {
+        false
+    }
+
     fn as_any(&mut self) -> &mut dyn std::any::Any {
         self
     }
@@ -189,3 +267,71 @@ impl Core for Dummy {
         &mut self.c
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A cart without any of the headers systems are detected by,
+    /// and optionally a valid GB header checksum.
+    fn cart(checksum: bool) -> Vec<u8> {
+        let mut cart = vec![0x11; 0x150];
+        if checksum {
+            cart[0x14D] = cart[0x134..=0x14C]
+                .iter()
+                .fold(0u8, |acc, b| acc.wrapping_sub(*b).wrapping_sub(1));
+        }
+        cart
+    }
+
+    #[test]
+    fn dummy_core_does_not_step() {
+        // Its clock never advances, this would loop forever
+        assert_eq!(dummy_core().step_cycles(1000), 0);
+    }
+
+    #[test]
+    fn gb_extensions() {
+        let ext = |p: &str| has_gb_extension(Some(&PathBuf::from(p)));
+        assert!(ext("game.gb"));
+        assert!(ext("dir.gba/GAME.GBC"));
+        assert!(ext("game.sgb"));
+        assert!(!ext("game.gba"));
+        assert!(!ext("gb"));
+        assert!(!has_gb_extension(None));
+    }
+
+    #[test]
+    fn gb_header_checksum() {
+        assert!(gb_header_checksum_valid(&cart(true)));
+        assert!(!gb_header_checksum_valid(&cart(false)));
+        assert!(!gb_header_checksum_valid(&cart(true)[..0x14F]));
+    }
+
+    #[test]
+    fn lenient_gb_detection() {
+        let strict = SystemConfig::default();
+        let lenient = SystemConfig {
+            gb_lenient_detection: true,
+            ..SystemConfig::default()
+        };
+        let gb = cart(true);
+        assert_eq!(detect_kind(&gb, None, &strict), None);
+        assert_eq!(detect_kind(&gb, None, &lenient), Some(CoreKind::Ggc));
+        let path = PathBuf::from("game.gb");
+        assert_eq!(
+            detect_kind(&cart(false), Some(&path), &lenient),
+            Some(CoreKind::Ggc)
+        );
+
+        // Other headers win over a checksum that happens to match
+        let mut nes = gb.clone();
+        nes[..4].copy_from_slice(b"NES\x1A");
+        let mut gba = gb;
+        gba[0xB5..0xBB].fill(0);
+        for (cart, kind) in [(nes, CoreKind::Nes), (gba, CoreKind::Gga)] {
+            assert!(gb_header_checksum_valid(&cart));
+            assert_eq!(detect_kind(&cart, None, &lenient), Some(kind));
+        }
+    }
+}
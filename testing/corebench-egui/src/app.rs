@@ -75,6 +75,17 @@ impl App {
             core.c.advance_delta(0.05);
             let elapsed = time.elapsed().as_micros() as f64;
             core.bench.add(now, elapsed / 1000.0);
+            for (name, count) in core.c.take_event_counts() {
+                let idx = match core.events.iter().position(|(n, _)| n == name) {
+                    Some(idx) => idx,
+                    None => {
+                        let history = History::new(10..5000, 30.0);
+                        core.events.push((name.to_string(), history));
+                        core.events.len() - 1
+                    }
+                };
+                core.events[idx].1.add(now, count as f64);
+            }
 
             let frame = core.c.c_mut().video_buffer.pop_recent().map(|p| {
                 p.into_iter()
@@ -178,6 +189,7 @@ impl App {
                 suites: vec![],
                 bench: History::new(10..5000, 30.0),
                 bench_iso: Arc::new(Mutex::new(History::new(10..5000, 100.0))),
+                events: vec![],
                 loader: gamegirl::dynamic::new_core,
                 idx: None,
                 name: "Baseline".to_string(),
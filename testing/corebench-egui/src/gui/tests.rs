@@ -94,3 +94,28 @@ pub(super) fn bench(app: &mut App, _ctx: &Context, ui: &mut Ui) {
             });
     }
 }
+
+pub(super) fn events(app: &mut App, _ctx: &Context, ui: &mut Ui) {
+    if app.cores.iter().all(|c| c.events.is_empty()) {
+        ui.label("No events recorded. Build the cores with the `event-stats` feature to enable.");
+        return;
+    }
+
+    Plot::new("events")
+        .legend(Legend::default())
+        .allow_scroll(false)
+        .allow_drag(false)
+        .include_x(30.0)
+        .x_axis_label("Real Time")
+        .y_axis_label("Events dispatched per frame")
+        .show(ui, |ui| {
+            for core in app.cores.iter() {
+                for (name, history) in &core.events {
+                    ui.line(
+                        Line::new(PlotPoints::from_iter(history.iter().map(|(t, s)| [t, s])))
+                            .name(format!("{}: {name}", core.name)),
+                    );
+                }
+            }
+        });
+}
@@ -31,6 +31,8 @@ pub struct DCore {
     suites: Vec<TestSuiteResult>,
     bench: History<f64>,
     bench_iso: Arc<Mutex<History<f64>>>,
+    /// Scheduler events dispatched per update, by event name.
+    events: Vec<(String, History<f64>)>,
     loader: NewCoreFn,
     idx: Option<usize>,
     name: String,
@@ -57,6 +59,7 @@ fn load_core(ctx: &mut DynamicContext, path: PathBuf) -> Result<DCore, libloadin
         suites: vec![],
         bench: History::new(10..5000, 30.0),
         bench_iso: Arc::new(Mutex::new(History::new(10..5000, 100.0))),
+        events: vec![],
         loader: ctx.get_core(idx).loader,
         idx: Some(idx),
         name: path.file_name().unwrap().to_string_lossy().to_string(),
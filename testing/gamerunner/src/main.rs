@@ -9,14 +9,16 @@ use std::{
 
 use clap::Parser;
 use gamegirl::{
-    common::common::{
-        input::Button,
-        options::{ConsoleBios, SystemConfig},
+    common::{
+        common::{
+            input::Button,
+            options::{ConsoleBios, SystemConfig},
+        },
+        numutil,
     },
     Core,
 };
 use indicatif::{MultiProgress, ProgressBar};
-use png::{BitDepth, ColorType, Encoder};
 use rayon::iter::{IntoParallelIterator, ParallelIterator};
 
 /// Simple program to greet a person
@@ -38,6 +40,12 @@ struct Args {
     /// Do not run games which already have a screenshot
     #[arg(short, long)]
     only_new: bool,
+    /// Instead of taking screenshots, run each game for the given amount
+    /// of frames and write a CRC32 of each frame to `<game>.hashes`.
+    /// If that file already exists, compare against it instead and report
+    /// the first frame that differs
+    #[arg(long)]
+    hash_frames: Option<usize>,
 
     /// BIOS file
     bios: PathBuf,
@@ -152,6 +160,13 @@ fn run_game(
     };
     let mut core = gamegirl::load_cart(rom, None, config, None, 0).unwrap();
 
+    if let Some(frames) = args.hash_frames {
+        let message = check_frame_hashes(&args.output_path, &mut core, name, frames);
+        bar.finish_with_message(Cow::Owned(message));
+        total_bar.inc(1);
+        return;
+    }
+
     for _ in 0..80 {
         let time = Instant::now();
         core.advance_delta(0.5);
@@ -184,26 +199,68 @@ fn run_game(
     total_bar.inc(1);
 }
 
-fn write_png(base_path: &Path, core: &mut Box<dyn Core>, name: &str, ext: &str) {
-    let Some(image) = core.c_mut().video_buffer.pop_recent() else {
-        return;
+/// Run the core for the given amount of frames, advancing by exact frame
+/// boundaries so that the result is deterministic. Hashes are written to
+/// the output directory, or compared against the ones already there.
+fn check_frame_hashes(
+    base_path: &Path,
+    core: &mut Box<dyn Core>,
+    name: &str,
+    frames: usize,
+) -> String {
+    // Every frame needs to be rendered, otherwise frame indices are meaningless
+    core.c_mut().video_buffer.frameskip = 0;
+    while core.c_mut().video_buffer.pop().is_some() {}
+
+    let cap = core.c().config.max_cycles_per_frame;
+    let mut hashes = Vec::with_capacity(frames);
+    for index in 0..frames {
+        let start = core.get_time();
+        let frame = loop {
+            if let Some(frame) = core.c_mut().video_buffer.pop() {
+                break frame;
+            }
+            if !core.c().debugger.running || core.get_time().saturating_sub(start) > cap {
+                return format!("{name}: No frame produced at frame {index}");
+            }
+            core.advance();
+        };
+        hashes.push(numutil::crc32(&frame.concat()));
+    }
+
+    let path = base_path.join(format!("{name}.hashes"));
+    let Ok(baseline) = fs::read_to_string(&path) else {
+        let text = hashes
+            .iter()
+            .map(|h| format!("{h:08X}\n"))
+            .collect::<String>();
+        fs::write(&path, text).unwrap();
+        return format!("{name}: Wrote {frames} frame hashes");
     };
-    let size = core.screen_size();
 
-    let mut encoder = Encoder::new(
-        File::create(base_path.join(format!("{name}.{ext}.png"))).unwrap(),
-        size[0] as u32,
-        size[1] as u32,
-    );
-    encoder.set_color(ColorType::Rgba);
-    encoder.set_depth(BitDepth::Eight);
-    let mut writer = encoder.write_header().unwrap();
-    writer
-        .write_image_data(
-            &image
-                .into_iter()
-                .flat_map(|i| i.into_iter())
-                .collect::<Vec<_>>(),
-        )
-        .unwrap();
+    let mut parsed = Vec::new();
+    for (line_no, line) in baseline.lines().enumerate() {
+        let Ok(hash) = u32::from_str_radix(line.trim(), 16) else {
+            return format!(
+                "{name}: Baseline line {} is malformed: '{line}'",
+                line_no + 1
+            );
+        };
+        parsed.push(hash);
+    }
+    let baseline = parsed;
+    match hashes.iter().zip(&baseline).position(|(a, b)| a != b) {
+        Some(frame) => format!("{name}: Frame {frame} differs from the baseline"),
+        None if baseline.len() < frames => format!(
+            "{name}: Matches, but baseline only has {} frames",
+            baseline.len()
+        ),
+        None => format!("{name}: All {frames} frames match"),
+    }
+}
+
+fn write_png(base_path: &Path, core: &mut Box<dyn Core>, name: &str, ext: &str) {
+    if let Some(png) = core.screenshot_png() {
+        fs::write(base_path.join(format!("{name}.{ext}.png")), png).unwrap();
+    }
 }